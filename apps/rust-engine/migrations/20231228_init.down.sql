@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS institution_usage;
+DROP TABLE IF EXISTS institutions;
+DROP TABLE IF EXISTS transactions;
+DROP TABLE IF EXISTS accounts;
+DROP TABLE IF EXISTS connections;
@@ -0,0 +1,2 @@
+ALTER TABLE transactions DROP COLUMN IF EXISTS status;
+ALTER TABLE connections DROP COLUMN IF EXISTS sync_cursor;
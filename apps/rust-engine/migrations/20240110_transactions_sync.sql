@@ -0,0 +1,5 @@
+-- Checkpoint of the last applied incremental transactions sync
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS sync_cursor TEXT;
+
+-- Pending transactions are replaced in place when they post
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'posted';
@@ -0,0 +1,2 @@
+-- Running balance after each transaction, for providers that report one
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS balance DECIMAL(20, 2);
@@ -0,0 +1,2 @@
+-- Track whether a connection can still be synced
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'active';
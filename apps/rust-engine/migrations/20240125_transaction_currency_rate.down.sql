@@ -0,0 +1,2 @@
+ALTER TABLE transactions DROP COLUMN IF EXISTS currency_source;
+ALTER TABLE transactions DROP COLUMN IF EXISTS currency_rate;
@@ -0,0 +1,3 @@
+-- Exchange details for transactions converted from another currency
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS currency_rate DOUBLE PRECISION;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS currency_source VARCHAR(3);
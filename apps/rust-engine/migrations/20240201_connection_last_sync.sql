@@ -0,0 +1,2 @@
+-- When a connection was last synced successfully, in UTC
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS last_sync TIMESTAMP;
@@ -0,0 +1,24 @@
+-- Durable background work shared by every engine instance
+CREATE TABLE IF NOT EXISTS jobs (
+    id UUID PRIMARY KEY,
+    kind VARCHAR(50) NOT NULL,
+    payload JSONB NOT NULL DEFAULT '{}',
+    -- queued, running, completed, dead or cancelled
+    status VARCHAR(20) NOT NULL DEFAULT 'queued',
+    -- Identifies the work so the same job isn't queued twice, e.g. a
+    -- connection's incremental sync
+    dedupe_key VARCHAR(255),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_by VARCHAR(255),
+    locked_until TIMESTAMP WITH TIME ZONE,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS jobs_status_run_at_idx ON jobs (status, run_at);
+
+CREATE UNIQUE INDEX IF NOT EXISTS jobs_active_dedupe_key_idx ON jobs (dedupe_key)
+    WHERE status IN ('queued', 'running');
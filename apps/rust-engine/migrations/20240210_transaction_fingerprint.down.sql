@@ -0,0 +1,4 @@
+ALTER TABLE jobs DROP COLUMN IF EXISTS result;
+
+DROP INDEX IF EXISTS transactions_account_fingerprint_idx;
+ALTER TABLE transactions DROP COLUMN IF EXISTS fingerprint;
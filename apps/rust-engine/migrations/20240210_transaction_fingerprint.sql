@@ -0,0 +1,8 @@
+-- Stable hash of a transaction's contents, used to recognise it again when
+-- the provider's id is missing or changes between reads
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS fingerprint VARCHAR(64);
+
+CREATE INDEX IF NOT EXISTS transactions_account_fingerprint_idx ON transactions (account_id, fingerprint);
+
+-- Outcome of a finished job, e.g. a sync's counts
+ALTER TABLE jobs ADD COLUMN IF NOT EXISTS result JSONB;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS transactions_superseded_by_idx;
+ALTER TABLE transactions DROP COLUMN IF EXISTS superseded_by;
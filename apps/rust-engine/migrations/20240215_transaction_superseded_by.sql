@@ -0,0 +1,5 @@
+-- The posted transaction that replaced a pending one under a different id
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS superseded_by VARCHAR(255)
+    REFERENCES transactions(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS transactions_superseded_by_idx ON transactions (superseded_by);
@@ -0,0 +1,12 @@
+-- End of day balance of each account, for charting balances over time
+CREATE TABLE IF NOT EXISTS balance_snapshots (
+    account_id VARCHAR(255) NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    date DATE NOT NULL,
+    balance DECIMAL(20, 2) NOT NULL,
+    currency VARCHAR(3),
+    -- reported, running or derived
+    source VARCHAR(20) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (account_id, date)
+);
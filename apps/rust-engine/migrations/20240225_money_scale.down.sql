@@ -0,0 +1,6 @@
+-- Rounds three decimal currencies to two, as they were stored before
+ALTER TABLE balance_snapshots ALTER COLUMN balance TYPE DECIMAL(20, 2);
+ALTER TABLE transactions ALTER COLUMN balance TYPE DECIMAL(20, 2);
+ALTER TABLE transactions ALTER COLUMN amount TYPE DECIMAL(20, 2);
+ALTER TABLE accounts ALTER COLUMN available TYPE DECIMAL(20, 2);
+ALTER TABLE accounts ALTER COLUMN balance TYPE DECIMAL(20, 2);
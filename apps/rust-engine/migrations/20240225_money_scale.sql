@@ -0,0 +1,7 @@
+-- Money is stored with exactly as many decimals as its currency uses, e.g.
+-- none for JPY and three for KWD, so the scale is no longer fixed at two
+ALTER TABLE accounts ALTER COLUMN balance TYPE NUMERIC;
+ALTER TABLE accounts ALTER COLUMN available TYPE NUMERIC;
+ALTER TABLE transactions ALTER COLUMN amount TYPE NUMERIC;
+ALTER TABLE transactions ALTER COLUMN balance TYPE NUMERIC;
+ALTER TABLE balance_snapshots ALTER COLUMN balance TYPE NUMERIC;
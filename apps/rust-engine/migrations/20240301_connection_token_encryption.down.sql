@@ -0,0 +1,6 @@
+-- Encrypted tokens can't be read without their data key, so they go too
+UPDATE connections SET access_token = NULL, refresh_token = NULL WHERE token_key_id IS NOT NULL;
+
+DROP INDEX IF EXISTS connections_token_key_id_idx;
+ALTER TABLE connections DROP COLUMN IF EXISTS token_data_key;
+ALTER TABLE connections DROP COLUMN IF EXISTS token_key_id;
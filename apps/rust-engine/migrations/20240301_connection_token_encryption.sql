@@ -0,0 +1,7 @@
+-- Provider tokens are encrypted with a per-connection data key, stored
+-- wrapped by the master key named in token_key_id. Rows without a key id
+-- still hold plaintext tokens until the reencrypt_tokens job gets to them
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS token_key_id VARCHAR(64);
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS token_data_key BYTEA;
+
+CREATE INDEX IF NOT EXISTS connections_token_key_id_idx ON connections (token_key_id);
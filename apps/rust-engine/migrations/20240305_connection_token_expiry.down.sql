@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS connections_expires_at_idx;
+ALTER TABLE connections DROP COLUMN IF EXISTS status_reason;
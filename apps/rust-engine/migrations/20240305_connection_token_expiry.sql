@@ -0,0 +1,5 @@
+-- Why a connection is in the error state, e.g. its refresh token was revoked
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS status_reason TEXT;
+
+-- The scheduler looks for active connections whose tokens expire soon
+CREATE INDEX IF NOT EXISTS connections_expires_at_idx ON connections (expires_at) WHERE status = 'active';
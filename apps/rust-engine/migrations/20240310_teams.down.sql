@@ -0,0 +1,23 @@
+DROP POLICY IF EXISTS team_isolation ON transactions;
+DROP POLICY IF EXISTS team_isolation ON accounts;
+DROP POLICY IF EXISTS team_isolation ON connections;
+ALTER TABLE transactions NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE transactions DISABLE ROW LEVEL SECURITY;
+ALTER TABLE accounts NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE accounts DISABLE ROW LEVEL SECURITY;
+ALTER TABLE connections NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE connections DISABLE ROW LEVEL SECURITY;
+
+DROP TRIGGER IF EXISTS transactions_team_id ON transactions;
+DROP TRIGGER IF EXISTS accounts_team_id ON accounts;
+DROP FUNCTION IF EXISTS set_team_from_connection();
+
+DROP INDEX IF EXISTS transactions_team_id_date_idx;
+DROP INDEX IF EXISTS accounts_team_id_idx;
+DROP INDEX IF EXISTS connections_team_id_idx;
+
+ALTER TABLE transactions DROP COLUMN IF EXISTS team_id;
+ALTER TABLE accounts DROP COLUMN IF EXISTS team_id;
+ALTER TABLE connections DROP COLUMN IF EXISTS team_id;
+
+DROP TABLE IF EXISTS teams;
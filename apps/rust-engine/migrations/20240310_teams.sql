@@ -0,0 +1,67 @@
+-- Teams own connections and everything synced through them
+CREATE TABLE IF NOT EXISTS teams (
+    id UUID PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Data from before teams is given to a single team of its own
+INSERT INTO teams (id, name)
+SELECT '00000000-0000-0000-0000-000000000000', 'Default'
+WHERE EXISTS (SELECT 1 FROM connections) OR EXISTS (SELECT 1 FROM accounts) OR EXISTS (SELECT 1 FROM transactions);
+
+ALTER TABLE connections ADD COLUMN IF NOT EXISTS team_id UUID REFERENCES teams(id) ON DELETE CASCADE;
+ALTER TABLE accounts ADD COLUMN IF NOT EXISTS team_id UUID REFERENCES teams(id) ON DELETE CASCADE;
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS team_id UUID REFERENCES teams(id) ON DELETE CASCADE;
+
+UPDATE connections SET team_id = '00000000-0000-0000-0000-000000000000' WHERE team_id IS NULL;
+UPDATE accounts a
+SET team_id = COALESCE((SELECT c.team_id FROM connections c WHERE c.id = a.connection_id), '00000000-0000-0000-0000-000000000000')
+WHERE team_id IS NULL;
+UPDATE transactions t
+SET team_id = COALESCE((SELECT c.team_id FROM connections c WHERE c.id = t.connection_id), '00000000-0000-0000-0000-000000000000')
+WHERE team_id IS NULL;
+
+ALTER TABLE connections ALTER COLUMN team_id SET NOT NULL;
+ALTER TABLE accounts ALTER COLUMN team_id SET NOT NULL;
+ALTER TABLE transactions ALTER COLUMN team_id SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS connections_team_id_idx ON connections (team_id);
+CREATE INDEX IF NOT EXISTS accounts_team_id_idx ON accounts (team_id);
+CREATE INDEX IF NOT EXISTS transactions_team_id_date_idx ON transactions (team_id, transaction_date DESC);
+
+-- Accounts and transactions always belong to their connection's team
+CREATE OR REPLACE FUNCTION set_team_from_connection() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.team_id := (SELECT team_id FROM connections WHERE id = NEW.connection_id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS accounts_team_id ON accounts;
+CREATE TRIGGER accounts_team_id BEFORE INSERT OR UPDATE OF connection_id ON accounts
+    FOR EACH ROW EXECUTE FUNCTION set_team_from_connection();
+DROP TRIGGER IF EXISTS transactions_team_id ON transactions;
+CREATE TRIGGER transactions_team_id BEFORE INSERT OR UPDATE OF connection_id ON transactions
+    FOR EACH ROW EXECUTE FUNCTION set_team_from_connection();
+
+-- Requests run with app.team_id set for the transaction and only see that
+-- team's rows, whatever their queries ask for. Background work runs
+-- without it and sees every team. FORCE applies the policies to the
+-- tables' owner too
+ALTER TABLE connections ENABLE ROW LEVEL SECURITY;
+ALTER TABLE connections FORCE ROW LEVEL SECURITY;
+ALTER TABLE accounts ENABLE ROW LEVEL SECURITY;
+ALTER TABLE accounts FORCE ROW LEVEL SECURITY;
+ALTER TABLE transactions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE transactions FORCE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS team_isolation ON connections;
+CREATE POLICY team_isolation ON connections
+    USING (COALESCE(current_setting('app.team_id', true), '') IN ('', team_id::text));
+DROP POLICY IF EXISTS team_isolation ON accounts;
+CREATE POLICY team_isolation ON accounts
+    USING (COALESCE(current_setting('app.team_id', true), '') IN ('', team_id::text));
+DROP POLICY IF EXISTS team_isolation ON transactions;
+CREATE POLICY team_isolation ON transactions
+    USING (COALESCE(current_setting('app.team_id', true), '') IN ('', team_id::text));
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS transactions_team_id_date_idx ON transactions (team_id, transaction_date DESC);
+DROP INDEX IF EXISTS transactions_team_id_date_id_idx;
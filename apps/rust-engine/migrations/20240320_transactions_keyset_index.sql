@@ -0,0 +1,4 @@
+-- Transactions are paged through by date and then id, so the id breaks ties
+-- in the index too
+CREATE INDEX IF NOT EXISTS transactions_team_id_date_id_idx ON transactions (team_id, transaction_date DESC, id DESC);
+DROP INDEX IF EXISTS transactions_team_id_date_idx;
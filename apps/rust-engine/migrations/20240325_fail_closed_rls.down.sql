@@ -0,0 +1,9 @@
+DROP POLICY IF EXISTS team_isolation ON transactions;
+CREATE POLICY team_isolation ON transactions
+    USING (COALESCE(current_setting('app.team_id', true), '') IN ('', team_id::text));
+DROP POLICY IF EXISTS team_isolation ON accounts;
+CREATE POLICY team_isolation ON accounts
+    USING (COALESCE(current_setting('app.team_id', true), '') IN ('', team_id::text));
+DROP POLICY IF EXISTS team_isolation ON connections;
+CREATE POLICY team_isolation ON connections
+    USING (COALESCE(current_setting('app.team_id', true), '') IN ('', team_id::text));
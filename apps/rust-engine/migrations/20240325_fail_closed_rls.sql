@@ -0,0 +1,12 @@
+-- A transaction only sees a team's rows once it sets app.team_id to that
+-- team. Background work sets app.bypass_rls to see every team. Anything
+-- that sets neither sees nothing
+DROP POLICY IF EXISTS team_isolation ON connections;
+CREATE POLICY team_isolation ON connections
+    USING (current_setting('app.bypass_rls', true) = 'on' OR team_id::text = current_setting('app.team_id', true));
+DROP POLICY IF EXISTS team_isolation ON accounts;
+CREATE POLICY team_isolation ON accounts
+    USING (current_setting('app.bypass_rls', true) = 'on' OR team_id::text = current_setting('app.team_id', true));
+DROP POLICY IF EXISTS team_isolation ON transactions;
+CREATE POLICY team_isolation ON transactions
+    USING (current_setting('app.bypass_rls', true) = 'on' OR team_id::text = current_setting('app.team_id', true));
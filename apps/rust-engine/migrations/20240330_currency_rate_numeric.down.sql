@@ -0,0 +1 @@
+ALTER TABLE transactions ALTER COLUMN currency_rate TYPE DOUBLE PRECISION;
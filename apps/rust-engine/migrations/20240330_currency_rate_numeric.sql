@@ -0,0 +1,2 @@
+-- Exchange rates are exact decimals like amounts, rather than floats
+ALTER TABLE transactions ALTER COLUMN currency_rate TYPE NUMERIC;
@@ -0,0 +1,329 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgExecutor, PgPool};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    tenant::{Principal, Scope, Scopes, Subject, Tenant},
+    utils::crypto::random_bytes,
+};
+
+/// Keys read `mde_<prefix>_<secret>`, both parts hex.
+const KEY_PREFIX: &str = "mde_";
+const PREFIX_BYTES: usize = 6;
+const SECRET_BYTES: usize = 32;
+
+/// `last_used_at` is only written this often, rather than on every request.
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+/// An API key as stored. The key itself is never stored, only its hash, so
+/// it can't be shown again after it is created.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub team_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    key_hash: Vec<u8>,
+}
+
+impl ApiKey {
+    fn scopes(&self) -> Scopes {
+        self.scopes.iter().filter_map(|scope| scope.parse().ok()).collect()
+    }
+
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// A new key along with the key itself, which is only ever returned here.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Creates a key for a team.
+pub async fn create<'e>(executor: impl PgExecutor<'e>, team_id: Uuid, new: &NewApiKey) -> AppResult<CreatedApiKey> {
+    if new.scopes.is_empty() {
+        return Err(AppError::BadRequest("An API key needs at least one scope".to_string()));
+    }
+
+    let prefix = hex::encode(random_bytes::<PREFIX_BYTES>());
+    let key = format!("{}{}_{}", KEY_PREFIX, prefix, hex::encode(random_bytes::<SECRET_BYTES>()));
+    let scopes: Vec<&str> = new.scopes.iter().map(Scope::as_str).collect();
+
+    let api_key = sqlx::query_as::<_, ApiKey>(
+        r#"
+        INSERT INTO api_keys (id, team_id, name, prefix, key_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(team_id)
+    .bind(&new.name)
+    .bind(&prefix)
+    .bind(hash(&key))
+    .bind(&scopes)
+    .bind(new.expires_at)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(CreatedApiKey { api_key, key })
+}
+
+/// A team's keys, newest first, including revoked and expired ones.
+pub async fn list(pool: &PgPool, team_id: Uuid) -> AppResult<Vec<ApiKey>> {
+    Ok(
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE team_id = $1 ORDER BY created_at DESC")
+            .bind(team_id)
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+/// Stops a key from working straight away.
+pub async fn revoke(pool: &PgPool, team_id: Uuid, id: Uuid) -> AppResult<ApiKey> {
+    sqlx::query_as::<_, ApiKey>(
+        r#"
+        UPDATE api_keys
+        SET revoked_at = COALESCE(revoked_at, now())
+        WHERE id = $1 AND team_id = $2
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("API key not found".to_string()))
+}
+
+/// Replaces a key with a new one with the same name, scopes and expiry. The
+/// old key keeps working for `grace` so callers can switch over. Both happen
+/// in one transaction, so a failed rotation leaves the old key as it was.
+pub async fn rotate(pool: &PgPool, team_id: Uuid, id: Uuid, grace: Duration) -> AppResult<CreatedApiKey> {
+    let mut tx = pool.begin().await?;
+
+    let old = sqlx::query_as::<_, ApiKey>(
+        "SELECT * FROM api_keys WHERE id = $1 AND team_id = $2 AND revoked_at IS NULL FOR UPDATE",
+    )
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("API key not found".to_string()))?;
+
+    sqlx::query("UPDATE api_keys SET expires_at = LEAST(expires_at, $1) WHERE id = $2")
+        .bind(Utc::now() + grace)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    let new = NewApiKey {
+        name: old.name.clone(),
+        scopes: old.scopes().to_vec(),
+        expires_at: old.expires_at,
+    };
+    let created = create(&mut *tx, team_id, &new).await?;
+    tx.commit().await?;
+
+    Ok(created)
+}
+
+/// Finds the active key `key` belongs to and returns it as a principal, or
+/// `None` if it isn't a valid key.
+pub async fn verify(pool: &PgPool, key: &str) -> AppResult<Option<Principal>> {
+    let Some((prefix, _)) = key.strip_prefix(KEY_PREFIX).and_then(|rest| rest.split_once('_')) else {
+        return Ok(None);
+    };
+
+    let api_key = sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE prefix = $1")
+        .bind(prefix)
+        .fetch_optional(pool)
+        .await?;
+    let Some(api_key) = api_key else {
+        return Ok(None);
+    };
+
+    let matches: bool = hash(key).ct_eq(&api_key.key_hash).into();
+    if !matches || !api_key.is_active(Utc::now()) {
+        return Ok(None);
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE api_keys SET last_used_at = now()
+        WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - $2 * interval '1 second')
+        "#,
+    )
+    .bind(api_key.id)
+    .bind(LAST_USED_RESOLUTION_SECONDS as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(Some(Principal {
+        subject: Subject::ApiKey(api_key.id),
+        tenant: Tenant::new(api_key.team_id, api_key.scopes()),
+    }))
+}
+
+/// Keys are long and random, so a fast hash is as good as a slow one here.
+fn hash(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+/// Runs `api-keys create <team_id> <name> <scope,...>`, which creates the
+/// team too if it doesn't exist yet, e.g. to issue the first admin key.
+pub async fn run_command(pool: &PgPool, args: &[String]) -> AppResult<()> {
+    let (Some("create"), Some(team_id), Some(name), Some(scopes)) = (
+        args.first().map(String::as_str),
+        args.get(1),
+        args.get(2),
+        args.get(3),
+    ) else {
+        eprintln!("Expected api-keys create <team_id> <name> <scope,...>");
+        std::process::exit(2);
+    };
+
+    let team_id = Uuid::parse_str(team_id).map_err(|_| AppError::BadRequest("Invalid team id".to_string()))?;
+    let scopes = scopes.split(',').map(|scope| scope.trim().parse()).collect::<AppResult<_>>()?;
+
+    sqlx::query("INSERT INTO teams (id, name) VALUES ($1, $2) ON CONFLICT (id) DO NOTHING")
+        .bind(team_id)
+        .bind(team_id.to_string())
+        .execute(pool)
+        .await?;
+    let created = create(
+        pool,
+        team_id,
+        &NewApiKey {
+            name: name.clone(),
+            scopes,
+            expires_at: None,
+        },
+    )
+    .await?;
+
+    println!("Created API key {} ({})", created.api_key.id, created.api_key.prefix);
+    println!("{}", created.key);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{test_pool, TEST_TEAM_ID};
+
+    fn new_key(scopes: Vec<Scope>) -> NewApiKey {
+        NewApiKey {
+            name: "Dashboard".to_string(),
+            scopes,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_and_verify() {
+        let Some(pool) = test_pool().await else { return };
+
+        let created = create(&pool, TEST_TEAM_ID, &new_key(vec![Scope::ReadTransactions])).await.unwrap();
+        assert!(created.key.starts_with(&format!("mde_{}_", created.api_key.prefix)));
+
+        let stored: Vec<u8> = sqlx::query_scalar("SELECT key_hash FROM api_keys")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_ne!(stored, created.key.as_bytes());
+
+        let principal = verify(&pool, &created.key).await.unwrap().unwrap();
+        assert_eq!(principal.subject, Subject::ApiKey(created.api_key.id));
+        assert_eq!(principal.tenant.team_id, TEST_TEAM_ID);
+        assert_eq!(principal.tenant.scopes.to_vec(), vec![Scope::ReadTransactions]);
+        assert!(list(&pool, TEST_TEAM_ID).await.unwrap()[0].last_used_at.is_some());
+
+        // Same prefix, different secret
+        let forged = format!("mde_{}_{}", created.api_key.prefix, "0".repeat(64));
+        assert_eq!(verify(&pool, &forged).await.unwrap(), None);
+        assert_eq!(verify(&pool, "not-a-key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_revoked_and_expired_keys() {
+        let Some(pool) = test_pool().await else { return };
+
+        let revoked = create(&pool, TEST_TEAM_ID, &new_key(vec![Scope::Admin])).await.unwrap();
+        revoke(&pool, TEST_TEAM_ID, revoked.api_key.id).await.unwrap();
+        assert_eq!(verify(&pool, &revoked.key).await.unwrap(), None);
+
+        let expired = NewApiKey {
+            expires_at: Some(Utc::now() - Duration::minutes(1)),
+            ..new_key(vec![Scope::Admin])
+        };
+        let expired = create(&pool, TEST_TEAM_ID, &expired).await.unwrap();
+        assert_eq!(verify(&pool, &expired.key).await.unwrap(), None);
+
+        // Another team can't revoke it
+        assert!(revoke(&pool, Uuid::new_v4(), expired.api_key.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rotate() {
+        let Some(pool) = test_pool().await else { return };
+        let old = create(&pool, TEST_TEAM_ID, &new_key(vec![Scope::ReadAccounts])).await.unwrap();
+
+        let new = rotate(&pool, TEST_TEAM_ID, old.api_key.id, Duration::zero()).await.unwrap();
+
+        assert_eq!(new.api_key.name, "Dashboard");
+        assert_eq!(new.api_key.scopes, vec!["read:accounts".to_string()]);
+        assert_eq!(verify(&pool, &old.key).await.unwrap(), None);
+        assert!(verify(&pool, &new.key).await.unwrap().is_some());
+
+        // With a grace period both work for a while
+        let newer = rotate(&pool, TEST_TEAM_ID, new.api_key.id, Duration::hours(1)).await.unwrap();
+        assert!(verify(&pool, &new.key).await.unwrap().is_some());
+        assert!(verify(&pool, &newer.key).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_rotate_keeps_the_old_key() {
+        let Some(pool) = test_pool().await else { return };
+        let old = create(&pool, TEST_TEAM_ID, &new_key(vec![Scope::ReadAccounts])).await.unwrap();
+        // A key the replacement can't be created for
+        sqlx::query("UPDATE api_keys SET scopes = '{}' WHERE id = $1")
+            .bind(old.api_key.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(rotate(&pool, TEST_TEAM_ID, old.api_key.id, Duration::zero()).await.is_err());
+
+        let expires_at: Option<DateTime<Utc>> = sqlx::query_scalar("SELECT expires_at FROM api_keys WHERE id = $1")
+            .bind(old.api_key.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(expires_at, None);
+        assert_eq!(list(&pool, TEST_TEAM_ID).await.unwrap().len(), 1);
+    }
+}
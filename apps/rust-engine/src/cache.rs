@@ -0,0 +1,274 @@
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Whose data a cached response holds. Invalidating a partition drops every
+/// response cached for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    Public,
+    Team(Uuid),
+}
+
+impl Partition {
+    fn key(&self) -> String {
+        match self {
+            Partition::Public => "public".to_string(),
+            Partition::Team(team_id) => format!("team:{}", team_id),
+        }
+    }
+}
+
+/// A response as cached, along with the validators sent with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub content_type: Option<String>,
+    pub body: String,
+    pub etag: String,
+    pub last_modified: String,
+}
+
+struct Entry {
+    response: CachedResponse,
+    expires_at: Instant,
+    used: u64,
+}
+
+/// Least recently used responses, up to `capacity`.
+#[derive(Default)]
+struct Memory {
+    entries: HashMap<String, Entry>,
+    generations: HashMap<String, u64>,
+    capacity: usize,
+    clock: u64,
+}
+
+/// Cached HTTP responses, in Redis so every instance shares them, or in
+/// memory when Redis isn't available.
+///
+/// Keys include their partition's generation, so invalidating a partition
+/// only bumps that. Stale entries are never read again and age out on their
+/// own.
+pub struct ResponseCache {
+    redis: Option<ConnectionManager>,
+    redis_failing: AtomicBool,
+    memory: Mutex<Memory>,
+}
+
+impl ResponseCache {
+    /// A cache of at most `capacity` responses in this instance only.
+    pub fn in_memory(capacity: usize) -> Self {
+        Self {
+            redis: None,
+            redis_failing: AtomicBool::new(false),
+            memory: Mutex::new(Memory {
+                capacity: capacity.max(1),
+                ..Memory::default()
+            }),
+        }
+    }
+
+    /// A cache in Redis, or in memory if Redis can't be reached at startup.
+    /// Once connected, calls that fail fall back to memory until Redis is
+    /// back.
+    pub async fn connect(redis_url: &str, capacity: usize) -> Self {
+        let connection = async {
+            let client = redis::Client::open(redis_url)?;
+            client.get_connection_manager().await
+        };
+
+        match tokio::time::timeout(Duration::from_secs(2), connection).await {
+            Ok(Ok(redis)) => Self {
+                redis: Some(redis),
+                ..Self::in_memory(capacity)
+            },
+            Ok(Err(e)) => {
+                log::warn!("Caching responses in memory, Redis is unavailable: {}", e);
+                Self::in_memory(capacity)
+            }
+            Err(_) => {
+                log::warn!("Caching responses in memory, Redis timed out");
+                Self::in_memory(capacity)
+            }
+        }
+    }
+
+    /// The key a response is cached under. `vary` is whatever else the
+    /// response depends on, such as the path and query.
+    pub async fn key(&self, partition: Partition, vary: &str) -> String {
+        format!("cache:{}:{}:{}", partition.key(), self.generation(partition).await, vary)
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CachedResponse> {
+        if let Some(redis) = &self.redis {
+            let result: redis::RedisResult<Option<String>> = redis.clone().get(key).await;
+            if let Some(cached) = self.redis_result(result) {
+                return cached.and_then(|cached| serde_json::from_str(&cached).ok());
+            }
+        }
+
+        let mut memory = self.memory();
+        memory.clock += 1;
+        let clock = memory.clock;
+        match memory.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.used = clock;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                memory.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub async fn put(&self, key: &str, response: &CachedResponse, ttl: Duration) {
+        if let Some(redis) = &self.redis {
+            let Ok(value) = serde_json::to_string(response) else {
+                return;
+            };
+            let result: redis::RedisResult<()> = redis.clone().set_ex(key, value, ttl.as_secs().max(1) as usize).await;
+            if self.redis_result(result).is_some() {
+                return;
+            }
+        }
+
+        let mut memory = self.memory();
+        if memory.entries.len() >= memory.capacity && !memory.entries.contains_key(key) {
+            let now = Instant::now();
+            memory.entries.retain(|_, entry| entry.expires_at > now);
+            if memory.entries.len() >= memory.capacity {
+                let oldest = memory
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    memory.entries.remove(&oldest);
+                }
+            }
+        }
+        memory.clock += 1;
+        let used = memory.clock;
+        memory.entries.insert(
+            key.to_string(),
+            Entry {
+                response: response.clone(),
+                expires_at: Instant::now() + ttl,
+                used,
+            },
+        );
+    }
+
+    /// Drops everything cached for `partition`, e.g. once a sync has written
+    /// a team's new transactions.
+    pub async fn invalidate(&self, partition: Partition) {
+        let key = format!("cache:generation:{}", partition.key());
+        if let Some(redis) = &self.redis {
+            let result: redis::RedisResult<u64> = redis.clone().incr(&key, 1).await;
+            if self.redis_result(result).is_some() {
+                return;
+            }
+        }
+
+        *self.memory().generations.entry(key).or_default() += 1;
+    }
+
+    async fn generation(&self, partition: Partition) -> u64 {
+        let key = format!("cache:generation:{}", partition.key());
+        if let Some(redis) = &self.redis {
+            let result: redis::RedisResult<Option<u64>> = redis.clone().get(&key).await;
+            if let Some(generation) = self.redis_result(result) {
+                return generation.unwrap_or(0);
+            }
+        }
+
+        self.memory().generations.get(&key).copied().unwrap_or(0)
+    }
+
+    /// The result of a Redis call, or `None` to fall back to memory.
+    fn redis_result<T>(&self, result: redis::RedisResult<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                if self.redis_failing.swap(false, Ordering::Relaxed) {
+                    log::info!("Caching responses in Redis again");
+                }
+                Some(value)
+            }
+            Err(e) => {
+                if !self.redis_failing.swap(true, Ordering::Relaxed) {
+                    log::warn!("Caching responses in memory, Redis failed: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    fn memory(&self) -> std::sync::MutexGuard<'_, Memory> {
+        self.memory.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            content_type: Some("application/json".to_string()),
+            body: body.to_string(),
+            etag: format!("\"{}\"", body),
+            last_modified: "Mon, 01 Jan 2024 00:00:00 GMT".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_only_drops_its_partition() {
+        let cache = ResponseCache::in_memory(10);
+        let team = cache.key(Partition::Team(Uuid::from_u128(1)), "/accounts").await;
+        let other_team = cache.key(Partition::Team(Uuid::from_u128(2)), "/accounts").await;
+        cache.put(&team, &response("team"), Duration::from_secs(60)).await;
+        cache.put(&other_team, &response("other"), Duration::from_secs(60)).await;
+
+        cache.invalidate(Partition::Team(Uuid::from_u128(1))).await;
+
+        assert_ne!(cache.key(Partition::Team(Uuid::from_u128(1)), "/accounts").await, team);
+        assert_eq!(cache.key(Partition::Team(Uuid::from_u128(2)), "/accounts").await, other_team);
+        assert_eq!(cache.get(&other_team).await, Some(response("other")));
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let cache = ResponseCache::in_memory(2);
+        cache.put("a", &response("a"), Duration::from_secs(60)).await;
+        cache.put("b", &response("b"), Duration::from_secs(60)).await;
+        cache.get("a").await;
+
+        cache.put("c", &response("c"), Duration::from_secs(60)).await;
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+
+        cache.put("d", &response("d"), Duration::ZERO).await;
+        assert!(cache.get("d").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_memory_without_redis() {
+        let cache = ResponseCache::connect("redis://127.0.0.1:1", 10).await;
+        assert!(cache.redis.is_none());
+
+        cache.put("a", &response("a"), Duration::from_secs(60)).await;
+        assert_eq!(cache.get("a").await, Some(response("a")));
+    }
+}
@@ -0,0 +1,352 @@
+use sqlx::{FromRow, PgExecutor, PgPool};
+use std::fmt;
+
+use crate::{
+    error::{AppError, AppResult},
+    utils::crypto::Keyring,
+};
+
+/// How many connections the re-encrypt task locks and rewrites at once
+const REENCRYPT_BATCH: i64 = 100;
+
+/// A connection's provider tokens in the clear. Only ever held in memory:
+/// they're encrypted before being stored and `Debug` leaves them out.
+#[derive(Clone, Default, PartialEq)]
+pub struct Credentials {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_token", &self.access_token.as_ref().map(|_| "[redacted]"))
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "[redacted]"))
+            .finish()
+    }
+}
+
+/// Tokens as stored in `connections`.
+#[derive(Debug, Clone, FromRow)]
+pub struct SealedCredentials {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    /// `None` for rows written before tokens were encrypted, which still
+    /// hold them in plaintext
+    pub token_key_id: Option<String>,
+    pub token_data_key: Option<Vec<u8>>,
+}
+
+/// Encrypts a connection's tokens under a new data key. They're bound to the
+/// connection, so they can't be copied to another row and used there.
+pub fn seal(keyring: &Keyring, connection_id: &str, credentials: &Credentials) -> AppResult<SealedCredentials> {
+    let data_key = keyring.new_data_key()?;
+    let encrypt = |token: &Option<String>, column: &str| {
+        token
+            .as_deref()
+            .map(|token| data_key.encrypt(token, &context(connection_id, column)))
+            .transpose()
+    };
+
+    Ok(SealedCredentials {
+        access_token: encrypt(&credentials.access_token, "access_token")?,
+        refresh_token: encrypt(&credentials.refresh_token, "refresh_token")?,
+        token_key_id: Some(data_key.key_id().to_string()),
+        token_data_key: Some(data_key.wrapped().to_vec()),
+    })
+}
+
+pub fn open(keyring: &Keyring, connection_id: &str, sealed: &SealedCredentials) -> AppResult<Credentials> {
+    let (Some(key_id), Some(wrapped)) = (&sealed.token_key_id, &sealed.token_data_key) else {
+        return Ok(Credentials {
+            access_token: sealed.access_token.clone(),
+            refresh_token: sealed.refresh_token.clone(),
+        });
+    };
+
+    let data_key = keyring.open_data_key(key_id, wrapped)?;
+    let decrypt = |token: &Option<String>, column: &str| {
+        token
+            .as_deref()
+            .map(|token| data_key.decrypt(token, &context(connection_id, column)))
+            .transpose()
+    };
+
+    Ok(Credentials {
+        access_token: decrypt(&sealed.access_token, "access_token")?,
+        refresh_token: decrypt(&sealed.refresh_token, "refresh_token")?,
+    })
+}
+
+/// Reads and decrypts a connection's tokens.
+pub async fn load(pool: &PgPool, keyring: &Keyring, connection_id: &str) -> AppResult<Credentials> {
+    let sealed = sqlx::query_as::<_, SealedCredentials>(
+        "SELECT access_token, refresh_token, token_key_id, token_data_key FROM connections WHERE id = $1",
+    )
+    .bind(connection_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Connection not found".to_string()))?;
+
+    open(keyring, connection_id, &sealed)
+}
+
+/// Encrypts and stores a connection's tokens, replacing the old ones.
+pub async fn store<'e>(
+    executor: impl PgExecutor<'e>,
+    keyring: &Keyring,
+    connection_id: &str,
+    credentials: &Credentials,
+) -> AppResult<()> {
+    let sealed = seal(keyring, connection_id, credentials)?;
+
+    sqlx::query(
+        r#"
+        UPDATE connections
+        SET access_token = $1,
+            refresh_token = $2,
+            token_key_id = $3,
+            token_data_key = $4,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $5
+        "#,
+    )
+    .bind(&sealed.access_token)
+    .bind(&sealed.refresh_token)
+    .bind(&sealed.token_key_id)
+    .bind(&sealed.token_data_key)
+    .bind(connection_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether any connection has tokens the re-encrypt task can move onto the
+/// active key: plaintext ones, or ones under a key that is still in the
+/// keyring. Tokens under a key that has been dropped from it can't be moved.
+pub async fn needs_reencryption(pool: &PgPool, keyring: &Keyring) -> AppResult<bool> {
+    Ok(sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM connections
+            WHERE (token_key_id IS NULL OR token_key_id = ANY($1))
+              AND (access_token IS NOT NULL OR refresh_token IS NOT NULL)
+        )
+        "#,
+    )
+    .bind(keyring.retired_key_ids())
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Moves every connection onto the active key: plaintext tokens are
+/// encrypted and data keys wrapped by older keys are rewrapped. Runs in
+/// small batches alongside normal traffic. Tokens that can't be opened, like
+/// ones under a key no longer in the keyring, are logged and left as they
+/// are. Returns how many were updated.
+pub async fn reencrypt(pool: &PgPool, keyring: &Keyring) -> AppResult<usize> {
+    let active = keyring.active_key_id()?;
+    let mut total = 0;
+    // Pages past skipped rows, which would otherwise come back every batch
+    let mut after = String::new();
+
+    loop {
+        let mut tx = pool.begin().await?;
+        let batch = sqlx::query_as::<_, StaleConnection>(
+            r#"
+            SELECT id, access_token, refresh_token, token_key_id, token_data_key
+            FROM connections
+            WHERE token_key_id IS DISTINCT FROM $1
+              AND (access_token IS NOT NULL OR refresh_token IS NOT NULL)
+              AND id > $3
+            ORDER BY id
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(active)
+        .bind(REENCRYPT_BATCH)
+        .bind(&after)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let Some(last) = batch.last() else {
+            return Ok(total);
+        };
+        after = last.id.clone();
+
+        for connection in &batch {
+            let sealed = &connection.sealed;
+            match (&sealed.token_key_id, &sealed.token_data_key) {
+                (Some(key_id), Some(wrapped)) => {
+                    let data_key = match keyring
+                        .open_data_key(key_id, wrapped)
+                        .and_then(|data_key| keyring.rewrap(&data_key))
+                    {
+                        Ok(data_key) => data_key,
+                        Err(e) => {
+                            log::warn!("Skipping re-encryption of connection {}: {}", connection.id, e);
+                            continue;
+                        }
+                    };
+                    sqlx::query("UPDATE connections SET token_key_id = $1, token_data_key = $2 WHERE id = $3")
+                        .bind(data_key.key_id())
+                        .bind(data_key.wrapped())
+                        .bind(&connection.id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                _ => {
+                    let credentials = match open(keyring, &connection.id, sealed) {
+                        Ok(credentials) => credentials,
+                        Err(e) => {
+                            log::warn!("Skipping re-encryption of connection {}: {}", connection.id, e);
+                            continue;
+                        }
+                    };
+                    store(&mut *tx, keyring, &connection.id, &credentials).await?;
+                }
+            }
+            total += 1;
+        }
+
+        tx.commit().await?;
+    }
+}
+
+#[derive(FromRow)]
+struct StaleConnection {
+    id: String,
+    #[sqlx(flatten)]
+    sealed: SealedCredentials,
+}
+
+fn context(connection_id: &str, column: &str) -> String {
+    format!("connections:{}:{}", connection_id, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{test_config, test_pool, TEST_TEAM_ID};
+
+    const NEW_KEY: &str = "new:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    fn credentials(access_token: &str) -> Credentials {
+        Credentials {
+            access_token: Some(access_token.to_string()),
+            refresh_token: Some(format!("{}_refresh", access_token)),
+        }
+    }
+
+    async fn create_connection(pool: &PgPool, id: &str, access_token: Option<&str>) {
+        sqlx::query("INSERT INTO connections (id, provider, access_token, team_id) VALUES ($1, 'plaid', $2, $3)")
+            .bind(id)
+            .bind(access_token)
+            .bind(TEST_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn stored(pool: &PgPool, id: &str) -> SealedCredentials {
+        sqlx::query_as(
+            "SELECT access_token, refresh_token, token_key_id, token_data_key FROM connections WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_store_encrypts_tokens() {
+        let Some(pool) = test_pool().await else { return };
+        let keyring = test_config().token_keys;
+        create_connection(&pool, "conn_1", None).await;
+
+        store(&pool, &keyring, "conn_1", &credentials("secret")).await.unwrap();
+
+        let sealed = stored(&pool, "conn_1").await;
+        assert_eq!(sealed.token_key_id.as_deref(), Some("test"));
+        assert!(!sealed.access_token.unwrap().contains("secret"));
+        assert!(!sealed.refresh_token.unwrap().contains("secret"));
+        assert_eq!(load(&pool, &keyring, "conn_1").await.unwrap(), credentials("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_bound_to_their_connection() {
+        let Some(pool) = test_pool().await else { return };
+        let keyring = test_config().token_keys;
+        create_connection(&pool, "conn_1", None).await;
+        create_connection(&pool, "conn_2", None).await;
+        store(&pool, &keyring, "conn_1", &credentials("secret")).await.unwrap();
+
+        // Copying the encrypted tokens to another connection doesn't work
+        sqlx::query(
+            r#"
+            UPDATE connections c
+            SET access_token = o.access_token, token_key_id = o.token_key_id, token_data_key = o.token_data_key
+            FROM connections o
+            WHERE c.id = 'conn_2' AND o.id = 'conn_1'
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert!(load(&pool, &keyring, "conn_2").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reencrypt_moves_everything_to_the_active_key() {
+        let Some(pool) = test_pool().await else { return };
+        let old = test_config().token_keys;
+        create_connection(&pool, "conn_plain", Some("plain")).await;
+        create_connection(&pool, "conn_old", None).await;
+        create_connection(&pool, "conn_empty", None).await;
+        store(&pool, &old, "conn_old", &credentials("old")).await.unwrap();
+
+        let rotated = Keyring::parse(&format!("{},test:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=", NEW_KEY)).unwrap();
+        assert_eq!(reencrypt(&pool, &rotated).await.unwrap(), 2);
+        assert_eq!(reencrypt(&pool, &rotated).await.unwrap(), 0);
+
+        // Everything can be read once the old key is retired
+        let retired = Keyring::parse(NEW_KEY).unwrap();
+        assert_eq!(stored(&pool, "conn_plain").await.token_key_id.as_deref(), Some("new"));
+        assert_eq!(
+            load(&pool, &retired, "conn_plain").await.unwrap().access_token.as_deref(),
+            Some("plain")
+        );
+        assert_eq!(load(&pool, &retired, "conn_old").await.unwrap(), credentials("old"));
+        assert_eq!(stored(&pool, "conn_empty").await.token_key_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_reencrypt_skips_tokens_under_a_dropped_key() {
+        let Some(pool) = test_pool().await else { return };
+        let keyring = test_config().token_keys;
+        create_connection(&pool, "conn_lost", None).await;
+        create_connection(&pool, "conn_plain", Some("plain")).await;
+        let dropped = Keyring::parse("dropped:AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=").unwrap();
+        store(&pool, &dropped, "conn_lost", &credentials("lost")).await.unwrap();
+        assert!(needs_reencryption(&pool, &keyring).await.unwrap());
+
+        assert_eq!(reencrypt(&pool, &keyring).await.unwrap(), 1);
+
+        assert_eq!(stored(&pool, "conn_lost").await.token_key_id.as_deref(), Some("dropped"));
+        assert_eq!(stored(&pool, "conn_plain").await.token_key_id.as_deref(), Some("test"));
+        // Nothing is left that a new run could move
+        assert!(!needs_reencryption(&pool, &keyring).await.unwrap());
+        assert_eq!(reencrypt(&pool, &keyring).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_debug_hides_tokens() {
+        let debug = format!("{:?}", credentials("secret"));
+
+        assert!(!debug.contains("secret"));
+        assert!(debug.contains("[redacted]"));
+    }
+}
@@ -17,9 +17,15 @@ pub enum AppError {
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
     #[error("Provider error: {0}")]
     Provider(String),
 
+    #[error("Unsupported capability: {0}")]
+    UnsupportedCapability(String),
+
     #[error("Cache error: {0}")]
     Cache(String),
 
@@ -28,9 +34,6 @@ pub enum AppError {
 
     #[error("Internal server error: {0}")]
     Internal(String),
-
-    #[error("External error: {0}")]
-    External(String),
 }
 
 #[derive(Serialize)]
@@ -48,11 +51,12 @@ impl ResponseError for AppError {
             AppError::Authorization(_) => "authorization_error",
             AppError::BadRequest(_) => "bad_request",
             AppError::NotFound(_) => "not_found",
+            AppError::RateLimited(_) => "rate_limited",
             AppError::Provider(_) => "provider_error",
+            AppError::UnsupportedCapability(_) => "unsupported_capability",
             AppError::Cache(_) => "cache_error",
             AppError::Database(_) => "database_error",
             AppError::Internal(_) => "internal_error",
-            AppError::External(_) => "external_error",
         };
 
         HttpResponse::build(status).json(ErrorResponse {
@@ -68,11 +72,12 @@ impl ResponseError for AppError {
             AppError::Authorization(_) => StatusCode::FORBIDDEN,
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             AppError::Provider(_) => StatusCode::BAD_GATEWAY,
+            AppError::UnsupportedCapability(_) => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::Cache(_) 
             | AppError::Database(_) 
             | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            AppError::External(_) => StatusCode::INTERNAL_SERVER_ERROR, // Added this match arm
         }
     }
 }
@@ -0,0 +1,527 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::{fmt, str::FromStr, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    utils::retry::RetryConfig,
+};
+
+pub mod worker;
+
+pub use worker::JobWorker;
+
+/// Backoff between attempts of a failed job. After `max_attempts` the job is
+/// moved to the dead letter state and only runs again when retried by hand.
+pub const JOB_RETRY: RetryConfig = RetryConfig {
+    max_attempts: 5,
+    initial_delay_ms: 30_000,
+    max_delay_ms: 60 * 60 * 1000,
+    backoff_factor: 2.0,
+};
+
+/// A unit of background work. Serializes as `{"kind": ..., "payload": ...}`,
+/// matching the columns it is stored in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "payload", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Reads a connection's full history, ignoring any stored checkpoint
+    InitialSync { connection_id: String },
+    IncrementalSync { connection_id: String },
+    TokenRefresh { connection_id: String },
+    InstitutionImport {
+        provider: String,
+        #[serde(default)]
+        country: Option<String>,
+    },
+    LogoFetch { institution_id: String },
+    /// Moves every connection's tokens onto the active encryption key
+    ReencryptTokens {},
+}
+
+impl JobKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobKind::InitialSync { .. } => "initial_sync",
+            JobKind::IncrementalSync { .. } => "incremental_sync",
+            JobKind::TokenRefresh { .. } => "token_refresh",
+            JobKind::InstitutionImport { .. } => "institution_import",
+            JobKind::LogoFetch { .. } => "logo_fetch",
+            JobKind::ReencryptTokens {} => "reencrypt_tokens",
+        }
+    }
+
+    /// Only one job per key can be queued or running at a time.
+    pub fn dedupe_key(&self) -> String {
+        let subject = match self {
+            JobKind::InitialSync { connection_id }
+            | JobKind::IncrementalSync { connection_id }
+            | JobKind::TokenRefresh { connection_id } => connection_id.clone(),
+            JobKind::InstitutionImport { provider, country } => {
+                format!("{}:{}", provider, country.as_deref().unwrap_or("*"))
+            }
+            JobKind::LogoFetch { institution_id } => institution_id.clone(),
+            JobKind::ReencryptTokens {} => "*".to_string(),
+        };
+        format!("{}:{}", self.name(), subject)
+    }
+
+    /// The connection the job works on, for kinds that work on one.
+    pub fn connection_id(&self) -> Option<&str> {
+        match self {
+            JobKind::InitialSync { connection_id }
+            | JobKind::IncrementalSync { connection_id }
+            | JobKind::TokenRefresh { connection_id } => Some(connection_id),
+            _ => None,
+        }
+    }
+
+    fn payload(&self) -> AppResult<serde_json::Value> {
+        let mut value = serde_json::to_value(self).map_err(|e| AppError::Internal(e.to_string()))?;
+        Ok(value["payload"].take())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    /// Ran out of attempts or failed in a way retrying won't fix
+    Dead,
+    Cancelled,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Queued => write!(f, "queued"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Completed => write!(f, "completed"),
+            JobStatus::Dead => write!(f, "dead"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = AppError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "queued" => Ok(JobStatus::Queued),
+            "running" => Ok(JobStatus::Running),
+            "completed" => Ok(JobStatus::Completed),
+            "dead" => Ok(JobStatus::Dead),
+            "cancelled" => Ok(JobStatus::Cancelled),
+            _ => Err(AppError::BadRequest(format!("Invalid job status {}", status))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub dedupe_key: Option<String>,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: DateTime<Utc>,
+    pub locked_by: Option<String>,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// Set by jobs that report an outcome, such as a sync's counts
+    pub result: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    pub fn job_kind(&self) -> AppResult<JobKind> {
+        serde_json::from_value(serde_json::json!({ "kind": self.kind, "payload": self.payload }))
+            .map_err(|e| AppError::BadRequest(format!("Invalid {} job: {}", self.kind, e)))
+    }
+}
+
+/// Queues a job to run as soon as a worker is free. If the same work is
+/// already queued or running, that job is returned instead.
+pub async fn enqueue(pool: &PgPool, kind: &JobKind) -> AppResult<Job> {
+    let dedupe_key = kind.dedupe_key();
+
+    let job = sqlx::query_as::<_, Job>(
+        r#"
+        INSERT INTO jobs (id, kind, payload, dedupe_key, max_attempts)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (dedupe_key) WHERE status IN ('queued', 'running') DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(kind.name())
+    .bind(kind.payload()?)
+    .bind(&dedupe_key)
+    .bind(JOB_RETRY.max_attempts as i32)
+    .fetch_optional(pool)
+    .await?;
+
+    match job {
+        Some(job) => Ok(job),
+        None => Ok(sqlx::query_as::<_, Job>(
+            "SELECT * FROM jobs WHERE dedupe_key = $1 AND status IN ('queued', 'running')",
+        )
+        .bind(&dedupe_key)
+        .fetch_one(pool)
+        .await?),
+    }
+}
+
+/// Claims up to `limit` jobs that are due for `worker_id`. Rows locked by
+/// another instance are skipped rather than waited on, so any number of
+/// engines can poll the same table. A job whose lease runs out, e.g. because
+/// its instance died, can be claimed again.
+pub async fn lease(pool: &PgPool, worker_id: &str, limit: usize, lease: Duration) -> AppResult<Vec<Job>> {
+    let jobs = sqlx::query_as::<_, Job>(
+        r#"
+        UPDATE jobs
+        SET status = 'running',
+            attempts = attempts + 1,
+            locked_by = $1,
+            locked_until = now() + $2 * interval '1 second',
+            updated_at = now()
+        WHERE id IN (
+            SELECT id FROM jobs
+            WHERE (status = 'queued' AND run_at <= now())
+               OR (status = 'running' AND locked_until < now())
+            ORDER BY run_at
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(worker_id)
+    .bind(lease.as_secs_f64())
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(jobs)
+}
+
+/// Marks a leased job as done. Does nothing if the lease was lost in the
+/// meantime.
+pub async fn complete(pool: &PgPool, job: &Job, result: Option<serde_json::Value>) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE jobs
+        SET status = 'completed', result = $3, locked_by = NULL, locked_until = NULL, updated_at = now()
+        WHERE id = $1 AND status = 'running' AND locked_by = $2
+        "#,
+    )
+    .bind(job.id)
+    .bind(&job.locked_by)
+    .bind(result)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records a failed attempt. The job is queued again after a backoff, unless
+/// it has used up its attempts or `permanent` is set, in which case it is
+/// dead lettered.
+pub async fn fail(pool: &PgPool, job: &Job, error: &str, permanent: bool) -> AppResult<JobStatus> {
+    let status = if permanent || job.attempts >= job.max_attempts {
+        JobStatus::Dead
+    } else {
+        JobStatus::Queued
+    };
+    let delay = JOB_RETRY.delay_for(job.attempts.max(1) as u32);
+
+    sqlx::query(
+        r#"
+        UPDATE jobs
+        SET status = $1,
+            last_error = $2,
+            run_at = now() + $3 * interval '1 millisecond',
+            locked_by = NULL,
+            locked_until = NULL,
+            updated_at = now()
+        WHERE id = $4 AND status = 'running' AND locked_by = $5
+        "#,
+    )
+    .bind(status.to_string())
+    .bind(error)
+    .bind(delay.as_millis() as f64)
+    .bind(job.id)
+    .bind(&job.locked_by)
+    .execute(pool)
+    .await?;
+
+    Ok(status)
+}
+
+/// The most recent jobs. With `team_id` only the ones on that team's
+/// connections, without it every job, including ones that belong to no team
+/// such as institution imports.
+pub async fn list(
+    pool: &PgPool,
+    team_id: Option<Uuid>,
+    status: Option<JobStatus>,
+    kind: Option<&str>,
+    limit: i64,
+) -> AppResult<Vec<Job>> {
+    let jobs = sqlx::query_as::<_, Job>(&format!(
+        r#"
+        SELECT * FROM jobs
+        WHERE ($1::VARCHAR IS NULL OR status = $1)
+          AND ($2::VARCHAR IS NULL OR kind = $2)
+          AND {}
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
+        team_condition(4)
+    ))
+    .bind(status.map(|status| status.to_string()))
+    .bind(kind)
+    .bind(limit)
+    .bind(team_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(jobs)
+}
+
+/// Gives a dead or cancelled job a fresh set of attempts, starting now. With
+/// `team_id` jobs on other teams' connections aren't found.
+pub async fn retry(pool: &PgPool, team_id: Option<Uuid>, id: Uuid) -> AppResult<Job> {
+    let job = sqlx::query_as::<_, Job>(&format!(
+        r#"
+        UPDATE jobs
+        SET status = 'queued', attempts = 0, run_at = now(), updated_at = now()
+        WHERE id = $1 AND status IN ('dead', 'cancelled') AND {}
+        RETURNING *
+        "#,
+        team_condition(2)
+    ))
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(pool)
+    .await;
+
+    match job {
+        Ok(Some(job)) => Ok(job),
+        Ok(None) => Err(transition_error(pool, team_id, id, "Only dead or cancelled jobs can be retried").await),
+        // The same work was queued again after this job stopped
+        Err(e) if e.as_database_error().and_then(|e| e.code()).as_deref() == Some("23505") => {
+            Err(AppError::BadRequest("An identical job is already queued".to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Stops a queued job from running. Jobs that are already running are left
+/// to finish. With `team_id` jobs on other teams' connections aren't found.
+pub async fn cancel(pool: &PgPool, team_id: Option<Uuid>, id: Uuid) -> AppResult<Job> {
+    let job = sqlx::query_as::<_, Job>(&format!(
+        r#"
+        UPDATE jobs
+        SET status = 'cancelled', updated_at = now()
+        WHERE id = $1 AND status = 'queued' AND {}
+        RETURNING *
+        "#,
+        team_condition(2)
+    ))
+    .bind(id)
+    .bind(team_id)
+    .fetch_optional(pool)
+    .await?;
+
+    match job {
+        Some(job) => Ok(job),
+        None => Err(transition_error(pool, team_id, id, "Only queued jobs can be cancelled").await),
+    }
+}
+
+/// Holds for jobs on a connection of the team bound to `$n`, or for every
+/// job if it is null.
+fn team_condition(n: usize) -> String {
+    format!(
+        "(${n}::UUID IS NULL OR payload->>'connection_id' IN (SELECT id FROM connections WHERE team_id = ${n}))",
+        n = n
+    )
+}
+
+async fn transition_error(pool: &PgPool, team_id: Option<Uuid>, id: Uuid, message: &str) -> AppError {
+    let exists = sqlx::query_scalar::<_, bool>(&format!(
+        "SELECT EXISTS(SELECT 1 FROM jobs WHERE id = $1 AND {})",
+        team_condition(2)
+    ))
+    .bind(id)
+    .bind(team_id)
+    .fetch_one(pool)
+    .await;
+
+    match exists {
+        Ok(true) => AppError::BadRequest(message.to_string()),
+        Ok(false) => AppError::NotFound("Job not found".to_string()),
+        Err(e) => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::test_pool;
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    fn sync_job(connection_id: &str) -> JobKind {
+        JobKind::IncrementalSync {
+            connection_id: connection_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_job_kind_round_trip() {
+        let kind = JobKind::InstitutionImport {
+            provider: "gocardless".to_string(),
+            country: Some("GB".to_string()),
+        };
+
+        assert_eq!(kind.payload().unwrap(), serde_json::json!({ "provider": "gocardless", "country": "GB" }));
+        assert_eq!(kind.dedupe_key(), "institution_import:gocardless:GB");
+        assert_eq!(sync_job("conn_1").dedupe_key(), "incremental_sync:conn_1");
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_dedupes_active_jobs() {
+        let Some(pool) = test_pool().await else { return };
+
+        let first = enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+        let second = enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+        let other = enqueue(&pool, &sync_job("conn_2")).await.unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_ne!(first.id, other.id);
+        assert_eq!(first.job_kind().unwrap(), sync_job("conn_1"));
+
+        // Once it has finished the same work can be queued again
+        let leased = lease(&pool, "worker_1", 1, LEASE).await.unwrap();
+        complete(&pool, &leased[0], None).await.unwrap();
+        let third = enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+        assert_ne!(first.id, third.id);
+    }
+
+    #[tokio::test]
+    async fn test_lease_skips_locked_jobs() {
+        let Some(pool) = test_pool().await else { return };
+        let first = enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+        let second = enqueue(&pool, &sync_job("conn_2")).await.unwrap();
+
+        // Another instance is in the middle of claiming the first job
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("SELECT id FROM jobs WHERE id = $1 FOR UPDATE")
+            .bind(first.id)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let leased = lease(&pool, "worker_2", 10, LEASE).await.unwrap();
+        tx.rollback().await.unwrap();
+
+        assert_eq!(leased.len(), 1);
+        assert_eq!(leased[0].id, second.id);
+        assert_eq!(leased[0].attempts, 1);
+        assert_eq!(leased[0].locked_by.as_deref(), Some("worker_2"));
+
+        let leased = lease(&pool, "worker_1", 10, LEASE).await.unwrap();
+        assert_eq!(leased.len(), 1);
+        assert_eq!(leased[0].id, first.id);
+        assert!(lease(&pool, "worker_1", 10, LEASE).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_is_reclaimed() {
+        let Some(pool) = test_pool().await else { return };
+        enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+
+        let stale = lease(&pool, "worker_1", 1, Duration::ZERO).await.unwrap();
+        let reclaimed = lease(&pool, "worker_2", 1, LEASE).await.unwrap();
+
+        assert_eq!(reclaimed[0].id, stale[0].id);
+        assert_eq!(reclaimed[0].attempts, 2);
+
+        // The first worker no longer holds the lease, so its result is ignored
+        complete(&pool, &stale[0], None).await.unwrap();
+        let status: String = sqlx::query_scalar("SELECT status FROM jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "running");
+    }
+
+    #[tokio::test]
+    async fn test_fail_backs_off_then_dead_letters() {
+        let Some(pool) = test_pool().await else { return };
+        let job = enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+
+        for attempt in 1..=JOB_RETRY.max_attempts {
+            // Make the backed off job due straight away
+            sqlx::query("UPDATE jobs SET run_at = now()")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let leased = lease(&pool, "worker_1", 1, LEASE).await.unwrap();
+            assert_eq!(leased[0].attempts, attempt as i32);
+
+            let status = fail(&pool, &leased[0], "bank unavailable", false).await.unwrap();
+            if attempt < JOB_RETRY.max_attempts {
+                assert_eq!(status, JobStatus::Queued);
+                assert!(lease(&pool, "worker_1", 1, LEASE).await.unwrap().is_empty());
+            } else {
+                assert_eq!(status, JobStatus::Dead);
+            }
+        }
+
+        let dead = list(&pool, None, Some(JobStatus::Dead), None, 10).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].last_error.as_deref(), Some("bank unavailable"));
+
+        let retried = retry(&pool, None, job.id).await.unwrap();
+        assert_eq!(retried.status, "queued");
+        assert_eq!(retried.attempts, 0);
+        assert_eq!(lease(&pool, "worker_1", 1, LEASE).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failure_is_dead_lettered() {
+        let Some(pool) = test_pool().await else { return };
+        enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+
+        let leased = lease(&pool, "worker_1", 1, LEASE).await.unwrap();
+        let status = fail(&pool, &leased[0], "Connection not found", true).await.unwrap();
+
+        assert_eq!(status, JobStatus::Dead);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_retry() {
+        let Some(pool) = test_pool().await else { return };
+        let job = enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+
+        assert!(matches!(retry(&pool, None, job.id).await, Err(AppError::BadRequest(_))));
+        assert_eq!(cancel(&pool, None, job.id).await.unwrap().status, "cancelled");
+        assert!(matches!(cancel(&pool, None, job.id).await, Err(AppError::BadRequest(_))));
+        assert!(matches!(cancel(&pool, None, Uuid::new_v4()).await, Err(AppError::NotFound(_))));
+
+        // Queued again in the meantime, so retrying would run it twice
+        enqueue(&pool, &sync_job("conn_1")).await.unwrap();
+        assert!(matches!(retry(&pool, None, job.id).await, Err(AppError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,412 @@
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::{sync::Semaphore, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::{
+    cache::{Partition, ResponseCache},
+    credentials,
+    error::{AppError, AppResult},
+    providers::{types::GetInstitutionsRequest, Capability, ProviderFactory},
+    sync,
+    utils::{config::Config, get_institution_logo},
+};
+
+use super::{Job, JobKind};
+
+/// A job that takes longer than this gives up its slot so a hung bank can't
+/// hold on to it forever.
+const JOB_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+/// How long a leased job stays with this instance before another one may
+/// take it over. Longer than `JOB_TIMEOUT` so a healthy instance never loses
+/// a job it is still running.
+const LEASE: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Runs queued jobs, at most `max_concurrency` at a time. Any number of
+/// instances can run a worker against the same database.
+pub struct JobWorker {
+    pool: PgPool,
+    provider_factory: Arc<ProviderFactory>,
+    cache: Arc<ResponseCache>,
+    id: String,
+    semaphore: Arc<Semaphore>,
+    poll: std::time::Duration,
+}
+
+impl JobWorker {
+    pub fn new(
+        pool: PgPool,
+        provider_factory: Arc<ProviderFactory>,
+        cache: Arc<ResponseCache>,
+        config: &Config,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            pool,
+            provider_factory,
+            cache,
+            id: format!("worker_{}", Uuid::new_v4().simple()),
+            semaphore: Arc::new(Semaphore::new(config.sync_max_concurrency.max(1))),
+            poll: std::time::Duration::from_secs(config.job_poll_seconds.max(1)),
+        })
+    }
+
+    /// Runs the worker until the process exits.
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.poll);
+            loop {
+                ticker.tick().await;
+                // Jobs keep running in the background and the next poll only
+                // leases as many as there are free slots
+                if let Err(e) = self.run_once().await {
+                    log::error!("Failed to lease jobs: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Leases as many due jobs as there are free slots and returns the
+    /// spawned tasks running them.
+    pub async fn run_once(self: &Arc<Self>) -> AppResult<Vec<JoinHandle<()>>> {
+        let available = self.semaphore.available_permits();
+        if available == 0 {
+            return Ok(Vec::new());
+        }
+
+        let leased = super::lease(&self.pool, &self.id, available, LEASE).await?;
+        let mut tasks = Vec::new();
+
+        for job in leased {
+            let Ok(permit) = self.semaphore.clone().try_acquire_owned() else {
+                break;
+            };
+            let worker = self.clone();
+            tasks.push(tokio::spawn(async move {
+                worker.run(job).await;
+                drop(permit);
+            }));
+        }
+
+        Ok(tasks)
+    }
+
+    async fn run(&self, job: Job) {
+        let (error, permanent) = match tokio::time::timeout(JOB_TIMEOUT, self.execute(&job)).await {
+            Ok(Ok(result)) => {
+                log::info!("Completed {} job {}", job.kind, job.id);
+                if let Err(e) = super::complete(&self.pool, &job, result).await {
+                    log::error!("Failed to complete job {}: {}", job.id, e);
+                }
+                return;
+            }
+            Ok(Err(e)) => (e.to_string(), is_permanent(&e)),
+            Err(_) => ("Timed out".to_string(), false),
+        };
+
+        match super::fail(&self.pool, &job, &error, permanent).await {
+            Ok(status) => log::warn!("{} job {} failed ({}): {}", job.kind, job.id, status, error),
+            Err(e) => log::error!("Failed to record failure of job {}: {}", job.id, e),
+        }
+    }
+
+    /// Runs the job and returns its outcome, if it has one worth keeping.
+    async fn execute(&self, job: &Job) -> AppResult<Option<serde_json::Value>> {
+        let summary = match job.job_kind()? {
+            JobKind::InitialSync { connection_id } => {
+                let summary = sync::full_sync_connection(&self.pool, &self.provider_factory, &connection_id).await?;
+                self.invalidate_team_cache(&connection_id).await?;
+                summary
+            }
+            JobKind::IncrementalSync { connection_id } => {
+                let summary = sync::sync_connection(&self.pool, &self.provider_factory, &connection_id).await?;
+                self.invalidate_team_cache(&connection_id).await?;
+                summary
+            }
+            JobKind::TokenRefresh { connection_id } => {
+                sync::refresh_connection_token(&self.pool, &self.provider_factory, &connection_id).await?;
+                return Ok(None);
+            }
+            JobKind::InstitutionImport { provider, country } => {
+                self.import_institutions(&provider, country).await?;
+                return Ok(None);
+            }
+            JobKind::LogoFetch { institution_id } => {
+                self.fetch_logo(&institution_id).await?;
+                return Ok(None);
+            }
+            JobKind::ReencryptTokens {} => {
+                let keyring = &self.provider_factory.config().token_keys;
+                let reencrypted = credentials::reencrypt(&self.pool, keyring).await?;
+                return Ok(Some(serde_json::json!({ "reencrypted": reencrypted })));
+            }
+        };
+
+        let result = serde_json::to_value(summary).map_err(|e| AppError::Internal(e.to_string()))?;
+        Ok(Some(result))
+    }
+
+    /// Stores a provider's institutions and queues a logo fetch for the ones
+    /// that came without a logo.
+    async fn import_institutions(&self, provider_id: &str, country: Option<String>) -> AppResult<()> {
+        let provider = self.provider_factory.require(provider_id, Capability::Institutions)?;
+        let institutions = provider
+            .get_institutions(GetInstitutionsRequest { country, search: None })
+            .await?;
+
+        for institution in &institutions {
+            sqlx::query(
+                r#"
+                INSERT INTO institutions (id, name, logo, country, url, provider)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (id) DO UPDATE SET
+                    name = EXCLUDED.name,
+                    logo = COALESCE(EXCLUDED.logo, institutions.logo),
+                    country = EXCLUDED.country,
+                    url = EXCLUDED.url,
+                    provider = EXCLUDED.provider,
+                    last_update = CURRENT_TIMESTAMP,
+                    updated_at = CURRENT_TIMESTAMP
+                "#,
+            )
+            .bind(&institution.id)
+            .bind(&institution.name)
+            .bind(&institution.logo_url)
+            .bind(&institution.country)
+            .bind(&institution.website)
+            .bind(provider_id)
+            .execute(&self.pool)
+            .await?;
+
+            if institution.logo_url.is_none() {
+                super::enqueue(
+                    &self.pool,
+                    &JobKind::LogoFetch {
+                        institution_id: institution.id.clone(),
+                    },
+                )
+                .await?;
+            }
+        }
+
+        self.cache.invalidate(Partition::Public).await;
+        log::info!("Imported {} {} institutions", institutions.len(), provider_id);
+        Ok(())
+    }
+
+    /// Drops the cached responses of the team a connection belongs to, once
+    /// a sync has written to it.
+    async fn invalidate_team_cache(&self, connection_id: &str) -> AppResult<()> {
+        let team_id: Option<Uuid> = sqlx::query_scalar("SELECT team_id FROM connections WHERE id = $1")
+            .bind(connection_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        if let Some(team_id) = team_id {
+            self.cache.invalidate(Partition::Team(team_id)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a logo for an institution that doesn't have one, from the
+    /// known logos or else from its website's domain.
+    async fn fetch_logo(&self, institution_id: &str) -> AppResult<()> {
+        let (logo, url) = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+            "SELECT logo, url FROM institutions WHERE id = $1",
+        )
+        .bind(institution_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Institution not found".to_string()))?;
+
+        if logo.is_some() {
+            return Ok(());
+        }
+
+        let logo = get_institution_logo(institution_id)
+            .map(str::to_string)
+            .or_else(|| url.as_deref().and_then(website_logo))
+            .ok_or_else(|| AppError::NotFound(format!("No logo found for {}", institution_id)))?;
+
+        sqlx::query("UPDATE institutions SET logo = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+            .bind(logo)
+            .bind(institution_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn website_logo(url: &str) -> Option<String> {
+    let host = reqwest::Url::parse(url).ok()?.host_str()?.to_string();
+    let domain = host.strip_prefix("www.").unwrap_or(&host);
+    Some(format!("https://logo.clearbit.com/{}", domain))
+}
+
+/// Errors that will fail the same way however often the job is retried.
+fn is_permanent(error: &AppError) -> bool {
+    matches!(
+        error,
+        AppError::BadRequest(_) | AppError::NotFound(_) | AppError::UnsupportedCapability(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{self, JobStatus};
+    use crate::utils::testing::{test_config, test_pool, FakeProvider, TEST_TEAM_ID};
+    use std::sync::atomic::Ordering;
+
+    fn worker(pool: &PgPool, provider: Arc<FakeProvider>, max_concurrency: usize) -> Arc<JobWorker> {
+        let config = Config {
+            sync_max_concurrency: max_concurrency,
+            ..test_config()
+        };
+        let provider_factory = ProviderFactory::new(Arc::new(test_config())).with_provider("plaid", provider);
+
+        JobWorker::new(pool.clone(), Arc::new(provider_factory), Arc::new(ResponseCache::in_memory(10)), &config)
+    }
+
+    async fn create_connection(pool: &PgPool, id: &str) {
+        sqlx::query("INSERT INTO connections (id, provider, access_token, team_id) VALUES ($1, 'plaid', $1, $2)")
+            .bind(id)
+            .bind(TEST_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn run(worker: &Arc<JobWorker>) {
+        for task in worker.run_once().await.unwrap() {
+            task.await.unwrap();
+        }
+    }
+
+    async fn statuses(pool: &PgPool) -> Vec<(String, String)> {
+        sqlx::query_as("SELECT kind, status FROM jobs ORDER BY created_at, kind")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_runs_sync_jobs() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool, "conn_1").await;
+        let provider = Arc::new(FakeProvider::default());
+        let worker = worker(&pool, provider.clone(), 4);
+        jobs::enqueue(
+            &pool,
+            &JobKind::InitialSync {
+                connection_id: "conn_1".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+        let cache_key = worker.cache.key(Partition::Team(TEST_TEAM_ID), "/accounts").await;
+
+        run(&worker).await;
+
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(statuses(&pool).await, vec![("initial_sync".to_string(), "completed".to_string())]);
+        assert_ne!(worker.cache.key(Partition::Team(TEST_TEAM_ID), "/accounts").await, cache_key);
+        let result: serde_json::Value = sqlx::query_scalar("SELECT result FROM jobs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(result["inserted"], 1);
+        let transactions: Vec<String> = sqlx::query_scalar("SELECT id FROM transactions")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(transactions, vec!["tx_conn_1".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_limits_concurrency() {
+        let Some(pool) = test_pool().await else { return };
+        for i in 0..5 {
+            let connection_id = format!("conn_{}", i);
+            create_connection(&pool, &connection_id).await;
+            jobs::enqueue(&pool, &JobKind::IncrementalSync { connection_id }).await.unwrap();
+        }
+        let provider = Arc::new(FakeProvider::default());
+        let worker = worker(&pool, provider.clone(), 2);
+
+        let tasks = worker.run_once().await.unwrap();
+        assert_eq!(tasks.len(), 2);
+        // Every slot is taken, so nothing more is leased
+        assert!(worker.run_once().await.unwrap().is_empty());
+        for task in tasks {
+            task.await.unwrap();
+        }
+        while !jobs::list(&pool, None, Some(JobStatus::Queued), None, 10).await.unwrap().is_empty() {
+            run(&worker).await;
+        }
+
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 5);
+        assert_eq!(provider.max_concurrent.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_dead_lettered() {
+        let Some(pool) = test_pool().await else { return };
+        let worker = worker(&pool, Arc::new(FakeProvider::default()), 4);
+        jobs::enqueue(
+            &pool,
+            &JobKind::IncrementalSync {
+                connection_id: "conn_missing".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        run(&worker).await;
+
+        let dead = jobs::list(&pool, None, Some(JobStatus::Dead), None, 10).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 1);
+        assert_eq!(dead[0].last_error.as_deref(), Some("Not found: Connection not found"));
+    }
+
+    #[tokio::test]
+    async fn test_imports_institutions_and_fetches_logos() {
+        let Some(pool) = test_pool().await else { return };
+        let worker = worker(&pool, Arc::new(FakeProvider::default()), 4);
+        jobs::enqueue(
+            &pool,
+            &JobKind::InstitutionImport {
+                provider: "plaid".to_string(),
+                country: Some("US".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+
+        run(&worker).await;
+        run(&worker).await;
+
+        assert_eq!(
+            statuses(&pool).await,
+            vec![
+                ("institution_import".to_string(), "completed".to_string()),
+                ("logo_fetch".to_string(), "completed".to_string()),
+            ]
+        );
+        let logo: Option<String> = sqlx::query_scalar("SELECT logo FROM institutions WHERE id = 'chase'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logo.as_deref(), Some("https://logo.clearbit.com/chase.com"));
+    }
+
+    #[test]
+    fn test_website_logo() {
+        assert_eq!(
+            website_logo("https://www.monzo.com/about").as_deref(),
+            Some("https://logo.clearbit.com/monzo.com")
+        );
+        assert_eq!(website_logo("not a url"), None);
+    }
+}
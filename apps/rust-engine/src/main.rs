@@ -3,40 +3,49 @@ use std::sync::Arc;
 use actix_web::{web, App, HttpServer};
 use sqlx::postgres::PgPoolOptions;
 use env_logger::Env;
-use std::fs;
 use crate::{utils::config};
 
 
 
+mod api_keys;
+mod cache;
+mod credentials;
 mod error;
+mod jobs;
+mod jwt;
 mod middleware;
+mod migrate;
 mod providers;
+mod rate_limit;
 mod routes;
+#[allow(dead_code)]
 mod schemas;
+mod sync;
+mod tenant;
 mod utils;
 
 use crate::{
     config::Config,
-    middleware::{Auth, Cache, Logging, SecurityHeaders},
+    cache::ResponseCache,
+    middleware::{Auth, Cache, Logging, RateLimit, SecurityHeaders},
+    jobs::JobWorker,
     providers::ProviderFactory,
+    rate_limit::RateLimiter,
+    sync::SyncScheduler,
+    tenant::SystemPool,
     routes::{
-        accounts::get_accounts,
-        auth::{exchange_token, refresh_token_handler},
-        connections::{delete_connection, get_connections},
+        accounts::{get_account_balances, get_accounts},
+        api_keys::{create_api_key, get_api_keys, revoke_api_key, rotate_api_key},
+        auth::{create_gocardless_requisition, exchange_token, get_truelayer_auth_link, refresh_token_handler},
+        connections::{delete_connection, get_connection_status, get_connections, sync_connection},
         institutions::{get_institution, get_institutions, update_institution_usage},
+        jobs::{cancel_job, create_job, get_jobs, retry_job},
+        providers::get_providers,
         transactions::get_transactions,
         health::health_check,
     },
 };
 
-async fn run_migrations(pool: &sqlx::PgPool) -> Result<(), Box<dyn std::error::Error>> {
-    let migration_sql = fs::read_to_string("migrations/20231228_init.sql")?;
-    sqlx::query(&migration_sql)
-        .execute(pool)
-        .await?;
-    Ok(())
-}
-
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Initialize environment
@@ -47,42 +56,109 @@ async fn main() -> std::io::Result<()> {
     let config = Arc::new(Config::from_env().expect("Failed to load config"));
     let port = config.port;
 
-    // Initialize database connection pool
+    // Initialize database connection pools. Requests only see their team's
+    // rows; background work and migrations use the system pool, which sees
+    // every team
     let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&config.database_url)
         .await
         .expect("Failed to connect to Postgres");
+    let system_pool = SystemPool::connect(PgPoolOptions::new().max_connections(5), &config.database_url)
+        .await
+        .expect("Failed to connect to Postgres");
 
-    // Run migrations
-    run_migrations(&pool)
+    // `midday-engine migrate up|down [steps]|status` manages the schema
+    // and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        return migrate::run_command(&system_pool, &args[1..])
+            .await
+            .map_err(std::io::Error::other);
+    }
+
+    // Run migrations. Refuses to start against a schema from a newer binary
+    migrate::up(&system_pool)
         .await
         .expect("Failed to run database migrations");
 
+    // `midday-engine api-keys create <team_id> <name> <scope,...>` issues a
+    // key, e.g. the first admin one for a team, and exits
+    if args.first().map(String::as_str) == Some("api-keys") {
+        return api_keys::run_command(&system_pool, &args[1..])
+            .await
+            .map_err(std::io::Error::other);
+    }
+
     // Initialize provider factory, passing in the config
     let provider_factory = Arc::new(ProviderFactory::new(config.clone()));
 
+    // Responses are cached in Redis, or per instance if it can't be
+    // reached. Syncs drop the cached responses they make stale
+    let response_cache = Arc::new(ResponseCache::connect(&config.redis_url, config.cache_capacity).await);
+
+    // Keep connections up to date in the background. Queued work is shared
+    // with any other instance using the same database
+    SyncScheduler::new((*system_pool).clone(), provider_factory.clone(), &config).spawn();
+    JobWorker::new((*system_pool).clone(), provider_factory.clone(), response_cache.clone(), &config).spawn();
+
+    // Picks up plaintext tokens and ones under a retired key after a rotation
+    if credentials::needs_reencryption(&system_pool, &config.token_keys)
+        .await
+        .expect("Failed to check token encryption")
+    {
+        jobs::enqueue(&system_pool, &jobs::JobKind::ReencryptTokens {})
+            .await
+            .expect("Failed to queue token re-encryption");
+    }
+
+    // Request counts are shared through Redis, or kept per instance if it
+    // can't be reached
+    let redis = redis::Client::open(config.redis_url.as_str()).expect("Invalid REDIS_URL");
+    let rate_limiter = web::Data::new(
+        RateLimiter::connect(&config.redis_url, config.rate_limit_window_seconds).await,
+    );
+
     // Start HTTP server
     HttpServer::new(move || {
         App::new()
+            .wrap(Cache)
             .wrap(Logging::new())
             .wrap(SecurityHeaders::new())
+            .wrap(RateLimit)
             .wrap(Auth::new())
-            .wrap(Cache)
             .app_data(web::Data::new(pool.clone()))
-            .app_data(web::Data::new(provider_factory.clone()))
+            .app_data(web::Data::new(system_pool.clone()))
+            .app_data(web::Data::new(redis.clone()))
+            .app_data(rate_limiter.clone())
+            .app_data(web::Data::from(response_cache.clone()))
+            .app_data(web::Data::from(provider_factory.clone()))
             .app_data(web::Data::new(config.clone()))
             .service(
                 web::scope("/api/v1")
                     .service(health_check)
                     .service(get_accounts)
+                    .service(get_account_balances)
                     .service(exchange_token)
                     .service(refresh_token_handler)
+                    .service(create_gocardless_requisition)
+                    .service(get_truelayer_auth_link)
                     .service(get_connections)
                     .service(delete_connection)
+                    .service(sync_connection)
+                    .service(get_connection_status)
                     .service(get_institutions)
                     .service(get_institution)
                     .service(update_institution_usage)
+                    .service(get_providers)
+                    .service(get_jobs)
+                    .service(create_job)
+                    .service(retry_job)
+                    .service(cancel_job)
+                    .service(get_api_keys)
+                    .service(create_api_key)
+                    .service(revoke_api_key)
+                    .service(rotate_api_key)
                     .service(get_transactions),
             )
     })
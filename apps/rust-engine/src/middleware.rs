@@ -1,10 +1,34 @@
 use actix_web::{
+    body::{EitherBody, MessageBody},
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
-    http::header::{HeaderName, HeaderValue},
+    http::{
+        header::{
+            HeaderMap, HeaderName, HeaderValue, HttpDate, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, ETAG,
+            IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+        },
+        Method, StatusCode,
+    },
+    web, Error, HttpMessage, HttpResponse, ResponseError,
 };
 use futures_util::future::LocalBoxFuture;
-use std::future::{ready, Ready};
+use sqlx::PgPool;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    api_keys,
+    cache::{CachedResponse, Partition, ResponseCache},
+    error::AppError,
+    rate_limit::{Decision, RateLimiter},
+    tenant::{Principal, Scope, Subject, Tenant},
+    utils::config::Config,
+};
 
 pub struct Auth;
 
@@ -16,7 +40,7 @@ impl Auth {
 
 impl<S, B> Transform<S, ServiceRequest> for Auth
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -27,17 +51,19 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(AuthMiddleware { service }))
+        ready(Ok(AuthMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct AuthMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -58,71 +84,314 @@ where
             });
         }
 
-        // Get API key from header
-        let api_key = match req.headers().get("x-api-key") {
-            Some(key) => key.to_str().unwrap_or(""),
-            None => "",
-        };
+        // Either a bearer token or an API key, which decides the team and
+        // what the request may do there
+        let bearer = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let api_key = req
+            .headers()
+            .get("x-api-key")
+            .and_then(|key| key.to_str().ok())
+            .map(str::to_string);
+        let config = req.app_data::<web::Data<Arc<Config>>>().cloned();
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let service = self.service.clone();
 
-        // Validate API key
-        if api_key.is_empty() || api_key != std::env::var("API_SECRET_KEY").unwrap_or_default() {
-            return Box::pin(async move {
-                Err(actix_web::error::ErrorUnauthorized("Invalid API key"))
-            });
-        }
+        Box::pin(async move {
+            let principal = match (bearer, api_key) {
+                (Some(token), _) => config
+                    .as_ref()
+                    .and_then(|config| config.jwt.as_ref())
+                    .ok_or_else(|| AppError::Authentication("Bearer tokens are not accepted".to_string()))?
+                    .verify(&token)?,
+                (None, Some(api_key)) => {
+                    let pool = pool.ok_or_else(|| AppError::Internal("No database pool".to_string()))?;
+                    api_keys::verify(&pool, &api_key)
+                        .await?
+                        .ok_or_else(|| AppError::Authentication("Invalid API key".to_string()))?
+                }
+                (None, None) => return Err(AppError::Authentication("Missing credentials".to_string()).into()),
+            };
+            req.extensions_mut().insert(principal.tenant);
+            req.extensions_mut().insert(principal);
+
+            service.call(req).await
+        })
+    }
+}
+
+/// Limits each API key or bearer token, and each team across all of its
+/// credentials, per route group: the first path segment after `/api/v1`,
+/// e.g. `transactions` or `admin`. Runs inside `Auth`, and lets requests
+/// without a principal through.
+pub struct RateLimit;
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let principal = req.extensions().get::<Principal>().cloned();
+        let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+        let config = req.app_data::<web::Data<Arc<Config>>>().cloned();
+        let service = self.service.clone();
 
-        let fut = self.service.call(req);
         Box::pin(async move {
-            let res = fut.await?;
-            Ok(res)
+            let (Some(principal), Some(limiter), Some(config)) = (principal, limiter, config) else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            let group = route_group(req.path());
+            let subject = match &principal.subject {
+                Subject::ApiKey(id) => format!("key:{}", id),
+                Subject::User(sub) => format!("user:{}", sub),
+            };
+            let limit = |limits: &HashMap<String, u64>| {
+                limits.get(&group).or_else(|| limits.get("default")).copied().unwrap_or(u64::MAX)
+            };
+
+            let by_subject = limiter
+                .check(&format!("ratelimit:{}:{}", subject, group), limit(&config.rate_limits))
+                .await;
+            let decision = if by_subject.allowed() {
+                let by_team = limiter
+                    .check(
+                        &format!("ratelimit:team:{}:{}", principal.tenant.team_id, group),
+                        limit(&config.tenant_rate_limits),
+                    )
+                    .await;
+                // Report whichever is closer to running out
+                if !by_team.allowed() || by_team.remaining < by_subject.remaining {
+                    by_team
+                } else {
+                    by_subject
+                }
+            } else {
+                by_subject
+            };
+
+            if let Some(retry_after) = decision.retry_after {
+                let error = AppError::RateLimited(format!("Too many requests, retry in {}s", retry_after));
+                let mut response = error.error_response();
+                set_rate_limit_headers(response.headers_mut(), &decision);
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            set_rate_limit_headers(res.headers_mut(), &decision);
+            Ok(res.map_into_left_body())
         })
     }
 }
 
+fn route_group(path: &str) -> String {
+    path.trim_start_matches("/api/v1")
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(decision.limit));
+    headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(decision.remaining));
+    headers.insert(HeaderName::from_static("x-ratelimit-reset"), HeaderValue::from(decision.reset_after));
+}
+
+/// Route groups whose responses are the same for everyone. Responses for
+/// other groups are cached per team and credential scopes.
+const PUBLIC_CACHE_GROUPS: [&str; 2] = ["institutions", "rates"];
+
+/// Caches successful JSON responses to GET requests for the route groups
+/// with a TTL in `cache_ttls`, varying on the path and query string and, for
+/// tenant routes, the team and scopes. Responses carry an `ETag` and
+/// `Last-Modified`, and a matching `If-None-Match` gets a 304. Runs inside
+/// `Auth`.
 pub struct Cache;
 
 impl<S, B> Transform<S, ServiceRequest> for Cache
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = CacheMiddleware<S>;
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(CacheMiddleware { service }))
+        ready(Ok(CacheMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct CacheMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S, B> Service<ServiceRequest> for CacheMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let fut = self.service.call(req);
+        let group = route_group(req.path());
+        let ttl = req
+            .app_data::<web::Data<Arc<Config>>>()
+            .and_then(|config| config.cache_ttls.get(&group).copied());
+        let cache = req.app_data::<web::Data<ResponseCache>>().cloned();
+        let tenant = req.extensions().get::<Tenant>().copied();
+        let service = self.service.clone();
+
+        let public = PUBLIC_CACHE_GROUPS.contains(&group.as_str());
+        let (Some(ttl), Some(cache), true) = (ttl, cache, req.method() == Method::GET) else {
+            return Box::pin(async move { service.call(req).await.map(ServiceResponse::map_into_left_body) });
+        };
+        let (partition, vary) = match (public, tenant) {
+            (true, _) => (Partition::Public, String::new()),
+            (false, Some(tenant)) => (
+                Partition::Team(tenant.team_id),
+                tenant.scopes.to_vec().iter().map(Scope::as_str).collect::<Vec<_>>().join(","),
+            ),
+            (false, None) => {
+                return Box::pin(async move { service.call(req).await.map(ServiceResponse::map_into_left_body) });
+            }
+        };
+        let vary = format!("{}:{}", vary, req.uri());
+        let if_none_match = req
+            .headers()
+            .get(IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let cache_control = if public {
+            format!("public, max-age={}", ttl)
+        } else {
+            "private, no-cache".to_string()
+        };
+
         Box::pin(async move {
-            let res = fut.await?;
-            Ok(res)
+            let key = cache.key(partition, &hex::encode(Sha256::digest(vary.as_bytes()))).await;
+
+            if let Some(cached) = cache.get(&key).await {
+                let response = cached_response(&cached, &cache_control, if_none_match.as_deref(), "HIT");
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+
+            let res = service.call(req).await?;
+            let is_json = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("application/json"));
+            if res.status() != StatusCode::OK || !is_json {
+                return Ok(res.map_into_left_body());
+            }
+
+            let (req, res) = res.into_parts();
+            let content_type = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let body = actix_web::body::to_bytes(res.into_body())
+                .await
+                .map_err(|_| AppError::Internal("Failed to read response body".to_string()))?;
+            let body = String::from_utf8(body.to_vec())
+                .map_err(|_| AppError::Internal("Response body is not UTF-8".to_string()))?;
+
+            let cached = CachedResponse {
+                content_type,
+                etag: format!("\"{}\"", &hex::encode(Sha256::digest(body.as_bytes()))[..32]),
+                last_modified: HttpDate::from(SystemTime::now()).to_string(),
+                body,
+            };
+            cache.put(&key, &cached, Duration::from_secs(ttl)).await;
+
+            let response = cached_response(&cached, &cache_control, if_none_match.as_deref(), "MISS");
+            Ok(ServiceResponse::new(req, response).map_into_right_body())
         })
     }
 }
 
+/// The response for a cached body, or a 304 if the client has it already.
+fn cached_response(
+    cached: &CachedResponse,
+    cache_control: &str,
+    if_none_match: Option<&str>,
+    status: &'static str,
+) -> HttpResponse {
+    let not_modified = if_none_match.is_some_and(|tags| {
+        tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == cached.etag)
+    });
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header((ETAG, cached.etag.as_str()))
+        .insert_header((LAST_MODIFIED, cached.last_modified.as_str()))
+        .insert_header((CACHE_CONTROL, cache_control))
+        .insert_header(("x-cache", status));
+    if not_modified {
+        return response.finish();
+    }
+    if let Some(content_type) = &cached.content_type {
+        response.insert_header((CONTENT_TYPE, content_type.as_str()));
+    }
+    response.body(cached.body.clone())
+}
+
 pub struct SecurityHeaders;
 
 impl SecurityHeaders {
@@ -261,16 +530,199 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix_web::test;
-    use actix_web::http::header;
+    use crate::{
+        jwt::JwtVerifier,
+        tenant::{Scopes, Tenant},
+        utils::testing::{test_config, TEST_TEAM_ID},
+    };
+    use actix_web::{get, test, App, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use uuid::Uuid;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    #[get("/api/v1/health")]
+    async fn health() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[get("/api/v1/whoami")]
+    async fn whoami(principal: Principal) -> HttpResponse {
+        HttpResponse::Ok().body(principal.tenant.team_id.to_string())
+    }
+
+    async fn call(request: test::TestRequest) -> (u16, String) {
+        let config = Config {
+            jwt: Some(JwtVerifier::new(Some("secret"), None, "engine", "issuer").unwrap()),
+            ..test_config()
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(Auth::new())
+                .app_data(web::Data::new(Arc::new(config)))
+                .service(health)
+                .service(whoami),
+        )
+        .await;
+
+        match test::try_call_service(&app, request.to_request()).await {
+            Ok(response) => (
+                response.status().as_u16(),
+                String::from_utf8(test::read_body(response).await.to_vec()).unwrap(),
+            ),
+            Err(e) => (e.as_response_error().status_code().as_u16(), String::new()),
+        }
+    }
 
     #[actix_web::test]
     async fn test_auth_middleware_public_path() {
-        // Test implementation
+        assert_eq!(call(test::TestRequest::get().uri("/api/v1/health")).await.0, 200);
     }
 
     #[actix_web::test]
     async fn test_auth_middleware_protected_path() {
-        // Test implementation
+        assert_eq!(call(test::TestRequest::get().uri("/api/v1/whoami")).await.0, 401);
+
+        let claims = serde_json::json!({
+            "sub": "user_1",
+            "team_id": TEST_TEAM_ID,
+            "aud": "engine",
+            "iss": "issuer",
+            "exp": chrono::Utc::now().timestamp() + 60,
+        });
+        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/whoami")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+        assert_eq!(call(request).await, (200, TEST_TEAM_ID.to_string()));
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/whoami")
+            .insert_header((AUTHORIZATION, format!("Bearer {}x", token)));
+        assert_eq!(call(request).await.0, 401);
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_per_key_and_team() {
+        let config = Config {
+            rate_limits: HashMap::from([("default".to_string(), 2)]),
+            tenant_rate_limits: HashMap::from([("default".to_string(), 3)]),
+            ..test_config()
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(RateLimit)
+                .wrap_fn(|req, srv| {
+                    // Stands in for `Auth`, with the key taken from the header
+                    let id = req.headers().get("x-api-key").unwrap().to_str().unwrap().parse().unwrap();
+                    req.extensions_mut().insert(Principal {
+                        subject: Subject::ApiKey(id),
+                        tenant: Tenant::new(TEST_TEAM_ID, Scopes::all()),
+                    });
+                    srv.call(req)
+                })
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(RateLimiter::in_memory(60)))
+                .service(whoami),
+        )
+        .await;
+        let call = |key: Uuid| {
+            test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri("/api/v1/whoami")
+                    .insert_header(("x-api-key", key.to_string()))
+                    .to_request(),
+            )
+        };
+        let (first_key, second_key) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let response = call(first_key).await;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers().get("x-ratelimit-limit").unwrap(), "2");
+        assert_eq!(response.headers().get("x-ratelimit-remaining").unwrap(), "1");
+        assert_eq!(call(first_key).await.status().as_u16(), 200);
+
+        let response = call(first_key).await;
+        assert_eq!(response.status().as_u16(), 429);
+        assert_eq!(response.headers().get("x-ratelimit-remaining").unwrap(), "0");
+        assert!(response.headers().get(RETRY_AFTER).is_some());
+
+        // Another key of the same team has its own limit, but shares the
+        // team's
+        assert_eq!(call(second_key).await.status().as_u16(), 200);
+        let response = call(second_key).await;
+        assert_eq!(response.status().as_u16(), 429);
+        assert_eq!(response.headers().get("x-ratelimit-limit").unwrap(), "3");
+    }
+
+    #[get("/api/v1/accounts")]
+    async fn accounts(tenant: Tenant, calls: web::Data<AtomicUsize>) -> HttpResponse {
+        let calls = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        HttpResponse::Ok().json(serde_json::json!({ "team_id": tenant.team_id, "calls": calls }))
+    }
+
+    #[actix_web::test]
+    async fn test_cache_serves_and_revalidates_responses() {
+        let config = Config {
+            cache_ttls: HashMap::from([("accounts".to_string(), 60)]),
+            ..test_config()
+        };
+        let cache = web::Data::new(ResponseCache::in_memory(10));
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .wrap(Cache)
+                .wrap_fn(|req, srv| {
+                    // Stands in for `Auth`, with the team taken from the header
+                    let team_id = req.headers().get("x-team").unwrap().to_str().unwrap().parse().unwrap();
+                    req.extensions_mut().insert(Tenant::new(team_id, Scopes::all()));
+                    srv.call(req)
+                })
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(cache.clone())
+                .app_data(calls.clone())
+                .service(accounts),
+        )
+        .await;
+        let get = |uri: &str, team_id: Uuid| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("x-team", team_id.to_string()))
+        };
+        let other_team = Uuid::from_u128(2);
+
+        let first = test::call_service(&app, get("/api/v1/accounts", TEST_TEAM_ID).to_request()).await;
+        assert_eq!(first.headers().get("x-cache").unwrap(), "MISS");
+        let etag = first.headers().get(ETAG).unwrap().clone();
+        assert!(first.headers().get(LAST_MODIFIED).is_some());
+        let first: serde_json::Value = test::read_body_json(first).await;
+
+        let second = test::call_service(&app, get("/api/v1/accounts", TEST_TEAM_ID).to_request()).await;
+        assert_eq!(second.headers().get("x-cache").unwrap(), "HIT");
+        assert_eq!(second.headers().get(ETAG).unwrap(), &etag);
+        let second: serde_json::Value = test::read_body_json(second).await;
+        assert_eq!(second, first);
+
+        let request = get("/api/v1/accounts", TEST_TEAM_ID).insert_header((IF_NONE_MATCH, etag.clone()));
+        let not_modified = test::call_service(&app, request.to_request()).await;
+        assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+        assert!(test::read_body(not_modified).await.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Another query string or team isn't served the same response
+        test::call_service(&app, get("/api/v1/accounts?currency=EUR", TEST_TEAM_ID).to_request()).await;
+        let response = test::call_service(&app, get("/api/v1/accounts", other_team).to_request()).await;
+        let response: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(response["team_id"], other_team.to_string());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Nor once the team's cache has been invalidated
+        cache.invalidate(Partition::Team(TEST_TEAM_ID)).await;
+        let request = get("/api/v1/accounts", TEST_TEAM_ID).insert_header((IF_NONE_MATCH, etag.clone()));
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("x-cache").unwrap(), "MISS");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
     }
 }
@@ -0,0 +1,373 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// A schema change compiled into the binary, so it runs the same from any
+/// working directory.
+#[derive(Debug)]
+pub struct Migration {
+    /// File name without the extension, starting with the version
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+impl Migration {
+    /// The date prefix of the name, e.g. `20240205` for `20240205_jobs`
+    pub fn version(&self) -> i64 {
+        self.name
+            .split('_')
+            .next()
+            .and_then(|version| version.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Hash of the up migration, to notice it being edited once applied
+    pub fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.up.as_bytes()))
+    }
+}
+
+macro_rules! migration {
+    ($name:literal) => {
+        Migration {
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
+
+/// Every migration, oldest first. New ones go at the end.
+pub static MIGRATIONS: &[Migration] = &[
+    migration!("20231228_init"),
+    migration!("20240110_transactions_sync"),
+    migration!("20240115_transaction_balance"),
+    migration!("20240120_connection_status"),
+    migration!("20240125_transaction_currency_rate"),
+    migration!("20240201_connection_last_sync"),
+    migration!("20240205_jobs"),
+    migration!("20240210_transaction_fingerprint"),
+    migration!("20240215_transaction_superseded_by"),
+    migration!("20240220_balance_snapshots"),
+    migration!("20240225_money_scale"),
+    migration!("20240301_connection_token_encryption"),
+    migration!("20240305_connection_token_expiry"),
+    migration!("20240310_teams"),
+    migration!("20240315_api_keys"),
+    migration!("20240320_transactions_keyset_index"),
+    migration!("20240325_fail_closed_rls"),
+    migration!("20240330_currency_rate_numeric"),
+];
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("Migration {0} was changed after it was applied")]
+    ChecksumMismatch(&'static str),
+
+    #[error("Database schema is at version {database}, newer than this binary's {binary}")]
+    NewerSchema { database: i64, binary: i64 },
+}
+
+/// Where a migration stands in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    /// `None` while pending
+    pub applied_at: Option<DateTime<Utc>>,
+    /// Applied, but the file has changed since
+    pub modified: bool,
+    /// Applied by a newer binary, so this one doesn't know it
+    pub unknown: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+    applied_at: DateTime<Utc>,
+}
+
+/// Applies every pending migration, each in its own transaction, and returns
+/// the ones applied. Refuses to touch a database that a newer binary has
+/// migrated, or whose applied migrations no longer match their files.
+pub async fn up(pool: &PgPool) -> Result<Vec<&'static Migration>, MigrationError> {
+    let mut conn = pool.acquire().await?;
+    lock(&mut conn).await?;
+    let result = apply_pending(&mut conn).await;
+    unlock(&mut conn).await?;
+    result
+}
+
+/// Reverts the latest `steps` applied migrations, newest first, and returns
+/// the ones reverted.
+pub async fn down(pool: &PgPool, steps: usize) -> Result<Vec<&'static Migration>, MigrationError> {
+    let mut conn = pool.acquire().await?;
+    lock(&mut conn).await?;
+    let result = revert_latest(&mut conn, steps).await;
+    unlock(&mut conn).await?;
+    result
+}
+
+/// Every known migration, plus any applied ones this binary doesn't know,
+/// in version order.
+pub async fn status(pool: &PgPool) -> Result<Vec<MigrationStatus>, MigrationError> {
+    let mut conn = pool.acquire().await?;
+    let mut applied = applied(&mut conn).await?;
+
+    let mut statuses: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|migration| {
+            let row = applied.remove(&migration.version());
+            MigrationStatus {
+                version: migration.version(),
+                name: migration.name.to_string(),
+                applied_at: row.as_ref().map(|row| row.applied_at),
+                modified: row.is_some_and(|row| row.checksum != migration.checksum()),
+                unknown: false,
+            }
+        })
+        .collect();
+    statuses.extend(applied.into_values().map(|row| MigrationStatus {
+        version: row.version,
+        name: row.name,
+        applied_at: Some(row.applied_at),
+        modified: false,
+        unknown: true,
+    }));
+    statuses.sort_by_key(|status| status.version);
+
+    Ok(statuses)
+}
+
+/// Runs `migrate up`, `migrate down [steps]` or `migrate status`, printing
+/// what happened.
+pub async fn run_command(pool: &PgPool, args: &[String]) -> Result<(), MigrationError> {
+    match args.first().map(String::as_str) {
+        Some("up") | None => {
+            let applied = up(pool).await?;
+            println!("Applied {} migration(s)", applied.len());
+            for migration in applied {
+                println!("  {}", migration.name);
+            }
+        }
+        Some("down") => {
+            let steps = args.get(1).and_then(|steps| steps.parse().ok()).unwrap_or(1);
+            let reverted = down(pool, steps).await?;
+            println!("Reverted {} migration(s)", reverted.len());
+            for migration in reverted {
+                println!("  {}", migration.name);
+            }
+        }
+        Some("status") => {
+            for status in status(pool).await? {
+                let state = match (&status.applied_at, status.modified, status.unknown) {
+                    (_, _, true) => "unknown to this binary".to_string(),
+                    (Some(_), true, _) => "modified since applied".to_string(),
+                    (Some(applied_at), _, _) => format!("applied {}", applied_at.format("%Y-%m-%d %H:%M:%S")),
+                    (None, _, _) => "pending".to_string(),
+                };
+                println!("{:<40} {}", status.name, state);
+            }
+        }
+        Some(other) => {
+            eprintln!("Unknown migrate command '{}', expected up, down [steps] or status", other);
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
+
+/// Locks the current schema so instances starting together don't run the
+/// same migration twice.
+async fn lock(conn: &mut PgConnection) -> Result<(), MigrationError> {
+    conn.execute("SELECT pg_advisory_lock(hashtext('schema_migrations.' || current_schema()))")
+        .await?;
+    Ok(())
+}
+
+async fn unlock(conn: &mut PgConnection) -> Result<(), MigrationError> {
+    conn.execute("SELECT pg_advisory_unlock(hashtext('schema_migrations.' || current_schema()))")
+        .await?;
+    Ok(())
+}
+
+async fn apply_pending(conn: &mut PgConnection) -> Result<Vec<&'static Migration>, MigrationError> {
+    let applied = applied(conn).await?;
+    check_not_newer(&applied)?;
+    for migration in MIGRATIONS {
+        if let Some(row) = applied.get(&migration.version()) {
+            if row.checksum != migration.checksum() {
+                return Err(MigrationError::ChecksumMismatch(migration.name));
+            }
+        }
+    }
+
+    let mut done = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| !applied.contains_key(&migration.version())) {
+        let mut tx = conn.begin().await?;
+        tx.execute(migration.up).await?;
+        sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)")
+            .bind(migration.version())
+            .bind(migration.name)
+            .bind(migration.checksum())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        log::info!("Applied migration {}", migration.name);
+        done.push(migration);
+    }
+
+    Ok(done)
+}
+
+async fn revert_latest(conn: &mut PgConnection, steps: usize) -> Result<Vec<&'static Migration>, MigrationError> {
+    let applied = applied(conn).await?;
+    check_not_newer(&applied)?;
+
+    let mut done = Vec::new();
+    for migration in MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|migration| applied.contains_key(&migration.version()))
+        .take(steps)
+    {
+        let mut tx = conn.begin().await?;
+        tx.execute(migration.down).await?;
+        sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+            .bind(migration.version())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        log::info!("Reverted migration {}", migration.name);
+        done.push(migration);
+    }
+
+    Ok(done)
+}
+
+async fn create_table(conn: &mut PgConnection) -> Result<(), MigrationError> {
+    conn.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            checksum VARCHAR(64) NOT NULL,
+            applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn applied(conn: &mut PgConnection) -> Result<HashMap<i64, AppliedMigration>, MigrationError> {
+    create_table(conn).await?;
+    let rows = sqlx::query_as::<_, AppliedMigration>(
+        "SELECT version, name, checksum, applied_at FROM schema_migrations",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.version, row)).collect())
+}
+
+fn check_not_newer(applied: &HashMap<i64, AppliedMigration>) -> Result<(), MigrationError> {
+    let binary = MIGRATIONS.last().map(Migration::version).unwrap_or_default();
+    match applied.keys().max() {
+        Some(&database) if database > binary => Err(MigrationError::NewerSchema { database, binary }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::test_pool;
+
+    async fn table_exists(pool: &PgPool, table: &str) -> bool {
+        sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(table)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let versions: Vec<i64> = MIGRATIONS.iter().map(Migration::version).collect();
+
+        assert!(versions.iter().all(|&version| version > 20000000));
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_up_applies_each_migration_once() {
+        let Some(pool) = test_pool().await else { return };
+
+        assert!(up(&pool).await.unwrap().is_empty());
+
+        let statuses = status(&pool).await.unwrap();
+        assert_eq!(statuses.len(), MIGRATIONS.len());
+        assert!(statuses
+            .iter()
+            .all(|status| status.applied_at.is_some() && !status.modified && !status.unknown));
+    }
+
+    #[tokio::test]
+    async fn test_down_and_up_again() {
+        let Some(pool) = test_pool().await else { return };
+
+        let reverted = down(&pool, 1).await.unwrap();
+        assert_eq!(reverted.len(), 1);
+        assert_eq!(reverted[0].name, MIGRATIONS.last().unwrap().name);
+        let statuses = status(&pool).await.unwrap();
+        assert_eq!(statuses.last().unwrap().applied_at, None);
+
+        // Every down migration undoes its up migration
+        down(&pool, MIGRATIONS.len()).await.unwrap();
+        assert!(!table_exists(&pool, "connections").await);
+        assert!(!table_exists(&pool, "balance_snapshots").await);
+
+        assert_eq!(up(&pool).await.unwrap().len(), MIGRATIONS.len());
+        assert!(table_exists(&pool, "balance_snapshots").await);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_changed_migrations() {
+        let Some(pool) = test_pool().await else { return };
+        sqlx::query("UPDATE schema_migrations SET checksum = 'edited' WHERE version = 20240205")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let error = up(&pool).await.unwrap_err();
+
+        assert!(matches!(error, MigrationError::ChecksumMismatch("20240205_jobs")));
+        assert!(status(&pool).await.unwrap().iter().any(|status| status.modified));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_newer_schema() {
+        let Some(pool) = test_pool().await else { return };
+        sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES (29991231, '29991231_future', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            up(&pool).await.unwrap_err(),
+            MigrationError::NewerSchema { database: 29991231, .. }
+        ));
+        assert!(matches!(down(&pool, 1).await.unwrap_err(), MigrationError::NewerSchema { .. }));
+        assert!(status(&pool).await.unwrap().last().unwrap().unknown);
+    }
+}
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
@@ -6,16 +7,16 @@ use sqlx::FromRow;
 pub struct DbTransaction {
     pub id: String,
     pub account_id: String,
-    pub amount: f64,
+    pub amount: Decimal,
     pub currency: String,
     pub date: DateTime<Utc>,
     pub status: String,
-    pub balance: Option<f64>,
+    pub balance: Option<Decimal>,
     pub category: Option<String>,
     pub method: String,
     pub name: String,
     pub description: Option<String>,
-    pub currency_rate: Option<f64>,
+    pub currency_rate: Option<Decimal>,
     pub currency_source: Option<String>,
 }
 
@@ -34,7 +35,7 @@ pub struct DbAccount {
     pub currency: String,
     pub account_type: String,
     pub institution_id: String,
-    pub balance_amount: f64,
+    pub balance_amount: Decimal,
     pub balance_currency: String,
     pub enrollment_id: Option<String>,
 }
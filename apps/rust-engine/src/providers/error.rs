@@ -0,0 +1,64 @@
+use std::fmt;
+
+use crate::error::AppError;
+
+/// Errors every provider reports through the `Provider` trait. Callers act on
+/// the variant (e.g. `Unauthorized` means the user has to reconnect), so
+/// provider modules map their API's own error codes onto these.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderError {
+    /// The request was malformed or referenced something the provider rejects
+    BadRequest(String),
+    /// Credentials were rejected or consent has expired
+    Unauthorized(String),
+    NotFound(String),
+    RateLimited(String),
+    /// The provider or the institution behind it is temporarily down
+    Unavailable(String),
+    /// The provider doesn't offer this operation
+    Unsupported(String),
+    /// The provider is missing or has invalid configuration on our side
+    Configuration(String),
+    Other(String),
+}
+
+pub type ProviderResult<T> = Result<T, ProviderError>;
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::BadRequest(msg) => write!(f, "Bad Request: {}", msg),
+            ProviderError::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            ProviderError::NotFound(msg) => write!(f, "Not Found: {}", msg),
+            ProviderError::RateLimited(msg) => write!(f, "Rate Limited: {}", msg),
+            ProviderError::Unavailable(msg) => write!(f, "Unavailable: {}", msg),
+            ProviderError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
+            ProviderError::Configuration(msg) => write!(f, "Configuration Error: {}", msg),
+            ProviderError::Other(msg) => write!(f, "Provider Error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() || err.is_connect() {
+            ProviderError::Unavailable(err.to_string())
+        } else {
+            ProviderError::Other(err.to_string())
+        }
+    }
+}
+
+impl From<ProviderError> for AppError {
+    fn from(err: ProviderError) -> Self {
+        match err {
+            ProviderError::BadRequest(msg) => AppError::BadRequest(msg),
+            ProviderError::Unsupported(msg) => AppError::UnsupportedCapability(msg),
+            ProviderError::NotFound(msg) => AppError::NotFound(msg),
+            ProviderError::Configuration(msg) => AppError::Internal(msg),
+            err => AppError::Provider(err.to_string()),
+        }
+    }
+}
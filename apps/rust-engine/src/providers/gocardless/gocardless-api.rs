@@ -1,91 +1,277 @@
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
 
-use crate::{
-    config::Config,
-    utils::{ApiError, ApiResult},
-};
+use crate::config::Config;
+use crate::providers::{ProviderError, ProviderResult};
 
 use super::types::*;
 
+const GOCARDLESS_API_URL: &str = "https://bankaccountdata.gocardless.com/api/v2";
+/// Access scopes requested for every end user agreement.
+const ACCESS_SCOPE: [&str; 3] = ["balances", "details", "transactions"];
+/// Used when an institution doesn't advertise its maximum agreement length.
+const DEFAULT_ACCESS_VALID_FOR_DAYS: u32 = 90;
+/// Tokens are renewed this long before they actually expire.
+const TOKEN_EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+struct CachedToken {
+    access: String,
+    access_expires_at: DateTime<Utc>,
+    refresh: String,
+    refresh_expires_at: DateTime<Utc>,
+}
+
 pub struct GoCardlessApi {
     client: Client,
-    api_key: String,
-    environment: String,
+    secret_id: String,
+    secret_key: String,
+    base_url: String,
+    token: Mutex<Option<CachedToken>>,
 }
 
 impl GoCardlessApi {
     pub fn new(config: &Config) -> Self {
+        Self::with_base_url(&config.gocardless_secret_id, &config.gocardless_secret_key, GOCARDLESS_API_URL)
+    }
+
+    pub fn with_base_url(secret_id: &str, secret_key: &str, base_url: &str) -> Self {
         Self {
             client: Client::new(),
-            api_key: config.gocardless.api_key.clone(),
-            environment: config.gocardless.environment.clone(),
+            secret_id: secret_id.to_string(),
+            secret_key: secret_key.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: Mutex::new(None),
         }
     }
 
-    pub async fn get_institutions(&self, country: Option<&str>) -> ApiResult<Vec<GoCardlessInstitution>> {
-        let url = format!(
-            "https://{}-api.gocardless.com/institutions",
-            self.environment
-        );
-        
-        let mut request = self.client
-            .get(&url)
-            .bearer_auth(&self.api_key);
+    /// Returns a valid access token, reusing the cached one while it lasts
+    /// and falling back to the refresh token before asking for a new pair.
+    /// These tokens are the application's own, so failing to get one says
+    /// nothing about any connection and is never `Unauthorized`.
+    async fn access_token(&self) -> ProviderResult<String> {
+        let mut token = self.token.lock().await;
+        let now = Utc::now() + Duration::seconds(TOKEN_EXPIRY_MARGIN_SECONDS);
+
+        if let Some(cached) = token.as_mut() {
+            if cached.access_expires_at > now {
+                return Ok(cached.access.clone());
+            }
 
-        if let Some(country) = country {
-            request = request.query(&[("country", country)]);
+            if cached.refresh_expires_at > now {
+                let refreshed: GoCardlessRefreshResponse = send(
+                    self.client
+                        .post(format!("{}/token/refresh/", self.base_url))
+                        .json(&serde_json::json!({ "refresh": cached.refresh })),
+                )
+                .await
+                .map_err(token_error)?;
+
+                cached.access = refreshed.access;
+                cached.access_expires_at = Utc::now() + Duration::seconds(refreshed.access_expires);
+                return Ok(cached.access.clone());
+            }
         }
 
-        let response = request.send().await?;
-        let data = response.json().await?;
-        Ok(data)
-    }
-
-    pub async fn get_accounts(&self, requisition_id: &str) -> ApiResult<Vec<GoCardlessAccount>> {
-        let url = format!(
-            "https://{}-api.gocardless.com/accounts",
-            self.environment
-        );
-        
-        let response = self.client
-            .get(&url)
-            .bearer_auth(&self.api_key)
-            .query(&[("requisition_id", requisition_id)])
-            .send()
-            .await?;
+        let issued: GoCardlessTokenResponse = send(
+            self.client
+                .post(format!("{}/token/new/", self.base_url))
+                .json(&serde_json::json!({
+                    "secret_id": self.secret_id,
+                    "secret_key": self.secret_key,
+                })),
+        )
+        .await
+        .map_err(token_error)?;
 
-        let data = response.json().await?;
-        Ok(data)
+        let issued_at = Utc::now();
+        let access = issued.access.clone();
+        *token = Some(CachedToken {
+            access: issued.access,
+            access_expires_at: issued_at + Duration::seconds(issued.access_expires),
+            refresh: issued.refresh,
+            refresh_expires_at: issued_at + Duration::seconds(issued.refresh_expires),
+        });
+
+        Ok(access)
+    }
+
+    /// Drops the cached token pair if it's still the one `access` came from,
+    /// so the next request asks for a new pair.
+    async fn forget_token(&self, access: &str) {
+        let mut token = self.token.lock().await;
+        if token.as_ref().is_some_and(|cached| cached.access == access) {
+            *token = None;
+        }
     }
 
-    pub async fn get_transactions(
+    /// Sends a request with the application's token. A token rejected before
+    /// it was due to expire, like one revoked when the secrets are rotated,
+    /// is dropped and the request retried once with a new one.
+    async fn request<T: DeserializeOwned>(
         &self,
-        account_id: &str,
-        date_from: Option<DateTime<Utc>>,
-        date_to: Option<DateTime<Utc>>,
-    ) -> ApiResult<Vec<GoCardlessTransaction>> {
-        let url = format!(
-            "https://{}-api.gocardless.com/accounts/{}/transactions",
-            self.environment,
-            account_id
-        );
-        
-        let mut request = self.client
-            .get(&url)
-            .bearer_auth(&self.api_key);
-
-        if let Some(date_from) = date_from {
-            request = request.query(&[("date_from", date_from.format("%Y-%m-%d").to_string())]);
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<serde_json::Value>,
+    ) -> ProviderResult<T> {
+        let build = |token: &str| {
+            let request = self.client
+                .request(method.clone(), format!("{}{}", self.base_url, path))
+                .bearer_auth(token)
+                .query(query);
+
+            match &body {
+                Some(body) => request.json(body),
+                None => request,
+            }
+        };
+
+        let token = self.access_token().await?;
+        let response = build(&token).send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return parse(response).await;
         }
 
-        if let Some(date_to) = date_to {
-            request = request.query(&[("date_to", date_to.format("%Y-%m-%d").to_string())]);
+        self.forget_token(&token).await;
+        let token = self.access_token().await?;
+        parse(build(&token).send().await?).await
+    }
+
+    pub async fn get_institutions(&self, country: Option<&str>) -> ProviderResult<Vec<GoCardlessInstitution>> {
+        let query: Vec<_> = country.map(|country| ("country", country.to_lowercase())).into_iter().collect();
+        self.request(Method::GET, "/institutions/", &query, None).await
+    }
+
+    pub async fn get_institution(&self, institution_id: &str) -> ProviderResult<GoCardlessInstitution> {
+        self.request(Method::GET, &format!("/institutions/{}/", institution_id), &[], None).await
+    }
+
+    /// Creates an end user agreement asking for as much history and as long
+    /// an access period as the institution allows.
+    pub async fn create_agreement(&self, institution: &GoCardlessInstitution) -> ProviderResult<GoCardlessAgreement> {
+        self.request(
+            Method::POST,
+            "/agreements/enduser/",
+            &[],
+            Some(serde_json::json!({
+                "institution_id": institution.id,
+                "max_historical_days": institution.transaction_total_days,
+                "access_valid_for_days": institution
+                    .max_access_valid_for_days
+                    .unwrap_or(DEFAULT_ACCESS_VALID_FOR_DAYS),
+                "access_scope": ACCESS_SCOPE,
+            })),
+        )
+        .await
+    }
+
+    pub async fn get_agreement(&self, agreement_id: &str) -> ProviderResult<GoCardlessAgreement> {
+        self.request(Method::GET, &format!("/agreements/enduser/{}/", agreement_id), &[], None).await
+    }
+
+    pub async fn create_requisition(
+        &self,
+        institution_id: &str,
+        agreement_id: &str,
+        redirect: &str,
+        reference: Option<&str>,
+    ) -> ProviderResult<GoCardlessRequisition> {
+        let mut body = serde_json::json!({
+            "institution_id": institution_id,
+            "agreement": agreement_id,
+            "redirect": redirect,
+        });
+        if let Some(reference) = reference {
+            body["reference"] = serde_json::json!(reference);
         }
 
-        let response = request.send().await?;
-        let data = response.json().await?;
-        Ok(data)
+        self.request(Method::POST, "/requisitions/", &[], Some(body)).await
+    }
+
+    pub async fn get_requisition(&self, requisition_id: &str) -> ProviderResult<GoCardlessRequisition> {
+        self.request(Method::GET, &format!("/requisitions/{}/", requisition_id), &[], None).await
+    }
+
+    pub async fn delete_requisition(&self, requisition_id: &str) -> ProviderResult<()> {
+        let _: serde_json::Value = self
+            .request(Method::DELETE, &format!("/requisitions/{}/", requisition_id), &[], None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_account(&self, account_id: &str) -> ProviderResult<GoCardlessAccount> {
+        self.request(Method::GET, &format!("/accounts/{}/", account_id), &[], None).await
+    }
+
+    pub async fn get_account_details(&self, account_id: &str) -> ProviderResult<GoCardlessAccountDetails> {
+        let data: GoCardlessDetailsResponse = self
+            .request(Method::GET, &format!("/accounts/{}/details/", account_id), &[], None)
+            .await?;
+        Ok(data.account)
+    }
+
+    pub async fn get_account_balances(&self, account_id: &str) -> ProviderResult<Vec<GoCardlessBalance>> {
+        let data: GoCardlessBalancesResponse = self
+            .request(Method::GET, &format!("/accounts/{}/balances/", account_id), &[], None)
+            .await?;
+        Ok(data.balances)
+    }
+
+    pub async fn get_account_transactions(
+        &self,
+        account_id: &str,
+        date_from: Option<NaiveDate>,
+        date_to: Option<NaiveDate>,
+    ) -> ProviderResult<GoCardlessTransactions> {
+        let query: Vec<_> = [("date_from", date_from), ("date_to", date_to)]
+            .into_iter()
+            .filter_map(|(name, date)| date.map(|date| (name, date.format("%Y-%m-%d").to_string())))
+            .collect();
+        let data: GoCardlessTransactionsResponse = self
+            .request(Method::GET, &format!("/accounts/{}/transactions/", account_id), &query, None)
+            .await?;
+        Ok(data.transactions)
+    }
+}
+
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> ProviderResult<T> {
+    parse(request.send().await?).await
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> ProviderResult<T> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = match response.json::<GoCardlessError>().await {
+            Ok(error) => format!("{}: {}", error.summary, error.detail),
+            Err(_) => format!("GoCardless request failed with {}", status),
+        };
+        return Err(map_error(status, message));
+    }
+
+    Ok(response.json().await?)
+}
+
+fn map_error(status: StatusCode, message: String) -> ProviderError {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ProviderError::Unauthorized(message),
+        StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+        StatusCode::BAD_REQUEST => ProviderError::BadRequest(message),
+        StatusCode::TOO_MANY_REQUESTS => ProviderError::RateLimited(message),
+        status if status.is_server_error() => ProviderError::Unavailable(message),
+        _ => ProviderError::Other(message),
+    }
+}
+
+/// Failing to get the application's token is a problem with its secrets or
+/// with GoCardless, which retrying or fixing the configuration resolves, so
+/// it mustn't disconnect the connection being synced.
+fn token_error(error: ProviderError) -> ProviderError {
+    match error {
+        ProviderError::Unauthorized(message) | ProviderError::BadRequest(message) | ProviderError::NotFound(message) => {
+            ProviderError::Other(format!("GoCardless rejected the application's secrets: {}", message))
+        }
+        error => error,
     }
 }
@@ -1,250 +1,570 @@
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-
-use crate::{
-    providers::{interface::Provider, types::*},
-    utils::{ApiError, ApiResult, retry, RetryConfig},
+use chrono::Utc;
+use std::sync::Arc;
+
+use crate::providers::{
+    types::{
+        Account, Balance, Capabilities, ConnectionState, ConnectionStatus, DeleteConnectionRequest,
+        ExchangeTokenRequest, GetAccountBalanceRequest, GetAccountsRequest, GetConnectionStatusRequest,
+        GetInstitutionsRequest, GetTransactionsRequest, Institution, RefreshTokenRequest, TokenResponse,
+        TransactionPage,
+    },
+    Provider, ProviderError, ProviderResult,
 };
+use crate::utils::config::Config;
+
+use super::gocardless_api::GoCardlessApi;
+use super::transform::{transform_account, transform_balance, transform_institution, transform_transactions};
+use super::types::GoCardlessRequisition;
+use super::utils::{agreement_expires_at, is_agreement_expired, is_requisition_active};
+
+/// Requisition status once the user has finished linking their accounts.
+const REQUISITION_LINKED: &str = "LN";
 
+/// GoCardless connections are identified by their requisition id, which is
+/// what gets stored as the connection's access token. API calls themselves
+/// are authenticated with the application's own secret.
 pub struct GoCardlessProvider {
-    client: Client,
-    secret_id: String,
-    secret_key: String,
-    environment: String,
+    api: GoCardlessApi,
 }
 
 impl GoCardlessProvider {
-    pub fn new(secret_id: String, secret_key: String, environment: String) -> Self {
-        Self {
-            client: Client::new(),
-            secret_id,
-            secret_key,
-            environment: environment.to_lowercase(),
-        }
+    pub fn new(config: Arc<Config>) -> Self {
+        Self::from_api(GoCardlessApi::new(&config))
     }
 
-    fn api_url(&self) -> String {
-        match self.environment.as_str() {
-            "sandbox" => "https://bankable.gocardless.com/api/v2/sandbox".to_string(),
-            _ => "https://bankable.gocardless.com/api/v2".to_string(),
-        }
+    pub fn from_api(api: GoCardlessApi) -> Self {
+        Self { api }
     }
 
-    async fn request<T: for<'de> Deserialize<'de>>(
+    /// Starts a bank connection: creates an end user agreement covering as
+    /// much history as the institution offers, then a requisition whose
+    /// `link` the user follows to authorise access.
+    pub async fn create_requisition(
         &self,
-        method: reqwest::Method,
-        path: &str,
-        body: Option<serde_json::Value>,
-    ) -> ApiResult<T> {
-        let url = format!("{}{}", self.api_url(), path);
-        let config = RetryConfig::default();
-
-        retry(|| async {
-            let mut req = self.client
-                .request(method.clone(), &url)
-                .basic_auth(&self.secret_id, Some(&self.secret_key))
-                .header("Content-Type", "application/json")
-                .header("Accept", "application/json");
-
-            if let Some(body) = &body {
-                req = req.json(body);
-            }
+        institution_id: &str,
+        redirect: &str,
+        reference: Option<&str>,
+    ) -> ProviderResult<GoCardlessRequisition> {
+        let institution = self.api.get_institution(institution_id).await?;
+        let agreement = self.api.create_agreement(&institution).await?;
 
-            let response = req.send().await?;
+        self.api
+            .create_requisition(&institution.id, &agreement.id, redirect, reference)
+            .await
+    }
+
+    /// Fails with `Unauthorized` once the requisition or its agreement has
+    /// expired, so callers can mark the connection as disconnected.
+    async fn get_active_requisition(&self, requisition_id: &str) -> ProviderResult<GoCardlessRequisition> {
+        let requisition = self.api.get_requisition(requisition_id).await?;
+        if !is_requisition_active(&requisition) {
+            return Err(ProviderError::Unauthorized(format!(
+                "Requisition {} is no longer active ({})",
+                requisition.id, requisition.status
+            )));
+        }
 
-            if !response.status().is_success() {
-                let error: GoCardlessError = response.json().await?;
-                return Err(ApiError::Provider(error.message));
+        if let Some(agreement_id) = &requisition.agreement {
+            let agreement = self.api.get_agreement(agreement_id).await?;
+            if is_agreement_expired(&agreement, Utc::now()) {
+                return Err(ProviderError::Unauthorized(format!(
+                    "End user agreement {} has expired",
+                    agreement.id
+                )));
             }
+        }
 
-            Ok(response.json::<T>().await?)
-        }, config).await
+        Ok(requisition)
     }
 }
 
 #[async_trait]
 impl Provider for GoCardlessProvider {
-    async fn exchange_token(
-        &self,
-        code: &str,
-        redirect_uri: &str,
-    ) -> ApiResult<TokenResponse> {
-        let response: GoCardlessTokenResponse = self.request(
-            reqwest::Method::POST,
-            "/auth/access-token",
-            Some(serde_json::json!({
-                "code": code,
-                "redirect_uri": redirect_uri,
-            })),
-        ).await?;
-
-        Ok(TokenResponse {
-            access_token: response.access_token,
-            refresh_token: response.refresh_token,
-            expires_in: response.expires_in,
-            expires_at: Utc::now() + chrono::Duration::seconds(response.expires_in as i64),
-        })
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            accounts: true,
+            balances: true,
+            transactions: true,
+            pending_transactions: true,
+            transaction_sync: false,
+            date_range: true,
+            // Many banks leave out `transactionId` or issue a new one on
+            // every request
+            stable_transaction_ids: false,
+            token_refresh: false,
+            identity: false,
+            institutions: true,
+            delete_connection: true,
+        }
     }
 
-    async fn refresh_token(&self, token: &str) -> ApiResult<TokenResponse> {
-        let response: GoCardlessTokenResponse = self.request(
-            reqwest::Method::POST,
-            "/auth/refresh",
-            Some(serde_json::json!({
-                "refresh_token": token,
-            })),
-        ).await?;
+    /// There is no OAuth code to exchange. Once the user returns from the
+    /// requisition link, the requisition id itself becomes the token, valid
+    /// for as long as its end user agreement.
+    async fn exchange_token(&self, request: ExchangeTokenRequest) -> ProviderResult<TokenResponse> {
+        let requisition = self.api.get_requisition(&request.code).await?;
+        if requisition.status != REQUISITION_LINKED {
+            return Err(ProviderError::BadRequest(format!(
+                "Requisition {} has not been linked yet ({})",
+                requisition.id, requisition.status
+            )));
+        }
+
+        let expires_at = match &requisition.agreement {
+            Some(agreement_id) => agreement_expires_at(&self.api.get_agreement(agreement_id).await?),
+            None => None,
+        };
 
         Ok(TokenResponse {
-            access_token: response.access_token,
-            refresh_token: response.refresh_token,
-            expires_in: response.expires_in,
-            expires_at: Utc::now() + chrono::Duration::seconds(response.expires_in as i64),
+            access_token: requisition.id,
+            refresh_token: None,
+            expires_at,
         })
     }
 
-    async fn get_accounts(&self, access_token: &str) -> ApiResult<Vec<Account>> {
-        let accounts: GoCardlessAccountsResponse = self.request(
-            reqwest::Method::GET,
-            "/accounts",
-            None,
-        ).await?;
-
-        Ok(accounts.accounts.into_iter().map(|acc| Account {
-            id: format!("gc_{}", acc.id),
-            connection_id: access_token.to_string(),
-            name: acc.name,
-            account_type: acc.account_type,
-            currency: acc.currency,
-            balance: Balance {
-                current: acc.balance.current,
-                available: Some(acc.balance.available),
-                limit: acc.balance.limit,
-            },
-            last_sync: Some(Utc::now()),
-        }).collect())
-    }
-
-    async fn get_transactions(
-        &self,
-        access_token: &str,
-        account_id: &str,
-        from_date: Option<DateTime<Utc>>,
-        to_date: Option<DateTime<Utc>>,
-    ) -> ApiResult<Vec<Transaction>> {
-        let account_id = account_id.strip_prefix("gc_").unwrap_or(account_id);
-        let mut params = vec![];
-        
-        if let Some(from) = from_date {
-            params.push(("from_date", from.to_rfc3339()));
+    /// Agreements can't be extended, so expired requisitions need to be
+    /// replaced with a new one.
+    async fn refresh_token(&self, _request: RefreshTokenRequest) -> ProviderResult<TokenResponse> {
+        Err(ProviderError::Unsupported(
+            "GoCardless agreements can't be extended, create a new requisition instead".to_string(),
+        ))
+    }
+
+    async fn get_accounts(&self, request: GetAccountsRequest) -> ProviderResult<Vec<Account>> {
+        let requisition = self.get_active_requisition(&request.access_token).await?;
+        let mut accounts = Vec::with_capacity(requisition.accounts.len());
+
+        for account_id in &requisition.accounts {
+            let account = self.api.get_account(account_id).await?;
+            let details = self.api.get_account_details(account_id).await?;
+            let balances = self.api.get_account_balances(account_id).await?;
+            accounts.push(transform_account(&account, &details, &balances));
         }
-        if let Some(to) = to_date {
-            params.push(("to_date", to.to_rfc3339()));
+
+        Ok(accounts)
+    }
+
+    async fn get_account_balance(&self, request: GetAccountBalanceRequest) -> ProviderResult<Balance> {
+        let balances = self.api.get_account_balances(&request.account_id).await?;
+        if balances.is_empty() {
+            let details = self.api.get_account_details(&request.account_id).await?;
+            return Ok(transform_balance(&balances, &details.currency));
         }
 
-        let transactions: GoCardlessTransactionsResponse = self.request(
-            reqwest::Method::GET,
-            &format!("/accounts/{}/transactions", account_id),
-            None,
-        ).await?;
-
-        Ok(transactions.transactions.into_iter().map(|tx| Transaction {
-            id: format!("gc_{}", tx.id),
-            account_id: format!("gc_{}", tx.account_id),
-            date: tx.date,
-            description: Some(tx.description),
-            amount: tx.amount,
-            currency: tx.currency,
-            category: tx.category,
-            merchant: tx.merchant_name,
-            pending: tx.status == "pending",
-        }).collect())
-    }
-
-    async fn get_institution(&self, institution_id: &str) -> ApiResult<Institution> {
-        let institution: GoCardlessInstitution = self.request(
-            reqwest::Method::GET,
-            &format!("/institutions/{}", institution_id),
-            None,
-        ).await?;
-
-        Ok(Institution {
-            id: format!("gc_{}", institution.id),
-            name: institution.name,
-            country: institution.country,
-            provider: "gocardless".to_string(),
-            logo_url: institution.logo_url,
-            primary_color: institution.color,
-            url: institution.url,
-            oauth_support: institution.oauth_enabled,
-            products: institution.capabilities,
-            last_update: Utc::now(),
-        })
+        Ok(transform_balance(&balances, ""))
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessError {
-    message: String,
-}
+    /// GoCardless returns the whole range in one response, so pagination
+    /// happens here.
+    async fn get_transactions(&self, request: GetTransactionsRequest) -> ProviderResult<TransactionPage> {
+        let transactions = self
+            .api
+            .get_account_transactions(
+                &request.account_id,
+                request.date_range.start_date,
+                request.date_range.end_date,
+            )
+            .await?;
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessTokenResponse {
-    access_token: String,
-    refresh_token: Option<String>,
-    expires_in: i32,
-}
+        TransactionPage::from_all(
+            transform_transactions(&transactions, &request.account_id),
+            &request.pagination,
+        )
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessAccountsResponse {
-    accounts: Vec<GoCardlessAccount>,
-}
+    async fn get_institutions(&self, request: GetInstitutionsRequest) -> ProviderResult<Vec<Institution>> {
+        let institutions = self.api.get_institutions(request.country.as_deref()).await?;
+        // Institutions can operate in several countries and GoCardless has
+        // already filtered on the one asked for
+        let search = GetInstitutionsRequest {
+            country: None,
+            search: request.search,
+        };
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessAccount {
-    id: String,
-    name: String,
-    account_type: String,
-    currency: String,
-    balance: GoCardlessBalance,
-}
+        Ok(institutions
+            .iter()
+            .map(transform_institution)
+            .filter(|institution| search.matches(institution))
+            .collect())
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessBalance {
-    current: f64,
-    available: f64,
-    limit: Option<f64>,
-}
+    async fn get_connection_status(&self, request: GetConnectionStatusRequest) -> ProviderResult<ConnectionStatus> {
+        let status = match self.get_active_requisition(&request.access_token).await {
+            Ok(requisition) if requisition.status == REQUISITION_LINKED => ConnectionState::Connected,
+            // Still waiting on the user to finish consent at their bank
+            Ok(_) => ConnectionState::Error,
+            Err(ProviderError::Unauthorized(_)) | Err(ProviderError::NotFound(_)) => ConnectionState::Disconnected,
+            Err(error) => return Err(error),
+        };
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessTransactionsResponse {
-    transactions: Vec<GoCardlessTransaction>,
-}
+        Ok(ConnectionStatus { status })
+    }
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessTransaction {
-    id: String,
-    account_id: String,
-    date: DateTime<Utc>,
-    description: String,
-    amount: f64,
-    currency: String,
-    status: String,
-    category: Option<String>,
-    merchant_name: Option<String>,
+    async fn delete_connection(&self, request: DeleteConnectionRequest) -> ProviderResult<()> {
+        self.api.delete_requisition(&request.access_token).await
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct GoCardlessInstitution {
-    id: String,
-    name: String,
-    country: String,
-    logo_url: Option<String>,
-    color: Option<String>,
-    url: Option<String>,
-    oauth_enabled: bool,
-    capabilities: Vec<String>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::types::{DateRange, Pagination};
+    use rust_decimal_macros::dec;
+    use chrono::NaiveDate;
+    use mockito::{Matcher, Server, ServerGuard};
+    use serde_json::json;
+
+    fn provider(server: &Server) -> GoCardlessProvider {
+        GoCardlessProvider::from_api(GoCardlessApi::with_base_url("secret_id", "secret_key", &server.url()))
+    }
+
+    async fn mock_token(server: &mut ServerGuard, expected: usize) -> mockito::Mock {
+        server
+            .mock("POST", "/token/new/")
+            .match_body(Matcher::PartialJson(json!({ "secret_id": "secret_id", "secret_key": "secret_key" })))
+            .with_body(json!({ "access": "app_token", "access_expires": 86400, "refresh": "refresh", "refresh_expires": 2592000 }).to_string())
+            .expect(expected)
+            .create_async()
+            .await
+    }
+
+    fn requisition(status: &str) -> String {
+        json!({
+            "id": "req_1",
+            "created": "2024-01-01T00:00:00Z",
+            "redirect": "https://app.midday.ai/callback",
+            "status": status,
+            "institution_id": "SANDBOXFINANCE_SFIN0000",
+            "agreement": "agr_1",
+            "reference": null,
+            "accounts": ["acc_1"],
+            "link": "https://ob.gocardless.com/psd2/start/req_1"
+        })
+        .to_string()
+    }
+
+    fn agreement(accepted: Option<String>) -> String {
+        json!({
+            "id": "agr_1",
+            "created": "2024-01-01T00:00:00Z",
+            "institution_id": "SANDBOXFINANCE_SFIN0000",
+            "max_historical_days": 730,
+            "access_valid_for_days": 90,
+            "access_scope": ["balances", "details", "transactions"],
+            "accepted": accepted
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_create_requisition_uses_institution_history() {
+        let mut server = Server::new_async().await;
+        let token = mock_token(&mut server, 1).await;
+        server
+            .mock("GET", "/institutions/SANDBOXFINANCE_SFIN0000/")
+            .match_header("authorization", "Bearer app_token")
+            .with_body(
+                json!({
+                    "id": "SANDBOXFINANCE_SFIN0000",
+                    "name": "Sandbox Finance",
+                    "bic": "SFIN0000",
+                    "transaction_total_days": "730",
+                    "max_access_valid_for_days": "180",
+                    "countries": ["XX"],
+                    "logo": null
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let create_agreement = server
+            .mock("POST", "/agreements/enduser/")
+            .match_body(Matcher::PartialJson(json!({
+                "institution_id": "SANDBOXFINANCE_SFIN0000",
+                "max_historical_days": 730,
+                "access_valid_for_days": 180
+            })))
+            .with_body(agreement(None))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/requisitions/")
+            .match_body(Matcher::PartialJson(json!({
+                "agreement": "agr_1",
+                "redirect": "https://app.midday.ai/callback"
+            })))
+            .with_body(requisition("CR"))
+            .create_async()
+            .await;
+
+        let requisition = provider(&server)
+            .create_requisition("SANDBOXFINANCE_SFIN0000", "https://app.midday.ai/callback", None)
+            .await
+            .unwrap();
+
+        token.assert_async().await;
+        create_agreement.assert_async().await;
+        assert_eq!(requisition.link, "https://ob.gocardless.com/psd2/start/req_1");
+    }
+
+    #[tokio::test]
+    async fn test_exchange_token_requires_linked_requisition() {
+        let mut server = Server::new_async().await;
+        mock_token(&mut server, 1).await;
+        server
+            .mock("GET", "/requisitions/req_1/")
+            .with_body(requisition("GC"))
+            .create_async()
+            .await;
+
+        let error = provider(&server)
+            .exchange_token(ExchangeTokenRequest {
+                code: "req_1".to_string(),
+                redirect_uri: String::new(),
+            })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ProviderError::BadRequest(_)));
+        assert!(error.to_string().contains("not been linked"));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_app_token_is_replaced() {
+        let mut server = Server::new_async().await;
+        // The first token has been revoked, so a second one is asked for
+        let token_body = |access: &str| {
+            json!({ "access": access, "access_expires": 86400, "refresh": "refresh", "refresh_expires": 2592000 }).to_string()
+        };
+        let revoked = server
+            .mock("POST", "/token/new/")
+            .with_body(token_body("revoked_token"))
+            .expect(1)
+            .create_async()
+            .await;
+        let fresh = server
+            .mock("POST", "/token/new/")
+            .with_body(token_body("app_token"))
+            .expect(1)
+            .create_async()
+            .await;
+        let rejected = server
+            .mock("GET", "/requisitions/req_1/")
+            .match_header("authorization", "Bearer revoked_token")
+            .with_status(401)
+            .with_body(json!({ "summary": "Invalid token", "detail": "Token is invalid or expired" }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/requisitions/req_1/")
+            .match_header("authorization", "Bearer app_token")
+            .with_body(requisition("GC"))
+            .create_async()
+            .await;
+
+        let error = provider(&server)
+            .exchange_token(ExchangeTokenRequest {
+                code: "req_1".to_string(),
+                redirect_uri: String::new(),
+            })
+            .await
+            .unwrap_err();
+
+        revoked.assert_async().await;
+        fresh.assert_async().await;
+        rejected.assert_async().await;
+        assert!(matches!(error, ProviderError::BadRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_app_secrets_dont_disconnect() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/token/new/")
+            .with_status(401)
+            .with_body(json!({ "summary": "Authentication failed", "detail": "No active account found with the given credentials" }).to_string())
+            .create_async()
+            .await;
+
+        let error = provider(&server)
+            .get_connection_status(GetConnectionStatusRequest {
+                access_token: "req_1".to_string(),
+            })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ProviderError::Other(_)));
+    }
+
+    #[tokio::test]
+    async fn test_exchange_token_expires_with_agreement() {
+        let mut server = Server::new_async().await;
+        mock_token(&mut server, 1).await;
+        server
+            .mock("GET", "/requisitions/req_1/")
+            .with_body(requisition("LN"))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/agreements/enduser/agr_1/")
+            .with_body(agreement(Some("2024-01-10T00:00:00Z".to_string())))
+            .create_async()
+            .await;
+
+        let token = provider(&server)
+            .exchange_token(ExchangeTokenRequest {
+                code: "req_1".to_string(),
+                redirect_uri: String::new(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(token.access_token, "req_1");
+        assert_eq!(token.expires_at.unwrap().to_rfc3339(), "2024-04-09T00:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_passes_date_range() {
+        let mut server = Server::new_async().await;
+        mock_token(&mut server, 1).await;
+        let transaction = |id: &str| {
+            json!({
+                "transactionId": id,
+                "bookingDate": "2024-02-10",
+                "transactionAmount": { "amount": "-10.00", "currency": "EUR" },
+                "remittanceInformationUnstructured": "Groceries"
+            })
+        };
+        server
+            .mock("GET", "/accounts/acc_1/transactions/")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("date_from".into(), "2024-02-01".into()),
+                Matcher::UrlEncoded("date_to".into(), "2024-02-29".into()),
+            ]))
+            .with_body(
+                json!({ "transactions": { "booked": [transaction("tx_1"), transaction("tx_2")], "pending": [] } })
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let page = provider(&server)
+            .get_transactions(GetTransactionsRequest {
+                access_token: "req_1".to_string(),
+                account_id: "acc_1".to_string(),
+                date_range: DateRange {
+                    start_date: NaiveDate::from_ymd_opt(2024, 2, 1),
+                    end_date: NaiveDate::from_ymd_opt(2024, 2, 29),
+                },
+                pagination: Pagination {
+                    cursor: None,
+                    limit: Some(1),
+                },
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].id, "tx_1");
+        assert_eq!(page.next_cursor.as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_get_accounts_resolves_requisition() {
+        let mut server = Server::new_async().await;
+        mock_token(&mut server, 1).await;
+        server
+            .mock("GET", "/requisitions/req_1/")
+            .with_body(requisition("LN"))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/agreements/enduser/agr_1/")
+            .with_body(agreement(Some(Utc::now().to_rfc3339())))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/accounts/acc_1/")
+            .with_body(json!({ "id": "acc_1", "iban": "GL0000", "institution_id": "SANDBOXFINANCE_SFIN0000", "status": "READY", "owner_name": "Jane" }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/accounts/acc_1/details/")
+            .with_body(json!({ "account": { "currency": "EUR", "name": "Main Account", "cashAccountType": "CACC" } }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/accounts/acc_1/balances/")
+            .with_body(
+                json!({
+                    "balances": [
+                        { "balanceAmount": { "amount": "1500.00", "currency": "EUR" }, "balanceType": "closingBooked", "referenceDate": "2024-03-01" },
+                        { "balanceAmount": { "amount": "1450.50", "currency": "EUR" }, "balanceType": "interimAvailable", "referenceDate": "2024-03-02" }
+                    ]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let accounts = provider(&server)
+            .get_accounts(GetAccountsRequest {
+                access_token: "req_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].name, "Main Account");
+        assert_eq!(accounts[0].balance.amount, dec!(1450.5));
+    }
+
+    #[tokio::test]
+    async fn test_connection_status_expired_agreement() {
+        let mut server = Server::new_async().await;
+        // The app token is fetched once and reused for both calls
+        let token = mock_token(&mut server, 1).await;
+        server
+            .mock("GET", "/requisitions/req_1/")
+            .with_body(requisition("LN"))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/agreements/enduser/agr_1/")
+            .with_body(agreement(Some("2023-01-01T00:00:00Z".to_string())))
+            .create_async()
+            .await;
+
+        let status = provider(&server)
+            .get_connection_status(GetConnectionStatusRequest {
+                access_token: "req_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        token.assert_async().await;
+        assert_eq!(status.status, ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_connection_status_expired_requisition() {
+        let mut server = Server::new_async().await;
+        mock_token(&mut server, 1).await;
+        server
+            .mock("GET", "/requisitions/req_1/")
+            .with_body(requisition("EX"))
+            .create_async()
+            .await;
+
+        let status = provider(&server)
+            .get_connection_status(GetConnectionStatusRequest {
+                access_token: "req_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(status.status, ConnectionState::Disconnected);
+    }
 }
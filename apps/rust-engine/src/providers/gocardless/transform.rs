@@ -1,100 +1,137 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{TimeZone, Utc};
+use rust_decimal::Decimal;
 
-use crate::providers::types::*;
+use crate::providers::types::{Account, Balance, Institution, Transaction, TransactionStatus};
+use crate::utils::money;
+
+use super::types::*;
+use super::utils::{get_account_type, get_available_balance, get_preferred_balance, parse_amount};
+
+pub fn transform_account(
+    account: &GoCardlessAccount,
+    details: &GoCardlessAccountDetails,
+    balances: &[GoCardlessBalance],
+) -> Account {
+    let name = details
+        .name
+        .clone()
+        .or_else(|| details.product.clone())
+        .or_else(|| details.owner_name.clone())
+        .unwrap_or_else(|| "Account".to_string());
 
-pub fn transform_account(account: GoCardlessAccount) -> Account {
     Account {
-        id: format!("gc_{}", account.id),
-        connection_id: account.connection_id,
-        name: account.name,
-        account_type: transform_account_type(account.account_type),
-        currency: account.currency,
-        balance: Balance {
-            current: account.balance.current,
-            available: Some(account.balance.available),
-            limit: account.balance.limit,
-        },
+        id: account.id.clone(),
+        name,
+        account_type: get_account_type(details),
+        balance: transform_balance(balances, &details.currency),
+        currency: details.currency.clone(),
+        institution_id: account.institution_id.clone(),
         last_sync: Some(Utc::now()),
     }
 }
 
-pub fn transform_transaction(transaction: GoCardlessTransaction) -> Transaction {
-    Transaction {
-        id: format!("gc_{}", transaction.id),
-        account_id: format!("gc_{}", transaction.account_id),
-        date: transaction.date,
-        description: Some(transaction.description),
-        amount: transaction.amount,
-        currency: transaction.currency,
-        category: transaction.category,
-        merchant: transaction.merchant_name,
-        pending: transaction.status == "pending",
-    }
-}
+pub fn transform_balance(balances: &[GoCardlessBalance], currency: &str) -> Balance {
+    let available = get_available_balance(balances).and_then(|balance| {
+        parse_amount(&balance.balance_amount.amount)
+            .map(|amount| money::round(amount, &balance.balance_amount.currency))
+    });
 
-pub fn transform_institution(institution: GoCardlessInstitution) -> Institution {
-    Institution {
-        id: format!("gc_{}", institution.id),
-        name: institution.name,
-        country: institution.country,
-        provider: "gocardless".to_string(),
-        logo_url: institution.logo_url,
-        primary_color: institution.color,
-        url: institution.url,
-        oauth_support: institution.oauth_enabled,
-        products: institution.capabilities,
-        last_update: Utc::now(),
+    match get_preferred_balance(balances) {
+        Some(balance) => Balance {
+            amount: money::round(
+                parse_amount(&balance.balance_amount.amount).unwrap_or_default(),
+                &balance.balance_amount.currency,
+            ),
+            currency: balance.balance_amount.currency.clone(),
+            available,
+        },
+        None => Balance {
+            amount: money::round(Decimal::ZERO, currency),
+            currency: currency.to_string(),
+            available,
+        },
     }
 }
 
-fn transform_account_type(account_type: String) -> String {
-    match account_type.as_str() {
-        "current" => "checking",
-        "savings" => "savings",
-        "credit_card" => "credit",
-        _ => "other",
-    }.to_string()
-}
+/// Transactions the bank sends without any identifier get an empty `id` and
+/// are stored under their fingerprint.
+pub fn transform_transaction(
+    transaction: &GoCardlessTransaction,
+    account_id: &str,
+    status: TransactionStatus,
+) -> Transaction {
+    let id = transaction
+        .transaction_id
+        .clone()
+        .or_else(|| transaction.internal_transaction_id.clone())
+        .unwrap_or_default();
+    let currency = &transaction.transaction_amount.currency;
+    let amount = money::round(
+        parse_amount(&transaction.transaction_amount.amount).unwrap_or_default(),
+        currency,
+    );
+    let date = transaction
+        .booking_date
+        .or(transaction.value_date)
+        .unwrap_or_else(|| Utc::now().date_naive());
+    let counterparty = if amount.is_sign_negative() {
+        transaction.creditor_name.clone()
+    } else {
+        transaction.debtor_name.clone()
+    };
+    let description = transaction
+        .remittance_information_unstructured
+        .clone()
+        .or_else(|| {
+            Some(transaction.remittance_information_unstructured_array.join(" "))
+                .filter(|description| !description.is_empty())
+        })
+        .or_else(|| counterparty.clone())
+        .unwrap_or_default();
 
-#[derive(Debug, Deserialize)]
-pub struct GoCardlessAccount {
-    pub id: String,
-    pub connection_id: String,
-    pub name: String,
-    pub account_type: String,
-    pub currency: String,
-    pub balance: GoCardlessBalance,
+    Transaction {
+        id,
+        account_id: account_id.to_string(),
+        amount,
+        currency: currency.clone(),
+        date: Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default()),
+        description,
+        merchant: counterparty,
+        category: None,
+        status,
+        balance: transaction
+            .balance_after_transaction
+            .as_ref()
+            .and_then(|balance| parse_amount(&balance.balance_amount.amount))
+            .map(|balance| money::round(balance, currency)),
+        currency_rate: None,
+        currency_source: None,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct GoCardlessBalance {
-    pub current: f64,
-    pub available: f64,
-    pub limit: Option<f64>,
-}
+pub fn transform_transactions(transactions: &GoCardlessTransactions, account_id: &str) -> Vec<Transaction> {
+    let booked = transactions
+        .booked
+        .iter()
+        .map(|transaction| transform_transaction(transaction, account_id, TransactionStatus::Posted));
+    let pending = transactions
+        .pending
+        .iter()
+        .map(|transaction| transform_transaction(transaction, account_id, TransactionStatus::Pending));
 
-#[derive(Debug, Deserialize)]
-pub struct GoCardlessTransaction {
-    pub id: String,
-    pub account_id: String,
-    pub date: DateTime<Utc>,
-    pub description: String,
-    pub amount: f64,
-    pub currency: String,
-    pub status: String,
-    pub category: Option<String>,
-    pub merchant_name: Option<String>,
+    booked.chain(pending).collect()
 }
 
-#[derive(Debug, Deserialize)]
-pub struct GoCardlessInstitution {
-    pub id: String,
-    pub name: String,
-    pub country: String,
-    pub logo_url: Option<String>,
-    pub color: Option<String>,
-    pub url: Option<String>,
-    pub oauth_enabled: bool,
-    pub capabilities: Vec<String>,
+pub fn transform_institution(institution: &GoCardlessInstitution) -> Institution {
+    Institution {
+        id: institution.id.clone(),
+        name: institution.name.clone(),
+        logo_url: institution.logo.clone(),
+        website: None,
+        country: institution.countries.first().cloned().unwrap_or_default(),
+    }
 }
+
+#[cfg(test)]
+#[path = "transform.test.rs"]
+mod tests;
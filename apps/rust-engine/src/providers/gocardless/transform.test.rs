@@ -0,0 +1,137 @@
+use super::*;
+use chrono::NaiveDate;
+use rust_decimal_macros::dec;
+
+use crate::providers::types::AccountType;
+
+fn amount(amount: &str) -> GoCardlessAmount {
+    GoCardlessAmount {
+        amount: amount.to_string(),
+        currency: "EUR".to_string(),
+    }
+}
+
+fn gocardless_transaction() -> GoCardlessTransaction {
+    GoCardlessTransaction {
+        transaction_id: Some("tx123".to_string()),
+        internal_transaction_id: Some("int123".to_string()),
+        booking_date: Some(NaiveDate::from_ymd_opt(2023, 1, 2).unwrap()),
+        value_date: Some(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap()),
+        transaction_amount: amount("-50.25"),
+        creditor_name: Some("Coffee Shop".to_string()),
+        debtor_name: None,
+        remittance_information_unstructured: None,
+        remittance_information_unstructured_array: vec!["Card".to_string(), "payment".to_string()],
+        bank_transaction_code: None,
+        proprietary_bank_transaction_code: None,
+        balance_after_transaction: Some(GoCardlessBalanceAfterTransaction {
+            balance_amount: amount("949.75"),
+            balance_type: Some("interimBooked".to_string()),
+        }),
+    }
+}
+
+#[test]
+fn test_transform_account() {
+    let account = GoCardlessAccount {
+        id: "acc123".to_string(),
+        iban: Some("GB33BUKB20201555555555".to_string()),
+        institution_id: "REVOLUT_REVOGB21".to_string(),
+        status: "READY".to_string(),
+        owner_name: Some("Jane Doe".to_string()),
+    };
+    let details = GoCardlessAccountDetails {
+        resource_id: None,
+        iban: None,
+        currency: "GBP".to_string(),
+        owner_name: Some("Jane Doe".to_string()),
+        name: None,
+        product: Some("Current Account".to_string()),
+        cash_account_type: Some("CACC".to_string()),
+    };
+
+    let account = transform_account(&account, &details, &[]);
+    assert_eq!(account.id, "acc123");
+    assert_eq!(account.name, "Current Account");
+    assert_eq!(account.account_type, AccountType::Checking);
+    assert_eq!(account.institution_id, "REVOLUT_REVOGB21");
+    assert_eq!(account.balance.amount, dec!(0));
+    assert_eq!(account.balance.currency, "GBP");
+}
+
+#[test]
+fn test_transform_balance_prefers_available() {
+    let balances = vec![
+        GoCardlessBalance {
+            balance_amount: amount("100.00"),
+            balance_type: "closingBooked".to_string(),
+            reference_date: None,
+        },
+        GoCardlessBalance {
+            balance_amount: amount("80.50"),
+            balance_type: "interimAvailable".to_string(),
+            reference_date: None,
+        },
+    ];
+
+    let balance = transform_balance(&balances, "EUR");
+    assert_eq!(balance.amount, dec!(80.5));
+    assert_eq!(balance.available, Some(dec!(80.5)));
+
+    // Booked balances say nothing about what can be spent
+    assert_eq!(transform_balance(&balances[..1], "EUR").available, None);
+}
+
+#[test]
+fn test_transform_transaction() {
+    let transaction =
+        transform_transaction(&gocardless_transaction(), "acc123", TransactionStatus::Posted);
+    assert_eq!(transaction.id, "tx123");
+    assert_eq!(transaction.account_id, "acc123");
+    assert_eq!(transaction.amount, dec!(-50.25));
+    assert_eq!(transaction.currency, "EUR");
+    assert_eq!(transaction.date.date_naive(), NaiveDate::from_ymd_opt(2023, 1, 2).unwrap());
+    assert_eq!(transaction.description, "Card payment");
+    assert_eq!(transaction.merchant, Some("Coffee Shop".to_string()));
+    assert_eq!(transaction.balance, Some(dec!(949.75)));
+}
+
+#[test]
+fn test_transform_transaction_keeps_currency_decimals() {
+    let mut dinars = gocardless_transaction();
+    dinars.transaction_amount = GoCardlessAmount {
+        amount: "-12.345".to_string(),
+        currency: "KWD".to_string(),
+    };
+    let mut yen = gocardless_transaction();
+    yen.transaction_amount = GoCardlessAmount {
+        amount: "-1500".to_string(),
+        currency: "JPY".to_string(),
+    };
+
+    let dinars = transform_transaction(&dinars, "acc123", TransactionStatus::Posted);
+    let yen = transform_transaction(&yen, "acc123", TransactionStatus::Posted);
+
+    assert_eq!(serde_json::to_value(&dinars).unwrap()["amount"], "-12.345");
+    assert_eq!(serde_json::to_value(&yen).unwrap()["amount"], "-1500");
+}
+
+#[test]
+fn test_transform_transactions_keeps_missing_ids() {
+    let mut pending = gocardless_transaction();
+    pending.transaction_id = None;
+    pending.internal_transaction_id = None;
+
+    let transactions = transform_transactions(
+        &GoCardlessTransactions {
+            booked: vec![gocardless_transaction()],
+            pending: vec![pending],
+        },
+        "acc123",
+    );
+
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0].status, TransactionStatus::Posted);
+    assert_eq!(transactions[1].status, TransactionStatus::Pending);
+    assert_eq!(transactions[1].id, "");
+}
@@ -1,45 +1,169 @@
-use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use chrono::{DateTime, NaiveDate, Utc};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GoCardlessInstitution {
     pub id: String,
     pub name: String,
     pub bic: Option<String>,
-    pub transaction_total_days: i32,
+    /// How much history the institution exposes. Sent as a string by the API.
+    #[serde(deserialize_with = "deserialize_days")]
+    pub transaction_total_days: u32,
+    #[serde(default, deserialize_with = "deserialize_optional_days")]
+    pub max_access_valid_for_days: Option<u32>,
     pub countries: Vec<String>,
     pub logo: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoCardlessAgreement {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    pub institution_id: String,
+    pub max_historical_days: u32,
+    pub access_valid_for_days: u32,
+    pub access_scope: Vec<String>,
+    pub accepted: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoCardlessRequisition {
+    pub id: String,
+    pub created: Option<DateTime<Utc>>,
+    pub redirect: Option<String>,
+    pub status: String,
+    pub institution_id: String,
+    pub agreement: Option<String>,
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub accounts: Vec<String>,
+    pub link: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GoCardlessAccount {
     pub id: String,
-    pub iban: String,
+    pub iban: Option<String>,
     pub institution_id: String,
     pub status: String,
-    pub owner_name: String,
+    pub owner_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoCardlessAccountDetails {
+    pub resource_id: Option<String>,
+    pub iban: Option<String>,
+    pub currency: String,
+    pub owner_name: Option<String>,
+    pub name: Option<String>,
     pub product: Option<String>,
-    pub balances: GoCardlessBalances,
+    pub cash_account_type: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GoCardlessBalances {
-    pub available: Option<f64>,
-    pub current: f64,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoCardlessAmount {
+    pub amount: String,
     pub currency: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoCardlessBalance {
+    pub balance_amount: GoCardlessAmount,
+    pub balance_type: String,
+    pub reference_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GoCardlessTransaction {
-    pub id: String,
-    pub date: DateTime<Utc>,
-    pub amount: f64,
-    pub currency: String,
-    pub status: String,
-    pub remittance_information_unstructured: Option<String>,
+    pub transaction_id: Option<String>,
+    pub internal_transaction_id: Option<String>,
+    pub booking_date: Option<NaiveDate>,
+    pub value_date: Option<NaiveDate>,
+    pub transaction_amount: GoCardlessAmount,
     pub creditor_name: Option<String>,
     pub debtor_name: Option<String>,
-    pub transaction_type: Option<String>,
-    pub booking_date: Option<DateTime<Utc>>,
-    pub value_date: Option<DateTime<Utc>>,
+    pub remittance_information_unstructured: Option<String>,
+    #[serde(default)]
+    pub remittance_information_unstructured_array: Vec<String>,
+    pub bank_transaction_code: Option<String>,
+    pub proprietary_bank_transaction_code: Option<String>,
+    pub balance_after_transaction: Option<GoCardlessBalanceAfterTransaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoCardlessBalanceAfterTransaction {
+    pub balance_amount: GoCardlessAmount,
+    pub balance_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessTokenResponse {
+    pub access: String,
+    pub access_expires: i64,
+    pub refresh: String,
+    pub refresh_expires: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessRefreshResponse {
+    pub access: String,
+    pub access_expires: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessDetailsResponse {
+    pub account: GoCardlessAccountDetails,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessBalancesResponse {
+    pub balances: Vec<GoCardlessBalance>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessTransactionsResponse {
+    pub transactions: GoCardlessTransactions,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessTransactions {
+    #[serde(default)]
+    pub booked: Vec<GoCardlessTransaction>,
+    #[serde(default)]
+    pub pending: Vec<GoCardlessTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoCardlessError {
+    pub summary: String,
+    pub detail: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Days {
+    Number(u32),
+    Text(String),
+}
+
+impl Days {
+    fn parse<E: serde::de::Error>(self) -> Result<u32, E> {
+        match self {
+            Days::Number(days) => Ok(days),
+            Days::Text(days) => days.parse().map_err(E::custom),
+        }
+    }
+}
+
+fn deserialize_days<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    Days::deserialize(deserializer)?.parse()
+}
+
+fn deserialize_optional_days<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    Option::<Days>::deserialize(deserializer)?.map(Days::parse).transpose()
 }
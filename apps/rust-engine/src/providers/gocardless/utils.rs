@@ -1,19 +1,65 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+
+use crate::providers::types::AccountType;
+use crate::utils::money;
+
 use super::types::*;
 
-pub fn get_account_type(account: &GoCardlessAccount) -> String {
-    match account.product.as_deref() {
-        Some("checking") => "checking".to_string(),
-        Some("savings") => "savings".to_string(),
-        Some("credit_card") => "credit".to_string(),
-        Some("loan") => "loan".to_string(),
-        _ => "other".to_string(),
+/// Requisition statuses after which the bank connection can no longer be
+/// used: expired, rejected and suspended.
+const TERMINAL_REQUISITION_STATUSES: [&str; 3] = ["EX", "RJ", "SU"];
+
+/// Maps the ISO 20022 cash account type reported in account details.
+pub fn get_account_type(details: &GoCardlessAccountDetails) -> AccountType {
+    match details.cash_account_type.as_deref() {
+        Some("CACC") | Some("CASH") | Some("TRAN") => AccountType::Checking,
+        Some("SVGS") | Some("MOMA") => AccountType::Savings,
+        Some("CARD") => AccountType::Credit,
+        Some("LOAN") => AccountType::Loan,
+        _ => AccountType::Other,
     }
 }
 
+#[allow(dead_code)]
 pub fn get_transaction_type(transaction: &GoCardlessTransaction) -> String {
-    match transaction.transaction_type.as_deref() {
+    match transaction.proprietary_bank_transaction_code.as_deref() {
         Some("SEPA_CREDIT_TRANSFER") => "transfer".to_string(),
         Some("SEPA_DIRECT_DEBIT") => "payment".to_string(),
         _ => "other".to_string(),
     }
 }
+
+/// Picks the most current balance the bank reports.
+pub fn get_preferred_balance(balances: &[GoCardlessBalance]) -> Option<&GoCardlessBalance> {
+    ["interimAvailable", "expected", "interimBooked", "closingBooked"]
+        .iter()
+        .find_map(|balance_type| balances.iter().find(|balance| balance.balance_type == *balance_type))
+        .or_else(|| balances.first())
+}
+
+/// The balance that says what can be spent, if the bank reports one.
+pub fn get_available_balance(balances: &[GoCardlessBalance]) -> Option<&GoCardlessBalance> {
+    ["interimAvailable", "closingAvailable", "forwardAvailable"]
+        .iter()
+        .find_map(|balance_type| balances.iter().find(|balance| balance.balance_type == *balance_type))
+}
+
+pub fn parse_amount(amount: &str) -> Option<Decimal> {
+    money::parse(amount)
+}
+
+pub fn is_requisition_active(requisition: &GoCardlessRequisition) -> bool {
+    !TERMINAL_REQUISITION_STATUSES.contains(&requisition.status.as_str())
+}
+
+/// Agreements start counting down once the user accepts them.
+pub fn agreement_expires_at(agreement: &GoCardlessAgreement) -> Option<DateTime<Utc>> {
+    agreement
+        .accepted
+        .map(|accepted| accepted + Duration::days(agreement.access_valid_for_days as i64))
+}
+
+pub fn is_agreement_expired(agreement: &GoCardlessAgreement, now: DateTime<Utc>) -> bool {
+    agreement_expires_at(agreement).is_some_and(|expires_at| expires_at <= now)
+}
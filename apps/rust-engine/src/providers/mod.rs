@@ -1,83 +1,271 @@
 use async_trait::async_trait;
 use std::sync::Arc;
 use std::collections::HashMap;
-use std::error::Error;
 use crate::utils::config;
 
+pub mod error;
+pub use error::{ProviderError, ProviderResult};
+
 pub mod types;
 pub use types::*;
 
+pub mod gocardless;
 pub mod plaid;
+pub mod teller;
+pub mod truelayer;
 pub mod wise;
 
+/// Bumped whenever the `Provider` trait or its request/response types change
+/// in a way that provider modules have to follow.
+pub const PROVIDER_CONTRACT_VERSION: u32 = 1;
+
 #[async_trait]
 pub trait Provider: Send + Sync + 'static {
-    async fn exchange_token(
-        &self,
-        code: &str,
-        redirect_uri: &str,
-    ) -> Result<(String, String), Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn refresh_token(
-        &self,
-        refresh_token: &str,
-    ) -> Result<(String, String), Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn get_accounts(
-        &self,
-        access_token: &str,
-    ) -> Result<Vec<Account>, Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn get_account_balance(
-        &self,
-        access_token: &str,
-        account_id: &str,
-    ) -> Result<Balance, Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn get_transactions(
-        &self,
-        access_token: &str,
-        account_id: &str,
-    ) -> Result<Vec<Transaction>, Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn get_institutions(&self) -> Result<Vec<Institution>, Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn get_connection_status(
-        &self,
-        access_token: &str,
-    ) -> Result<ConnectionStatus, Box<dyn Error + Send + Sync + 'static>>;
-    
-    async fn delete_connection(
-        &self,
-        access_token: &str,
-    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>>;
+    fn capabilities(&self) -> Capabilities;
+
+    async fn exchange_token(&self, request: ExchangeTokenRequest) -> ProviderResult<TokenResponse>;
+
+    async fn refresh_token(&self, request: RefreshTokenRequest) -> ProviderResult<TokenResponse>;
+
+    async fn get_accounts(&self, request: GetAccountsRequest) -> ProviderResult<Vec<Account>>;
+
+    /// Not used by the sync, which takes balances from `get_accounts`.
+    #[allow(dead_code)]
+    async fn get_account_balance(&self, request: GetAccountBalanceRequest) -> ProviderResult<Balance>;
+
+    async fn get_transactions(&self, request: GetTransactionsRequest) -> ProviderResult<TransactionPage>;
+
+    /// Returns every change since `checkpoint` (`None` for a first sync).
+    /// Providers without a native change feed fall back to re-reading all
+    /// accounts and reporting their transactions as added.
+    async fn sync_transactions(&self, request: SyncTransactionsRequest) -> ProviderResult<TransactionSync> {
+        let accounts = self
+            .get_accounts(GetAccountsRequest {
+                access_token: request.access_token.clone(),
+            })
+            .await?;
+        let mut added = Vec::new();
+        for account in &accounts {
+            let page = self
+                .get_transactions(GetTransactionsRequest {
+                    access_token: request.access_token.clone(),
+                    account_id: account.id.clone(),
+                    date_range: DateRange::default(),
+                    pagination: Pagination::default(),
+                })
+                .await?;
+            added.extend(page.transactions);
+        }
+
+        Ok(TransactionSync {
+            accounts,
+            added,
+            ..Default::default()
+        })
+    }
+
+    async fn get_institutions(&self, request: GetInstitutionsRequest) -> ProviderResult<Vec<Institution>>;
+
+    async fn get_connection_status(&self, request: GetConnectionStatusRequest) -> ProviderResult<ConnectionStatus>;
+
+    async fn delete_connection(&self, request: DeleteConnectionRequest) -> ProviderResult<()>;
 }
 
+/// Every provider the engine knows about, whether or not it is enabled.
+pub const PROVIDERS: [&str; 5] = ["gocardless", "plaid", "teller", "truelayer", "wise"];
+
 pub struct ProviderFactory {
     providers: HashMap<String, Arc<dyn Provider>>,
+    /// Reasons keyed by provider id
+    disabled: HashMap<String, String>,
+    gocardless: Option<Arc<gocardless::GoCardlessProvider>>,
+    truelayer: Option<Arc<truelayer::TrueLayerProvider>>,
     config: Arc<config::Config>,
 }
 
 impl ProviderFactory {
     pub fn new(config: Arc<config::Config>) -> Self {
-        let mut providers: HashMap<String, Arc<dyn Provider>> = HashMap::new();
-
-        providers.insert(
-            "plaid".to_string(), 
-            Arc::new(plaid::PlaidProvider::new(config.clone())) as Arc<dyn Provider>
-        );
-        providers.insert(
-            "wise".to_string(), 
-            Arc::new(wise::WiseProvider::new(config.clone())) as Arc<dyn Provider>
-        );
-
-        Self { 
-            providers,
-            config, 
+        let mut factory = Self {
+            providers: HashMap::new(),
+            disabled: HashMap::new(),
+            gocardless: None,
+            truelayer: None,
+            config: config.clone(),
+        };
+
+        if factory.enabled_in_config("gocardless") {
+            let gocardless = Arc::new(gocardless::GoCardlessProvider::new(config.clone()));
+            factory.gocardless = Some(gocardless.clone());
+            factory.register("gocardless", Ok(gocardless as Arc<dyn Provider>));
         }
+        if factory.enabled_in_config("truelayer") {
+            let truelayer = Arc::new(truelayer::TrueLayerProvider::new(config.clone()));
+            factory.truelayer = Some(truelayer.clone());
+            factory.register("truelayer", Ok(truelayer as Arc<dyn Provider>));
+        }
+        if factory.enabled_in_config("plaid") {
+            let plaid = plaid::PlaidProvider::new(config.clone());
+            factory.register("plaid", Ok(Arc::new(plaid) as Arc<dyn Provider>));
+        }
+        if factory.enabled_in_config("wise") {
+            let wise = wise::WiseProvider::new(config.clone());
+            factory.register("wise", wise.map(|provider| Arc::new(provider) as Arc<dyn Provider>));
+        }
+        if factory.enabled_in_config("teller") {
+            let teller = teller::TellerProvider::new(config.clone());
+            factory.register("teller", teller.map(|provider| Arc::new(provider) as Arc<dyn Provider>));
+        }
+
+        factory
+    }
+
+    fn enabled_in_config(&mut self, id: &str) -> bool {
+        if self.config.disabled_providers.iter().any(|disabled| disabled == id) {
+            self.disabled
+                .insert(id.to_string(), "Disabled by DISABLED_PROVIDERS".to_string());
+            return false;
+        }
+        true
+    }
+
+    fn register(&mut self, id: &str, provider: ProviderResult<Arc<dyn Provider>>) {
+        match provider {
+            Ok(provider) => {
+                self.providers.insert(id.to_string(), provider);
+            }
+            Err(e) => {
+                log::error!("{} provider disabled: {}", id, e);
+                self.disabled.insert(id.to_string(), e.to_string());
+            }
+        }
+    }
+
+    /// GoCardless connections are started through requisitions, which have
+    /// no equivalent in the `Provider` trait.
+    pub fn gocardless(&self) -> ProviderResult<Arc<gocardless::GoCardlessProvider>> {
+        self.gocardless.clone().ok_or_else(|| self.unavailable("gocardless"))
+    }
+
+    /// Used to build the TrueLayer auth link the user starts from.
+    pub fn truelayer(&self) -> ProviderResult<Arc<truelayer::TrueLayerProvider>> {
+        self.truelayer.clone().ok_or_else(|| self.unavailable("truelayer"))
+    }
+
+    pub fn config(&self) -> &config::Config {
+        &self.config
     }
 
     pub fn get_provider(&self, provider: &str) -> Option<Arc<dyn Provider>> {
         self.providers.get(provider).cloned()
     }
+
+    /// Looks up an enabled provider that supports `capability`, so callers
+    /// get an explicit error instead of an empty or partial result.
+    pub fn require(&self, provider: &str, capability: Capability) -> ProviderResult<Arc<dyn Provider>> {
+        let instance = self.get_provider(provider).ok_or_else(|| self.unavailable(provider))?;
+        if !instance.capabilities().supports(capability) {
+            return Err(ProviderError::Unsupported(format!(
+                "{} does not support {}",
+                provider, capability
+            )));
+        }
+
+        Ok(instance)
+    }
+
+    /// Every known provider with its capabilities, or the reason it is
+    /// disabled.
+    pub fn list(&self) -> Vec<ProviderInfo> {
+        PROVIDERS
+            .iter()
+            .map(|id| match self.providers.get(*id) {
+                Some(provider) => ProviderInfo {
+                    id: id.to_string(),
+                    enabled: true,
+                    disabled_reason: None,
+                    capabilities: Some(provider.capabilities()),
+                },
+                None => ProviderInfo {
+                    id: id.to_string(),
+                    enabled: false,
+                    disabled_reason: self.disabled.get(*id).cloned(),
+                    capabilities: None,
+                },
+            })
+            .collect()
+    }
+
+    /// Registers `provider` under `id`, replacing whatever was there.
+    #[cfg(test)]
+    pub fn with_provider(mut self, id: &str, provider: Arc<dyn Provider>) -> Self {
+        self.disabled.remove(id);
+        self.providers.insert(id.to_string(), provider);
+        self
+    }
+
+    fn unavailable(&self, provider: &str) -> ProviderError {
+        match self.disabled.get(provider) {
+            Some(reason) => ProviderError::BadRequest(format!("Provider {} is disabled: {}", provider, reason)),
+            None => ProviderError::BadRequest(format!("Invalid provider {}", provider)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+    use crate::utils::testing::test_config;
+    use actix_web::{http::StatusCode, ResponseError};
+
+    fn factory() -> ProviderFactory {
+        ProviderFactory::new(Arc::new(config::Config {
+            teller_environment: "production".to_string(),
+            wise_api_token: Some("personal_token".to_string()),
+            wise_team_id: Some(uuid::Uuid::nil()),
+            disabled_providers: vec!["plaid".to_string()],
+            ..test_config()
+        }))
+    }
+
+    #[test]
+    fn test_list_reports_disabled_providers() {
+        let providers = factory().list();
+
+        let ids: Vec<_> = providers.iter().map(|provider| provider.id.as_str()).collect();
+        assert_eq!(ids, PROVIDERS.to_vec());
+
+        let plaid = providers.iter().find(|provider| provider.id == "plaid").unwrap();
+        assert!(!plaid.enabled);
+        assert!(plaid.disabled_reason.as_deref().unwrap().contains("DISABLED_PROVIDERS"));
+
+        let teller = providers.iter().find(|provider| provider.id == "teller").unwrap();
+        assert!(!teller.enabled);
+        assert!(teller.disabled_reason.as_deref().unwrap().contains("TELLER_CERTIFICATE_PATH"));
+        assert!(teller.capabilities.is_none());
+
+        let wise = providers.iter().find(|provider| provider.id == "wise").unwrap();
+        assert!(wise.enabled);
+        assert!(!wise.capabilities.unwrap().pending_transactions);
+    }
+
+    #[test]
+    fn test_require_rejects_unsupported_capability() {
+        let factory = factory();
+
+        assert!(factory.require("truelayer", Capability::TokenRefresh).is_ok());
+
+        let error = AppError::from(factory.require("gocardless", Capability::TokenRefresh).err().unwrap());
+        assert!(matches!(error, AppError::UnsupportedCapability(_)));
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(error.to_string().contains("gocardless does not support token_refresh"));
+    }
+
+    #[test]
+    fn test_require_rejects_disabled_provider() {
+        let error = factory().require("plaid", Capability::Accounts).err().unwrap();
+
+        assert!(matches!(error, ProviderError::BadRequest(_)));
+        assert!(error.to_string().contains("disabled"));
+    }
 }
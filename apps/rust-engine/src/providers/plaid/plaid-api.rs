@@ -1,88 +1,280 @@
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use chrono::NaiveDate;
 
-use crate::{
-    config::Config,
-    utils::{ApiError, ApiResult},
-};
+use crate::config::Config;
+use crate::providers::{ProviderError, ProviderResult};
 
 use super::types::*;
 
+const TRANSACTIONS_PAGE_SIZE: u32 = 500;
+const TRANSACTIONS_SYNC_PAGE_SIZE: u32 = 500;
+const INSTITUTIONS_PAGE_SIZE: u32 = 500;
+const SYNC_MAX_RESTARTS: u32 = 3;
+const SYNC_MUTATION_DURING_PAGINATION: &str = "TRANSACTIONS_SYNC_MUTATION_DURING_PAGINATION";
+
 pub struct PlaidApi {
     client: Client,
     client_id: String,
     secret: String,
-    environment: String,
+    base_url: String,
 }
 
 impl PlaidApi {
     pub fn new(config: &Config) -> Self {
+        let base_url = match config.plaid_environment.as_str() {
+            "sandbox" => "https://sandbox.plaid.com",
+            "development" => "https://development.plaid.com",
+            _ => "https://production.plaid.com",
+        };
+
+        Self::with_base_url(&config.plaid_client_id, &config.plaid_secret, base_url)
+    }
+
+    pub fn with_base_url(client_id: &str, secret: &str, base_url: &str) -> Self {
         Self {
             client: Client::new(),
-            client_id: config.plaid.client_id.clone(),
-            secret: config.plaid.secret.clone(),
-            environment: config.plaid.environment.clone(),
+            client_id: client_id.to_string(),
+            secret: secret.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
         }
     }
 
-    pub async fn get_institutions(&self, country_codes: Option<&[&str]>) -> ApiResult<Vec<PlaidInstitution>> {
-        let url = format!("https://{}.plaid.com/institutions/get", self.environment);
-        
+    async fn post<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> ProviderResult<T> {
         let response = self.client
-            .post(&url)
-            .json(&serde_json::json!({
-                "client_id": self.client_id,
-                "secret": self.secret,
-                "count": 500,
-                "offset": 0,
-                "country_codes": country_codes.unwrap_or(&["US", "CA", "GB"]),
-            }))
+            .post(format!("{}{}", self.base_url, path))
+            .header("PLAID-CLIENT-ID", &self.client_id)
+            .header("PLAID-SECRET", &self.secret)
+            .json(body)
             .send()
             .await?;
 
-        let data = response.json().await?;
-        Ok(data)
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(match response.json::<PlaidError>().await {
+                Ok(error) => error.into(),
+                Err(_) => ProviderError::Other(format!("Plaid request to {} failed with {}", path, status)),
+            });
+        }
+
+        Ok(response.json().await?)
     }
 
-    pub async fn get_accounts(&self, access_token: &str) -> ApiResult<Vec<PlaidAccount>> {
-        let url = format!("https://{}.plaid.com/accounts/get", self.environment);
-        
-        let response = self.client
-            .post(&url)
-            .json(&serde_json::json!({
-                "client_id": self.client_id,
-                "secret": self.secret,
-                "access_token": access_token,
-            }))
-            .send()
+    pub async fn exchange_public_token(&self, public_token: &str) -> ProviderResult<PlaidExchangeTokenResponse> {
+        self.post(
+            "/item/public_token/exchange",
+            &serde_json::json!({ "public_token": public_token }),
+        )
+        .await
+    }
+
+    pub async fn get_institutions(&self, country_codes: Option<&[&str]>) -> ProviderResult<Vec<PlaidInstitution>> {
+        let country_codes = country_codes.unwrap_or(&["US", "CA", "GB"]);
+        let mut institutions = Vec::new();
+
+        loop {
+            let page: PlaidInstitutionsResponse = self.post(
+                "/institutions/get",
+                &serde_json::json!({
+                    "count": INSTITUTIONS_PAGE_SIZE,
+                    "offset": institutions.len(),
+                    "country_codes": country_codes,
+                    "options": { "include_optional_metadata": true },
+                }),
+            )
             .await?;
 
-        let data = response.json().await?;
-        Ok(data)
+            let fetched = page.institutions.len();
+            institutions.extend(page.institutions);
+
+            if fetched == 0 || institutions.len() >= page.total as usize {
+                return Ok(institutions);
+            }
+        }
     }
 
-    pub async fn get_transactions(
+    pub async fn get_accounts(&self, access_token: &str) -> ProviderResult<Vec<PlaidAccount>> {
+        let data: PlaidAccountsResponse = self.post(
+            "/accounts/get",
+            &serde_json::json!({ "access_token": access_token }),
+        )
+        .await?;
+
+        Ok(data.accounts)
+    }
+
+    /// Unlike `/accounts/get`, this forces Plaid to fetch real-time balances
+    /// from the institution.
+    #[allow(dead_code)]
+    pub async fn get_balances(
         &self,
         access_token: &str,
-        start_date: DateTime<Utc>,
-        end_date: DateTime<Utc>,
-    ) -> ApiResult<Vec<PlaidTransaction>> {
-        let url = format!("https://{}.plaid.com/transactions/get", self.environment);
-        
-        let response = self.client
-            .post(&url)
-            .json(&serde_json::json!({
-                "client_id": self.client_id,
-                "secret": self.secret,
+        account_ids: Option<&[&str]>,
+    ) -> ProviderResult<Vec<PlaidAccount>> {
+        let mut body = serde_json::json!({ "access_token": access_token });
+        if let Some(account_ids) = account_ids {
+            body["options"] = serde_json::json!({ "account_ids": account_ids });
+        }
+
+        let data: PlaidAccountsResponse = self.post("/accounts/balance/get", &body).await?;
+        Ok(data.accounts)
+    }
+
+    /// Fetches a single page of `/transactions/get`, starting `offset`
+    /// transactions into the range.
+    pub async fn get_transactions_page(
+        &self,
+        access_token: &str,
+        account_id: Option<&str>,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        offset: usize,
+        count: u32,
+    ) -> ProviderResult<PlaidTransactionsResponse> {
+        let mut options = serde_json::json!({
+            "count": count,
+            "offset": offset,
+        });
+        if let Some(account_id) = account_id {
+            options["account_ids"] = serde_json::json!([account_id]);
+        }
+
+        self.post(
+            "/transactions/get",
+            &serde_json::json!({
                 "access_token": access_token,
                 "start_date": start_date.format("%Y-%m-%d").to_string(),
                 "end_date": end_date.format("%Y-%m-%d").to_string(),
-            }))
-            .send()
-            .await?;
+                "options": options,
+            }),
+        )
+        .await
+    }
 
-        let data = response.json().await?;
-        Ok(data)
+    pub async fn get_transactions(
+        &self,
+        access_token: &str,
+        account_id: Option<&str>,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+    ) -> ProviderResult<Vec<PlaidTransaction>> {
+        let mut transactions = Vec::new();
+
+        loop {
+            let page = self
+                .get_transactions_page(
+                    access_token,
+                    account_id,
+                    start_date,
+                    end_date,
+                    transactions.len(),
+                    TRANSACTIONS_PAGE_SIZE,
+                )
+                .await?;
+
+            let fetched = page.transactions.len();
+            transactions.extend(page.transactions);
+
+            if fetched == 0 || transactions.len() >= page.total_transactions as usize {
+                return Ok(transactions);
+            }
+        }
+    }
+
+    /// Fetches a single page of `/transactions/sync`. An empty cursor asks
+    /// Plaid for the item's full history.
+    pub async fn sync_transactions(
+        &self,
+        access_token: &str,
+        cursor: Option<&str>,
+    ) -> ProviderResult<PlaidTransactionsSyncResponse> {
+        self.post(
+            "/transactions/sync",
+            &serde_json::json!({
+                "access_token": access_token,
+                "cursor": cursor.unwrap_or_default(),
+                "count": TRANSACTIONS_SYNC_PAGE_SIZE,
+                "options": { "include_personal_finance_category": true },
+            }),
+        )
+        .await
+    }
+
+    /// Pages through `/transactions/sync` from `cursor` until Plaid has no
+    /// more updates, merging every page into one response. If the item
+    /// changes while paginating, Plaid requires starting over from the
+    /// original cursor.
+    pub async fn sync_all_transactions(
+        &self,
+        access_token: &str,
+        cursor: Option<&str>,
+    ) -> ProviderResult<PlaidTransactionsSyncResponse> {
+        let mut restarts = 0;
+
+        'restart: loop {
+            let mut update = PlaidTransactionsSyncResponse {
+                accounts: Vec::new(),
+                added: Vec::new(),
+                modified: Vec::new(),
+                removed: Vec::new(),
+                next_cursor: cursor.unwrap_or_default().to_string(),
+                has_more: true,
+            };
+
+            while update.has_more {
+                let page = match self.sync_transactions(access_token, Some(&update.next_cursor)).await {
+                    Ok(page) => page,
+                    Err(ProviderError::Other(message))
+                        if message.starts_with(SYNC_MUTATION_DURING_PAGINATION) && restarts < SYNC_MAX_RESTARTS =>
+                    {
+                        restarts += 1;
+                        continue 'restart;
+                    }
+                    Err(error) => return Err(error),
+                };
+
+                update.accounts = page.accounts;
+                update.added.extend(page.added);
+                update.modified.extend(page.modified);
+                update.removed.extend(page.removed);
+                update.next_cursor = page.next_cursor;
+                update.has_more = page.has_more;
+            }
+
+            return Ok(update);
+        }
+    }
+
+    pub async fn get_item(&self, access_token: &str) -> ProviderResult<PlaidItem> {
+        let data: PlaidItemResponse = self.post(
+            "/item/get",
+            &serde_json::json!({ "access_token": access_token }),
+        )
+        .await?;
+
+        Ok(data.item)
+    }
+
+    pub async fn remove_item(&self, access_token: &str) -> ProviderResult<()> {
+        let _: serde_json::Value = self.post(
+            "/item/remove",
+            &serde_json::json!({ "access_token": access_token }),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl From<PlaidError> for ProviderError {
+    fn from(error: PlaidError) -> Self {
+        let message = format!("{}: {}", error.error_code, error.error_message);
+        match (error.error_type.as_str(), error.error_code.as_str()) {
+            (_, "ITEM_LOGIN_REQUIRED") | (_, "INVALID_ACCESS_TOKEN") => ProviderError::Unauthorized(message),
+            ("INVALID_REQUEST", _) | ("INVALID_INPUT", _) => ProviderError::BadRequest(message),
+            ("RATE_LIMIT_EXCEEDED", _) => ProviderError::RateLimited(message),
+            ("API_ERROR", _) | ("INSTITUTION_ERROR", _) => ProviderError::Unavailable(message),
+            _ => ProviderError::Other(message),
+        }
     }
 }
@@ -1,170 +1,581 @@
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use reqwest::Client;
-use std::error::Error;
 use std::sync::Arc;
 
 use crate::providers::{
-    interface::Provider,
     types::{
-        Account, AccountType, Balance, ConnectionState, ConnectionStatus, DeleteAccountsRequest,
-        DeleteConnectionRequest, GetAccountBalanceRequest, GetAccountsRequest,
-        GetConnectionStatusRequest, GetInstitutionsRequest, GetTransactionsRequest, Institution,
-        Transaction, TransactionStatus,
+        Account, Balance, Capabilities, ConnectionState, ConnectionStatus, DeleteConnectionRequest,
+        ExchangeTokenRequest, GetAccountBalanceRequest, GetAccountsRequest, GetConnectionStatusRequest,
+        GetInstitutionsRequest, GetTransactionsRequest, Institution, RefreshTokenRequest,
+        SyncTransactionsRequest, TokenResponse, TransactionPage, TransactionSync,
     },
+    Provider, ProviderError, ProviderResult,
 };
+use crate::utils::config::Config;
+
+use super::plaid_api::PlaidApi;
+use super::transform::{transform_account, transform_balance, transform_institution, transform_transaction};
+
+/// How far back `get_transactions` looks when no explicit range is given.
+const TRANSACTIONS_LOOKBACK_DAYS: i64 = 90;
 
 pub struct PlaidProvider {
-    client: Arc<Client>,
-    client_id: String,
-    secret: String,
-    environment: String,
+    api: PlaidApi,
 }
 
 impl PlaidProvider {
-    pub fn new(client_id: String, secret: String, environment: String) -> Self {
-        let client = Client::new();
-        Self {
-            client: Arc::new(client),
-            client_id,
-            secret,
-            environment,
-        }
+    pub fn new(config: Arc<Config>) -> Self {
+        Self::from_api(PlaidApi::new(&config))
     }
 
-    fn get_api_url(&self) -> String {
-        match self.environment.as_str() {
-            "sandbox" => "https://sandbox.plaid.com".to_string(),
-            "development" => "https://development.plaid.com".to_string(),
-            _ => "https://production.plaid.com".to_string(),
-        }
+    pub fn from_api(api: PlaidApi) -> Self {
+        Self { api }
     }
 }
 
 #[async_trait]
 impl Provider for PlaidProvider {
-    async fn get_transactions(
-        &self,
-        params: GetTransactionsRequest,
-    ) -> Result<Vec<Transaction>, Box<dyn Error>> {
-        let access_token = params.access_token.ok_or("Access token required")?;
-        let url = format!("{}/transactions/get", self.get_api_url());
-
-        // TODO: Implement actual Plaid API call
-        // This is a mock implementation
-        let transaction = Transaction {
-            id: "txn_123".to_string(),
-            amount: 50.0,
-            currency: "USD".to_string(),
-            date: Utc::now(),
-            status: TransactionStatus::Posted,
-            balance: Some(1000.0),
-            category: Some("Food".to_string()),
-            method: "card".to_string(),
-            name: "Restaurant".to_string(),
-            description: Some("Dinner".to_string()),
-            currency_rate: None,
-            currency_source: None,
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            accounts: true,
+            balances: true,
+            transactions: true,
+            pending_transactions: true,
+            transaction_sync: true,
+            date_range: true,
+            stable_transaction_ids: true,
+            token_refresh: false,
+            identity: false,
+            institutions: true,
+            delete_connection: true,
+        }
+    }
+
+    /// Exchanges a Link `public_token` for a permanent access token. Plaid
+    /// access tokens never expire, so there is no refresh token.
+    async fn exchange_token(&self, request: ExchangeTokenRequest) -> ProviderResult<TokenResponse> {
+        let response = self.api.exchange_public_token(&request.code).await?;
+        Ok(TokenResponse {
+            access_token: response.access_token,
+            refresh_token: None,
+            expires_at: None,
+        })
+    }
+
+    async fn refresh_token(&self, request: RefreshTokenRequest) -> ProviderResult<TokenResponse> {
+        // Nothing to renew, but make sure the item is still usable
+        self.api.get_item(&request.refresh_token).await?;
+        Ok(TokenResponse {
+            access_token: request.refresh_token,
+            refresh_token: None,
+            expires_at: None,
+        })
+    }
+
+    async fn get_accounts(&self, request: GetAccountsRequest) -> ProviderResult<Vec<Account>> {
+        let item = self.api.get_item(&request.access_token).await?;
+        let institution_id = item.institution_id.unwrap_or_default();
+        let accounts = self.api.get_accounts(&request.access_token).await?;
+
+        Ok(accounts
+            .iter()
+            .map(|account| transform_account(account, &institution_id))
+            .collect())
+    }
+
+    async fn get_account_balance(&self, request: GetAccountBalanceRequest) -> ProviderResult<Balance> {
+        let accounts = self
+            .api
+            .get_balances(&request.access_token, Some(&[request.account_id.as_str()]))
+            .await?;
+        let account = accounts
+            .iter()
+            .find(|account| account.account_id == request.account_id)
+            .ok_or_else(|| ProviderError::NotFound(format!("Account {} not found", request.account_id)))?;
+
+        Ok(transform_balance(&account.balances))
+    }
+
+    /// Uses Plaid's offset pagination directly; the cursor is the offset of
+    /// the next page.
+    async fn get_transactions(&self, request: GetTransactionsRequest) -> ProviderResult<TransactionPage> {
+        let (start_date, end_date) = request.date_range.resolve(TRANSACTIONS_LOOKBACK_DAYS);
+
+        let Some(limit) = request.pagination.limit else {
+            let transactions = self
+                .api
+                .get_transactions(&request.access_token, Some(&request.account_id), start_date, end_date)
+                .await?;
+            return TransactionPage::from_all(
+                transactions.iter().map(transform_transaction).collect(),
+                &request.pagination,
+            );
         };
 
-        Ok(vec![transaction])
-    }
-
-    async fn get_accounts(
-        &self,
-        params: GetAccountsRequest,
-    ) -> Result<Vec<Account>, Box<dyn Error>> {
-        let access_token = params.access_token.ok_or("Access token required")?;
-        let url = format!("{}/accounts/get", self.get_api_url());
-
-        // TODO: Implement actual Plaid API call
-        // This is a mock implementation
-        let account = Account {
-            id: "acc_123".to_string(),
-            name: "Checking Account".to_string(),
-            currency: "USD".to_string(),
-            account_type: AccountType::Checking,
-            institution: Institution {
-                id: "inst_1".to_string(),
-                name: "Test Bank".to_string(),
-                logo: None,
-                provider: crate::providers::types::Provider::Plaid,
-            },
-            balance: Balance {
-                amount: 1000.0,
-                currency: "USD".to_string(),
-            },
-            enrollment_id: None,
+        let offset = match &request.pagination.cursor {
+            Some(cursor) => cursor
+                .parse::<usize>()
+                .map_err(|_| ProviderError::BadRequest(format!("Invalid cursor {}", cursor)))?,
+            None => 0,
         };
+        let page = self
+            .api
+            .get_transactions_page(
+                &request.access_token,
+                Some(&request.account_id),
+                start_date,
+                end_date,
+                offset,
+                limit,
+            )
+            .await?;
+        let end = offset + page.transactions.len();
 
-        Ok(vec![account])
+        Ok(TransactionPage {
+            transactions: page.transactions.iter().map(transform_transaction).collect(),
+            next_cursor: (!page.transactions.is_empty() && end < page.total_transactions as usize)
+                .then(|| end.to_string()),
+        })
     }
 
-    async fn get_account_balance(
-        &self,
-        params: GetAccountBalanceRequest,
-    ) -> Result<Balance, Box<dyn Error>> {
-        let access_token = params.access_token.ok_or("Access token required")?;
-        
-        // TODO: Implement actual Plaid API call
-        // This is a mock implementation
-        Ok(Balance {
-            amount: 1000.0,
-            currency: "USD".to_string(),
+    async fn sync_transactions(&self, request: SyncTransactionsRequest) -> ProviderResult<TransactionSync> {
+        let item = self.api.get_item(&request.access_token).await?;
+        let institution_id = item.institution_id.unwrap_or_default();
+        let update = self
+            .api
+            .sync_all_transactions(&request.access_token, request.checkpoint.as_deref())
+            .await?;
+
+        Ok(TransactionSync {
+            accounts: update
+                .accounts
+                .iter()
+                .map(|account| transform_account(account, &institution_id))
+                .collect(),
+            added: update.added.iter().map(transform_transaction).collect(),
+            modified: update.modified.iter().map(transform_transaction).collect(),
+            removed: update.removed.into_iter().map(|removed| removed.transaction_id).collect(),
+            checkpoint: Some(update.next_cursor),
         })
     }
 
-    async fn get_institutions(
-        &self,
-        params: GetInstitutionsRequest,
-    ) -> Result<Vec<Institution>, Box<dyn Error>> {
-        let url = format!("{}/institutions/get", self.get_api_url());
+    async fn get_institutions(&self, request: GetInstitutionsRequest) -> ProviderResult<Vec<Institution>> {
+        let country_codes = request.country.as_deref().map(|country| [country]);
+        let institutions = self
+            .api
+            .get_institutions(country_codes.as_ref().map(|codes| codes.as_slice()))
+            .await?;
+        // Plaid already filtered by country, and institutions can belong to
+        // several countries, so only the search term is left to apply
+        let search = GetInstitutionsRequest {
+            country: None,
+            search: request.search,
+        };
+
+        Ok(institutions
+            .iter()
+            .map(transform_institution)
+            .filter(|institution| search.matches(institution))
+            .collect())
+    }
 
-        // TODO: Implement actual Plaid API call
-        // This is a mock implementation
-        let institution = Institution {
-            id: "inst_1".to_string(),
-            name: "Test Bank".to_string(),
-            logo: None,
-            provider: crate::providers::types::Provider::Plaid,
+    async fn get_connection_status(&self, request: GetConnectionStatusRequest) -> ProviderResult<ConnectionStatus> {
+        let status = match self.api.get_item(&request.access_token).await {
+            Ok(item) => match item.error {
+                None => ConnectionState::Connected,
+                Some(error) if is_disconnected_error(&error.error_code) => ConnectionState::Disconnected,
+                Some(_) => ConnectionState::Error,
+            },
+            Err(ProviderError::Unauthorized(_)) => ConnectionState::Disconnected,
+            Err(error) => return Err(error),
         };
 
-        Ok(vec![institution])
+        Ok(ConnectionStatus { status })
+    }
+
+    async fn delete_connection(&self, request: DeleteConnectionRequest) -> ProviderResult<()> {
+        self.api.remove_item(&request.access_token).await
+    }
+}
+
+fn is_disconnected_error(error_code: &str) -> bool {
+    matches!(
+        error_code,
+        "ITEM_LOGIN_REQUIRED" | "PENDING_EXPIRATION" | "USER_PERMISSION_REVOKED" | "ACCESS_NOT_GRANTED"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::types::{DateRange, Pagination, TransactionStatus};
+    use chrono::NaiveDate;
+    use mockito::{Matcher, Server};
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn provider(server: &Server) -> PlaidProvider {
+        PlaidProvider::from_api(PlaidApi::with_base_url("client", "secret", &server.url()))
+    }
+
+    fn transactions_request(pagination: Pagination) -> GetTransactionsRequest {
+        GetTransactionsRequest {
+            access_token: "access-sandbox-1".to_string(),
+            account_id: "acc_1".to_string(),
+            date_range: DateRange::default(),
+            pagination,
+        }
+    }
+
+    fn item(error: serde_json::Value) -> serde_json::Value {
+        json!({
+            "item": {
+                "item_id": "item_1",
+                "institution_id": "ins_56",
+                "error": error,
+                "consent_expiration_time": null
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_exchange_token() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/item/public_token/exchange")
+            .match_header("PLAID-CLIENT-ID", "client")
+            .match_header("PLAID-SECRET", "secret")
+            .match_body(Matcher::PartialJson(json!({ "public_token": "public-sandbox-1" })))
+            .with_body(json!({ "access_token": "access-sandbox-1", "item_id": "item_1" }).to_string())
+            .create_async()
+            .await;
+
+        let token = provider(&server)
+            .exchange_token(ExchangeTokenRequest {
+                code: "public-sandbox-1".to_string(),
+                redirect_uri: String::new(),
+            })
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(token.access_token, "access-sandbox-1");
+        assert_eq!(token.refresh_token, None);
+        assert_eq!(token.expires_at, None);
     }
 
-    async fn get_health_check(&self) -> Result<bool, Box<dyn Error>> {
-        let url = format!("{}/health", self.get_api_url());
-        
-        // TODO: Implement actual health check
-        Ok(true)
+    #[tokio::test]
+    async fn test_get_accounts() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/item/get")
+            .with_body(item(json!(null)).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/accounts/get")
+            .with_body(
+                json!({
+                    "accounts": [{
+                        "account_id": "acc_1",
+                        "balances": {
+                            "available": 90.5,
+                            "current": 100.25,
+                            "limit": null,
+                            "iso_currency_code": "USD",
+                            "unofficial_currency_code": null
+                        },
+                        "mask": "0000",
+                        "name": "Plaid Checking",
+                        "official_name": null,
+                        "type": "depository",
+                        "subtype": "checking",
+                        "verification_status": null
+                    }],
+                    "item": item(json!(null))["item"]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let accounts = provider(&server)
+            .get_accounts(GetAccountsRequest {
+                access_token: "access-sandbox-1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].id, "acc_1");
+        assert_eq!(accounts[0].institution_id, "ins_56");
+        assert_eq!(accounts[0].balance.amount, dec!(100.25));
     }
 
-    async fn delete_accounts(&self, params: DeleteAccountsRequest) -> Result<(), Box<dyn Error>> {
-        let access_token = params.access_token.ok_or("Access token required")?;
-        
-        // TODO: Implement account deletion
-        Ok(())
+    #[tokio::test]
+    async fn test_get_transactions_paginates() {
+        let mut server = Server::new_async().await;
+        let transaction = |id: &str| {
+            json!({
+                "transaction_id": id,
+                "account_id": "acc_1",
+                "amount": 12.5,
+                "iso_currency_code": "USD",
+                "unofficial_currency_code": null,
+                "date": "2024-03-01",
+                "name": "Uber",
+                "merchant_name": "Uber",
+                "payment_channel": "online",
+                "pending": false,
+                "transaction_type": null,
+                "category": ["Travel"],
+                "personal_finance_category": null,
+                "location": null
+            })
+        };
+        server
+            .mock("POST", "/transactions/get")
+            .match_body(Matcher::PartialJson(json!({ "options": { "offset": 0 } })))
+            .with_body(json!({ "accounts": [], "transactions": [transaction("tx_1")], "total_transactions": 2 }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/transactions/get")
+            .match_body(Matcher::PartialJson(json!({ "options": { "offset": 1, "account_ids": ["acc_1"] } })))
+            .with_body(json!({ "accounts": [], "transactions": [transaction("tx_2")], "total_transactions": 2 }).to_string())
+            .create_async()
+            .await;
+
+        let page = provider(&server)
+            .get_transactions(transactions_request(Pagination::default()))
+            .await
+            .unwrap();
+        let transactions = page.transactions;
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(transactions[1].id, "tx_2");
+        assert_eq!(transactions[1].amount, dec!(-12.5));
+        assert_eq!(transactions[1].status, TransactionStatus::Posted);
     }
 
-    async fn get_connection_status(
-        &self,
-        params: GetConnectionStatusRequest,
-    ) -> Result<ConnectionStatus, Box<dyn Error>> {
-        let access_token = params.access_token.ok_or("Access token required")?;
-        
-        // TODO: Implement actual status check
-        Ok(ConnectionStatus {
-            status: ConnectionState::Connected,
+    #[tokio::test]
+    async fn test_get_transactions_single_page() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/transactions/get")
+            .match_body(Matcher::PartialJson(json!({
+                "start_date": "2024-01-01",
+                "end_date": "2024-01-31",
+                "options": { "offset": 10, "count": 1 }
+            })))
+            .with_body(
+                json!({
+                    "accounts": [],
+                    "transactions": [{
+                        "transaction_id": "tx_11",
+                        "account_id": "acc_1",
+                        "amount": 3.0,
+                        "iso_currency_code": "USD",
+                        "unofficial_currency_code": null,
+                        "date": "2024-01-15",
+                        "name": "Parking",
+                        "merchant_name": null,
+                        "payment_channel": "in_store",
+                        "pending": false,
+                        "transaction_type": null,
+                        "category": null,
+                        "personal_finance_category": null,
+                        "location": null
+                    }],
+                    "total_transactions": 20
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut request = transactions_request(Pagination {
+            cursor: Some("10".to_string()),
+            limit: Some(1),
+        });
+        request.date_range = DateRange {
+            start_date: NaiveDate::from_ymd_opt(2024, 1, 1),
+            end_date: NaiveDate::from_ymd_opt(2024, 1, 31),
+        };
+        let page = provider(&server).get_transactions(request).await.unwrap();
+
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.next_cursor.as_deref(), Some("11"));
+    }
+
+    fn sync_page(cursor: &str, added: &[&str], removed: &[&str], has_more: bool) -> String {
+        json!({
+            "accounts": [],
+            "added": added.iter().map(|id| json!({
+                "transaction_id": id,
+                "account_id": "acc_1",
+                "amount": 4.5,
+                "iso_currency_code": "USD",
+                "unofficial_currency_code": null,
+                "date": "2024-03-02",
+                "name": "Coffee",
+                "merchant_name": null,
+                "payment_channel": "in_store",
+                "pending": true,
+                "transaction_type": null,
+                "category": null,
+                "personal_finance_category": null,
+                "location": null
+            })).collect::<Vec<_>>(),
+            "modified": [],
+            "removed": removed.iter().map(|id| json!({ "transaction_id": id, "account_id": "acc_1" })).collect::<Vec<_>>(),
+            "next_cursor": cursor,
+            "has_more": has_more
         })
+        .to_string()
     }
 
-    async fn delete_connection(
-        &self,
-        params: DeleteConnectionRequest,
-    ) -> Result<(), Box<dyn Error>> {
-        let access_token = params.access_token.ok_or("Access token required")?;
-        
-        // TODO: Implement connection deletion
-        Ok(())
+    #[tokio::test]
+    async fn test_sync_transactions_follows_cursor() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/item/get")
+            .with_body(item(json!(null)).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/transactions/sync")
+            .match_body(Matcher::PartialJson(json!({ "cursor": "cursor_0" })))
+            .with_body(sync_page("cursor_1", &["tx_1"], &[], true))
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/transactions/sync")
+            .match_body(Matcher::PartialJson(json!({ "cursor": "cursor_1" })))
+            .with_body(sync_page("cursor_2", &["tx_2"], &["tx_0"], false))
+            .create_async()
+            .await;
+
+        let sync = provider(&server)
+            .sync_transactions(SyncTransactionsRequest {
+                access_token: "access-sandbox-1".to_string(),
+                checkpoint: Some("cursor_0".to_string()),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(sync.added.len(), 2);
+        assert_eq!(sync.added[0].status, TransactionStatus::Pending);
+        assert_eq!(sync.removed, vec!["tx_0".to_string()]);
+        assert_eq!(sync.checkpoint.as_deref(), Some("cursor_2"));
+    }
+
+    #[tokio::test]
+    async fn test_sync_transactions_restarts_on_mutation() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/item/get")
+            .with_body(item(json!(null)).to_string())
+            .create_async()
+            .await;
+        let first_page = server
+            .mock("POST", "/transactions/sync")
+            .match_body(Matcher::PartialJson(json!({ "cursor": "" })))
+            .with_body(sync_page("cursor_1", &["tx_1"], &[], true))
+            .expect(2)
+            .create_async()
+            .await;
+        let mutated = server
+            .mock("POST", "/transactions/sync")
+            .match_body(Matcher::PartialJson(json!({ "cursor": "cursor_1" })))
+            .with_status(400)
+            .with_body(
+                json!({
+                    "error_type": "TRANSACTIONS_ERROR",
+                    "error_code": "TRANSACTIONS_SYNC_MUTATION_DURING_PAGINATION",
+                    "error_message": "underlying transaction data changed since last page was fetched",
+                    "display_message": null
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/transactions/sync")
+            .match_body(Matcher::PartialJson(json!({ "cursor": "cursor_1" })))
+            .with_body(sync_page("cursor_2", &["tx_2"], &[], false))
+            .create_async()
+            .await;
+
+        let sync = provider(&server)
+            .sync_transactions(SyncTransactionsRequest {
+                access_token: "access-sandbox-1".to_string(),
+                checkpoint: None,
+            })
+            .await
+            .unwrap();
+
+        first_page.assert_async().await;
+        mutated.assert_async().await;
+        let ids: Vec<_> = sync.added.iter().map(|tx| tx.id.as_str()).collect();
+        assert_eq!(ids, vec!["tx_1", "tx_2"]);
+        assert_eq!(sync.checkpoint.as_deref(), Some("cursor_2"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_status_login_required() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/item/get")
+            .with_body(
+                item(json!({
+                    "error_type": "ITEM_ERROR",
+                    "error_code": "ITEM_LOGIN_REQUIRED",
+                    "error_message": "the login details of this item have changed",
+                    "display_message": null
+                }))
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let status = provider(&server)
+            .get_connection_status(GetConnectionStatusRequest {
+                access_token: "access-sandbox-1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(status.status, ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_plaid_error_is_mapped() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/item/remove")
+            .with_status(400)
+            .with_body(
+                json!({
+                    "error_type": "INVALID_INPUT",
+                    "error_code": "INVALID_ACCESS_TOKEN",
+                    "error_message": "provided access token is in an invalid format",
+                    "display_message": null
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let error = provider(&server)
+            .delete_connection(DeleteConnectionRequest {
+                access_token: "bogus".to_string(),
+            })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ProviderError::Unauthorized(_)));
+        assert!(error.to_string().contains("INVALID_ACCESS_TOKEN"));
     }
 }
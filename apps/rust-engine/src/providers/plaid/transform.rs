@@ -1,105 +1,75 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{TimeZone, Utc};
 
-use crate::providers::types::*;
+use crate::providers::types::{Account, Balance, Institution, Transaction};
+use crate::utils::{get_institution_logo, money};
+
+use super::types::*;
+use super::utils::{get_account_type, get_currency, get_transaction_status};
+
+pub fn transform_account(account: &PlaidAccount, institution_id: &str) -> Account {
+    let currency = get_currency(&account.balances);
 
-pub fn transform_account(account: PlaidAccount) -> Account {
     Account {
-        id: format!("pl_{}", account.account_id),
-        connection_id: account.connection_id,
-        name: account.name,
-        account_type: transform_account_type(account.r#type),
-        currency: account.balances.iso_currency_code.unwrap_or_else(|| "USD".to_string()),
-        balance: Balance {
-            current: account.balances.current.unwrap_or(0.0),
-            available: account.balances.available,
-            limit: account.balances.limit,
-        },
+        id: account.account_id.clone(),
+        name: account.name.clone(),
+        account_type: get_account_type(account),
+        balance: transform_balance(&account.balances),
+        currency,
+        institution_id: institution_id.to_string(),
         last_sync: Some(Utc::now()),
     }
 }
 
-pub fn transform_transaction(transaction: PlaidTransaction) -> Transaction {
-    Transaction {
-        id: format!("pl_{}", transaction.transaction_id),
-        account_id: format!("pl_{}", transaction.account_id),
-        date: transaction.date,
-        description: Some(transaction.name),
-        amount: transaction.amount,
-        currency: transaction.iso_currency_code.unwrap_or_else(|| "USD".to_string()),
-        category: transaction.category.map(|c| c.join("/")),
-        merchant: transaction.merchant_name,
-        pending: transaction.pending,
-    }
-}
+pub fn transform_balance(balances: &PlaidBalances) -> Balance {
+    let currency = get_currency(balances);
 
-pub fn transform_institution(institution: PlaidInstitution) -> Institution {
-    Institution {
-        id: format!("pl_{}", institution.institution_id),
-        name: institution.name,
-        country: institution.country_codes.first().cloned().unwrap_or_else(|| "US".to_string()),
-        provider: "plaid".to_string(),
-        logo_url: institution.logo.map(|l| l.url),
-        primary_color: institution.primary_color,
-        url: None,
-        oauth_support: institution.oauth,
-        products: institution.products.into_iter().map(|p| p.to_string()).collect(),
-        last_update: Utc::now(),
+    Balance {
+        amount: money::round(balances.current.or(balances.available).unwrap_or_default(), &currency),
+        available: balances.available.map(|available| money::round(available, &currency)),
+        currency,
     }
 }
 
-fn transform_account_type(account_type: String) -> String {
-    match account_type.as_str() {
-        "depository" => "checking",
-        "credit" => "credit",
-        "loan" => "loan",
-        "investment" => "investment",
-        _ => "other",
-    }.to_string()
-}
+pub fn transform_transaction(transaction: &PlaidTransaction) -> Transaction {
+    let category = transaction
+        .personal_finance_category
+        .as_ref()
+        .map(|c| c.primary.to_lowercase())
+        .or_else(|| transaction.category.as_ref().and_then(|c| c.first().cloned()));
 
-#[derive(Debug, Deserialize)]
-pub struct PlaidAccount {
-    pub account_id: String,
-    pub connection_id: String,
-    pub name: String,
-    pub r#type: String,
-    pub balances: PlaidBalance,
-}
+    let currency = transaction
+        .iso_currency_code
+        .clone()
+        .or_else(|| transaction.unofficial_currency_code.clone())
+        .unwrap_or_else(|| "USD".to_string());
 
-#[derive(Debug, Deserialize)]
-pub struct PlaidBalance {
-    pub current: Option<f64>,
-    pub available: Option<f64>,
-    pub limit: Option<f64>,
-    pub iso_currency_code: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct PlaidTransaction {
-    pub transaction_id: String,
-    pub account_id: String,
-    pub date: DateTime<Utc>,
-    pub name: String,
-    pub amount: f64,
-    pub iso_currency_code: Option<String>,
-    pub category: Option<Vec<String>>,
-    pub merchant_name: Option<String>,
-    pub pending: bool,
+    Transaction {
+        id: transaction.transaction_id.clone(),
+        account_id: transaction.account_id.clone(),
+        // Plaid reports money leaving the account as a positive amount
+        amount: money::round(-transaction.amount, &currency),
+        currency,
+        date: Utc.from_utc_datetime(&transaction.date.and_hms_opt(0, 0, 0).unwrap_or_default()),
+        description: transaction.name.clone(),
+        merchant: transaction.merchant_name.clone(),
+        category,
+        status: get_transaction_status(transaction),
+        balance: None,
+        currency_rate: None,
+        currency_source: None,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PlaidInstitution {
-    pub institution_id: String,
-    pub name: String,
-    pub country_codes: Vec<String>,
-    pub products: Vec<String>,
-    pub logo: Option<PlaidLogo>,
-    pub primary_color: Option<String>,
-    pub oauth: bool,
+pub fn transform_institution(institution: &PlaidInstitution) -> Institution {
+    Institution {
+        id: institution.institution_id.clone(),
+        name: institution.name.clone(),
+        logo_url: get_institution_logo(&institution.institution_id).map(|logo| logo.to_string()),
+        website: institution.url.clone(),
+        country: institution.country_codes.first().cloned().unwrap_or_else(|| "US".to_string()),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PlaidLogo {
-    pub url: String,
-}
+#[cfg(test)]
+#[path = "transform.test.rs"]
+mod tests;
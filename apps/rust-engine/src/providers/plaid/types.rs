@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlaidInstitution {
     pub institution_id: String,
     pub name: String,
@@ -13,7 +14,7 @@ pub struct PlaidInstitution {
     pub products: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlaidAccount {
     pub account_id: String,
     pub balances: PlaidBalances,
@@ -25,28 +26,103 @@ pub struct PlaidAccount {
     pub verification_status: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlaidBalances {
-    pub available: Option<f64>,
-    pub current: f64,
-    pub limit: Option<f64>,
+    pub available: Option<Decimal>,
+    pub current: Option<Decimal>,
+    pub limit: Option<Decimal>,
     pub iso_currency_code: Option<String>,
     pub unofficial_currency_code: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlaidTransaction {
     pub transaction_id: String,
     pub account_id: String,
-    pub amount: f64,
+    pub amount: Decimal,
     pub iso_currency_code: Option<String>,
     pub unofficial_currency_code: Option<String>,
-    pub date: DateTime<Utc>,
+    pub date: NaiveDate,
     pub name: String,
     pub merchant_name: Option<String>,
     pub payment_channel: String,
     pub pending: bool,
     pub transaction_type: Option<String>,
     pub category: Option<Vec<String>>,
+    pub personal_finance_category: Option<PlaidPersonalFinanceCategory>,
     pub location: Option<PlaidLocation>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaidPersonalFinanceCategory {
+    pub primary: String,
+    pub detailed: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaidLocation {
+    pub address: Option<String>,
+    pub city: Option<String>,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaidItem {
+    pub item_id: String,
+    pub institution_id: Option<String>,
+    pub error: Option<PlaidError>,
+    pub consent_expiration_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaidError {
+    pub error_type: String,
+    pub error_code: String,
+    pub error_message: String,
+    pub display_message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaidExchangeTokenResponse {
+    pub access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaidAccountsResponse {
+    pub accounts: Vec<PlaidAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaidTransactionsResponse {
+    pub transactions: Vec<PlaidTransaction>,
+    pub total_transactions: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaidTransactionsSyncResponse {
+    pub accounts: Vec<PlaidAccount>,
+    pub added: Vec<PlaidTransaction>,
+    pub modified: Vec<PlaidTransaction>,
+    pub removed: Vec<PlaidRemovedTransaction>,
+    pub next_cursor: String,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaidRemovedTransaction {
+    pub transaction_id: String,
+    pub account_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaidInstitutionsResponse {
+    pub institutions: Vec<PlaidInstitution>,
+    pub total: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaidItemResponse {
+    pub item: PlaidItem,
+}
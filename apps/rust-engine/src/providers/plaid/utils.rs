@@ -1,15 +1,19 @@
+use crate::providers::types::{AccountType, TransactionStatus};
+
 use super::types::*;
 
-pub fn get_account_type(account: &PlaidAccount) -> String {
-    match account.r#type.as_str() {
-        "depository" => "checking".to_string(),
-        "credit" => "credit".to_string(),
-        "loan" => "loan".to_string(),
-        "investment" => "investment".to_string(),
-        _ => "other".to_string(),
+pub fn get_account_type(account: &PlaidAccount) -> AccountType {
+    match (account.r#type.as_str(), account.subtype.as_deref()) {
+        ("depository", Some("savings")) => AccountType::Savings,
+        ("depository", _) => AccountType::Checking,
+        ("credit", _) => AccountType::Credit,
+        ("loan", _) => AccountType::Loan,
+        ("investment", _) => AccountType::Investment,
+        _ => AccountType::Other,
     }
 }
 
+#[allow(dead_code)]
 pub fn get_transaction_type(transaction: &PlaidTransaction) -> String {
     if let Some(ref category) = transaction.category {
         if category.contains(&"Payment".to_string()) {
@@ -21,3 +25,19 @@ pub fn get_transaction_type(transaction: &PlaidTransaction) -> String {
     }
     "other".to_string()
 }
+
+pub fn get_transaction_status(transaction: &PlaidTransaction) -> TransactionStatus {
+    if transaction.pending {
+        TransactionStatus::Pending
+    } else {
+        TransactionStatus::Posted
+    }
+}
+
+pub fn get_currency(balances: &PlaidBalances) -> String {
+    balances
+        .iso_currency_code
+        .clone()
+        .or_else(|| balances.unofficial_currency_code.clone())
+        .unwrap_or_else(|| "USD".to_string())
+}
@@ -1,79 +1,184 @@
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use reqwest::{Client, Identity, Method, StatusCode};
+use serde::de::DeserializeOwned;
 
-use crate::{
-    config::Config,
-    utils::{ApiError, ApiResult},
-};
+use crate::config::Config;
+use crate::providers::{ProviderError, ProviderResult};
 
 use super::types::*;
 
+const TELLER_API_URL: &str = "https://api.teller.io";
+const TRANSACTIONS_PAGE_SIZE: usize = 250;
+
 pub struct TellerApi {
     client: Client,
-    api_key: String,
-    environment: String,
+    base_url: String,
 }
 
 impl TellerApi {
-    pub fn new(config: &Config) -> Self {
+    /// Builds a client that presents the application's certificate on every
+    /// request. Teller only accepts unauthenticated TLS in the sandbox, so
+    /// the certificate is optional there.
+    pub fn new(config: &Config) -> ProviderResult<Self> {
+        let client = match (&config.teller_certificate_path, &config.teller_private_key_path) {
+            (Some(certificate_path), Some(private_key_path)) => {
+                let certificate = read_pem(certificate_path)?;
+                let private_key = read_pem(private_key_path)?;
+                let identity = Identity::from_pkcs8_pem(&certificate, &private_key).map_err(|e| {
+                    ProviderError::Configuration(format!("Invalid Teller certificate: {}", e))
+                })?;
+
+                Client::builder().identity(identity).build()?
+            }
+            (None, None) if config.teller_environment == "sandbox" => Client::new(),
+            _ => {
+                return Err(ProviderError::Configuration(
+                    "TELLER_CERTIFICATE_PATH and TELLER_PRIVATE_KEY_PATH must both be set".to_string(),
+                ))
+            }
+        };
+
+        Ok(Self::with_client(client, TELLER_API_URL))
+    }
+
+    #[cfg(test)]
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self::with_client(Client::new(), base_url)
+    }
+
+    fn with_client(client: Client, base_url: &str) -> Self {
         Self {
-            client: Client::new(),
-            api_key: config.teller.api_key.clone(),
-            environment: config.teller.environment.clone(),
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
         }
     }
 
-    pub async fn get_institutions(&self) -> ApiResult<Vec<TellerInstitution>> {
-        let url = format!("https://api.{}.teller.io/institutions", self.environment);
-        
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        access_token: &str,
+        query: &[(&str, String)],
+    ) -> ProviderResult<T> {
         let response = self.client
-            .get(&url)
-            .bearer_auth(&self.api_key)
+            .request(method, format!("{}{}", self.base_url, path))
+            .header("Teller-Account-Token", access_token)
+            .query(query)
             .send()
             .await?;
 
-        let data = response.json().await?;
-        Ok(data)
+        let status = response.status();
+        if !status.is_success() {
+            let message = match response.json::<TellerErrorResponse>().await {
+                Ok(body) => format!("{}: {}", body.error.code, body.error.message),
+                Err(_) => format!("Teller request to {} failed with {}", path, status),
+            };
+            return Err(map_error(status, message));
+        }
+
+        Ok(response.json().await?)
     }
 
-    pub async fn get_accounts(&self, access_token: &str) -> ApiResult<Vec<TellerAccount>> {
-        let url = format!("https://api.{}.teller.io/accounts", self.environment);
-        
-        let response = self.client
-            .get(&url)
-            .bearer_auth(&self.api_key)
-            .header("Teller-Account-Token", access_token)
-            .send()
-            .await?;
+    pub async fn get_institutions(&self) -> ProviderResult<Vec<TellerInstitution>> {
+        // The institution list is public and ignores the account token
+        self.request(Method::GET, "/institutions", "", &[]).await
+    }
 
-        let data = response.json().await?;
-        Ok(data)
+    pub async fn get_accounts(&self, access_token: &str) -> ProviderResult<Vec<TellerAccount>> {
+        self.request(Method::GET, "/accounts", access_token, &[]).await
     }
 
-    pub async fn get_transactions(
+    pub async fn get_account(&self, access_token: &str, account_id: &str) -> ProviderResult<TellerAccount> {
+        self.request(Method::GET, &format!("/accounts/{}", account_id), access_token, &[]).await
+    }
+
+    pub async fn get_account_balances(
         &self,
         access_token: &str,
         account_id: &str,
-        from_id: Option<&str>,
-    ) -> ApiResult<Vec<TellerTransaction>> {
-        let url = format!(
-            "https://api.{}.teller.io/accounts/{}/transactions",
-            self.environment,
-            account_id
-        );
-        
-        let mut request = self.client
-            .get(&url)
-            .bearer_auth(&self.api_key)
-            .header("Teller-Account-Token", access_token);
+    ) -> ProviderResult<TellerBalances> {
+        self.request(
+            Method::GET,
+            &format!("/accounts/{}/balances", account_id),
+            access_token,
+            &[],
+        )
+        .await
+    }
 
+    /// Fetches up to `count` transactions, newest first, continuing after
+    /// `from_id` when given.
+    pub async fn get_transactions_page(
+        &self,
+        access_token: &str,
+        account_id: &str,
+        from_id: Option<&str>,
+        count: usize,
+    ) -> ProviderResult<Vec<TellerTransaction>> {
+        let mut query = vec![("count", count.to_string())];
         if let Some(from_id) = from_id {
-            request = request.query(&[("from_id", from_id)]);
+            query.push(("from_id", from_id.to_string()));
+        }
+
+        self.request(
+            Method::GET,
+            &format!("/accounts/{}/transactions", account_id),
+            access_token,
+            &query,
+        )
+        .await
+    }
+
+    /// Teller returns transactions newest first. Each page continues from the
+    /// last id of the previous one, and a short page means the history is
+    /// exhausted.
+    pub async fn get_transactions(
+        &self,
+        access_token: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<TellerTransaction>> {
+        let mut transactions: Vec<TellerTransaction> = Vec::new();
+
+        loop {
+            let from_id = transactions.last().map(|last| last.id.clone());
+            let page = self
+                .get_transactions_page(access_token, account_id, from_id.as_deref(), TRANSACTIONS_PAGE_SIZE)
+                .await?;
+            let fetched = page.len();
+            transactions.extend(page);
+
+            if fetched < TRANSACTIONS_PAGE_SIZE {
+                return Ok(transactions);
+            }
+        }
+    }
+
+    pub async fn delete_account(&self, access_token: &str, account_id: &str) -> ProviderResult<()> {
+        let response = self.client
+            .delete(format!("{}/accounts/{}", self.base_url, account_id))
+            .header("Teller-Account-Token", access_token)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(map_error(status, format!("Failed to delete Teller account {}", account_id)));
         }
 
-        let response = request.send().await?;
-        let data = response.json().await?;
-        Ok(data)
+        Ok(())
+    }
+}
+
+fn read_pem(path: &str) -> ProviderResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| ProviderError::Configuration(format!("Failed to read {}: {}", path, e)))
+}
+
+fn map_error(status: StatusCode, message: String) -> ProviderError {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ProviderError::Unauthorized(message),
+        StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+        StatusCode::BAD_REQUEST => ProviderError::BadRequest(message),
+        StatusCode::TOO_MANY_REQUESTS => ProviderError::RateLimited(message),
+        status if status.is_server_error() => ProviderError::Unavailable(message),
+        _ => ProviderError::Other(message),
     }
 }
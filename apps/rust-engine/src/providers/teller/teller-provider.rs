@@ -1,218 +1,384 @@
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-
-use crate::{
-    providers::{interface::Provider, types::*},
-    utils::{ApiError, ApiResult, retry, RetryConfig},
+use std::sync::Arc;
+
+use crate::providers::{
+    types::{
+        Account, Balance, Capabilities, ConnectionState, ConnectionStatus, DeleteConnectionRequest,
+        ExchangeTokenRequest, GetAccountBalanceRequest, GetAccountsRequest, GetConnectionStatusRequest,
+        GetInstitutionsRequest, GetTransactionsRequest, Institution, RefreshTokenRequest, TokenResponse,
+        TransactionPage,
+    },
+    Provider, ProviderError, ProviderResult,
 };
+use crate::utils::config::Config;
+
+use super::teller_api::TellerApi;
+use super::transform::{transform_account, transform_balance, transform_institution, transform_transaction};
+
+/// Error codes Teller uses when an enrollment needs the user to reconnect.
+const DISCONNECTED_ERROR_PREFIX: &str = "enrollment.disconnected";
 
 pub struct TellerProvider {
-    client: Client,
-    api_key: String,
-    environment: String,
+    api: TellerApi,
 }
 
 impl TellerProvider {
-    pub fn new(api_key: String, environment: String) -> Self {
-        Self {
-            client: Client::new(),
-            api_key,
-            environment: environment.to_lowercase(),
+    pub fn new(config: Arc<Config>) -> ProviderResult<Self> {
+        Ok(Self::from_api(TellerApi::new(&config)?))
+    }
+
+    pub fn from_api(api: TellerApi) -> Self {
+        Self { api }
+    }
+}
+
+#[async_trait]
+impl Provider for TellerProvider {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            accounts: true,
+            balances: true,
+            transactions: true,
+            pending_transactions: true,
+            transaction_sync: false,
+            date_range: false,
+            stable_transaction_ids: true,
+            token_refresh: false,
+            identity: false,
+            institutions: true,
+            delete_connection: true,
         }
     }
 
-    fn api_url(&self) -> String {
-        match self.environment.as_str() {
-            "sandbox" => "https://api.teller.io/sandbox".to_string(),
-            "development" => "https://api.teller.io/development".to_string(),
-            _ => "https://api.teller.io".to_string(),
+    /// Teller Connect hands the client an enrollment access token directly,
+    /// so there is no code to exchange. The token doesn't expire.
+    async fn exchange_token(&self, request: ExchangeTokenRequest) -> ProviderResult<TokenResponse> {
+        Ok(TokenResponse {
+            access_token: request.code,
+            refresh_token: None,
+            expires_at: None,
+        })
+    }
+
+    async fn refresh_token(&self, _request: RefreshTokenRequest) -> ProviderResult<TokenResponse> {
+        Err(ProviderError::Unsupported(
+            "Teller access tokens don't expire and can't be refreshed".to_string(),
+        ))
+    }
+
+    async fn get_accounts(&self, request: GetAccountsRequest) -> ProviderResult<Vec<Account>> {
+        let access_token = &request.access_token;
+        let accounts = self.api.get_accounts(access_token).await?;
+        let mut result = Vec::with_capacity(accounts.len());
+
+        for account in &accounts {
+            // Closed accounts no longer expose balances
+            let balances = if account.status == "open" {
+                Some(self.api.get_account_balances(access_token, &account.id).await?)
+            } else {
+                None
+            };
+            result.push(transform_account(account, balances.as_ref()));
         }
+
+        Ok(result)
     }
 
-    async fn request<T: for<'de> Deserialize<'de>>(
-        &self,
-        method: reqwest::Method,
-        path: &str,
-        body: Option<serde_json::Value>,
-    ) -> ApiResult<T> {
-        let url = format!("{}{}", self.api_url(), path);
-        let config = RetryConfig::default();
+    async fn get_account_balance(&self, request: GetAccountBalanceRequest) -> ProviderResult<Balance> {
+        let account = self.api.get_account(&request.access_token, &request.account_id).await?;
+        let balances = self
+            .api
+            .get_account_balances(&request.access_token, &request.account_id)
+            .await?;
+
+        Ok(transform_balance(Some(&balances), &account.currency))
+    }
 
-        retry(|| async {
-            let mut req = self.client
-                .request(method.clone(), &url)
-                .header("Authorization", format!("Basic {}", self.api_key))
-                .header("Content-Type", "application/json");
+    /// Teller has no date filters, so the range is applied to what comes
+    /// back. The cursor is the id of the last transaction on the previous
+    /// page.
+    async fn get_transactions(&self, request: GetTransactionsRequest) -> ProviderResult<TransactionPage> {
+        let account = self.api.get_account(&request.access_token, &request.account_id).await?;
+        let in_range = |date| request.date_range.contains(date);
 
-            if let Some(body) = &body {
-                req = req.json(body);
-            }
+        let Some(limit) = request.pagination.limit else {
+            let transactions = self.api.get_transactions(&request.access_token, &request.account_id).await?;
+            return Ok(TransactionPage {
+                transactions: transactions
+                    .iter()
+                    .filter(|transaction| in_range(transaction.date))
+                    .map(|transaction| transform_transaction(transaction, &account.currency))
+                    .collect(),
+                next_cursor: None,
+            });
+        };
 
-            let response = req.send().await?;
+        let page = self
+            .api
+            .get_transactions_page(
+                &request.access_token,
+                &request.account_id,
+                request.pagination.cursor.as_deref(),
+                limit as usize,
+            )
+            .await?;
+        // Transactions come newest first, so nothing after a page that
+        // reaches past the start of the range can be in it
+        let past_start = match (request.date_range.start_date, page.last()) {
+            (Some(start_date), Some(last)) => last.date < start_date,
+            _ => false,
+        };
+        let next_cursor = match page.last() {
+            Some(last) if page.len() == limit as usize && !past_start => Some(last.id.clone()),
+            _ => None,
+        };
 
-            if !response.status().is_success() {
-                let error: TellerError = response.json().await?;
-                return Err(ApiError::Provider(error.message));
+        Ok(TransactionPage {
+            transactions: page
+                .iter()
+                .filter(|transaction| in_range(transaction.date))
+                .map(|transaction| transform_transaction(transaction, &account.currency))
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    async fn get_institutions(&self, request: GetInstitutionsRequest) -> ProviderResult<Vec<Institution>> {
+        let institutions = self.api.get_institutions().await?;
+        Ok(institutions
+            .iter()
+            .map(transform_institution)
+            .filter(|institution| request.matches(institution))
+            .collect())
+    }
+
+    async fn get_connection_status(&self, request: GetConnectionStatusRequest) -> ProviderResult<ConnectionStatus> {
+        let status = match self.api.get_accounts(&request.access_token).await {
+            Ok(_) => ConnectionState::Connected,
+            Err(ProviderError::Unauthorized(_)) => ConnectionState::Disconnected,
+            Err(ProviderError::NotFound(message)) | Err(ProviderError::Other(message))
+                if message.starts_with(DISCONNECTED_ERROR_PREFIX) =>
+            {
+                ConnectionState::Disconnected
             }
+            Err(error) => return Err(error),
+        };
 
-            Ok(response.json::<T>().await?)
-        }, config).await
+        Ok(ConnectionStatus { status })
+    }
+
+    /// Removing every account revokes the whole enrollment.
+    async fn delete_connection(&self, request: DeleteConnectionRequest) -> ProviderResult<()> {
+        for account in self.api.get_accounts(&request.access_token).await? {
+            self.api.delete_account(&request.access_token, &account.id).await?;
+        }
+        Ok(())
     }
 }
 
-#[async_trait]
-impl Provider for TellerProvider {
-    async fn exchange_token(
-        &self,
-        code: &str,
-        _redirect_uri: &str,
-    ) -> ApiResult<TokenResponse> {
-        let response: TellerTokenResponse = self.request(
-            reqwest::Method::POST,
-            "/auth/exchange",
-            Some(serde_json::json!({
-                "enrollment_id": code,
-            })),
-        ).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::types::{DateRange, Pagination, TransactionStatus};
+    use chrono::NaiveDate;
+    use mockito::{Matcher, Server};
+    use rust_decimal_macros::dec;
+    use serde_json::json;
 
-        Ok(TokenResponse {
-            access_token: response.access_token,
-            refresh_token: Some(response.access_token.clone()), // Teller uses same token
-            expires_in: 7200, // 2 hours
-            expires_at: Utc::now() + chrono::Duration::hours(2),
+    fn provider(server: &Server) -> TellerProvider {
+        TellerProvider::from_api(TellerApi::with_base_url(&server.url()))
+    }
+
+    fn transactions_request(date_range: DateRange, pagination: Pagination) -> GetTransactionsRequest {
+        GetTransactionsRequest {
+            access_token: "token_1".to_string(),
+            account_id: "acc_1".to_string(),
+            date_range,
+            pagination,
+        }
+    }
+
+    fn account() -> serde_json::Value {
+        json!({
+            "id": "acc_1",
+            "currency": "USD",
+            "enrollment_id": "enr_1",
+            "institution": { "id": "chase", "name": "Chase" },
+            "last_four": "1234",
+            "links": { "balances": null, "transactions": null },
+            "name": "Checking",
+            "type": "depository",
+            "subtype": "checking",
+            "status": "open"
         })
     }
 
-    async fn refresh_token(&self, token: &str) -> ApiResult<TokenResponse> {
-        // Teller tokens don't expire, but we'll implement refresh for consistency
-        Ok(TokenResponse {
-            access_token: token.to_string(),
-            refresh_token: Some(token.to_string()),
-            expires_in: 7200,
-            expires_at: Utc::now() + chrono::Duration::hours(2),
+    fn transaction(id: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "account_id": "acc_1",
+            "amount": "-4.50",
+            "date": "2024-03-02",
+            "description": "Coffee",
+            "details": { "category": "dining", "counterparty": null, "processing_status": "complete" },
+            "running_balance": "95.50",
+            "status": "posted",
+            "type": "card_payment"
         })
     }
 
-    async fn get_accounts(&self, access_token: &str) -> ApiResult<Vec<Account>> {
-        let accounts: Vec<TellerAccount> = self.request(
-            reqwest::Method::GET,
-            "/accounts",
-            None,
-        ).await?;
-
-        Ok(accounts.into_iter().map(|acc| Account {
-            id: format!("tel_{}", acc.id),
-            connection_id: access_token.to_string(),
-            name: acc.name,
-            account_type: acc.r#type,
-            currency: acc.currency.unwrap_or_else(|| "USD".to_string()),
-            balance: Balance {
-                current: acc.balance.current,
-                available: Some(acc.balance.available),
-                limit: acc.balance.limit,
-            },
-            last_sync: Some(Utc::now()),
-        }).collect())
-    }
-
-    async fn get_transactions(
-        &self,
-        access_token: &str,
-        account_id: &str,
-        from_date: Option<DateTime<Utc>>,
-        to_date: Option<DateTime<Utc>>,
-    ) -> ApiResult<Vec<Transaction>> {
-        let account_id = account_id.strip_prefix("tel_").unwrap_or(account_id);
-        let transactions: Vec<TellerTransaction> = self.request(
-            reqwest::Method::GET,
-            &format!("/accounts/{}/transactions", account_id),
-            None,
-        ).await?;
-
-        Ok(transactions.into_iter()
-            .filter(|tx| {
-                let date = DateTime::parse_from_rfc3339(&tx.date)
-                    .map(|d| d.with_timezone(&Utc))
-                    .unwrap_or_else(|_| Utc::now());
-                
-                from_date.map_or(true, |from| date >= from) &&
-                to_date.map_or(true, |to| date <= to)
-            })
-            .map(|tx| Transaction {
-                id: format!("tel_{}", tx.id),
-                account_id: format!("tel_{}", tx.account_id),
-                date: DateTime::parse_from_rfc3339(&tx.date)
-                    .map(|d| d.with_timezone(&Utc))
-                    .unwrap_or_else(|_| Utc::now()),
-                description: Some(tx.description),
-                amount: tx.amount,
-                currency: tx.currency.unwrap_or_else(|| "USD".to_string()),
-                category: tx.category,
-                merchant: tx.merchant.map(|m| m.name),
-                pending: tx.status == "pending",
+    #[tokio::test]
+    async fn test_get_accounts_uses_account_token() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/accounts")
+            .match_header("Teller-Account-Token", "token_1")
+            .with_body(json!([account()]).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/accounts/acc_1/balances")
+            .match_header("Teller-Account-Token", "token_1")
+            .with_body(json!({ "account_id": "acc_1", "ledger": "100.25", "available": "90.00" }).to_string())
+            .create_async()
+            .await;
+
+        let accounts = provider(&server)
+            .get_accounts(GetAccountsRequest {
+                access_token: "token_1".to_string(),
             })
-            .collect())
+            .await
+            .unwrap();
+
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].institution_id, "chase");
+        assert_eq!(accounts[0].balance.amount, dec!(100.25));
     }
 
-    async fn get_institution(&self, institution_id: &str) -> ApiResult<Institution> {
-        Ok(Institution {
-            id: format!("tel_{}", institution_id),
-            name: "Teller Institution".to_string(), // Teller doesn't provide institution details
-            country: "US".to_string(),
-            provider: "teller".to_string(),
-            logo_url: None,
-            primary_color: None,
-            url: None,
-            oauth_support: true,
-            products: vec!["accounts".to_string(), "transactions".to_string()],
-            last_update: Utc::now(),
-        })
+    #[tokio::test]
+    async fn test_get_transactions_paginates_with_from_id() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/accounts/acc_1")
+            .with_body(account().to_string())
+            .create_async()
+            .await;
+        let full_page: Vec<_> = (0..250).map(|i| transaction(&format!("tx_{}", i))).collect();
+        server
+            .mock("GET", "/accounts/acc_1/transactions")
+            .match_query(Matcher::UrlEncoded("from_id".into(), "tx_249".into()))
+            .with_body(json!([transaction("tx_250")]).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/accounts/acc_1/transactions")
+            .match_query(Matcher::UrlEncoded("count".into(), "250".into()))
+            .with_body(json!(full_page).to_string())
+            .create_async()
+            .await;
+
+        let transactions = provider(&server)
+            .get_transactions(transactions_request(DateRange::default(), Pagination::default()))
+            .await
+            .unwrap()
+            .transactions;
+
+        assert_eq!(transactions.len(), 251);
+        assert_eq!(transactions[250].id, "tx_250");
+        assert_eq!(transactions[250].amount, dec!(-4.5));
+        assert_eq!(transactions[250].balance, Some(dec!(95.5)));
+        assert_eq!(transactions[250].status, TransactionStatus::Posted);
     }
-}
 
-#[derive(Debug, Deserialize)]
-struct TellerError {
-    message: String,
-}
+    #[tokio::test]
+    async fn test_get_transactions_page_stops_at_start_date() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/accounts/acc_1")
+            .with_body(account().to_string())
+            .create_async()
+            .await;
+        let mut older = transaction("tx_2");
+        older["date"] = json!("2024-02-20");
+        server
+            .mock("GET", "/accounts/acc_1/transactions")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("count".into(), "2".into()),
+                Matcher::UrlEncoded("from_id".into(), "tx_0".into()),
+            ]))
+            .with_body(json!([transaction("tx_1"), older]).to_string())
+            .create_async()
+            .await;
 
-#[derive(Debug, Deserialize)]
-struct TellerTokenResponse {
-    access_token: String,
-}
+        let page = provider(&server)
+            .get_transactions(transactions_request(
+                DateRange {
+                    start_date: NaiveDate::from_ymd_opt(2024, 3, 1),
+                    end_date: None,
+                },
+                Pagination {
+                    cursor: Some("tx_0".to_string()),
+                    limit: Some(2),
+                },
+            ))
+            .await
+            .unwrap();
 
-#[derive(Debug, Deserialize)]
-struct TellerAccount {
-    id: String,
-    name: String,
-    r#type: String,
-    currency: Option<String>,
-    balance: TellerBalance,
-}
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].id, "tx_1");
+        assert_eq!(page.next_cursor, None);
+    }
 
-#[derive(Debug, Deserialize)]
-struct TellerBalance {
-    current: f64,
-    available: f64,
-    limit: Option<f64>,
-}
+    #[tokio::test]
+    async fn test_connection_status_disconnected_enrollment() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/accounts")
+            .with_status(404)
+            .with_body(
+                json!({
+                    "error": {
+                        "code": "enrollment.disconnected.user_action.mfa_required",
+                        "message": "The enrollment requires the user to complete MFA"
+                    }
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
 
-#[derive(Debug, Deserialize)]
-struct TellerTransaction {
-    id: String,
-    account_id: String,
-    date: String,
-    description: String,
-    amount: f64,
-    currency: Option<String>,
-    status: String,
-    category: Option<String>,
-    merchant: Option<TellerMerchant>,
-}
+        let status = provider(&server)
+            .get_connection_status(GetConnectionStatusRequest {
+                access_token: "token_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(status.status, ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn test_delete_connection_removes_every_account() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/accounts")
+            .with_body(json!([account()]).to_string())
+            .create_async()
+            .await;
+        let delete = server
+            .mock("DELETE", "/accounts/acc_1")
+            .match_header("Teller-Account-Token", "token_1")
+            .with_status(204)
+            .create_async()
+            .await;
 
-#[derive(Debug, Deserialize)]
-struct TellerMerchant {
-    name: String,
+        provider(&server)
+            .delete_connection(DeleteConnectionRequest {
+                access_token: "token_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        delete.assert_async().await;
+    }
 }
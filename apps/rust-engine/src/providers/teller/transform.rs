@@ -1,94 +1,78 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{TimeZone, Utc};
 
-use crate::providers::types::*;
+use crate::providers::types::{Account, Balance, Institution, Transaction};
+use crate::utils::{get_institution_logo, money};
 
-pub fn transform_account(account: TellerAccount) -> Account {
+use super::types::*;
+use super::utils::{get_account_type, get_transaction_status, parse_amount};
+
+pub fn transform_account(account: &TellerAccount, balances: Option<&TellerBalances>) -> Account {
     Account {
-        id: format!("tel_{}", account.id),
-        connection_id: account.connection_id,
-        name: account.name,
-        account_type: transform_account_type(account.r#type),
-        currency: account.currency.unwrap_or_else(|| "USD".to_string()),
-        balance: Balance {
-            current: account.balance.current,
-            available: Some(account.balance.available),
-            limit: account.balance.limit,
-        },
+        id: account.id.clone(),
+        name: account.name.clone(),
+        account_type: get_account_type(account),
+        balance: transform_balance(balances, &account.currency),
+        currency: account.currency.clone(),
+        institution_id: account.institution.id.clone(),
         last_sync: Some(Utc::now()),
     }
 }
 
-pub fn transform_transaction(transaction: TellerTransaction) -> Transaction {
+/// Prefers the ledger balance, which includes pending transactions, and falls
+/// back to the available balance for accounts that only report that.
+pub fn transform_balance(balances: Option<&TellerBalances>, currency: &str) -> Balance {
+    let amount = balances
+        .and_then(|b| b.ledger.as_deref().or(b.available.as_deref()))
+        .and_then(parse_amount)
+        .unwrap_or_default();
+
+    let available = balances
+        .and_then(|b| b.available.as_deref())
+        .and_then(parse_amount)
+        .map(|available| money::round(available, currency));
+
+    Balance {
+        amount: money::round(amount, currency),
+        currency: currency.to_string(),
+        available,
+    }
+}
+
+pub fn transform_transaction(transaction: &TellerTransaction, currency: &str) -> Transaction {
     Transaction {
-        id: format!("tel_{}", transaction.id),
-        account_id: format!("tel_{}", transaction.account_id),
-        date: DateTime::parse_from_rfc3339(&transaction.date)
-            .map(|d| d.with_timezone(&Utc))
-            .unwrap_or_else(|_| Utc::now()),
-        description: Some(transaction.description),
-        amount: transaction.amount,
-        currency: transaction.currency.unwrap_or_else(|| "USD".to_string()),
-        category: transaction.category,
-        merchant: transaction.merchant.map(|m| m.name),
-        pending: transaction.status == "pending",
+        id: transaction.id.clone(),
+        account_id: transaction.account_id.clone(),
+        amount: money::round(parse_amount(&transaction.amount).unwrap_or_default(), currency),
+        currency: currency.to_string(),
+        date: Utc.from_utc_datetime(&transaction.date.and_hms_opt(0, 0, 0).unwrap_or_default()),
+        description: transaction.description.clone(),
+        merchant: transaction
+            .details
+            .counterparty
+            .as_ref()
+            .and_then(|counterparty| counterparty.name.clone()),
+        category: transaction.details.category.clone(),
+        status: get_transaction_status(transaction),
+        balance: transaction
+            .running_balance
+            .as_deref()
+            .and_then(parse_amount)
+            .map(|balance| money::round(balance, currency)),
+        currency_rate: None,
+        currency_source: None,
     }
 }
 
-pub fn transform_institution(institution_id: &str) -> Institution {
+pub fn transform_institution(institution: &TellerInstitution) -> Institution {
     Institution {
-        id: format!("tel_{}", institution_id),
-        name: "Teller Institution".to_string(),
+        id: institution.id.clone(),
+        name: institution.name.clone(),
+        logo_url: get_institution_logo(&institution.id).map(|logo| logo.to_string()),
+        website: None,
         country: "US".to_string(),
-        provider: "teller".to_string(),
-        logo_url: None,
-        primary_color: None,
-        url: None,
-        oauth_support: true,
-        products: vec!["accounts".to_string(), "transactions".to_string()],
-        last_update: Utc::now(),
     }
 }
 
-fn transform_account_type(account_type: String) -> String {
-    match account_type.as_str() {
-        "depository" => "checking",
-        "credit" => "credit",
-        _ => "other",
-    }.to_string()
-}
-
-#[derive(Debug, Deserialize)]
-pub struct TellerAccount {
-    pub id: String,
-    pub connection_id: String,
-    pub name: String,
-    pub r#type: String,
-    pub currency: Option<String>,
-    pub balance: TellerBalance,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct TellerBalance {
-    pub current: f64,
-    pub available: f64,
-    pub limit: Option<f64>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct TellerTransaction {
-    pub id: String,
-    pub account_id: String,
-    pub date: String,
-    pub description: String,
-    pub amount: f64,
-    pub currency: Option<String>,
-    pub status: String,
-    pub category: Option<String>,
-    pub merchant: Option<TellerMerchant>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct TellerMerchant {
-    pub name: String,
-}
+#[cfg(test)]
+#[path = "transform.test.rs"]
+mod tests;
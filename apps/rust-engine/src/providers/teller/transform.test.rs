@@ -1,61 +1,98 @@
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use chrono::{TimeZone, Utc};
-
-    #[test]
-    fn test_transform_account() {
-        let teller_account = TellerAccount {
-            id: "acc123".to_string(),
-            currency: "USD".to_string(),
-            enrollment_id: "enr123".to_string(),
-            institution: TellerInstitution {
-                id: "inst123".to_string(),
-                name: "Test Bank".to_string(),
-                capabilities: vec!["accounts".to_string()],
-            },
-            last_four: "1234".to_string(),
-            links: TellerLinks {
-                balances: "https://api.teller.io/accounts/acc123/balances".to_string(),
-                transactions: "https://api.teller.io/accounts/acc123/transactions".to_string(),
-            },
-            name: "Checking Account".to_string(),
-            r#type: "depository".to_string(),
-            subtype: Some("checking".to_string()),
-            status: "open".to_string(),
-        };
-
-        let account = transform_account(&teller_account);
-        assert_eq!(account.id, "acc123");
-        assert_eq!(account.name, "Checking Account");
-        assert_eq!(account.account_type, "checking");
-        assert_eq!(account.currency, "USD");
+use super::*;
+use chrono::NaiveDate;
+use rust_decimal_macros::dec;
+
+use crate::providers::types::{AccountType, TransactionStatus};
+
+fn teller_account() -> TellerAccount {
+    TellerAccount {
+        id: "acc123".to_string(),
+        currency: "USD".to_string(),
+        enrollment_id: "enr123".to_string(),
+        institution: TellerInstitution {
+            id: "chase".to_string(),
+            name: "Chase".to_string(),
+            capabilities: vec!["detail".to_string()],
+        },
+        last_four: "1234".to_string(),
+        links: TellerLinks {
+            balances: Some("https://api.teller.io/accounts/acc123/balances".to_string()),
+            transactions: Some("https://api.teller.io/accounts/acc123/transactions".to_string()),
+        },
+        name: "Checking Account".to_string(),
+        r#type: "depository".to_string(),
+        subtype: Some("checking".to_string()),
+        status: "open".to_string(),
     }
+}
 
-    #[test]
-    fn test_transform_transaction() {
-        let teller_transaction = TellerTransaction {
-            id: "tx123".to_string(),
-            account_id: "acc123".to_string(),
-            amount: "50.00".to_string(),
-            date: Utc.ymd(2023, 1, 1).and_hms(0, 0, 0),
-            description: "Coffee Shop".to_string(),
-            details: TellerTransactionDetails {
-                category: Some("food_and_drink".to_string()),
-                counterparty: None,
-                processing_status: "complete".to_string(),
-            },
-            running_balance: Some("1000.00".to_string()),
-            status: "posted".to_string(),
-            r#type: "pos".to_string(),
-        };
-
-        let transaction = transform_transaction(&teller_transaction);
-        assert_eq!(transaction.id, "tx123");
-        assert_eq!(transaction.account_id, "acc123");
-        assert_eq!(transaction.amount, 50.0);
-        assert_eq!(transaction.currency, "USD");
-        assert_eq!(transaction.description, "Coffee Shop");
-        assert_eq!(transaction.category, Some("food_and_drink".to_string()));
+fn teller_transaction() -> TellerTransaction {
+    TellerTransaction {
+        id: "tx123".to_string(),
+        account_id: "acc123".to_string(),
+        amount: "-50.25".to_string(),
+        date: NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
+        description: "Coffee Shop".to_string(),
+        details: TellerTransactionDetails {
+            category: Some("dining".to_string()),
+            counterparty: Some(TellerCounterparty {
+                name: Some("BLUE BOTTLE".to_string()),
+                r#type: Some("organization".to_string()),
+            }),
+            processing_status: "complete".to_string(),
+        },
+        running_balance: Some("1000.10".to_string()),
+        status: "posted".to_string(),
+        r#type: "card_payment".to_string(),
     }
 }
+
+#[test]
+fn test_transform_account() {
+    let balances = TellerBalances {
+        account_id: "acc123".to_string(),
+        ledger: Some("1250.75".to_string()),
+        available: Some("1200.00".to_string()),
+    };
+
+    let account = transform_account(&teller_account(), Some(&balances));
+    assert_eq!(account.id, "acc123");
+    assert_eq!(account.name, "Checking Account");
+    assert_eq!(account.account_type, AccountType::Checking);
+    assert_eq!(account.currency, "USD");
+    assert_eq!(account.institution_id, "chase");
+    assert_eq!(account.balance.amount, dec!(1250.75));
+    assert_eq!(account.balance.available, Some(dec!(1200)));
+}
+
+#[test]
+fn test_transform_account_without_balances() {
+    let account = transform_account(&teller_account(), None);
+    assert_eq!(account.balance.amount, dec!(0));
+    assert_eq!(account.balance.available, None);
+}
+
+#[test]
+fn test_transform_transaction() {
+    let transaction = transform_transaction(&teller_transaction(), "USD");
+    assert_eq!(transaction.id, "tx123");
+    assert_eq!(transaction.account_id, "acc123");
+    assert_eq!(transaction.amount, dec!(-50.25));
+    assert_eq!(transaction.currency, "USD");
+    assert_eq!(transaction.description, "Coffee Shop");
+    assert_eq!(transaction.merchant, Some("BLUE BOTTLE".to_string()));
+    assert_eq!(transaction.category, Some("dining".to_string()));
+    assert_eq!(transaction.status, TransactionStatus::Posted);
+    assert_eq!(transaction.balance, Some(dec!(1000.10)));
+}
+
+#[test]
+fn test_transform_pending_transaction_without_running_balance() {
+    let mut teller_transaction = teller_transaction();
+    teller_transaction.status = "pending".to_string();
+    teller_transaction.running_balance = None;
+
+    let transaction = transform_transaction(&teller_transaction, "USD");
+    assert_eq!(transaction.status, TransactionStatus::Pending);
+    assert_eq!(transaction.balance, None);
+}
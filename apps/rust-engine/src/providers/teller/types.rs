@@ -1,14 +1,15 @@
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
+use chrono::NaiveDate;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TellerInstitution {
     pub id: String,
     pub name: String,
+    #[serde(default)]
     pub capabilities: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TellerAccount {
     pub id: String,
     pub currency: String,
@@ -22,18 +23,25 @@ pub struct TellerAccount {
     pub status: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TellerLinks {
-    pub balances: String,
-    pub transactions: String,
+    pub balances: Option<String>,
+    pub transactions: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TellerBalances {
+    pub account_id: String,
+    pub ledger: Option<String>,
+    pub available: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TellerTransaction {
     pub id: String,
     pub account_id: String,
     pub amount: String,
-    pub date: DateTime<Utc>,
+    pub date: NaiveDate,
     pub description: String,
     pub details: TellerTransactionDetails,
     pub running_balance: Option<String>,
@@ -41,9 +49,26 @@ pub struct TellerTransaction {
     pub r#type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TellerTransactionDetails {
     pub category: Option<String>,
     pub counterparty: Option<TellerCounterparty>,
     pub processing_status: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TellerCounterparty {
+    pub name: Option<String>,
+    pub r#type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TellerErrorResponse {
+    pub error: TellerError,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TellerError {
+    pub code: String,
+    pub message: String,
+}
@@ -1,13 +1,20 @@
+use rust_decimal::Decimal;
+
+use crate::providers::types::{AccountType, TransactionStatus};
+use crate::utils::money;
+
 use super::types::*;
 
-pub fn get_account_type(account: &TellerAccount) -> String {
-    match account.r#type.as_str() {
-        "depository" => "checking".to_string(),
-        "credit" => "credit".to_string(),
-        _ => "other".to_string(),
+pub fn get_account_type(account: &TellerAccount) -> AccountType {
+    match (account.r#type.as_str(), account.subtype.as_deref()) {
+        ("depository", Some("savings")) | ("depository", Some("money_market")) => AccountType::Savings,
+        ("depository", _) => AccountType::Checking,
+        ("credit", _) => AccountType::Credit,
+        _ => AccountType::Other,
     }
 }
 
+#[allow(dead_code)]
 pub fn get_transaction_type(transaction: &TellerTransaction) -> String {
     match transaction.r#type.as_str() {
         "ach" => "transfer".to_string(),
@@ -16,3 +23,15 @@ pub fn get_transaction_type(transaction: &TellerTransaction) -> String {
         _ => "other".to_string(),
     }
 }
+
+pub fn get_transaction_status(transaction: &TellerTransaction) -> TransactionStatus {
+    match transaction.status.as_str() {
+        "pending" => TransactionStatus::Pending,
+        _ => TransactionStatus::Posted,
+    }
+}
+
+/// Teller sends money as decimal strings; anything unparseable is treated as missing.
+pub fn parse_amount(amount: &str) -> Option<Decimal> {
+    money::parse(amount)
+}
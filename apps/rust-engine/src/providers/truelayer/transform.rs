@@ -0,0 +1,89 @@
+use crate::providers::types::{
+    Account, AccountType, Balance, Institution, Transaction, TransactionStatus,
+};
+use crate::utils::money;
+use chrono::Utc;
+use rust_decimal::Decimal;
+
+use super::types::*;
+use super::utils::{get_account_type, get_category};
+
+pub fn transform_account(account: &TrueLayerAccount, balance: Option<&TrueLayerBalance>) -> Account {
+    Account {
+        id: account.account_id.clone(),
+        name: account.display_name.clone(),
+        account_type: get_account_type(&account.account_type),
+        balance: transform_balance(balance, &account.currency),
+        currency: account.currency.clone(),
+        institution_id: account.provider.provider_id.clone(),
+        last_sync: Some(Utc::now()),
+    }
+}
+
+pub fn transform_card(card: &TrueLayerCard, balance: Option<&TrueLayerBalance>) -> Account {
+    Account {
+        id: card.account_id.clone(),
+        name: card.display_name.clone(),
+        account_type: AccountType::Credit,
+        balance: transform_balance(balance, &card.currency),
+        currency: card.currency.clone(),
+        institution_id: card.provider.provider_id.clone(),
+        last_sync: Some(Utc::now()),
+    }
+}
+
+pub fn transform_balance(balance: Option<&TrueLayerBalance>, currency: &str) -> Balance {
+    match balance {
+        Some(balance) => Balance {
+            amount: money::round(balance.current, &balance.currency),
+            currency: balance.currency.clone(),
+            available: balance.available.map(|available| money::round(available, &balance.currency)),
+        },
+        None => Balance {
+            amount: money::round(Decimal::ZERO, currency),
+            currency: currency.to_string(),
+            available: None,
+        },
+    }
+}
+
+pub fn transform_transaction(
+    transaction: &TrueLayerTransaction,
+    account_id: &str,
+    status: TransactionStatus,
+) -> Transaction {
+    Transaction {
+        id: transaction.transaction_id.clone(),
+        account_id: account_id.to_string(),
+        amount: money::round(transaction.amount, &transaction.currency),
+        currency: transaction.currency.clone(),
+        date: transaction.timestamp,
+        description: transaction.description.clone(),
+        merchant: transaction.merchant_name.clone(),
+        category: get_category(
+            transaction.transaction_category.as_deref(),
+            &transaction.transaction_classification,
+        ),
+        status,
+        balance: transaction
+            .running_balance
+            .as_ref()
+            .map(|balance| money::round(balance.amount, &balance.currency)),
+        currency_rate: None,
+        currency_source: None,
+    }
+}
+
+pub fn transform_institution(institution: &TrueLayerInstitution) -> Institution {
+    Institution {
+        id: institution.provider_id.clone(),
+        name: institution.display_name.clone(),
+        logo_url: institution.logo_url.clone(),
+        website: None,
+        country: institution.country.clone().unwrap_or_default().to_uppercase(),
+    }
+}
+
+#[cfg(test)]
+#[path = "transform.test.rs"]
+mod tests;
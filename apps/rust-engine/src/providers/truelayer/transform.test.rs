@@ -0,0 +1,79 @@
+use super::*;
+use chrono::TimeZone;
+use rust_decimal_macros::dec;
+
+fn provider_info() -> TrueLayerProviderInfo {
+    TrueLayerProviderInfo {
+        provider_id: "ob-barclays".to_string(),
+        display_name: Some("Barclays".to_string()),
+        logo_uri: None,
+    }
+}
+
+#[test]
+fn test_transform_account() {
+    let account = TrueLayerAccount {
+        account_id: "acc123".to_string(),
+        account_type: "SAVINGS".to_string(),
+        display_name: "Saver".to_string(),
+        currency: "GBP".to_string(),
+        account_number: None,
+        provider: provider_info(),
+        update_timestamp: None,
+    };
+    let balance = TrueLayerBalance {
+        currency: "GBP".to_string(),
+        available: Some(dec!(90)),
+        current: dec!(100.5),
+        overdraft: None,
+        credit_limit: None,
+        update_timestamp: None,
+    };
+
+    let account = transform_account(&account, Some(&balance));
+    assert_eq!(account.id, "acc123");
+    assert_eq!(account.account_type, AccountType::Savings);
+    assert_eq!(account.institution_id, "ob-barclays");
+    assert_eq!(account.balance.amount, dec!(100.5));
+    assert_eq!(account.balance.available, Some(dec!(90)));
+}
+
+#[test]
+fn test_transform_transaction() {
+    let transaction = TrueLayerTransaction {
+        transaction_id: "tx123".to_string(),
+        timestamp: Utc.with_ymd_and_hms(2023, 1, 1, 9, 30, 0).unwrap(),
+        description: "PRET A MANGER".to_string(),
+        amount: dec!(-4.95),
+        currency: "GBP".to_string(),
+        transaction_type: "DEBIT".to_string(),
+        transaction_category: Some("PURCHASE".to_string()),
+        transaction_classification: vec![],
+        merchant_name: Some("Pret A Manger".to_string()),
+        running_balance: None,
+    };
+
+    let transaction = transform_transaction(&transaction, "acc123", TransactionStatus::Pending);
+    assert_eq!(transaction.id, "tx123");
+    assert_eq!(transaction.account_id, "acc123");
+    assert_eq!(transaction.amount, dec!(-4.95));
+    assert_eq!(transaction.merchant, Some("Pret A Manger".to_string()));
+    assert_eq!(transaction.category, Some("purchase".to_string()));
+    assert_eq!(transaction.status, TransactionStatus::Pending);
+    assert_eq!(transaction.balance, None);
+}
+
+#[test]
+fn test_transform_institution() {
+    let institution = TrueLayerInstitution {
+        provider_id: "ob-monzo".to_string(),
+        display_name: "Monzo".to_string(),
+        logo_url: Some("https://truelayer-provider-assets.s3.amazonaws.com/global/logos/monzo.svg".to_string()),
+        country: Some("uk".to_string()),
+        scopes: vec!["accounts".to_string()],
+    };
+
+    let institution = transform_institution(&institution);
+    assert_eq!(institution.id, "ob-monzo");
+    assert_eq!(institution.country, "UK");
+}
@@ -0,0 +1,215 @@
+use chrono::{DateTime, Utc};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::de::{DeserializeOwned, IgnoredAny};
+
+use crate::config::Config;
+use crate::providers::{ProviderError, ProviderResult};
+
+use super::types::*;
+
+/// Permissions requested from the user. `offline_access` is what makes
+/// TrueLayer issue a refresh token.
+const SCOPES: &str = "info accounts balance cards transactions offline_access";
+const PROVIDERS: &str = "uk-ob-all uk-oauth-all";
+
+pub struct TrueLayerApi {
+    client: Client,
+    client_id: String,
+    client_secret: String,
+    auth_url: String,
+    api_url: String,
+}
+
+impl TrueLayerApi {
+    pub fn new(config: &Config) -> Self {
+        let (auth_url, api_url) = match config.truelayer_environment.as_str() {
+            "sandbox" => ("https://auth.truelayer-sandbox.com", "https://api.truelayer-sandbox.com"),
+            _ => ("https://auth.truelayer.com", "https://api.truelayer.com"),
+        };
+
+        Self::with_base_urls(&config.truelayer_client_id, &config.truelayer_secret, auth_url, api_url)
+    }
+
+    pub fn with_base_urls(client_id: &str, client_secret: &str, auth_url: &str, api_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            auth_url: auth_url.trim_end_matches('/').to_string(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// The URL the user visits to pick their bank and grant access.
+    pub fn auth_link(&self, redirect_uri: &str, state: Option<&str>) -> ProviderResult<String> {
+        let mut url = reqwest::Url::parse(&format!("{}/", self.auth_url))
+            .map_err(|e| ProviderError::Configuration(format!("Invalid TrueLayer auth URL: {}", e)))?;
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("scope", SCOPES)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("providers", PROVIDERS);
+        if let Some(state) = state {
+            url.query_pairs_mut().append_pair("state", state);
+        }
+
+        Ok(url.into())
+    }
+
+    pub async fn exchange_code(&self, code: &str, redirect_uri: &str) -> ProviderResult<TrueLayerTokenResponse> {
+        self.token(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+        ])
+        .await
+    }
+
+    pub async fn refresh_token(&self, refresh_token: &str) -> ProviderResult<TrueLayerTokenResponse> {
+        self.token(&[("grant_type", "refresh_token"), ("refresh_token", refresh_token)]).await
+    }
+
+    async fn token(&self, params: &[(&str, &str)]) -> ProviderResult<TrueLayerTokenResponse> {
+        let mut form = vec![("client_id", self.client_id.as_str()), ("client_secret", self.client_secret.as_str())];
+        form.extend_from_slice(params);
+
+        send(self.client.post(format!("{}/connect/token", self.auth_url)).form(&form)).await
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> ProviderResult<Vec<T>> {
+        let response: TrueLayerResponse<T> = send(
+            self.client
+                .get(format!("{}/data/v1{}", self.api_url, path))
+                .bearer_auth(access_token)
+                .query(query),
+        )
+        .await?;
+
+        Ok(response.results)
+    }
+
+    async fn get_one<T: DeserializeOwned>(&self, access_token: &str, path: &str) -> ProviderResult<T> {
+        self.get(access_token, path, &[])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::NotFound(format!("TrueLayer returned no results for {}", path)))
+    }
+
+    pub async fn get_institutions(&self) -> ProviderResult<Vec<TrueLayerInstitution>> {
+        send(
+            self.client
+                .get(format!("{}/api/providers", self.auth_url))
+                .query(&[("clientId", &self.client_id)]),
+        )
+        .await
+    }
+
+    /// Only checks that the access token is still accepted.
+    pub async fn get_me(&self, access_token: &str) -> ProviderResult<()> {
+        let _: IgnoredAny = self.get_one(access_token, "/me").await?;
+        Ok(())
+    }
+
+    pub async fn get_accounts(&self, access_token: &str) -> ProviderResult<Vec<TrueLayerAccount>> {
+        self.get(access_token, "/accounts", &[]).await
+    }
+
+    pub async fn get_cards(&self, access_token: &str) -> ProviderResult<Vec<TrueLayerCard>> {
+        self.get(access_token, "/cards", &[]).await
+    }
+
+    /// `resource` is either `accounts` or `cards`; both expose the same
+    /// balance and transaction endpoints.
+    pub async fn get_balance(
+        &self,
+        access_token: &str,
+        resource: &str,
+        account_id: &str,
+    ) -> ProviderResult<TrueLayerBalance> {
+        self.get_one(access_token, &format!("/{}/{}/balance", resource, account_id)).await
+    }
+
+    pub async fn get_transactions(
+        &self,
+        access_token: &str,
+        resource: &str,
+        account_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> ProviderResult<Vec<TrueLayerTransaction>> {
+        self.get(
+            access_token,
+            &format!("/{}/{}/transactions", resource, account_id),
+            &[("from", from.to_rfc3339()), ("to", to.to_rfc3339())],
+        )
+        .await
+    }
+
+    pub async fn get_pending_transactions(
+        &self,
+        access_token: &str,
+        resource: &str,
+        account_id: &str,
+    ) -> ProviderResult<Vec<TrueLayerTransaction>> {
+        self.get(access_token, &format!("/{}/{}/transactions/pending", resource, account_id), &[])
+            .await
+    }
+
+    /// Revokes the access token and the consent behind it.
+    pub async fn delete_connection(&self, access_token: &str) -> ProviderResult<()> {
+        let response = self.client
+            .delete(format!("{}/api/delete", self.auth_url))
+            .bearer_auth(access_token)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(map_error(status, "Failed to delete TrueLayer connection".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> ProviderResult<T> {
+    let response = request.send().await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = match response.json::<TrueLayerError>().await {
+            Ok(error) => match error.error_description {
+                Some(description) => format!("{}: {}", error.error, description),
+                None => error.error,
+            },
+            Err(_) => format!("TrueLayer request failed with {}", status),
+        };
+        return Err(map_error(status, message));
+    }
+
+    Ok(response.json().await?)
+}
+
+fn map_error(status: StatusCode, message: String) -> ProviderError {
+    // Expired or revoked refresh tokens come back as a plain 400
+    if message.starts_with("invalid_grant") {
+        return ProviderError::Unauthorized(message);
+    }
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ProviderError::Unauthorized(message),
+        StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+        StatusCode::BAD_REQUEST => ProviderError::BadRequest(message),
+        StatusCode::TOO_MANY_REQUESTS => ProviderError::RateLimited(message),
+        status if status.is_server_error() => ProviderError::Unavailable(message),
+        _ => ProviderError::Other(message),
+    }
+}
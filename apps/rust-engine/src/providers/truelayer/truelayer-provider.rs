@@ -0,0 +1,454 @@
+use async_trait::async_trait;
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use std::sync::Arc;
+
+use crate::providers::{
+    types::{
+        Account, Balance, Capabilities, ConnectionState, ConnectionStatus, DeleteConnectionRequest,
+        ExchangeTokenRequest, GetAccountBalanceRequest, GetAccountsRequest, GetConnectionStatusRequest,
+        GetInstitutionsRequest, GetTransactionsRequest, Institution, RefreshTokenRequest, TokenResponse,
+        TransactionPage, TransactionStatus,
+    },
+    Provider, ProviderError, ProviderResult,
+};
+use crate::utils::config::Config;
+
+use super::transform::{
+    transform_account, transform_balance, transform_card, transform_institution, transform_transaction,
+};
+use super::truelayer_api::TrueLayerApi;
+use super::types::TrueLayerTokenResponse;
+
+/// How far back `get_transactions` looks when no explicit range is given.
+const TRANSACTIONS_LOOKBACK_DAYS: i64 = 90;
+const ACCOUNTS: &str = "accounts";
+const CARDS: &str = "cards";
+
+pub struct TrueLayerProvider {
+    api: TrueLayerApi,
+}
+
+impl TrueLayerProvider {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self::from_api(TrueLayerApi::new(&config))
+    }
+
+    pub fn from_api(api: TrueLayerApi) -> Self {
+        Self { api }
+    }
+
+    pub fn auth_link(&self, redirect_uri: &str, state: Option<&str>) -> ProviderResult<String> {
+        self.api.auth_link(redirect_uri, state)
+    }
+
+    /// Cards and bank accounts live under separate endpoints but share ids
+    /// in our model, so look the id up as an account first.
+    async fn get_resource(&self, access_token: &str, account_id: &str) -> ProviderResult<&'static str> {
+        match self.api.get_balance(access_token, ACCOUNTS, account_id).await {
+            Ok(_) => Ok(ACCOUNTS),
+            Err(ProviderError::NotFound(_)) | Err(ProviderError::BadRequest(_)) => Ok(CARDS),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for TrueLayerProvider {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            accounts: true,
+            balances: true,
+            transactions: true,
+            pending_transactions: true,
+            transaction_sync: false,
+            date_range: true,
+            stable_transaction_ids: true,
+            token_refresh: true,
+            identity: false,
+            institutions: true,
+            delete_connection: true,
+        }
+    }
+
+    async fn exchange_token(&self, request: ExchangeTokenRequest) -> ProviderResult<TokenResponse> {
+        let token = self.api.exchange_code(&request.code, &request.redirect_uri).await?;
+        Ok(token_response(token, None))
+    }
+
+    /// TrueLayer rotates refresh tokens, so the new one must replace the old.
+    async fn refresh_token(&self, request: RefreshTokenRequest) -> ProviderResult<TokenResponse> {
+        let token = self.api.refresh_token(&request.refresh_token).await?;
+        Ok(token_response(token, Some(request.refresh_token)))
+    }
+
+    async fn get_accounts(&self, request: GetAccountsRequest) -> ProviderResult<Vec<Account>> {
+        let access_token = &request.access_token;
+        let mut result = Vec::new();
+
+        for account in supported(self.api.get_accounts(access_token).await)? {
+            let balance = self.api.get_balance(access_token, ACCOUNTS, &account.account_id).await?;
+            result.push(transform_account(&account, Some(&balance)));
+        }
+
+        for card in supported(self.api.get_cards(access_token).await)? {
+            let balance = self.api.get_balance(access_token, CARDS, &card.account_id).await?;
+            result.push(transform_card(&card, Some(&balance)));
+        }
+
+        Ok(result)
+    }
+
+    async fn get_account_balance(&self, request: GetAccountBalanceRequest) -> ProviderResult<Balance> {
+        let (access_token, account_id) = (&request.access_token, &request.account_id);
+        let balance = match self.api.get_balance(access_token, ACCOUNTS, account_id).await {
+            Ok(balance) => balance,
+            Err(ProviderError::NotFound(_)) | Err(ProviderError::BadRequest(_)) => {
+                self.api.get_balance(access_token, CARDS, account_id).await?
+            }
+            Err(error) => return Err(error),
+        };
+
+        Ok(transform_balance(Some(&balance), &balance.currency))
+    }
+
+    /// Returns settled transactions from the range followed by any that are
+    /// still pending, as long as the range reaches today.
+    async fn get_transactions(&self, request: GetTransactionsRequest) -> ProviderResult<TransactionPage> {
+        let (access_token, account_id) = (&request.access_token, &request.account_id);
+        let resource = self.get_resource(access_token, account_id).await?;
+        let (start_date, end_date) = request.date_range.resolve(TRANSACTIONS_LOOKBACK_DAYS);
+
+        let (from, to) = (start_of_day(start_date), start_of_day(end_date + Duration::days(1)));
+
+        let booked = self.api.get_transactions(access_token, resource, account_id, from, to).await?;
+        let pending = if end_date >= Utc::now().date_naive() {
+            supported(self.api.get_pending_transactions(access_token, resource, account_id).await)?
+        } else {
+            Vec::new()
+        };
+
+        let transactions = booked
+            .iter()
+            .map(|transaction| transform_transaction(transaction, account_id, TransactionStatus::Posted))
+            .chain(
+                pending
+                    .iter()
+                    .map(|transaction| transform_transaction(transaction, account_id, TransactionStatus::Pending)),
+            )
+            .collect();
+
+        TransactionPage::from_all(transactions, &request.pagination)
+    }
+
+    async fn get_institutions(&self, request: GetInstitutionsRequest) -> ProviderResult<Vec<Institution>> {
+        let institutions = self.api.get_institutions().await?;
+        Ok(institutions
+            .iter()
+            .map(transform_institution)
+            .filter(|institution| request.matches(institution))
+            .collect())
+    }
+
+    async fn get_connection_status(&self, request: GetConnectionStatusRequest) -> ProviderResult<ConnectionStatus> {
+        let status = match self.api.get_me(&request.access_token).await {
+            Ok(_) => ConnectionState::Connected,
+            Err(ProviderError::Unauthorized(_)) => ConnectionState::Disconnected,
+            Err(error) => return Err(error),
+        };
+
+        Ok(ConnectionStatus { status })
+    }
+
+    async fn delete_connection(&self, request: DeleteConnectionRequest) -> ProviderResult<()> {
+        self.api.delete_connection(&request.access_token).await
+    }
+}
+
+/// Keeps `previous_refresh_token` when TrueLayer doesn't issue a new one.
+fn token_response(token: TrueLayerTokenResponse, previous_refresh_token: Option<String>) -> TokenResponse {
+    TokenResponse {
+        access_token: token.access_token,
+        refresh_token: token.refresh_token.or(previous_refresh_token),
+        expires_at: Some(Utc::now() + Duration::seconds(token.expires_in)),
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> chrono::DateTime<Utc> {
+    Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+/// Not every bank offers every endpoint; TrueLayer answers those with
+/// `endpoint_not_supported`, which just means there is nothing to fetch.
+fn supported<T>(result: ProviderResult<Vec<T>>) -> ProviderResult<Vec<T>> {
+    match result {
+        Err(ProviderError::Unavailable(message)) | Err(ProviderError::Other(message))
+            if message.starts_with("endpoint_not_supported") =>
+        {
+            Ok(Vec::new())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::types::DateRange;
+    use mockito::{Matcher, Server};
+    use rust_decimal_macros::dec;
+    use serde_json::json;
+
+    fn provider(server: &Server) -> TrueLayerProvider {
+        TrueLayerProvider::from_api(TrueLayerApi::with_base_urls("client", "secret", &server.url(), &server.url()))
+    }
+
+    fn provider_info() -> serde_json::Value {
+        json!({ "provider_id": "ob-monzo", "display_name": "Monzo", "logo_uri": null })
+    }
+
+    fn transaction(id: &str, amount: f64) -> serde_json::Value {
+        json!({
+            "transaction_id": id,
+            "timestamp": "2024-03-01T12:00:00+00:00",
+            "description": "TESCO STORES",
+            "amount": amount,
+            "currency": "GBP",
+            "transaction_type": "DEBIT",
+            "transaction_category": "PURCHASE",
+            "transaction_classification": ["Groceries"],
+            "merchant_name": "Tesco",
+            "running_balance": { "amount": 980.0, "currency": "GBP" }
+        })
+    }
+
+    #[test]
+    fn test_auth_link() {
+        let api = TrueLayerApi::with_base_urls("client", "secret", "https://auth.truelayer-sandbox.com", "");
+        let link = TrueLayerProvider::from_api(api)
+            .auth_link("https://app.midday.ai/callback", Some("state_1"))
+            .unwrap();
+
+        assert!(link.starts_with("https://auth.truelayer-sandbox.com/?response_type=code&client_id=client"));
+        assert!(link.contains("scope=info+accounts+balance+cards+transactions+offline_access"));
+        assert!(link.contains("redirect_uri=https%3A%2F%2Fapp.midday.ai%2Fcallback"));
+        assert!(link.ends_with("state=state_1"));
+    }
+
+    #[tokio::test]
+    async fn test_exchange_token() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/connect/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".into(), "authorization_code".into()),
+                Matcher::UrlEncoded("code".into(), "code_1".into()),
+                Matcher::UrlEncoded("client_secret".into(), "secret".into()),
+            ]))
+            .with_body(json!({ "access_token": "access_1", "expires_in": 3600, "token_type": "Bearer", "refresh_token": "refresh_1", "scope": "info" }).to_string())
+            .create_async()
+            .await;
+
+        let token = provider(&server)
+            .exchange_token(ExchangeTokenRequest {
+                code: "code_1".to_string(),
+                redirect_uri: "https://app.midday.ai/callback".to_string(),
+            })
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(token.access_token, "access_1");
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh_1"));
+        let expires_in = token.expires_at.unwrap() - Utc::now();
+        assert!(expires_in > Duration::seconds(3590) && expires_in <= Duration::seconds(3600));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_rejected() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("POST", "/connect/token")
+            .match_body(Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()))
+            .with_status(400)
+            .with_body(json!({ "error": "invalid_grant" }).to_string())
+            .create_async()
+            .await;
+
+        let error = provider(&server)
+            .refresh_token(RefreshTokenRequest {
+                refresh_token: "refresh_1".to_string(),
+            })
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, ProviderError::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn test_get_accounts_includes_cards() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/data/v1/accounts")
+            .match_header("authorization", "Bearer access_1")
+            .with_body(
+                json!({
+                    "results": [{
+                        "account_id": "acc_1",
+                        "account_type": "TRANSACTION",
+                        "display_name": "Current Account",
+                        "currency": "GBP",
+                        "account_number": { "iban": null, "number": "12345678", "sort_code": "01-02-03", "swift_bic": null },
+                        "provider": provider_info(),
+                        "update_timestamp": "2024-03-01T12:00:00Z"
+                    }],
+                    "status": "Succeeded"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data/v1/accounts/acc_1/balance")
+            .with_body(json!({ "results": [{ "currency": "GBP", "available": 950.0, "current": 1000.0 }] }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data/v1/cards")
+            .with_body(
+                json!({
+                    "results": [{
+                        "account_id": "card_1",
+                        "card_network": "VISA",
+                        "card_type": "CREDIT",
+                        "currency": "GBP",
+                        "display_name": "Credit Card",
+                        "partial_card_number": "1234",
+                        "name_on_card": "Jane",
+                        "provider": provider_info()
+                    }]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data/v1/cards/card_1/balance")
+            .with_body(json!({ "results": [{ "currency": "GBP", "available": 2800.0, "current": 200.0, "credit_limit": 3000.0 }] }).to_string())
+            .create_async()
+            .await;
+
+        let accounts = provider(&server)
+            .get_accounts(GetAccountsRequest {
+                access_token: "access_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].institution_id, "ob-monzo");
+        assert_eq!(accounts[0].balance.amount, dec!(1000));
+        assert_eq!(accounts[1].id, "card_1");
+        assert_eq!(accounts[1].account_type, crate::providers::types::AccountType::Credit);
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_includes_pending() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/data/v1/accounts/card_1/balance")
+            .with_status(404)
+            .with_body(json!({ "error": "account_not_found" }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data/v1/cards/card_1/transactions")
+            .match_query(Matcher::Regex("from=.*&to=.*".into()))
+            .with_body(json!({ "results": [transaction("tx_1", -20.0)] }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data/v1/cards/card_1/transactions/pending")
+            .with_body(json!({ "results": [transaction("tx_2", -5.5)] }).to_string())
+            .create_async()
+            .await;
+
+        let transactions = provider(&server)
+            .get_transactions(GetTransactionsRequest {
+                access_token: "access_1".to_string(),
+                account_id: "card_1".to_string(),
+                date_range: Default::default(),
+                pagination: Default::default(),
+            })
+            .await
+            .unwrap()
+            .transactions;
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].status, TransactionStatus::Posted);
+        assert_eq!(transactions[0].category, Some("groceries".to_string()));
+        assert_eq!(transactions[0].balance, Some(dec!(980)));
+        assert_eq!(transactions[1].id, "tx_2");
+        assert_eq!(transactions[1].status, TransactionStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_past_range_skips_pending() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/data/v1/accounts/acc_1/balance")
+            .with_body(json!({ "results": [{ "currency": "GBP", "available": 100.0, "current": 100.0 }] }).to_string())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/data/v1/accounts/acc_1/transactions")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("from".into(), "2024-01-01T00:00:00+00:00".into()),
+                Matcher::UrlEncoded("to".into(), "2024-02-01T00:00:00+00:00".into()),
+            ]))
+            .with_body(json!({ "results": [transaction("tx_1", -20.0)] }).to_string())
+            .create_async()
+            .await;
+        let pending = server
+            .mock("GET", "/data/v1/accounts/acc_1/transactions/pending")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let page = provider(&server)
+            .get_transactions(GetTransactionsRequest {
+                access_token: "access_1".to_string(),
+                account_id: "acc_1".to_string(),
+                date_range: DateRange {
+                    start_date: NaiveDate::from_ymd_opt(2024, 1, 1),
+                    end_date: NaiveDate::from_ymd_opt(2024, 1, 31),
+                },
+                pagination: Default::default(),
+            })
+            .await
+            .unwrap();
+
+        pending.assert_async().await;
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_connection_status_revoked() {
+        let mut server = Server::new_async().await;
+        server
+            .mock("GET", "/data/v1/me")
+            .with_status(401)
+            .with_body(json!({ "error": "invalid_token" }).to_string())
+            .create_async()
+            .await;
+
+        let status = provider(&server)
+            .get_connection_status(GetConnectionStatusRequest {
+                access_token: "access_1".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(status.status, ConnectionState::Disconnected);
+    }
+}
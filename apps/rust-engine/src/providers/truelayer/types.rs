@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+/// Every Data API response wraps its payload in `results`.
+#[derive(Debug, Deserialize)]
+pub struct TrueLayerResponse<T> {
+    pub results: Vec<T>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerProviderInfo {
+    pub provider_id: String,
+    pub display_name: Option<String>,
+    pub logo_uri: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerAccountNumber {
+    pub iban: Option<String>,
+    pub number: Option<String>,
+    pub sort_code: Option<String>,
+    pub swift_bic: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerAccount {
+    pub account_id: String,
+    pub account_type: String,
+    pub display_name: String,
+    pub currency: String,
+    pub account_number: Option<TrueLayerAccountNumber>,
+    pub provider: TrueLayerProviderInfo,
+    pub update_timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerCard {
+    pub account_id: String,
+    pub card_network: Option<String>,
+    pub card_type: String,
+    pub currency: String,
+    pub display_name: String,
+    pub partial_card_number: Option<String>,
+    pub name_on_card: Option<String>,
+    pub provider: TrueLayerProviderInfo,
+    pub update_timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerBalance {
+    pub currency: String,
+    pub available: Option<Decimal>,
+    pub current: Decimal,
+    pub overdraft: Option<Decimal>,
+    pub credit_limit: Option<Decimal>,
+    pub update_timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerRunningBalance {
+    pub amount: Decimal,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerTransaction {
+    pub transaction_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub description: String,
+    pub amount: Decimal,
+    pub currency: String,
+    pub transaction_type: String,
+    pub transaction_category: Option<String>,
+    #[serde(default)]
+    pub transaction_classification: Vec<String>,
+    pub merchant_name: Option<String>,
+    pub running_balance: Option<TrueLayerRunningBalance>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrueLayerInstitution {
+    pub provider_id: String,
+    pub display_name: String,
+    pub logo_url: Option<String>,
+    pub country: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrueLayerTokenResponse {
+    pub access_token: String,
+    pub expires_in: i64,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrueLayerError {
+    pub error: String,
+    pub error_description: Option<String>,
+}
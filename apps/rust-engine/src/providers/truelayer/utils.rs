@@ -0,0 +1,19 @@
+use crate::providers::types::AccountType;
+
+pub fn get_account_type(account_type: &str) -> AccountType {
+    match account_type {
+        "TRANSACTION" | "BUSINESS_TRANSACTION" => AccountType::Checking,
+        "SAVINGS" | "BUSINESS_SAVINGS" => AccountType::Savings,
+        _ => AccountType::Other,
+    }
+}
+
+/// TrueLayer sends a top level category such as `PURCHASE`, and sometimes a
+/// finer classification like `["Shopping", "Groceries"]`, which is preferred.
+pub fn get_category(category: Option<&str>, classification: &[String]) -> Option<String> {
+    classification
+        .first()
+        .cloned()
+        .or_else(|| category.map(str::to_string))
+        .map(|category| category.to_lowercase())
+}
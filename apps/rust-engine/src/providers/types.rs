@@ -1,8 +1,10 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
 use std::fmt;
 
+use super::error::{ProviderError, ProviderResult};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Account {
     pub id: String,
@@ -14,7 +16,7 @@ pub struct Account {
     pub last_sync: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AccountType {
     Checking,
     Savings,
@@ -24,32 +26,77 @@ pub enum AccountType {
     Other,
 }
 
+impl fmt::Display for AccountType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountType::Checking => write!(f, "checking"),
+            AccountType::Savings => write!(f, "savings"),
+            AccountType::Credit => write!(f, "credit"),
+            AccountType::Investment => write!(f, "investment"),
+            AccountType::Loan => write!(f, "loan"),
+            AccountType::Other => write!(f, "other"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Balance {
-    pub amount: f64,
+    /// In the currency's minor units, e.g. `"12.50"` for EUR or `"1500"` for JPY
+    pub amount: Decimal,
     pub currency: String,
+    /// What can be spent right now, where the provider reports it
+    pub available: Option<Decimal>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: String,
     pub account_id: String,
-    pub amount: f64,
+    /// In the currency's minor units, negative for money leaving the account
+    pub amount: Decimal,
     pub currency: String,
     pub date: DateTime<Utc>,
     pub description: String,
     pub merchant: Option<String>,
     pub category: Option<String>,
     pub status: TransactionStatus,
+    /// Account balance right after this transaction, when the provider reports it
+    pub balance: Option<Decimal>,
+    /// Exchange rate applied when the money was converted from another currency
+    pub currency_rate: Option<Decimal>,
+    /// The currency the money was converted from
+    pub currency_source: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TransactionStatus {
     Pending,
     Posted,
     Cancelled,
 }
 
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionStatus::Pending => write!(f, "pending"),
+            TransactionStatus::Posted => write!(f, "posted"),
+            TransactionStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Everything that changed for a connection since a sync checkpoint.
+/// `checkpoint` is opaque to callers and should be handed back verbatim on
+/// the next sync.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TransactionSync {
+    pub accounts: Vec<Account>,
+    pub added: Vec<Transaction>,
+    pub modified: Vec<Transaction>,
+    pub removed: Vec<String>,
+    pub checkpoint: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Institution {
     pub id: String,
@@ -64,139 +111,275 @@ pub struct ConnectionStatus {
     pub status: ConnectionState,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ConnectionState {
     Connected,
     Disconnected,
     Error,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GetAccountsRequest {
-    pub access_token: String,
+/// What a provider can do. Operations outside these fail with
+/// `ProviderError::Unsupported`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub accounts: bool,
+    pub balances: bool,
+    pub transactions: bool,
+    /// Transactions that haven't settled yet are reported as `Pending`
+    pub pending_transactions: bool,
+    /// `sync_transactions` uses a native change feed rather than re-reading
+    /// everything
+    pub transaction_sync: bool,
+    /// The provider filters transactions by `date_range` itself instead of
+    /// us reading the full history and discarding the rest
+    pub date_range: bool,
+    /// A transaction keeps the same id every time it is read. Without this,
+    /// transactions are stored under a fingerprint of their contents
+    pub stable_transaction_ids: bool,
+    /// Access tokens expire and have to be renewed with `refresh_token`
+    pub token_refresh: bool,
+    /// Account holder names, addresses and contact details
+    pub identity: bool,
+    pub institutions: bool,
+    /// `delete_connection` revokes access at the provider
+    pub delete_connection: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GetTransactionsRequest {
-    pub access_token: String,
-    pub account_id: String,
-    pub start_date: Option<DateTime<Utc>>,
-    pub end_date: Option<DateTime<Utc>>,
+impl Capabilities {
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Accounts => self.accounts,
+            Capability::Balances => self.balances,
+            Capability::Transactions => self.transactions,
+            Capability::PendingTransactions => self.pending_transactions,
+            Capability::TransactionSync => self.transaction_sync,
+            Capability::DateRange => self.date_range,
+            Capability::StableTransactionIds => self.stable_transaction_ids,
+            Capability::TokenRefresh => self.token_refresh,
+            Capability::Identity => self.identity,
+            Capability::Institutions => self.institutions,
+            Capability::DeleteConnection => self.delete_connection,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GetAccountBalanceRequest {
-    pub access_token: String,
-    pub account_id: String,
+/// Names a single field of `Capabilities`, for checking one before calling
+/// the operation that needs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Accounts,
+    Balances,
+    Transactions,
+    PendingTransactions,
+    TransactionSync,
+    DateRange,
+    StableTransactionIds,
+    TokenRefresh,
+    Identity,
+    Institutions,
+    DeleteConnection,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GetInstitutionsRequest {
-    pub country: Option<String>,
-    pub search: Option<String>,
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Capability::Accounts => write!(f, "accounts"),
+            Capability::Balances => write!(f, "balances"),
+            Capability::Transactions => write!(f, "transactions"),
+            Capability::PendingTransactions => write!(f, "pending_transactions"),
+            Capability::TransactionSync => write!(f, "transaction_sync"),
+            Capability::DateRange => write!(f, "date_range"),
+            Capability::StableTransactionIds => write!(f, "stable_transaction_ids"),
+            Capability::TokenRefresh => write!(f, "token_refresh"),
+            Capability::Identity => write!(f, "identity"),
+            Capability::Institutions => write!(f, "institutions"),
+            Capability::DeleteConnection => write!(f, "delete_connection"),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GetConnectionStatusRequest {
+/// How a provider is set up in this deployment.
+#[derive(Debug, Serialize)]
+pub struct ProviderInfo {
+    pub id: String,
+    pub enabled: bool,
+    pub disabled_reason: Option<String>,
+    /// Only known for enabled providers
+    pub capabilities: Option<Capabilities>,
+}
+
+/// `Debug` for types carrying credentials, with the secret fields shown as
+/// `[redacted]` so they can't end up in logs.
+macro_rules! redact_debug {
+    ($type:ident { $($secret:ident),* } { $($field:ident),* }) => {
+        impl fmt::Debug for $type {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($type))
+                    $(.field(stringify!($secret), &"[redacted]"))*
+                    $(.field(stringify!($field), &self.$field))*
+                    .finish()
+            }
+        }
+    };
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TokenResponse {
     pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// `None` for tokens that don't expire
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DeleteConnectionRequest {
+redact_debug!(TokenResponse { access_token, refresh_token } { expires_at });
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExchangeTokenRequest {
+    pub code: String,
+    pub redirect_uri: String,
+}
+
+redact_debug!(ExchangeTokenRequest { code } { redirect_uri });
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+redact_debug!(RefreshTokenRequest { refresh_token } {});
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GetAccountsRequest {
     pub access_token: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DeleteAccountsRequest {
+redact_debug!(GetAccountsRequest { access_token } {});
+
+#[allow(dead_code)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GetAccountBalanceRequest {
     pub access_token: String,
-    pub account_ids: Vec<String>,
+    pub account_id: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum Provider {
-    Teller,
-    Plaid,
-    Gocardless,
+redact_debug!(GetAccountBalanceRequest { access_token } { account_id });
+
+/// Both ends of the range are inclusive. Providers fall back to their own
+/// default window when they are omitted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DateRange {
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
 }
 
-impl fmt::Display for Provider {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Provider::Teller => write!(f, "teller"),
-            Provider::Plaid => write!(f, "plaid"),
-            Provider::Gocardless => write!(f, "gocardless"),
-        }
+impl DateRange {
+    /// Fills in whatever is missing with the `lookback_days` before today.
+    pub fn resolve(&self, lookback_days: i64) -> (NaiveDate, NaiveDate) {
+        let end_date = self.end_date.unwrap_or_else(|| Utc::now().date_naive());
+        let start_date = self
+            .start_date
+            .unwrap_or_else(|| end_date - Duration::days(lookback_days));
+        (start_date, end_date)
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start_date.is_none_or(|start| date >= start) && self.end_date.is_none_or(|end| date <= end)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct NewAccount {
-    pub id: String,
-    pub connection_id: String,
-    pub name: String,
-    pub account_type: String,
-    pub currency: String,
-    pub balance: Option<f64>,
-    pub available_balance: Option<f64>,
-    pub last_sync: Option<DateTime<Utc>>,
+/// `cursor` is opaque and comes from a previous page's `next_cursor`. Without
+/// a `limit` every page is fetched and returned at once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Pagination {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct NewTransaction {
-    pub id: String,
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GetTransactionsRequest {
+    pub access_token: String,
     pub account_id: String,
-    pub date: DateTime<Utc>,
-    pub description: String,
-    pub amount: f64,
-    pub currency: String,
-    pub pending: bool,
+    #[serde(default)]
+    pub date_range: DateRange,
+    #[serde(default)]
+    pub pagination: Pagination,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct NewInstitution {
-    pub id: String,
-    pub name: String,
-    pub logo: Option<String>,
-    pub provider: String,
-    pub country: String,
-    pub primary_color: Option<String>,
-    pub url: Option<String>,
-    pub oauth_support: bool,
-    pub products: Vec<String>,
-    pub last_update: DateTime<Utc>,
-}
+redact_debug!(GetTransactionsRequest { access_token } { account_id, date_range, pagination });
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum NewProvider {
-    Plaid,
-    Teller,
-    GoCardless,
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    pub next_cursor: Option<String>,
 }
 
-impl fmt::Display for NewProvider {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            NewProvider::Plaid => write!(f, "plaid"),
-            NewProvider::Teller => write!(f, "teller"),
-            NewProvider::GoCardless => write!(f, "gocardless"),
-        }
+impl TransactionPage {
+    /// Pages through transactions that were fetched in full, for providers
+    /// whose APIs have no pagination of their own. The cursor is an offset.
+    pub fn from_all(transactions: Vec<Transaction>, pagination: &Pagination) -> ProviderResult<Self> {
+        let offset = match &pagination.cursor {
+            Some(cursor) => cursor
+                .parse::<usize>()
+                .map_err(|_| ProviderError::BadRequest(format!("Invalid cursor {}", cursor)))?,
+            None => 0,
+        };
+        let Some(limit) = pagination.limit else {
+            return Ok(Self {
+                transactions: transactions.into_iter().skip(offset).collect(),
+                next_cursor: None,
+            });
+        };
+
+        let end = offset + limit as usize;
+        let next_cursor = (end < transactions.len()).then(|| end.to_string());
+
+        Ok(Self {
+            transactions: transactions.into_iter().skip(offset).take(limit as usize).collect(),
+            next_cursor,
+        })
     }
 }
 
-#[derive(Debug, Serialize)]
-pub struct GetAccountBalanceResponse {
-    pub currency: String,
-    pub amount: f64,
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SyncTransactionsRequest {
+    pub access_token: String,
+    /// `None` for a first sync
+    pub checkpoint: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct HealthCheckResponse {
-    pub healthy: bool,
+redact_debug!(SyncTransactionsRequest { access_token } { checkpoint });
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GetInstitutionsRequest {
+    pub country: Option<String>,
+    pub search: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct GetHealthCheckResponse {
-    pub teller: HealthCheckResponse,
-    pub gocardless: HealthCheckResponse,
-    pub plaid: HealthCheckResponse,
+impl GetInstitutionsRequest {
+    pub fn matches(&self, institution: &Institution) -> bool {
+        let country = self
+            .country
+            .as_ref()
+            .is_none_or(|country| institution.country.eq_ignore_ascii_case(country));
+        let search = self.search.as_ref().is_none_or(|search| {
+            institution.name.to_lowercase().contains(&search.to_lowercase())
+        });
+
+        country && search
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GetConnectionStatusRequest {
+    pub access_token: String,
 }
+
+redact_debug!(GetConnectionStatusRequest { access_token } {});
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeleteConnectionRequest {
+    pub access_token: String,
+}
+
+redact_debug!(DeleteConnectionRequest { access_token } {});
@@ -0,0 +1,80 @@
+use chrono::Utc;
+
+use crate::providers::types::{
+    Account, AccountType, Balance, Institution, Transaction, TransactionStatus,
+};
+use crate::utils::money;
+
+use super::types::*;
+use super::utils::{get_account_id, get_counterparty, get_exchange};
+
+pub const WISE_INSTITUTION_ID: &str = "wise";
+
+pub fn transform_account(profile: &WiseProfile, balance: &WiseBalance) -> Account {
+    let name = balance
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("{} balance", balance.currency));
+
+    Account {
+        id: get_account_id(profile.id, balance.id),
+        name,
+        account_type: AccountType::Checking,
+        balance: transform_balance(balance),
+        currency: balance.currency.clone(),
+        institution_id: WISE_INSTITUTION_ID.to_string(),
+        last_sync: Some(Utc::now()),
+    }
+}
+
+pub fn transform_balance(balance: &WiseBalance) -> Balance {
+    Balance {
+        amount: money::round(balance.amount.value, &balance.amount.currency),
+        currency: balance.amount.currency.clone(),
+        // Wise's amount already leaves out what is reserved
+        available: Some(money::round(balance.amount.value, &balance.amount.currency)),
+    }
+}
+
+/// Conversions show up on both balances with the same reference number, so
+/// the balance id is folded into the transaction id to keep them apart.
+pub fn transform_transaction(transaction: &WiseStatementTransaction, account_id: &str) -> Transaction {
+    let (currency_rate, currency_source) = get_exchange(transaction);
+    let details = &transaction.details;
+
+    Transaction {
+        id: format!("{}-{}", account_id, transaction.reference_number),
+        account_id: account_id.to_string(),
+        amount: money::round(transaction.amount.value, &transaction.amount.currency),
+        currency: transaction.amount.currency.clone(),
+        date: transaction.date,
+        description: details.description.clone().unwrap_or_default(),
+        merchant: get_counterparty(details),
+        category: details
+            .merchant
+            .as_ref()
+            .and_then(|merchant| merchant.category.clone())
+            .or_else(|| Some(details.r#type.to_lowercase())),
+        status: TransactionStatus::Posted,
+        balance: transaction
+            .running_balance
+            .as_ref()
+            .map(|balance| money::round(balance.value, &balance.currency)),
+        currency_rate,
+        currency_source,
+    }
+}
+
+pub fn transform_institution() -> Institution {
+    Institution {
+        id: WISE_INSTITUTION_ID.to_string(),
+        name: "Wise".to_string(),
+        logo_url: Some("https://wise.com/logo.png".to_string()),
+        website: Some("https://wise.com".to_string()),
+        country: "GB".to_string(),
+    }
+}
+
+#[cfg(test)]
+#[path = "transform.test.rs"]
+mod tests;
@@ -0,0 +1,86 @@
+use super::*;
+use chrono::TimeZone;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn amount(value: Decimal, currency: &str) -> WiseAmount {
+    WiseAmount {
+        value,
+        currency: currency.to_string(),
+    }
+}
+
+fn card_payment() -> WiseStatementTransaction {
+    WiseStatementTransaction {
+        r#type: "DEBIT".to_string(),
+        date: Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap(),
+        amount: amount(dec!(-10.2), "EUR"),
+        total_fees: None,
+        details: WiseTransactionDetails {
+            r#type: "CARD".to_string(),
+            description: Some("Card transaction of 12.00 USD issued by Uber".to_string()),
+            source_amount: None,
+            target_amount: None,
+            rate: None,
+            merchant: Some(WiseMerchant {
+                name: Some("Uber".to_string()),
+                category: Some("Taxicabs".to_string()),
+            }),
+            sender_name: None,
+            recipient: None,
+        },
+        exchange_details: Some(WiseExchangeDetails {
+            for_amount: Some(amount(dec!(12), "USD")),
+            rate: Some(dec!(1.17647)),
+        }),
+        running_balance: Some(amount(dec!(500), "EUR")),
+        reference_number: "CARD-42".to_string(),
+    }
+}
+
+#[test]
+fn test_transform_account() {
+    let profile = WiseProfile {
+        id: 100,
+        r#type: "BUSINESS".to_string(),
+        full_name: Some("Midday Ltd".to_string()),
+    };
+    let balance = WiseBalance {
+        id: 7,
+        currency: "JPY".to_string(),
+        amount: amount(dec!(15000), "JPY"),
+        reserved_amount: None,
+        r#type: "STANDARD".to_string(),
+        name: None,
+        visible: Some(true),
+    };
+
+    let account = transform_account(&profile, &balance);
+    assert_eq!(account.id, "100-7");
+    assert_eq!(account.name, "JPY balance");
+    assert_eq!(account.currency, "JPY");
+    assert_eq!(account.institution_id, "wise");
+    assert_eq!(account.balance.amount, dec!(15000));
+    assert_eq!(account.balance.available, Some(dec!(15000)));
+}
+
+#[test]
+fn test_transform_card_payment_in_foreign_currency() {
+    let transaction = transform_transaction(&card_payment(), "100-7");
+    assert_eq!(transaction.id, "100-7-CARD-42");
+    assert_eq!(transaction.amount, dec!(-10.2));
+    assert_eq!(transaction.merchant, Some("Uber".to_string()));
+    assert_eq!(transaction.category, Some("Taxicabs".to_string()));
+    assert_eq!(transaction.currency_rate, Some(dec!(1.17647)));
+    assert_eq!(transaction.currency_source, Some("USD".to_string()));
+}
+
+#[test]
+fn test_transform_transaction_without_exchange() {
+    let mut payment = card_payment();
+    payment.exchange_details = None;
+
+    let transaction = transform_transaction(&payment, "100-7");
+    assert_eq!(transaction.currency_rate, None);
+    assert_eq!(transaction.currency_source, None);
+}
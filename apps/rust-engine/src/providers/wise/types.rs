@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WiseProfile {
+    pub id: u64,
+    pub r#type: String,
+    pub full_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WiseAmount {
+    pub value: Decimal,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WiseBalance {
+    pub id: u64,
+    pub currency: String,
+    pub amount: WiseAmount,
+    pub reserved_amount: Option<WiseAmount>,
+    pub r#type: String,
+    pub name: Option<String>,
+    pub visible: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WiseStatement {
+    pub transactions: Vec<WiseStatementTransaction>,
+    pub end_of_statement_balance: Option<WiseAmount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WiseStatementTransaction {
+    pub r#type: String,
+    pub date: DateTime<Utc>,
+    pub amount: WiseAmount,
+    pub total_fees: Option<WiseAmount>,
+    pub details: WiseTransactionDetails,
+    pub exchange_details: Option<WiseExchangeDetails>,
+    pub running_balance: Option<WiseAmount>,
+    pub reference_number: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WiseTransactionDetails {
+    pub r#type: String,
+    pub description: Option<String>,
+    pub source_amount: Option<WiseAmount>,
+    pub target_amount: Option<WiseAmount>,
+    pub rate: Option<Decimal>,
+    pub merchant: Option<WiseMerchant>,
+    pub sender_name: Option<String>,
+    pub recipient: Option<WiseRecipient>,
+}
+
+/// Present on card payments made in a currency other than the balance's.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WiseExchangeDetails {
+    pub for_amount: Option<WiseAmount>,
+    pub rate: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WiseMerchant {
+    pub name: Option<String>,
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WiseRecipient {
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WiseTokenResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WiseError {
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+    #[serde(default)]
+    pub errors: Vec<WiseErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WiseErrorDetail {
+    pub code: Option<String>,
+    pub message: Option<String>,
+}
@@ -0,0 +1,52 @@
+use rust_decimal::Decimal;
+
+use super::types::*;
+
+/// Wise has no account numbers of its own for balances, so an account is
+/// identified by its profile and balance ids together.
+pub fn get_account_id(profile_id: u64, balance_id: u64) -> String {
+    format!("{}-{}", profile_id, balance_id)
+}
+
+pub fn parse_account_id(account_id: &str) -> Option<(u64, u64)> {
+    let (profile_id, balance_id) = account_id.split_once('-')?;
+    Some((profile_id.parse().ok()?, balance_id.parse().ok()?))
+}
+
+/// Returns the rate and original currency when money on this balance came
+/// from, or went to, a different currency.
+pub fn get_exchange(transaction: &WiseStatementTransaction) -> (Option<Decimal>, Option<String>) {
+    let currency = &transaction.amount.currency;
+
+    if let Some(exchange) = &transaction.exchange_details {
+        let source = exchange
+            .for_amount
+            .as_ref()
+            .map(|amount| amount.currency.clone())
+            .filter(|source| source != currency);
+        if source.is_some() {
+            return (exchange.rate, source);
+        }
+    }
+
+    let details = &transaction.details;
+    let source = [&details.source_amount, &details.target_amount]
+        .into_iter()
+        .flatten()
+        .map(|amount| amount.currency.clone())
+        .find(|other| other != currency);
+
+    match source {
+        Some(source) => (details.rate, Some(source)),
+        None => (None, None),
+    }
+}
+
+pub fn get_counterparty(details: &WiseTransactionDetails) -> Option<String> {
+    details
+        .merchant
+        .as_ref()
+        .and_then(|merchant| merchant.name.clone())
+        .or_else(|| details.sender_name.clone())
+        .or_else(|| details.recipient.as_ref().and_then(|recipient| recipient.name.clone()))
+}
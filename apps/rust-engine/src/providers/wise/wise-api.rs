@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::config::Config;
+use crate::providers::{ProviderError, ProviderResult};
+
+use super::types::*;
+
+/// Personal tokens are issued from the Wise settings page and act for their
+/// owner directly. OAuth needs the platform client credentials, which are
+/// also used to exchange and refresh user tokens.
+pub enum WiseAuth {
+    PersonalToken(String),
+    OAuth { client_id: String, client_secret: String },
+}
+
+impl WiseAuth {
+    pub fn from_config(config: &Config) -> ProviderResult<Self> {
+        match (&config.wise_api_token, &config.wise_client_id, &config.wise_secret) {
+            (Some(token), _, _) => match config.wise_team_id {
+                Some(_) => Ok(WiseAuth::PersonalToken(token.clone())),
+                None => Err(ProviderError::Configuration(
+                    "WISE_API_TOKEN needs WISE_TEAM_ID, the one team it may be connected for".to_string(),
+                )),
+            },
+            (None, Some(client_id), Some(client_secret)) => Ok(WiseAuth::OAuth {
+                client_id: client_id.clone(),
+                client_secret: client_secret.clone(),
+            }),
+            _ => Err(ProviderError::Configuration(
+                "Either WISE_API_TOKEN or WISE_CLIENT_ID and WISE_SECRET must be set".to_string(),
+            )),
+        }
+    }
+}
+
+pub struct WiseApi {
+    client: Client,
+    base_url: String,
+}
+
+impl WiseApi {
+    pub fn new(config: &Config) -> Self {
+        let base_url = match config.wise_environment.as_str() {
+            "sandbox" => "https://api.sandbox.transferwise.tech",
+            _ => "https://api.transferwise.com",
+        };
+
+        Self::with_base_url(base_url)
+    }
+
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> ProviderResult<T> {
+        send(
+            self.client
+                .get(format!("{}{}", self.base_url, path))
+                .bearer_auth(access_token)
+                .query(query),
+        )
+        .await
+    }
+
+    pub async fn exchange_code(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        code: &str,
+        redirect_uri: &str,
+    ) -> ProviderResult<WiseTokenResponse> {
+        self.token(
+            client_id,
+            client_secret,
+            &[
+                ("grant_type", "authorization_code"),
+                ("client_id", client_id),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+            ],
+        )
+        .await
+    }
+
+    pub async fn refresh_token(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        refresh_token: &str,
+    ) -> ProviderResult<WiseTokenResponse> {
+        self.token(
+            client_id,
+            client_secret,
+            &[("grant_type", "refresh_token"), ("refresh_token", refresh_token)],
+        )
+        .await
+    }
+
+    async fn token(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        form: &[(&str, &str)],
+    ) -> ProviderResult<WiseTokenResponse> {
+        send(
+            self.client
+                .post(format!("{}/oauth/token", self.base_url))
+                .basic_auth(client_id, Some(client_secret))
+                .form(form),
+        )
+        .await
+    }
+
+    pub async fn get_profiles(&self, access_token: &str) -> ProviderResult<Vec<WiseProfile>> {
+        self.get(access_token, "/v2/profiles", &[]).await
+    }
+
+    pub async fn get_balances(&self, access_token: &str, profile_id: u64) -> ProviderResult<Vec<WiseBalance>> {
+        self.get(
+            access_token,
+            &format!("/v4/profiles/{}/balances", profile_id),
+            &[("types", "STANDARD".to_string())],
+        )
+        .await
+    }
+
+    pub async fn get_balance(
+        &self,
+        access_token: &str,
+        profile_id: u64,
+        balance_id: u64,
+    ) -> ProviderResult<WiseBalance> {
+        self.get(
+            access_token,
+            &format!("/v4/profiles/{}/balances/{}", profile_id, balance_id),
+            &[],
+        )
+        .await
+    }
+
+    pub async fn get_statement(
+        &self,
+        access_token: &str,
+        profile_id: u64,
+        balance_id: u64,
+        currency: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> ProviderResult<WiseStatement> {
+        self.get(
+            access_token,
+            &format!("/v1/profiles/{}/balance-statements/{}/statement.json", profile_id, balance_id),
+            &[
+                ("currency", currency.to_string()),
+                ("intervalStart", start.to_rfc3339()),
+                ("intervalEnd", end.to_rfc3339()),
+                ("type", "COMPACT".to_string()),
+            ],
+        )
+        .await
+    }
+}
+
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> ProviderResult<T> {
+    let response = request.send().await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        // Statements on EU and UK profiles require strong customer
+        // authentication. The token itself is still valid.
+        if response.headers().contains_key("x-2fa-approval") {
+            return Err(ProviderError::Other(
+                "Wise requires strong customer authentication for this request".to_string(),
+            ));
+        }
+
+        let message = match response.json::<WiseError>().await {
+            Ok(error) => error
+                .error_description
+                .or(error.error)
+                .or_else(|| error.errors.into_iter().find_map(|detail| detail.message.or(detail.code)))
+                .unwrap_or_else(|| format!("Wise request failed with {}", status)),
+            Err(_) => format!("Wise request failed with {}", status),
+        };
+        return Err(map_error(status, message));
+    }
+
+    Ok(response.json().await?)
+}
+
+fn map_error(status: StatusCode, message: String) -> ProviderError {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ProviderError::Unauthorized(message),
+        StatusCode::NOT_FOUND => ProviderError::NotFound(message),
+        StatusCode::BAD_REQUEST => ProviderError::BadRequest(message),
+        StatusCode::TOO_MANY_REQUESTS => ProviderError::RateLimited(message),
+        status if status.is_server_error() => ProviderError::Unavailable(message),
+        _ => ProviderError::Other(message),
+    }
+}
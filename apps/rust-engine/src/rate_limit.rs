@@ -0,0 +1,260 @@
+use redis::{aio::ConnectionManager, AsyncCommands};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Memory windows are pruned once there are this many keys.
+const MAX_MEMORY_KEYS: usize = 10_000;
+
+/// Counts requests in fixed windows and limits them over a sliding one: the
+/// previous window's count is weighted by how much of it still overlaps the
+/// last `window` seconds. Counts live in Redis so every instance shares
+/// them, or in memory when Redis isn't available.
+pub struct RateLimiter {
+    redis: Option<ConnectionManager>,
+    redis_failing: AtomicBool,
+    memory: Mutex<HashMap<String, Counts>>,
+    window: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    index: u64,
+    previous: u64,
+    current: u64,
+}
+
+/// The outcome of a request against one limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the current window ends
+    pub reset_after: u64,
+    /// Set when the request is over the limit, to how many seconds until
+    /// one would be allowed
+    pub retry_after: Option<u64>,
+}
+
+impl Decision {
+    pub fn allowed(&self) -> bool {
+        self.retry_after.is_none()
+    }
+}
+
+impl RateLimiter {
+    /// A limiter that only counts this instance's requests.
+    pub fn in_memory(window_seconds: u64) -> Self {
+        Self {
+            redis: None,
+            redis_failing: AtomicBool::new(false),
+            memory: Mutex::new(HashMap::new()),
+            window: window_seconds.max(1),
+        }
+    }
+
+    /// A limiter backed by Redis, or by memory if Redis can't be reached at
+    /// startup. Once connected, calls that fail fall back to memory until
+    /// Redis is back.
+    pub async fn connect(redis_url: &str, window_seconds: u64) -> Self {
+        let connection = async {
+            let client = redis::Client::open(redis_url)?;
+            client.get_connection_manager().await
+        };
+
+        match tokio::time::timeout(Duration::from_secs(2), connection).await {
+            Ok(Ok(redis)) => Self {
+                redis: Some(redis),
+                ..Self::in_memory(window_seconds)
+            },
+            Ok(Err(e)) => {
+                log::warn!("Rate limiting in memory, Redis is unavailable: {}", e);
+                Self::in_memory(window_seconds)
+            }
+            Err(_) => {
+                log::warn!("Rate limiting in memory, Redis timed out");
+                Self::in_memory(window_seconds)
+            }
+        }
+    }
+
+    /// Counts a request against `key` and decides whether it is within
+    /// `limit` per window. Rejected requests aren't counted.
+    pub async fn check(&self, key: &str, limit: u64) -> Decision {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        self.check_at(key, limit, now).await
+    }
+
+    async fn check_at(&self, key: &str, limit: u64, now: f64) -> Decision {
+        let index = now as u64 / self.window;
+        let elapsed = now - (index * self.window) as f64;
+
+        if let Some(redis) = &self.redis {
+            match self.check_redis(redis.clone(), key, limit, index, elapsed).await {
+                Ok(decision) => {
+                    if self.redis_failing.swap(false, Ordering::Relaxed) {
+                        log::info!("Rate limiting in Redis again");
+                    }
+                    return decision;
+                }
+                Err(e) => {
+                    if !self.redis_failing.swap(true, Ordering::Relaxed) {
+                        log::warn!("Rate limiting in memory, Redis failed: {}", e);
+                    }
+                }
+            }
+        }
+
+        self.check_memory(key, limit, index, elapsed)
+    }
+
+    async fn check_redis(
+        &self,
+        mut redis: ConnectionManager,
+        key: &str,
+        limit: u64,
+        index: u64,
+        elapsed: f64,
+    ) -> redis::RedisResult<Decision> {
+        let current_key = format!("{}:{}", key, index);
+        let previous_key = format!("{}:{}", key, index.saturating_sub(1));
+
+        let (current, previous): (u64, Option<u64>) = redis::pipe()
+            .atomic()
+            .incr(&current_key, 1)
+            .expire(&current_key, (self.window * 2) as usize)
+            .ignore()
+            .get(&previous_key)
+            .query_async(&mut redis)
+            .await?;
+
+        let decision = decide(limit, self.window, elapsed, previous.unwrap_or(0), current);
+        if !decision.allowed() {
+            redis.decr::<_, _, ()>(&current_key, 1).await?;
+        }
+        Ok(decision)
+    }
+
+    fn check_memory(&self, key: &str, limit: u64, index: u64, elapsed: f64) -> Decision {
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        if memory.len() >= MAX_MEMORY_KEYS {
+            memory.retain(|_, counts| counts.index + 1 >= index);
+        }
+
+        let counts = memory.entry(key.to_string()).or_default();
+        if counts.index != index {
+            let previous = if counts.index + 1 == index { counts.current } else { 0 };
+            *counts = Counts {
+                index,
+                previous,
+                current: 0,
+            };
+        }
+
+        let decision = decide(limit, self.window, elapsed, counts.previous, counts.current + 1);
+        if decision.allowed() {
+            counts.current += 1;
+        }
+        decision
+    }
+}
+
+/// Whether `current` requests so far this window, including this one, are
+/// within `limit` once the previous window's are weighted in.
+fn decide(limit: u64, window: u64, elapsed: f64, previous: u64, current: u64) -> Decision {
+    let window = window as f64;
+    let estimate = previous as f64 * (1.0 - elapsed / window) + current as f64;
+    let reset_after = (window - elapsed).ceil() as u64;
+
+    if estimate <= limit as f64 {
+        return Decision {
+            limit,
+            remaining: (limit as f64 - estimate).floor() as u64,
+            reset_after,
+            retry_after: None,
+        };
+    }
+
+    // How long until one more request fits, either as the previous window
+    // slides out or, if this one is full already, into the next window
+    let before = current - 1;
+    let wait = if before < limit && previous > 0 {
+        window * (1.0 - (limit - 1 - before) as f64 / previous as f64) - elapsed
+    } else if limit == 0 {
+        f64::INFINITY
+    } else {
+        let into_next = if before > 0 {
+            window * (1.0 - (limit - 1) as f64 / before as f64)
+        } else {
+            0.0
+        };
+        window - elapsed + into_next.max(0.0)
+    };
+
+    Decision {
+        limit,
+        remaining: 0,
+        reset_after,
+        retry_after: Some(wait.min(window * 2.0).ceil().max(1.0) as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limits_within_a_window() {
+        let limiter = RateLimiter::in_memory(60);
+
+        for remaining in [2, 1, 0] {
+            let decision = limiter.check_at("key", 3, 600.0).await;
+            assert_eq!(decision.remaining, remaining);
+            assert!(decision.allowed());
+        }
+
+        // Full, so it has to wait for the next window, and then until these
+        // three have slid out far enough
+        let decision = limiter.check_at("key", 3, 610.0).await;
+        assert_eq!(decision.reset_after, 50);
+        assert_eq!(decision.retry_after, Some(70));
+
+        // Other keys have their own count
+        assert!(limiter.check_at("other", 3, 610.0).await.allowed());
+    }
+
+    #[tokio::test]
+    async fn test_previous_window_slides_out() {
+        let limiter = RateLimiter::in_memory(60);
+        for _ in 0..4 {
+            assert!(limiter.check_at("key", 4, 630.0).await.allowed());
+        }
+
+        // A quarter into the next window three quarters of those still count
+        let decision = limiter.check_at("key", 4, 675.0).await;
+        assert_eq!(decision.remaining, 0);
+        let decision = limiter.check_at("key", 4, 675.0).await;
+        assert_eq!(decision.retry_after, Some(15));
+
+        // Rejected requests don't count, so waiting that long is enough
+        assert!(limiter.check_at("key", 4, 690.0).await.allowed());
+
+        // Two windows later nothing from the first is left
+        let decision = limiter.check_at("key", 4, 800.0).await;
+        assert_eq!(decision.remaining, 3);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_memory_without_redis() {
+        let limiter = RateLimiter::connect("redis://127.0.0.1:1", 60).await;
+        assert!(limiter.redis.is_none());
+
+        assert!(limiter.check("key", 1).await.allowed());
+        assert!(!limiter.check("key", 1).await.allowed());
+    }
+}
@@ -1,109 +1,395 @@
 use actix_web::{get, web, HttpResponse};
-use sqlx::{PgPool, FromRow};
-use chrono::{DateTime, NaiveDateTime, Utc};
-use serde_json::json;
+use sqlx::{PgPool, FromRow, QueryBuilder};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
 
 pub mod schema;
-use crate::utils::logo::get_institution_logo;
-use crate::error::AppResult;
-use crate::routes::accounts::schema::{AccountQuery, Balance, EnrichedAccount, Institution};
+use crate::utils::{
+    money,
+    paginate::{Cursor, Keyset, PaginatedResponse, PaginationParams},
+    query::{Filter, Value},
+};
+use crate::error::{AppError, AppResult};
+use crate::tenant::{Scope, Tenant};
+use crate::routes::accounts::schema::{
+    AccountQuery, Balance, BalanceHistoryQuery, BalanceHistoryResponse, BalancePoint, EnrichedAccount,
+};
+
+/// Days of history returned when no `from` is given
+const DEFAULT_BALANCE_DAYS: i64 = 90;
+
+/// Accounts are listed by name, then id. Unnamed ones sort first.
+const ACCOUNTS_KEYSET: Keyset = Keyset {
+    key: "COALESCE(a.name, '')",
+    id: "a.id",
+    descending: false,
+};
 
 #[derive(Debug, FromRow)]
 struct Account {
-    id: i32,
+    id: String,
     connection_id: String,
-    name: String,
-    account_type: String,
-    currency: String,
-    current_balance: f64,
-    available_balance: f64,
-    credit_limit: f64,
-    last_sync: NaiveDateTime,
-    institution_id: i32,
-    institution_name: String,
+    provider: String,
+    name: Option<String>,
+    account_type: Option<String>,
+    currency: Option<String>,
+    balance: Option<Decimal>,
+    available: Option<Decimal>,
+    last_sync: Option<NaiveDateTime>,
 }
 
-#[get("/api/v1/accounts")]
+impl From<Account> for EnrichedAccount {
+    fn from(account: Account) -> Self {
+        let round = |amount: Option<Decimal>| match &account.currency {
+            Some(currency) => amount.map(|amount| money::round(amount, currency)),
+            None => amount,
+        };
+
+        EnrichedAccount {
+            balance: Balance {
+                current: round(account.balance),
+                available: round(account.available),
+            },
+            id: account.id,
+            connection_id: account.connection_id,
+            provider: account.provider,
+            name: account.name,
+            account_type: account.account_type,
+            currency: account.currency,
+            last_sync: account.last_sync.map(|last_sync| DateTime::<Utc>::from_naive_utc_and_offset(last_sync, Utc)),
+        }
+    }
+}
+
+#[get("/accounts")]
 pub async fn get_accounts(
+    tenant: Tenant,
     pool: web::Data<PgPool>,
+    pagination: web::Query<PaginationParams>,
     query: web::Query<AccountQuery>,
 ) -> AppResult<HttpResponse> {
-    let page = i64::from(query.page);
-    let per_page = i64::from(query.limit.min(100));
-    let offset = (page - 1) * per_page;
-
-    let mut sql = "SELECT a.*, i.id as institution_id, i.name as institution_name 
-         FROM accounts a 
-         LEFT JOIN institutions i ON a.institution_id = i.id 
-         WHERE 1=1".to_string();
-    let mut params = vec![];
-
-    if let Some(connection_id) = &query.connection_id {
-        sql.push_str(" AND a.connection_id = $1");
-        params.push(connection_id);
+    tenant.require(Scope::ReadAccounts)?;
+    pagination.validate().map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let cursor = pagination.cursor()?;
+
+    let currencies: Vec<String> = query.currency.iter().map(|currency| currency.to_uppercase()).collect();
+    let filter = Filter::new()
+        .eq("a.team_id", tenant.team_id)
+        .any("a.connection_id", &query.connection_id)
+        .any("a.account_type", &query.account_type)
+        .any("a.currency", &currencies);
+    let page_filter = match &cursor {
+        Some(cursor) => ACCOUNTS_KEYSET.past(filter.clone(), cursor, Value::from(&cursor.key)),
+        None => filter.clone(),
+    };
+
+    let mut sql_query = QueryBuilder::new(
+        "SELECT a.id, a.connection_id, c.provider, a.name, a.account_type, a.currency,
+                a.balance, a.available, c.last_sync
+         FROM accounts a
+         JOIN connections c ON c.id = a.connection_id",
+    );
+    page_filter.push_where(&mut sql_query);
+    ACCOUNTS_KEYSET.push_page(&mut sql_query, &pagination, cursor.as_ref());
+
+    let mut tx = tenant.begin(pool.get_ref()).await?;
+    let accounts: Vec<Account> = sql_query.build_query_as::<Account>().fetch_all(&mut *tx).await?;
+
+    let mut response = PaginatedResponse::keyset(accounts, &pagination, cursor.as_ref(), |account| Cursor {
+        key: account.name.clone().unwrap_or_default(),
+        id: account.id.clone(),
+        ..Cursor::default()
+    })
+    .map(EnrichedAccount::from);
+
+    if pagination.include_total {
+        let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM accounts a");
+        filter.push_where(&mut count_query);
+        let total: i64 = count_query.build_query_scalar().fetch_one(&mut *tx).await?;
+        response = response.with_total(pagination.limit, total as u64);
     }
+    tx.commit().await?;
 
-    if let Some(account_type) = &query.account_type {
-        sql.push_str(" AND a.account_type = $2");
-        params.push(account_type);
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// End of period balances of an account between `from` and `to`, one per
+/// day, week or month.
+#[get("/accounts/{id}/balances")]
+pub async fn get_account_balances(
+    tenant: Tenant,
+    path: web::Path<String>,
+    pool: web::Data<PgPool>,
+    query: web::Query<BalanceHistoryQuery>,
+) -> AppResult<HttpResponse> {
+    tenant.require(Scope::ReadAccounts)?;
+    let account_id = path.into_inner();
+    let to = query.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = query.from.unwrap_or(to - Duration::days(DEFAULT_BALANCE_DAYS));
+    if from > to {
+        return Err(AppError::BadRequest("from must not be after to".to_string()));
     }
 
-    if let Some(currency) = &query.currency {
-        sql.push_str(" AND a.currency = $3");
-        params.push(currency);
+    let mut tx = tenant.begin(pool.get_ref()).await?;
+    let currency = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT currency FROM accounts WHERE id = $1 AND team_id = $2",
+    )
+    .bind(&account_id)
+    .bind(tenant.team_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Account not found".to_string()))?;
+
+    let mut balances = sqlx::query_as::<_, BalancePoint>(
+        r#"
+        SELECT DISTINCT ON (period) period AS date, balance
+        FROM (
+            SELECT date_trunc($2, date)::DATE AS period, date AS snapshot_date, balance
+            FROM balance_snapshots
+            WHERE account_id = $1 AND date BETWEEN $3 AND $4
+        ) AS snapshots
+        ORDER BY period, snapshot_date DESC
+        "#,
+    )
+    .bind(&account_id)
+    .bind(query.interval.as_str())
+    .bind(from)
+    .bind(to)
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    if let Some(currency) = &currency {
+        for point in &mut balances {
+            point.balance = money::round(point.balance, currency);
+        }
     }
 
-    // Count total records
-    let count_sql = format!("SELECT COUNT(*) FROM ({}) as count_query", sql);
-    let total = sqlx::query_scalar::<_, i64>(&count_sql)
-        .bind(query.connection_id.as_deref())
-        .bind(query.account_type.as_deref())
-        .bind(query.currency.as_deref())
-        .fetch_one(pool.get_ref())
-        .await?;
-
-    // Add pagination
-    sql.push_str(" ORDER BY a.id LIMIT $4 OFFSET $5");
-
-    let accounts = sqlx::query_as::<_, Account>(&sql)
-        .bind(query.connection_id.as_deref())
-        .bind(query.account_type.as_deref())
-        .bind(query.currency.as_deref())
-        .bind(per_page)
-        .bind(offset)
-        .fetch_all(pool.get_ref())
-        .await?;
-
-    let enriched_accounts: Vec<EnrichedAccount> = accounts
-        .into_iter()
-        .map(|account| EnrichedAccount {
-            id: account.id.to_string(),
-            connection_id: account.connection_id,
-            name: account.name,
-            account_type: account.account_type,
-            currency: account.currency,
-            balance: Balance {
-                current: account.current_balance,
-                available: Some(account.available_balance),
-                limit: Some(account.credit_limit),
-            },
-            institution: Institution {
-                id: account.institution_id.to_string(),
-                name: account.institution_name,
-                logo_url: get_institution_logo(&account.institution_id.to_string()).map(|s| s.to_string()),
-            },
-            last_sync: Some(DateTime::<Utc>::from_naive_utc_and_offset(account.last_sync, Utc)),
-        })
-        .collect();
-
-    let total_pages = (total as f64 / per_page as f64).ceil() as i64;
-    let has_more = page < total_pages;
-
-    Ok(HttpResponse::Ok().json(json!({
-        "data": enriched_accounts,
-        "page": page,
-        "total_pages": total_pages,
-        "total_items": total,
-        "has_more": has_more,
-    })))
+    Ok(HttpResponse::Ok().json(BalanceHistoryResponse {
+        account_id,
+        currency,
+        interval: query.interval,
+        balances,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tenant::Scopes,
+        utils::testing::{test_pool, TEST_TEAM_ID},
+    };
+    use actix_web::{test, App, HttpMessage};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    async fn create_account(pool: &PgPool) {
+        sqlx::query("INSERT INTO connections (id, provider, team_id) VALUES ('conn_1', 'plaid', $1)")
+            .bind(TEST_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO accounts (id, connection_id, currency) VALUES ('acc_1', 'conn_1', 'USD')")
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO balance_snapshots (account_id, date, balance, currency, source)
+            SELECT 'acc_1', day::DATE, 100 + EXTRACT(DAY FROM day), 'USD', 'derived'
+            FROM generate_series('2024-02-20'::DATE, '2024-03-10'::DATE, interval '1 day') AS day
+            "#,
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn get(pool: &PgPool, uri: &str) -> (u16, serde_json::Value) {
+        get_as(pool, TEST_TEAM_ID, uri).await
+    }
+
+    async fn get_as(pool: &PgPool, team_id: Uuid, uri: &str) -> (u16, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(get_accounts)
+                .service(get_account_balances),
+        )
+        .await;
+        let request = test::TestRequest::get().uri(uri).to_request();
+        request.extensions_mut().insert(Tenant::new(team_id, Scopes::all()));
+        let response = test::call_service(&app, request).await;
+        let status = response.status().as_u16();
+        let body = test::read_body(response).await;
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    /// Three accounts over two connections, and another team's.
+    async fn create_accounts(pool: &PgPool) {
+        let other_team = Uuid::new_v4();
+        sqlx::query("INSERT INTO teams (id, name) VALUES ($1, 'Other')")
+            .bind(other_team)
+            .execute(pool)
+            .await
+            .unwrap();
+        for (id, provider, team_id) in [
+            ("conn_1", "plaid", TEST_TEAM_ID),
+            ("conn_2", "teller", TEST_TEAM_ID),
+            ("conn_3", "plaid", other_team),
+        ] {
+            sqlx::query("INSERT INTO connections (id, provider, team_id) VALUES ($1, $2, $3)")
+                .bind(id)
+                .bind(provider)
+                .bind(team_id)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+        sqlx::Executor::execute(
+            pool,
+            r#"
+            INSERT INTO accounts (id, connection_id, name, account_type, currency, balance, available) VALUES
+                ('acc_1', 'conn_1', 'Checking', 'depository', 'USD', 100.5, 90),
+                ('acc_2', 'conn_1', 'Savings', 'depository', 'EUR', 2000, NULL),
+                ('acc_3', 'conn_2', 'Card', 'credit', 'USD', -250.255, NULL),
+                ('acc_4', 'conn_3', 'Other', 'depository', 'USD', 1, NULL);
+            "#,
+        )
+        .await
+        .unwrap();
+    }
+
+    fn ids(page: &serde_json::Value) -> Vec<&str> {
+        page["data"].as_array().unwrap().iter().map(|row| row["id"].as_str().unwrap()).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_get_accounts_filters() {
+        let Some(pool) = test_pool().await else { return };
+        create_accounts(&pool).await;
+
+        let (status, body) = get(&pool, "/accounts").await;
+        assert_eq!(status, 200);
+        assert_eq!(ids(&body), vec!["acc_3", "acc_1", "acc_2"]);
+        assert_eq!(
+            body["data"][1],
+            json!({
+                "id": "acc_1",
+                "connection_id": "conn_1",
+                "provider": "plaid",
+                "name": "Checking",
+                "account_type": "depository",
+                "currency": "USD",
+                "balance": { "current": "100.50", "available": "90.00" },
+                "last_sync": null,
+            })
+        );
+        assert_eq!(body["data"][0]["balance"]["current"], "-250.26");
+
+        // Any combination of filters, each with any number of values
+        for (filter, expected) in [
+            ("connection_id=conn_1", vec!["acc_1", "acc_2"]),
+            ("connection_id=conn_2,conn_3", vec!["acc_3"]),
+            ("account_type=credit", vec!["acc_3"]),
+            ("currency=usd", vec!["acc_3", "acc_1"]),
+            ("connection_id=conn_1&currency=USD", vec!["acc_1"]),
+            ("account_type=credit,depository&currency=eur", vec!["acc_2"]),
+            ("connection_id=conn_2&account_type=depository&currency=USD", vec![]),
+        ] {
+            let (status, body) = get(&pool, &format!("/accounts?{}", filter)).await;
+            assert_eq!(status, 200, "{}", filter);
+            assert_eq!(ids(&body), expected, "{}", filter);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_get_accounts_pages() {
+        let Some(pool) = test_pool().await else { return };
+        create_accounts(&pool).await;
+
+        let (_, first) = get(&pool, "/accounts?limit=2&include_total=true").await;
+        assert_eq!(ids(&first), vec!["acc_3", "acc_1"]);
+        assert_eq!(first["total_items"], 3);
+        assert_eq!(first["total_pages"], 2);
+
+        let next = first["next_cursor"].as_str().unwrap();
+        let (_, second) = get(&pool, &format!("/accounts?limit=2&cursor={}", next)).await;
+        assert_eq!(ids(&second), vec!["acc_2"]);
+        assert_eq!(second["has_more"], false);
+
+        let prev = second["prev_cursor"].as_str().unwrap();
+        let (_, back) = get(&pool, &format!("/accounts?limit=2&cursor={}", prev)).await;
+        assert_eq!(ids(&back), vec!["acc_3", "acc_1"]);
+
+        let (_, by_page) = get(&pool, "/accounts?page=2&per_page=2").await;
+        assert_eq!(ids(&by_page), vec!["acc_2"]);
+
+        assert_eq!(get(&pool, "/accounts?cursor=nonsense").await.0, 400);
+        assert_eq!(get(&pool, "/accounts?limit=0").await.0, 400);
+    }
+
+    #[actix_web::test]
+    async fn test_get_account_balances_by_day() {
+        let Some(pool) = test_pool().await else { return };
+        create_account(&pool).await;
+
+        let (status, body) = get(&pool, "/accounts/acc_1/balances?from=2024-03-01&to=2024-03-03").await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["currency"], "USD");
+        assert_eq!(
+            body["balances"],
+            json!([
+                { "date": "2024-03-01", "balance": "101.00" },
+                { "date": "2024-03-02", "balance": "102.00" },
+                { "date": "2024-03-03", "balance": "103.00" },
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_get_account_balances_by_month() {
+        let Some(pool) = test_pool().await else { return };
+        create_account(&pool).await;
+
+        let (status, body) =
+            get(&pool, "/accounts/acc_1/balances?from=2024-02-01&to=2024-03-31&interval=month").await;
+
+        // The last balance in each month
+        assert_eq!(status, 200);
+        assert_eq!(
+            body["balances"],
+            json!([
+                { "date": "2024-02-01", "balance": "129.00" },
+                { "date": "2024-03-01", "balance": "110.00" },
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_get_account_balances_errors() {
+        let Some(pool) = test_pool().await else { return };
+        create_account(&pool).await;
+
+        assert_eq!(get(&pool, "/accounts/acc_missing/balances").await.0, 404);
+        assert_eq!(get(&pool, "/accounts/acc_1/balances?from=2024-03-02&to=2024-03-01").await.0, 400);
+        assert_eq!(get(&pool, "/accounts/acc_1/balances?interval=year").await.0, 400);
+    }
+
+    #[actix_web::test]
+    async fn test_get_account_balances_of_another_team() {
+        let Some(pool) = test_pool().await else { return };
+        create_account(&pool).await;
+        let other_team = Uuid::new_v4();
+        sqlx::query("INSERT INTO teams (id, name) VALUES ($1, 'Other')")
+            .bind(other_team)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(get_as(&pool, other_team, "/accounts/acc_1/balances").await.0, 404);
+    }
 }
@@ -1,81 +1,83 @@
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use utoipa::ToSchema;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
 
-#[derive(Debug, Deserialize, ToSchema)]
-pub struct AccountQuery {
-    pub connection_id: Option<String>,
-    pub account_type: Option<String>,
-    pub currency: Option<String>,
-    #[serde(default = "default_page")]
-    pub page: u32,
-    #[serde(default = "default_limit")]
-    pub limit: u32,
-}
+use crate::utils::query::comma_separated;
 
-fn default_page() -> u32 {
-    1
-}
-
-fn default_limit() -> u32 {
-    20
-}
-
-#[derive(Debug, Serialize, ToSchema)]
-pub struct AccountsResponse {
-    pub data: Vec<EnrichedAccount>,
-    pub page: u32,
-    pub total_pages: u32,
-    pub total_items: u64,
-    pub has_more: bool,
+/// Filters for listing accounts, each taking one or more comma separated
+/// values and matching any of them.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct AccountQuery {
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub connection_id: Vec<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub account_type: Vec<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub currency: Vec<String>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
 pub struct EnrichedAccount {
     pub id: String,
     pub connection_id: String,
-    pub name: String,
-    pub account_type: String,
-    pub currency: String,
+    pub provider: String,
+    pub name: Option<String>,
+    pub account_type: Option<String>,
+    pub currency: Option<String>,
     pub balance: Balance,
-    pub institution: Institution,
     pub last_sync: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
 pub struct Balance {
-    pub current: f64,
-    pub available: Option<f64>,
-    pub limit: Option<f64>,
+    pub current: Option<Decimal>,
+    pub available: Option<Decimal>,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct Institution {
-    pub id: String,
-    pub name: String,
-    pub logo_url: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BalanceInterval {
+    #[default]
+    Day,
+    Week,
+    Month,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct AccountStats {
-    pub total_accounts: u64,
-    pub total_balance: f64,
-    pub by_type: Vec<TypeStat>,
-    pub by_currency: Vec<CurrencyStat>,
+impl BalanceInterval {
+    /// The field passed to Postgres `date_trunc`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BalanceInterval::Day => "day",
+            BalanceInterval::Week => "week",
+            BalanceInterval::Month => "month",
+        }
+    }
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct TypeStat {
-    pub account_type: String,
-    pub count: u64,
-    pub total_balance: f64,
-    pub percentage: f64,
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BalanceHistoryQuery {
+    /// Defaults to 90 days before `to`
+    pub from: Option<NaiveDate>,
+    /// Defaults to today
+    pub to: Option<NaiveDate>,
+    #[serde(default)]
+    pub interval: BalanceInterval,
+}
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct BalancePoint {
+    /// First day of the interval
+    pub date: NaiveDate,
+    /// Balance at the end of the last day of the interval we have a snapshot for
+    pub balance: Decimal,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
-pub struct CurrencyStat {
-    pub currency: String,
-    pub count: u64,
-    pub total_balance: f64,
-    pub percentage: f64,
+pub struct BalanceHistoryResponse {
+    pub account_id: String,
+    pub currency: Option<String>,
+    pub interval: BalanceInterval,
+    pub balances: Vec<BalancePoint>,
 }
@@ -0,0 +1,139 @@
+use actix_web::{get, post, web, HttpResponse};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    api_keys::{self, ApiKey, NewApiKey},
+    error::AppError,
+    tenant::{Scope, Tenant},
+};
+
+const DEFAULT_GRACE_MINUTES: i64 = 60;
+
+#[derive(Debug, Deserialize)]
+pub struct RotateQuery {
+    /// How long the old key keeps working, in minutes.
+    pub grace_minutes: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeysResponse {
+    api_keys: Vec<ApiKey>,
+}
+
+#[get("/admin/api-keys")]
+pub async fn get_api_keys(
+    tenant: Tenant,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let api_keys = api_keys::list(&db, tenant.team_id).await?;
+
+    Ok(HttpResponse::Ok().json(ApiKeysResponse { api_keys }))
+}
+
+/// Creates a key for the caller's team, e.g. `{"name": "Dashboard",
+/// "scopes": ["read:accounts", "read:transactions"]}`. The response is the
+/// only time the key is shown.
+#[post("/admin/api-keys")]
+pub async fn create_api_key(
+    tenant: Tenant,
+    request: web::Json<NewApiKey>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let api_key = api_keys::create(db.get_ref(), tenant.team_id, &request).await?;
+
+    Ok(HttpResponse::Created().json(api_key))
+}
+
+#[post("/admin/api-keys/{id}/revoke")]
+pub async fn revoke_api_key(
+    tenant: Tenant,
+    path: web::Path<Uuid>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let api_key = api_keys::revoke(&db, tenant.team_id, path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(api_key))
+}
+
+/// Issues a replacement key, e.g. `?grace_minutes=0` to stop the old one
+/// working straight away.
+#[post("/admin/api-keys/{id}/rotate")]
+pub async fn rotate_api_key(
+    tenant: Tenant,
+    path: web::Path<Uuid>,
+    query: web::Query<RotateQuery>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let grace_minutes = query.grace_minutes.unwrap_or(DEFAULT_GRACE_MINUTES);
+    if grace_minutes < 0 {
+        return Err(AppError::BadRequest("grace_minutes must not be negative".to_string()));
+    }
+
+    let api_key = api_keys::rotate(&db, tenant.team_id, path.into_inner(), Duration::minutes(grace_minutes)).await?;
+
+    Ok(HttpResponse::Created().json(api_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tenant::Scopes,
+        utils::testing::{test_pool, TEST_TEAM_ID},
+    };
+    use actix_web::{http::StatusCode, test, App, HttpMessage};
+
+    #[actix_web::test]
+    async fn test_api_keys_need_admin_and_stay_in_their_team() {
+        let Some(pool) = test_pool().await else { return };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(get_api_keys)
+                .service(create_api_key)
+                .service(revoke_api_key),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/admin/api-keys")
+            .set_json(serde_json::json!({"name": "Dashboard", "scopes": ["read:accounts"]}))
+            .to_request();
+        request.extensions_mut().insert(Tenant::new(TEST_TEAM_ID, Scopes::all()));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert!(body["key"].as_str().unwrap().starts_with("mde_"));
+        assert!(body.get("key_hash").is_none());
+        let id = body["id"].as_str().unwrap();
+
+        // The key it made can't manage keys
+        let request = test::TestRequest::get().uri("/admin/api-keys").to_request();
+        request
+            .extensions_mut()
+            .insert(Tenant::new(TEST_TEAM_ID, [Scope::ReadAccounts].into_iter().collect()));
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Another team's admin can't see or revoke it
+        let other_team = Tenant::new(Uuid::from_u128(2), Scopes::all());
+        let request = test::TestRequest::get().uri("/admin/api-keys").to_request();
+        request.extensions_mut().insert(other_team);
+        let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["api_keys"], serde_json::json!([]));
+
+        let request = test::TestRequest::post()
+            .uri(&format!("/admin/api-keys/{}/revoke", id))
+            .to_request();
+        request.extensions_mut().insert(other_team);
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,21 +1,31 @@
-use actix_web::{post, web, HttpResponse};
+use actix_web::{get, post, web, HttpResponse};
 use sqlx::{PgPool, FromRow};
 use serde::{Serialize, Deserialize};
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use uuid::Uuid;
 
 use crate::{
-    error::AppError,
-    providers::ProviderFactory,
-    utils::ApiError,
+    config::Config,
+    credentials::{self, Credentials},
+    error::{AppError, AppResult},
+    jobs::{self, JobKind},
+    providers::{self, ProviderFactory},
+    routes::connections::require_connection,
+    sync,
+    tenant::{Scope, SystemPool, Tenant},
 };
 
+/// A connection as returned by the auth endpoints. Its tokens stay on the
+/// server.
 #[derive(Debug, Serialize, FromRow)]
 pub struct Connection {
     pub id: String,
     pub provider: String,
     pub status: String,
+    /// Why the connection is in the error or disconnected state
+    pub error: Option<String>,
     pub last_sync: Option<NaiveDateTime>,
-    pub refresh_token: String,
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,101 +37,206 @@ pub struct ExchangeTokenRequest {
 
 #[derive(Debug, Deserialize)]
 pub struct RefreshTokenRequest {
-    pub provider: String,
-    pub refresh_token: String,
+    pub connection_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRequisitionRequest {
+    pub institution_id: String,
+    pub redirect: String,
+    pub reference: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
-pub struct TokenResponse {
-    pub access_token: String,
-    pub refresh_token: String,
+pub struct CreateRequisitionResponse {
+    pub id: String,
+    pub link: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthLinkQuery {
+    pub redirect_uri: String,
+    pub state: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthLinkResponse {
+    pub link: String,
 }
 
 #[post("/api/v1/auth/exchange")]
 pub async fn exchange_token(
+    tenant: Tenant,
     request: web::Json<ExchangeTokenRequest>,
     db: web::Data<PgPool>,
     provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::WriteConnections)?;
+    require_provider_for_team(provider_factory.config(), &tenant, &request.provider)?;
     let provider = provider_factory
         .get_provider(&request.provider)
         .ok_or(AppError::BadRequest("Invalid provider".to_string()))?;
 
-    let (access_token, refresh_token) = provider
-        .exchange_token(&request.code, &request.redirect_uri)
-        .await
-        .map_err(|e| AppError::External(e.to_string()))?;
+    let token = provider
+        .exchange_token(providers::ExchangeTokenRequest {
+            code: request.code.clone(),
+            redirect_uri: request.redirect_uri.clone(),
+        })
+        .await?;
 
-    // Create connection record
+    // Create connection record, with the tokens encrypted for its id
+    let id = Uuid::new_v4().to_string();
+    let sealed = credentials::seal(
+        &provider_factory.config().token_keys,
+        &id,
+        &Credentials {
+            access_token: Some(token.access_token),
+            refresh_token: token.refresh_token,
+        },
+    )?;
+    let mut tx = tenant.begin(&db).await?;
     let connection = sqlx::query_as::<_, Connection>(
         r#"
-        INSERT INTO connections (provider, status, access_token, refresh_token)
-        VALUES ($1, 'active', $2, $3)
-        RETURNING id, provider, status, last_sync, refresh_token
+        INSERT INTO connections (
+            id, team_id, provider, status, access_token, refresh_token, token_key_id, token_data_key, expires_at
+        )
+        VALUES ($1, $2, $3, 'active', $4, $5, $6, $7, $8)
+        RETURNING id, provider, status, status_reason AS error, last_sync, expires_at
         "#,
     )
+    .bind(&id)
+    .bind(tenant.team_id)
     .bind(&request.provider)
-    .bind(&access_token)
-    .bind(&refresh_token)
-    .fetch_one(&**db)
+    .bind(&sealed.access_token)
+    .bind(&sealed.refresh_token)
+    .bind(&sealed.token_key_id)
+    .bind(&sealed.token_data_key)
+    .bind(token.expires_at)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|e| AppError::Database(e.to_string()))?;
+    tx.commit().await?;
 
-    Ok(HttpResponse::Ok().json(TokenResponse {
-        access_token,
-        refresh_token,
-    }))
+    // Pull the history in the background rather than making the client wait
+    jobs::enqueue(
+        &db,
+        &JobKind::InitialSync {
+            connection_id: connection.id.clone(),
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(connection))
+}
+
+/// The Wise personal token reads its owner's own accounts, so only the team
+/// it is configured for may connect it.
+fn require_provider_for_team(config: &Config, tenant: &Tenant, provider: &str) -> AppResult<()> {
+    if provider == "wise" && config.wise_api_token.is_some() && config.wise_team_id != Some(tenant.team_id) {
+        return Err(AppError::Authorization(
+            "Wise is only available to the team its personal token is for".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
+/// Renews a connection's provider tokens now, rather than waiting for them
+/// to be refreshed in the background.
 #[post("/api/v1/auth/refresh")]
 pub async fn refresh_token_handler(
+    tenant: Tenant,
     request: web::Json<RefreshTokenRequest>,
     db: web::Data<PgPool>,
+    system: web::Data<SystemPool>,
     provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
-    // Get connection
+    tenant.require(Scope::WriteConnections)?;
+    require_connection(&db, &tenant, &request.connection_id).await?;
+    sync::refresh_connection_token(&system, &provider_factory, &request.connection_id).await?;
+
+    let mut tx = tenant.begin(&db).await?;
     let connection = sqlx::query_as::<_, Connection>(
         r#"
-        SELECT id, provider, status, last_sync, refresh_token
-        FROM connections 
-        WHERE provider = $1 AND refresh_token = $2
+        SELECT id, provider, status, status_reason AS error, last_sync, expires_at
+        FROM connections
+        WHERE id = $1 AND team_id = $2
         "#,
     )
-    .bind(&request.provider)
-    .bind(&request.refresh_token)
-    .fetch_optional(&**db)
-    .await
-    .map_err(|e| AppError::Database(e.to_string()))?
+    .bind(&request.connection_id)
+    .bind(tenant.team_id)
+    .fetch_optional(&mut *tx)
+    .await?
     .ok_or(AppError::NotFound("Connection not found".to_string()))?;
+    tx.commit().await?;
 
-    // Get provider
-    let provider = provider_factory
-        .get_provider(&connection.provider)
-        .ok_or(AppError::BadRequest("Invalid provider".to_string()))?;
-
-    // Refresh token
-    let (new_access_token, new_refresh_token) = provider
-        .refresh_token(&connection.refresh_token)
-        .await
-        .map_err(|e| AppError::External(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(connection))
+}
 
-    // Update connection
-    sqlx::query(
-        r#"
-        UPDATE connections
-        SET access_token = $1, refresh_token = $2
-        WHERE id = $3
-        "#,
-    )
-    .bind(&new_access_token)
-    .bind(&new_refresh_token)
-    .bind(&connection.id)
-    .execute(&**db)
-    .await
-    .map_err(|e| AppError::Database(e.to_string()))?;
+/// Starts a GoCardless bank connection. The user is sent to `link`, and once
+/// they are redirected back the requisition id is exchanged like a code.
+#[post("/auth/gocardless/requisitions")]
+pub async fn create_gocardless_requisition(
+    tenant: Tenant,
+    request: web::Json<CreateRequisitionRequest>,
+    provider_factory: web::Data<ProviderFactory>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::WriteConnections)?;
+    let requisition = provider_factory
+        .gocardless()?
+        .create_requisition(&request.institution_id, &request.redirect, request.reference.as_deref())
+        .await?;
 
-    Ok(HttpResponse::Ok().json(TokenResponse {
-        access_token: new_access_token,
-        refresh_token: new_refresh_token,
+    Ok(HttpResponse::Ok().json(CreateRequisitionResponse {
+        id: requisition.id,
+        link: requisition.link,
     }))
 }
+
+/// Returns the TrueLayer consent URL. The code TrueLayer redirects back with
+/// goes through the regular exchange endpoint.
+#[get("/auth/truelayer/link")]
+pub async fn get_truelayer_auth_link(
+    tenant: Tenant,
+    query: web::Query<AuthLinkQuery>,
+    provider_factory: web::Data<ProviderFactory>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::WriteConnections)?;
+    let link = provider_factory
+        .truelayer()?
+        .auth_link(&query.redirect_uri, query.state.as_deref())?;
+
+    Ok(HttpResponse::Ok().json(AuthLinkResponse { link }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tenant::Scopes, utils::testing::test_config};
+
+    #[test]
+    fn test_wise_personal_token_is_only_for_its_team() {
+        let team_id = Uuid::from_u128(1);
+        let config = Config {
+            wise_api_token: Some("personal_token".to_string()),
+            wise_team_id: Some(team_id),
+            ..test_config()
+        };
+
+        assert!(require_provider_for_team(&config, &Tenant::new(team_id, Scopes::all()), "wise").is_ok());
+        let other = Tenant::new(Uuid::from_u128(2), Scopes::all());
+        assert!(matches!(
+            require_provider_for_team(&config, &other, "wise"),
+            Err(AppError::Authorization(_))
+        ));
+        assert!(require_provider_for_team(&config, &other, "plaid").is_ok());
+
+        // Each user connects their own account over OAuth
+        let oauth = Config {
+            wise_client_id: Some("client_id".to_string()),
+            wise_secret: Some("secret".to_string()),
+            ..test_config()
+        };
+        assert!(require_provider_for_team(&oauth, &other, "wise").is_ok());
+    }
+}
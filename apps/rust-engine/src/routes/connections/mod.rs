@@ -1,19 +1,23 @@
-use actix_web::{delete, get, web, HttpResponse};
+use actix_web::{delete, get, post, web, HttpResponse};
 use sqlx::{PgPool, FromRow};
 use serde::Serialize;
 use chrono::NaiveDateTime;
 
 use crate::{
-    error::AppError,
+    cache::{Partition, ResponseCache},
+    error::{AppError, AppResult},
+    jobs::{self, JobKind},
     providers::ProviderFactory,
+    sync,
+    tenant::{Scope, SystemPool, Tenant},
 };
 
 #[derive(Serialize, FromRow)]
 pub struct Connection {
     id: String,
-    institution_id: String,
-    institution_name: String,
+    provider: String,
     status: String,
+    error: Option<String>,
     last_sync: Option<NaiveDateTime>,
 }
 
@@ -37,25 +41,25 @@ pub struct ConnectionsResponse {
 
 #[get("/connections")]
 pub async fn get_connections(
+    tenant: Tenant,
     db: web::Data<PgPool>,
     _provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::ReadConnections)?;
+    let mut tx = tenant.begin(&db).await?;
     let connections: Vec<Connection> = sqlx::query_as::<_, Connection>(
         r#"
-        SELECT 
-            c.id,
-            c.institution_id,
-            i.name as institution_name,
-            c.status,
-            c.last_sync
-        FROM connections c
-        JOIN institutions i ON i.id = c.institution_id
-        ORDER BY c.last_sync DESC
+        SELECT id, provider, status, status_reason AS error, last_sync
+        FROM connections
+        WHERE team_id = $1
+        ORDER BY last_sync DESC NULLS LAST, id
         "#,
     )
-    .fetch_all(&**db)
+    .bind(tenant.team_id)
+    .fetch_all(&mut *tx)
     .await
     .map_err(|e| AppError::Database(e.to_string()))?;
+    tx.commit().await?;
 
 
     let total = connections.len() as i64;
@@ -68,33 +72,210 @@ pub async fn get_connections(
     }))
 }
 
+/// Revokes the connection's access at the provider, where it supports
+/// that, and then deletes it.
 #[delete("/connections/{id}")]
 pub async fn delete_connection(
+    tenant: Tenant,
     path: web::Path<String>,
     db: web::Data<PgPool>,
-    _provider_factory: web::Data<ProviderFactory>,
+    system: web::Data<SystemPool>,
+    cache: web::Data<ResponseCache>,
+    provider_factory: web::Data<ProviderFactory>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::WriteConnections)?;
+    let connection_id = path.into_inner();
+    require_connection(&db, &tenant, &connection_id).await?;
+    sync::revoke_connection(&system, &provider_factory, &connection_id).await?;
+
+    let mut tx = tenant.begin(&db).await?;
+    let deleted = sqlx::query("DELETE FROM connections WHERE id = $1 AND team_id = $2")
+        .bind(&connection_id)
+        .bind(tenant.team_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Database(e.to_string()))?
+        .rows_affected();
+    tx.commit().await?;
+
+    if deleted == 0 {
+        return Err(AppError::NotFound("Connection not found".to_string()));
+    }
+    cache.invalidate(Partition::Team(tenant.team_id)).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Queues an incremental sync and returns the job, which can be followed
+/// through the admin jobs endpoints.
+#[post("/connections/{id}/sync")]
+pub async fn sync_connection(
+    tenant: Tenant,
+    path: web::Path<String>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::WriteConnections)?;
+    let connection_id = path.into_inner();
+    require_connection(&db, &tenant, &connection_id).await?;
+
+    let job = jobs::enqueue(&db, &JobKind::IncrementalSync { connection_id }).await?;
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+#[get("/connections/{id}/status")]
+pub async fn get_connection_status(
+    tenant: Tenant,
+    path: web::Path<String>,
+    db: web::Data<PgPool>,
+    system: web::Data<SystemPool>,
+    provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::ReadConnections)?;
     let connection_id = path.into_inner();
+    require_connection(&db, &tenant, &connection_id).await?;
 
-    // Check if connection exists
+    let status = sync::check_connection_status(&system, &provider_factory, &connection_id).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": status })))
+}
+
+/// Fails with not found unless the connection belongs to the tenant's team,
+/// before handing it to code that works across teams, like syncs.
+pub async fn require_connection(pool: &PgPool, tenant: &Tenant, connection_id: &str) -> AppResult<()> {
+    let mut tx = tenant.begin(pool).await?;
     let exists = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM connections WHERE id = $1)",
+        "SELECT EXISTS(SELECT 1 FROM connections WHERE id = $1 AND team_id = $2)",
     )
-    .bind(&connection_id)
-    .fetch_one(&**db)
-    .await
-    .map_err(|e| AppError::Database(e.to_string()))?;
+    .bind(connection_id)
+    .bind(tenant.team_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
 
     if !exists {
         return Err(AppError::NotFound("Connection not found".to_string()));
     }
 
-    // Delete connection
-    sqlx::query("DELETE FROM connections WHERE id = $1")
-        .bind(&connection_id)
-        .execute(&**db)
-        .await
-        .map_err(|e| AppError::Database(e.to_string()))?;
+    Ok(())
+}
 
-    Ok(HttpResponse::NoContent().finish())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tenant::Scopes,
+        utils::testing::{test_config, test_pool, TEST_TEAM_ID},
+    };
+    use actix_web::{test, App, HttpMessage};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    const OTHER_TEAM_ID: Uuid = Uuid::from_u128(2);
+
+    async fn create_connections(pool: &PgPool) {
+        sqlx::query("INSERT INTO teams (id, name) VALUES ($1, 'Other')")
+            .bind(OTHER_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+        for (id, team_id) in [("conn_1", TEST_TEAM_ID), ("conn_2", OTHER_TEAM_ID)] {
+            sqlx::query("INSERT INTO connections (id, provider, team_id) VALUES ($1, 'plaid', $2)")
+                .bind(id)
+                .bind(team_id)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn call(pool: &PgPool, scopes: Scopes, request: test::TestRequest) -> u16 {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(SystemPool::for_tests(pool.clone())))
+                .app_data(web::Data::new(ProviderFactory::new(Arc::new(test_config()))))
+                .app_data(web::Data::new(ResponseCache::in_memory(10)))
+                .service(sync_connection)
+                .service(delete_connection),
+        )
+        .await;
+        let request = request.to_request();
+        request.extensions_mut().insert(Tenant::new(TEST_TEAM_ID, scopes));
+
+        test::call_service(&app, request).await.status().as_u16()
+    }
+
+    async fn connection_ids(pool: &PgPool) -> Vec<String> {
+        sqlx::query_scalar("SELECT id FROM connections ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_each_team_lists_its_own_connections() {
+        let Some(pool) = test_pool().await else { return };
+        create_connections(&pool).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ProviderFactory::new(Arc::new(test_config()))))
+                .service(get_connections),
+        )
+        .await;
+
+        for (team_id, expected) in [(TEST_TEAM_ID, "conn_1"), (OTHER_TEAM_ID, "conn_2")] {
+            let request = test::TestRequest::get().uri("/connections").to_request();
+            request.extensions_mut().insert(Tenant::new(team_id, Scopes::all()));
+            let body: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+
+            let ids: Vec<&str> = body["connections"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|connection| connection["id"].as_str().unwrap())
+                .collect();
+            assert_eq!(ids, vec![expected]);
+            assert_eq!(body["connections"][0]["provider"], "plaid");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_sync_connection_of_another_team() {
+        let Some(pool) = test_pool().await else { return };
+        create_connections(&pool).await;
+
+        assert_eq!(call(&pool, Scopes::all(), test::TestRequest::post().uri("/connections/conn_2/sync")).await, 404);
+        assert_eq!(call(&pool, Scopes::all(), test::TestRequest::post().uri("/connections/conn_1/sync")).await, 202);
+
+        let queued: Vec<String> = sqlx::query_scalar("SELECT dedupe_key FROM jobs")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(queued, vec!["incremental_sync:conn_1".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn test_delete_connection_of_another_team() {
+        let Some(pool) = test_pool().await else { return };
+        create_connections(&pool).await;
+
+        assert_eq!(call(&pool, Scopes::all(), test::TestRequest::delete().uri("/connections/conn_2")).await, 404);
+        assert_eq!(connection_ids(&pool).await, vec!["conn_1", "conn_2"]);
+
+        assert_eq!(call(&pool, Scopes::all(), test::TestRequest::delete().uri("/connections/conn_1")).await, 204);
+        assert_eq!(connection_ids(&pool).await, vec!["conn_2"]);
+    }
+
+    #[actix_web::test]
+    async fn test_changes_need_write_scope() {
+        let Some(pool) = test_pool().await else { return };
+        create_connections(&pool).await;
+        let read_only: Scopes = [Scope::ReadConnections].into_iter().collect();
+
+        assert_eq!(call(&pool, read_only, test::TestRequest::delete().uri("/connections/conn_1")).await, 403);
+        assert_eq!(call(&pool, read_only, test::TestRequest::post().uri("/connections/conn_1/sync")).await, 403);
+        assert_eq!(connection_ids(&pool).await, vec!["conn_1", "conn_2"]);
+    }
 }
@@ -1,6 +1,5 @@
 use actix_web::{get, web, HttpResponse};
 use sqlx::PgPool;
-use redis::aio::Connection;
 use serde::Serialize;
 use log;
 
@@ -1,21 +1,16 @@
 use actix_web::{get, post, web, HttpResponse};
-use sqlx::{PgPool, FromRow};
+use sqlx::{PgPool, FromRow, QueryBuilder};
 use serde::{Serialize, Deserialize};
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Utc};
 use crate::{
     error::AppError,
     providers::ProviderFactory,
-    utils::get_institution_logo,
+    utils::{get_institution_logo, query::Filter},
 };
 
-#[derive(Serialize, FromRow)]
-pub struct Connection {
-    id: String,
-    institution_id: String,
-    institution_name: String,
-    status: String,
-    last_sync: Option<NaiveDateTime>,
-}
+/// Columns as `Institution` reads them.
+const INSTITUTION_COLUMNS: &str = "id, name, logo AS logo_url, url AS website_url, primary_color, country, \
+     provider, COALESCE(oauth_support, false) AS oauth_support, last_update";
 
 #[derive(Serialize, FromRow)]
 pub struct Institution {
@@ -24,10 +19,10 @@ pub struct Institution {
     logo_url: Option<String>,
     website_url: Option<String>,
     primary_color: Option<String>,
-    country: String,
+    country: Option<String>,
     provider: String,
     oauth_support: bool,
-    last_update: Option<NaiveDateTime>,
+    last_update: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize)]
@@ -53,40 +48,26 @@ pub async fn get_institutions(
     db: web::Data<PgPool>,
     _provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
-    let page = query.page.unwrap_or(1);
-    let per_page = query.per_page.unwrap_or(10).min(100);
-    let offset = ((page - 1) * per_page) as i64;
-
-    // Build base query
-    let mut sql_query = sqlx::QueryBuilder::new(
-        "SELECT * FROM institutions WHERE 1=1",
-    );
-
-    // Add filters
-    if let Some(country) = &query.country {
-        sql_query.push(" AND country = ");
-        sql_query.push_bind(country);
-    }
-
-    if let Some(provider) = &query.provider {
-        sql_query.push(" AND provider = ");
-        sql_query.push_bind(provider);
-    }
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(10).clamp(1, 100);
+    let offset = (page - 1) * per_page;
 
+    let mut filter = Filter::new()
+        .eq("country", query.country.as_deref())
+        .eq("provider", query.provider.as_deref());
     if let Some(true) = query.oauth_only {
-        sql_query.push(" AND oauth_support = true");
+        filter = filter.sql("oauth_support");
     }
 
     // Get total count
-    let count_sql = format!("SELECT COUNT(*) FROM ({}) AS t", sql_query.sql());
-    let total: i64 = sqlx::query_scalar(&count_sql)
-        .fetch_one(&**db)
-        .await
-        .map_err(|e| AppError::Database(e.to_string()))?;
-
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM institutions");
+    filter.push_where(&mut count_query);
+    let total: i64 = count_query.build_query_scalar().fetch_one(&**db).await?;
 
     // Add pagination
-    sql_query.push(" ORDER BY name ASC LIMIT ");
+    let mut sql_query = QueryBuilder::new(format!("SELECT {} FROM institutions", INSTITUTION_COLUMNS));
+    filter.push_where(&mut sql_query);
+    sql_query.push(" ORDER BY name ASC, id ASC LIMIT ");
     sql_query.push_bind(per_page);
     sql_query.push(" OFFSET ");
     sql_query.push_bind(offset);
@@ -94,9 +75,7 @@ pub async fn get_institutions(
     let mut institutions: Vec<Institution> = sql_query
         .build_query_as::<Institution>()
         .fetch_all(&**db)
-        .await
-        .map_err(|e| AppError::Database(e.to_string()))?;
-
+        .await?;
 
     // Update logo URLs
     for institution in &mut institutions {
@@ -119,9 +98,10 @@ pub async fn get_institution(
     db: web::Data<PgPool>,
     _provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
-    let mut institution = sqlx::query_as::<_, Institution>(
-        "SELECT * FROM institutions WHERE id = $1",
-    )
+    let mut institution = sqlx::query_as::<_, Institution>(&format!(
+        "SELECT {} FROM institutions WHERE id = $1",
+        INSTITUTION_COLUMNS
+    ))
     .bind(path.into_inner())
     .fetch_optional(&**db)
     .await
@@ -151,3 +131,70 @@ pub async fn update_institution_usage(
 
     Ok(HttpResponse::NoContent().finish())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{test_config, test_pool};
+    use actix_web::{test, App};
+    use std::sync::Arc;
+
+    async fn get(pool: &PgPool, uri: &str) -> (u16, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ProviderFactory::new(Arc::new(test_config()))))
+                .service(get_institutions)
+                .service(get_institution),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        let status = response.status().as_u16();
+        let body = test::read_body(response).await;
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[actix_web::test]
+    async fn test_get_institutions() {
+        let Some(pool) = test_pool().await else { return };
+        sqlx::Executor::execute(
+            &pool,
+            r#"
+            INSERT INTO institutions (id, name, logo, country, url, provider, oauth_support) VALUES
+                ('ins_1', 'Chase', NULL, 'US', 'https://www.chase.com', 'plaid', true),
+                ('ins_2', 'Ally', NULL, 'US', NULL, 'teller', NULL),
+                ('ins_3', 'Monzo', 'https://logo', 'GB', NULL, 'gocardless', false);
+            "#,
+        )
+        .await
+        .unwrap();
+
+        let names = |body: &serde_json::Value| -> Vec<String> {
+            body["institutions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|institution| institution["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let (status, body) = get(&pool, "/institutions").await;
+        assert_eq!(status, 200);
+        assert_eq!(names(&body), vec!["Ally", "Chase", "Monzo"]);
+        assert_eq!(body["total"], 3);
+
+        let (_, body) = get(&pool, "/institutions?country=US&oauth_only=true").await;
+        assert_eq!(names(&body), vec!["Chase"]);
+        assert_eq!(body["institutions"][0]["website_url"], "https://www.chase.com");
+
+        let (_, body) = get(&pool, "/institutions?provider=teller&per_page=1").await;
+        assert_eq!(names(&body), vec!["Ally"]);
+        assert_eq!(body["institutions"][0]["oauth_support"], false);
+
+        let (status, body) = get(&pool, "/institutions/ins_3").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["logo_url"], "https://logo");
+        assert_eq!(get(&pool, "/institutions/ins_missing").await.0, 404);
+    }
+}
@@ -0,0 +1,196 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    jobs::{self, Job, JobKind, JobStatus},
+    routes::connections::require_connection,
+    tenant::{Scope, SystemPool, Tenant},
+};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct JobsQuery {
+    pub status: Option<String>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct JobsResponse {
+    jobs: Vec<Job>,
+}
+
+/// Lists the most recent jobs on the team's connections, e.g.
+/// `?status=dead` for the dead letters. Like retrying and cancelling, it
+/// goes through the system pool, since jobs are matched to the team through
+/// its connections and filtered by it explicitly.
+#[get("/admin/jobs")]
+pub async fn get_jobs(
+    tenant: Tenant,
+    query: web::Query<JobsQuery>,
+    db: web::Data<SystemPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let status = query.status.as_deref().map(str::parse::<JobStatus>).transpose()?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let jobs = jobs::list(&db, Some(tenant.team_id), status, query.kind.as_deref(), limit).await?;
+
+    Ok(HttpResponse::Ok().json(JobsResponse { jobs }))
+}
+
+/// Queues a job on one of the team's connections by hand, e.g.
+/// `{"kind": "initial_sync", "payload": {"connection_id": "..."}}`. Jobs
+/// that belong to no team, like institution imports, can't be queued here.
+#[post("/admin/jobs")]
+pub async fn create_job(
+    tenant: Tenant,
+    request: web::Json<JobKind>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let Some(connection_id) = request.connection_id() else {
+        return Err(AppError::Authorization(format!(
+            "{} jobs can't be queued by a team",
+            request.name()
+        )));
+    };
+    require_connection(&db, &tenant, connection_id).await?;
+    let job = jobs::enqueue(&db, &request).await?;
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+#[post("/admin/jobs/{id}/retry")]
+pub async fn retry_job(
+    tenant: Tenant,
+    path: web::Path<Uuid>,
+    db: web::Data<SystemPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let job = jobs::retry(&db, Some(tenant.team_id), path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(job))
+}
+
+#[post("/admin/jobs/{id}/cancel")]
+pub async fn cancel_job(
+    tenant: Tenant,
+    path: web::Path<Uuid>,
+    db: web::Data<SystemPool>,
+) -> Result<HttpResponse, AppError> {
+    tenant.require(Scope::Admin)?;
+    let job = jobs::cancel(&db, Some(tenant.team_id), path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(job))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tenant::Scopes,
+        utils::testing::{test_pool, TEST_TEAM_ID},
+    };
+    use actix_web::{test, App, HttpMessage};
+
+    const OTHER_TEAM_ID: Uuid = Uuid::from_u128(2);
+
+    /// A dead sync on each team's connection.
+    async fn create_jobs(pool: &PgPool) -> (Job, Job) {
+        sqlx::query("INSERT INTO teams (id, name) VALUES ($1, 'Other')")
+            .bind(OTHER_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+        let mut jobs = Vec::new();
+        for (id, team_id) in [("conn_1", TEST_TEAM_ID), ("conn_2", OTHER_TEAM_ID)] {
+            sqlx::query("INSERT INTO connections (id, provider, team_id) VALUES ($1, 'plaid', $2)")
+                .bind(id)
+                .bind(team_id)
+                .execute(pool)
+                .await
+                .unwrap();
+            let kind = JobKind::IncrementalSync {
+                connection_id: id.to_string(),
+            };
+            let job = jobs::enqueue(pool, &kind).await.unwrap();
+            sqlx::query("UPDATE jobs SET status = 'dead', last_error = 'boom' WHERE id = $1")
+                .bind(job.id)
+                .execute(pool)
+                .await
+                .unwrap();
+            jobs.push(job);
+        }
+        (jobs.remove(0), jobs.remove(0))
+    }
+
+    async fn call(pool: &PgPool, request: test::TestRequest) -> (u16, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(SystemPool::for_tests(pool.clone())))
+                .service(get_jobs)
+                .service(create_job)
+                .service(retry_job)
+                .service(cancel_job),
+        )
+        .await;
+        let request = request.to_request();
+        request.extensions_mut().insert(Tenant::new(TEST_TEAM_ID, Scopes::all()));
+        let response = test::call_service(&app, request).await;
+        let status = response.status().as_u16();
+        let body = test::read_body(response).await;
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[actix_web::test]
+    async fn test_jobs_of_another_team() {
+        let Some(pool) = test_pool().await else { return };
+        let (own, other) = create_jobs(&pool).await;
+
+        let (status, body) = call(&pool, test::TestRequest::get().uri("/admin/jobs")).await;
+        assert_eq!(status, 200);
+        let ids: Vec<&str> = body["jobs"].as_array().unwrap().iter().map(|job| job["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec![own.id.to_string()]);
+
+        let retry = |id: Uuid| test::TestRequest::post().uri(&format!("/admin/jobs/{}/retry", id));
+        assert_eq!(call(&pool, retry(other.id)).await.0, 404);
+        assert_eq!(call(&pool, retry(own.id)).await.0, 200);
+        let cancel = |id: Uuid| test::TestRequest::post().uri(&format!("/admin/jobs/{}/cancel", id));
+        assert_eq!(call(&pool, cancel(other.id)).await.0, 404);
+        assert_eq!(call(&pool, cancel(own.id)).await.0, 200);
+
+        let statuses: Vec<String> = sqlx::query_scalar("SELECT status FROM jobs ORDER BY payload->>'connection_id'")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(statuses, vec!["cancelled", "dead"]);
+    }
+
+    #[actix_web::test]
+    async fn test_create_job_of_another_team() {
+        let Some(pool) = test_pool().await else { return };
+        create_jobs(&pool).await;
+        let create = |body: serde_json::Value| test::TestRequest::post().uri("/admin/jobs").set_json(body);
+
+        let foreign = serde_json::json!({ "kind": "initial_sync", "payload": { "connection_id": "conn_2" } });
+        assert_eq!(call(&pool, create(foreign)).await.0, 404);
+        let global = serde_json::json!({ "kind": "reencrypt_tokens", "payload": {} });
+        assert_eq!(call(&pool, create(global)).await.0, 403);
+        let own = serde_json::json!({ "kind": "initial_sync", "payload": { "connection_id": "conn_1" } });
+        assert_eq!(call(&pool, create(own)).await.0, 202);
+
+        let queued: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM jobs WHERE status = 'queued'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(queued, 1);
+    }
+}
@@ -0,0 +1,26 @@
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    providers::{ProviderFactory, ProviderInfo, PROVIDER_CONTRACT_VERSION},
+};
+
+#[derive(Serialize)]
+pub struct ProvidersResponse {
+    /// The `Provider` contract version this build's providers follow
+    contract_version: u32,
+    providers: Vec<ProviderInfo>,
+}
+
+/// Lists every provider, whether it is enabled in this deployment and what
+/// it supports, so clients can hide features a provider doesn't offer.
+#[get("/providers")]
+pub async fn get_providers(
+    provider_factory: web::Data<ProviderFactory>,
+) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(ProvidersResponse {
+        contract_version: PROVIDER_CONTRACT_VERSION,
+        providers: provider_factory.list(),
+    }))
+}
@@ -27,10 +27,10 @@ pub async fn get_rates(
 ) -> Result<HttpResponse, actix_web::Error> {
     let query = query.into_inner();
 
-    let symbols = query
+    let symbols: Vec<String> = query
         .symbols
         .map(|s| s.split(',').map(|s| s.trim().to_string()).collect())
-        .unwrap_or_else(|| vec![]);
+        .unwrap_or_default();
 
     // Use your new method
     let rates_map = rates_client
@@ -1,105 +1,322 @@
 use actix_web::{get, web, HttpResponse};
-use sqlx::{PgPool, FromRow};
+use sqlx::{PgPool, FromRow, QueryBuilder};
 use serde::Serialize;
 use chrono::NaiveDate;
+use rust_decimal::Decimal;
 
+pub mod schema;
 use crate::{
     error::AppError,
-    providers::ProviderFactory,
+    routes::transactions::schema::{Direction, TransactionsQuery},
+    tenant::{Scope, Tenant},
+    utils::{
+        paginate::{Cursor, Keyset, PaginatedResponse, PaginationParams},
+        query::{Filter, Value},
+    },
 };
 
 #[derive(Serialize, FromRow)]
 pub struct Transaction {
     id: String,
     account_id: String,
-    amount: f64,
-    currency: String,
-    description: String,
+    amount: Decimal,
+    currency: Option<String>,
+    description: Option<String>,
+    merchant: Option<String>,
+    category: Option<String>,
+    status: String,
     date: NaiveDate,
     account_name: Option<String>,
     account_type: Option<String>,
 }
 
-#[derive(Serialize)]
-pub struct TransactionsResponse {
-    transactions: Vec<Transaction>,
-    total: i64,
-    page: i64,
-    per_page: i64,
-}
-
-#[derive(serde::Deserialize)]
-pub struct TransactionQuery {
-    page: Option<i64>,
-    per_page: Option<i64>,
-    account_id: Option<String>,
-    connection_id: Option<String>,
-    start_date: Option<NaiveDate>,
-    end_date: Option<NaiveDate>,
-}
-
+/// Newest first unless `sort` says otherwise, with the id breaking ties so
+/// rows keep a stable order to page through.
 #[get("/transactions")]
 pub async fn get_transactions(
-    query: web::Query<TransactionQuery>,
+    tenant: Tenant,
+    pagination: web::Query<PaginationParams>,
+    query: web::Query<TransactionsQuery>,
     db: web::Data<PgPool>,
-    _provider_factory: web::Data<ProviderFactory>,
 ) -> Result<HttpResponse, AppError> {
-    let page = query.page.unwrap_or(1);
-    let per_page = query.per_page.unwrap_or(10).min(100);
-    let offset = ((page - 1) * per_page) as i64;
-
-    // Build base query
-    let mut sql_query = sqlx::QueryBuilder::new(
-        "SELECT t.*, a.name as account_name, a.account_type 
-         FROM transactions t 
-         LEFT JOIN accounts a ON t.account_id = a.id 
-         WHERE 1=1",
+    tenant.require(Scope::ReadTransactions)?;
+    pagination.validate().map_err(|e| AppError::BadRequest(e.to_string()))?;
+    query.validate().map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let sort = query.sort;
+    let cursor = pagination.cursor()?;
+    if cursor.as_ref().is_some_and(|cursor| cursor.sort != sort.as_str()) {
+        return Err(AppError::BadRequest("Cursor is for a different sort".to_string()));
+    }
+
+    // Rows after the cursor, or before it paging backward, which are
+    // fetched nearest first and put back in order afterwards
+    let keyset = Keyset {
+        key: sort.column(),
+        id: "t.id",
+        descending: sort.is_descending(),
+    };
+    let filter = filter(&tenant, &query);
+    let page_filter = match &cursor {
+        Some(cursor) => {
+            let invalid = || AppError::BadRequest("Invalid cursor".to_string());
+            let key = if sort.is_by_amount() {
+                Value::from(cursor.key.parse::<Decimal>().map_err(|_| invalid())?)
+            } else {
+                Value::from(NaiveDate::parse_from_str(&cursor.key, "%Y-%m-%d").map_err(|_| invalid())?)
+            };
+            keyset.past(filter.clone(), cursor, key)
+        }
+        None => filter.clone(),
+    };
+
+    let mut sql_query = QueryBuilder::new(
+        "SELECT t.id, t.account_id, t.amount, t.currency, t.description,
+                t.merchant_name AS merchant, t.merchant_category AS category, t.status,
+                t.transaction_date AS date, a.name AS account_name, a.account_type
+         FROM transactions t
+         LEFT JOIN accounts a ON t.account_id = a.id",
     );
+    page_filter.push_where(&mut sql_query);
+    keyset.push_page(&mut sql_query, &pagination, cursor.as_ref());
+
+    let mut tx = tenant.begin(&db).await?;
+    let transactions: Vec<Transaction> = sql_query
+        .build_query_as::<Transaction>()
+        .fetch_all(&mut *tx)
+        .await?;
 
-    // Add filters
-    if let Some(account_id) = &query.account_id {
-        sql_query.push(" AND t.account_id = ");
-        sql_query.push_bind(account_id);
+    let mut response = PaginatedResponse::keyset(transactions, &pagination, cursor.as_ref(), |transaction| Cursor {
+        key: if sort.is_by_amount() {
+            transaction.amount.to_string()
+        } else {
+            transaction.date.to_string()
+        },
+        id: transaction.id.clone(),
+        sort: sort.as_str().to_string(),
+        backward: false,
+    });
+
+    if pagination.include_total {
+        let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM transactions t");
+        filter.push_where(&mut count_query);
+        let total: i64 = count_query.build_query_scalar().fetch_one(&mut *tx).await?;
+        response = response.with_total(pagination.limit, total as u64);
     }
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
 
-    if let Some(connection_id) = &query.connection_id {
-        sql_query.push(" AND t.connection_id = ");
-        sql_query.push_bind(connection_id);
+/// The team's current transactions matching `query`.
+fn filter(tenant: &Tenant, query: &TransactionsQuery) -> Filter {
+    let currencies: Vec<String> = query.currency.iter().map(|currency| currency.to_uppercase()).collect();
+    let filter = Filter::new()
+        .eq("t.team_id", tenant.team_id)
+        .sql("t.superseded_by IS NULL")
+        .any("t.account_id", &query.account_id)
+        .any("t.connection_id", &query.connection_id)
+        .gte("t.transaction_date", query.from_date)
+        .lte("t.transaction_date", query.to_date)
+        .gte("t.amount", query.min_amount)
+        .lte("t.amount", query.max_amount)
+        .any("t.merchant_category", &query.category)
+        .contains("t.merchant_name", query.merchant.as_deref())
+        .eq("t.status", query.status.map(|status| status.as_str()))
+        .any("t.currency", &currencies)
+        .contains("t.description", query.search.as_deref());
+
+    match query.direction {
+        Some(Direction::Inflow) => filter.sql("t.amount > 0"),
+        Some(Direction::Outflow) => filter.sql("t.amount < 0"),
+        None => filter,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tenant::Scopes,
+        utils::testing::{test_pool, TEST_TEAM_ID},
+    };
+    use actix_web::{test, App, HttpMessage};
+    use serde_json::Value;
+
+    /// Five transactions over three days, two of which share a date.
+    async fn create_transactions(pool: &PgPool) {
+        sqlx::query("INSERT INTO connections (id, provider, team_id) VALUES ('conn_1', 'plaid', $1)")
+            .bind(TEST_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO accounts (id, connection_id, name, currency) VALUES ('acc_1', 'conn_1', 'Checking', 'USD')")
+            .execute(pool)
+            .await
+            .unwrap();
+        for (id, date) in [
+            ("tx_1", "2024-01-01"),
+            ("tx_2", "2024-01-02"),
+            ("tx_3", "2024-01-02"),
+            ("tx_4", "2024-01-03"),
+            ("tx_5", "2024-01-03"),
+        ] {
+            sqlx::query(
+                "INSERT INTO transactions (id, connection_id, account_id, amount, currency, description, transaction_date)
+                 VALUES ($1, 'conn_1', 'acc_1', -10, 'USD', 'Coffee', $2::date)",
+            )
+            .bind(id)
+            .bind(date)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    async fn get(pool: &PgPool, uri: &str) -> (u16, Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .service(get_transactions),
+        )
+        .await;
+        let request = test::TestRequest::get().uri(uri).to_request();
+        request.extensions_mut().insert(Tenant::new(TEST_TEAM_ID, Scopes::all()));
 
-    if let Some(start_date) = &query.start_date {
-        sql_query.push(" AND t.date >= ");
-        sql_query.push_bind(start_date);
+        let response = test::call_service(&app, request).await;
+        let status = response.status().as_u16();
+        let body = test::read_body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
     }
 
-    if let Some(end_date) = &query.end_date {
-        sql_query.push(" AND t.date <= ");
-        sql_query.push_bind(end_date);
+    fn ids(page: &Value) -> Vec<&str> {
+        page["data"].as_array().unwrap().iter().map(|row| row["id"].as_str().unwrap()).collect()
     }
 
-    // Get total count
-    let count_sql = format!("SELECT COUNT(*) FROM ({}) AS t", sql_query.sql());
-    let total: i64 = sqlx::query_scalar(&count_sql)
-        .fetch_one(&**db)
+    #[actix_web::test]
+    async fn test_pages_through_with_cursors() {
+        let Some(pool) = test_pool().await else { return };
+        create_transactions(&pool).await;
+
+        let (_, first) = get(&pool, "/transactions?limit=2").await;
+        assert_eq!(ids(&first), vec!["tx_5", "tx_4"]);
+        assert_eq!(first["data"][0]["date"], "2024-01-03");
+        assert!(first.get("total_items").is_none());
+        assert!(first.get("prev_cursor").is_none());
+
+        // A transaction arriving mid-scroll doesn't shift the next page
+        sqlx::query(
+            "INSERT INTO transactions (id, connection_id, account_id, amount, transaction_date)
+             VALUES ('tx_6', 'conn_1', 'acc_1', -5, '2024-01-04')",
+        )
+        .execute(&pool)
         .await
-        .map_err(|e| AppError::Database(e.to_string()))?;
+        .unwrap();
 
-    // Add sorting and pagination
-    sql_query.push(" ORDER BY t.date DESC LIMIT ");
-    sql_query.push_bind(per_page);
-    sql_query.push(" OFFSET ");
-    sql_query.push_bind(offset);
+        let next = first["next_cursor"].as_str().unwrap();
+        let (_, second) = get(&pool, &format!("/transactions?limit=2&cursor={}", next)).await;
+        assert_eq!(ids(&second), vec!["tx_3", "tx_2"]);
+        assert_eq!(second["has_more"], true);
 
-    let transactions: Vec<Transaction> = sql_query
-        .build_query_as::<Transaction>()
-        .fetch_all(&**db)
+        let next = second["next_cursor"].as_str().unwrap();
+        let (_, last) = get(&pool, &format!("/transactions?limit=2&cursor={}", next)).await;
+        assert_eq!(ids(&last), vec!["tx_1"]);
+        assert_eq!(last["has_more"], false);
+        assert!(last.get("next_cursor").is_none());
+
+        // And back again
+        let prev = last["prev_cursor"].as_str().unwrap();
+        let (_, back) = get(&pool, &format!("/transactions?limit=2&cursor={}", prev)).await;
+        assert_eq!(ids(&back), vec!["tx_3", "tx_2"]);
+        let prev = back["prev_cursor"].as_str().unwrap();
+        let (_, back) = get(&pool, &format!("/transactions?limit=2&cursor={}", prev)).await;
+        assert_eq!(ids(&back), vec!["tx_5", "tx_4"]);
+        let prev = back["prev_cursor"].as_str().unwrap();
+        let (_, back) = get(&pool, &format!("/transactions?limit=2&cursor={}", prev)).await;
+        assert_eq!(ids(&back), vec!["tx_6"]);
+        assert!(back.get("prev_cursor").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_pages_by_number_with_total() {
+        let Some(pool) = test_pool().await else { return };
+        create_transactions(&pool).await;
+
+        let (_, page) = get(&pool, "/transactions?page=2&per_page=2&include_total=true").await;
+        assert_eq!(ids(&page), vec!["tx_3", "tx_2"]);
+        assert_eq!(page["page"], 2);
+        assert_eq!(page["total_items"], 5);
+        assert_eq!(page["total_pages"], 3);
+
+        let (_, filtered) = get(&pool, "/transactions?start_date=2024-01-02&end_date=2024-01-02&include_total=true").await;
+        assert_eq!(ids(&filtered), vec!["tx_3", "tx_2"]);
+        assert_eq!(filtered["total_items"], 2);
+    }
+
+    #[actix_web::test]
+    async fn test_filters_and_sorts() {
+        let Some(pool) = test_pool().await else { return };
+        create_transactions(&pool).await;
+        sqlx::Executor::execute(
+            &pool,
+            r#"
+            INSERT INTO accounts (id, connection_id, name, currency) VALUES ('acc_2', 'conn_1', 'Savings', 'EUR');
+            UPDATE transactions SET amount = 2500, description = 'Salary', merchant_name = 'Acme Inc', merchant_category = 'income'
+            WHERE id = 'tx_1';
+            UPDATE transactions SET amount = -42.50, merchant_name = 'Corner Store', merchant_category = 'groceries', status = 'pending'
+            WHERE id = 'tx_2';
+            UPDATE transactions SET account_id = 'acc_2', currency = 'EUR', amount = -3, description = '100% Arabica'
+            WHERE id = 'tx_4';
+            "#,
+        )
         .await
-        .map_err(|e| AppError::Database(e.to_string()))?;
-
-    Ok(HttpResponse::Ok().json(TransactionsResponse {
-        transactions,
-        total,
-        page,
-        per_page,
-    }))
+        .unwrap();
+
+        for (filter, expected) in [
+            ("direction=inflow", vec!["tx_1"]),
+            ("direction=outflow&min_amount=-20", vec!["tx_5", "tx_4", "tx_3"]),
+            ("min_amount=-10&max_amount=-3", vec!["tx_5", "tx_4", "tx_3"]),
+            ("status=pending", vec!["tx_2"]),
+            ("status=posted&account_id=acc_1", vec!["tx_5", "tx_3", "tx_1"]),
+            ("category=groceries,income", vec!["tx_2", "tx_1"]),
+            ("merchant=store", vec!["tx_2"]),
+            ("currency=eur", vec!["tx_4"]),
+            ("account_id=acc_2,acc_3", vec!["tx_4"]),
+            ("search=COFFEE", vec!["tx_5", "tx_3", "tx_2"]),
+            // Wildcards are matched literally
+            ("search=%25", vec!["tx_4"]),
+            ("sort=amount", vec!["tx_2", "tx_3", "tx_5", "tx_4", "tx_1"]),
+            ("sort=date&from_date=2024-01-02", vec!["tx_2", "tx_3", "tx_4", "tx_5"]),
+        ] {
+            let (status, page) = get(&pool, &format!("/transactions?{}", filter)).await;
+            assert_eq!(status, 200, "{}", filter);
+            assert_eq!(ids(&page), expected, "{}", filter);
+        }
+
+        // Cursors follow the sort they came from
+        let (_, first) = get(&pool, "/transactions?sort=-amount&limit=2").await;
+        assert_eq!(ids(&first), vec!["tx_1", "tx_4"]);
+        let next = first["next_cursor"].as_str().unwrap();
+        let (_, second) = get(&pool, &format!("/transactions?sort=-amount&limit=2&cursor={}", next)).await;
+        assert_eq!(ids(&second), vec!["tx_5", "tx_3"]);
+        assert_eq!(get(&pool, &format!("/transactions?sort=date&cursor={}", next)).await.0, 400);
+
+        assert_eq!(get(&pool, "/transactions?min_amount=10&max_amount=1").await.0, 400);
+        assert_eq!(get(&pool, "/transactions?sort=merchant").await.0, 400);
+        assert_eq!(get(&pool, "/transactions?direction=sideways").await.0, 400);
+    }
+
+    #[actix_web::test]
+    async fn test_rejects_invalid_cursor() {
+        let Some(pool) = test_pool().await else { return };
+
+        assert_eq!(get(&pool, "/transactions?cursor=nonsense").await.0, 400);
+        let wrong_key = Cursor {
+            key: "yesterday".to_string(),
+            id: "tx_1".to_string(),
+            sort: "-date".to_string(),
+            backward: false,
+        };
+        assert_eq!(get(&pool, &format!("/transactions?cursor={}", wrong_key.encode())).await.0, 400);
+        assert_eq!(get(&pool, "/transactions?limit=101").await.0, 400);
+    }
 }
@@ -1,65 +1,116 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Deserialize;
 use utoipa::ToSchema;
 
-#[derive(Debug, Deserialize, ToSchema)]
+use crate::utils::query::comma_separated;
+
+/// Filters for listing transactions. Fields taking more than one value take
+/// them comma separated, e.g. `account_id=a,b`, and match any of them.
+#[derive(Debug, Default, Deserialize, ToSchema)]
 pub struct TransactionsQuery {
-    pub account_id: Option<String>,
-    pub from_date: Option<DateTime<Utc>>,
-    pub to_date: Option<DateTime<Utc>>,
-    pub min_amount: Option<f64>,
-    pub max_amount: Option<f64>,
-    pub category: Option<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub account_id: Vec<String>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub connection_id: Vec<String>,
+    #[serde(alias = "start_date")]
+    pub from_date: Option<NaiveDate>,
+    #[serde(alias = "end_date")]
+    pub to_date: Option<NaiveDate>,
+    /// Amounts are signed, outflows negative
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub category: Vec<String>,
+    /// Part of the merchant's name, in any case
     pub merchant: Option<String>,
-    #[serde(default = "default_page")]
-    pub page: u32,
-    #[serde(default = "default_limit")]
-    pub limit: u32,
+    pub status: Option<TransactionStatusFilter>,
+    pub direction: Option<Direction>,
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub currency: Vec<String>,
+    /// Part of the description, in any case
+    pub search: Option<String>,
+    #[serde(default)]
+    pub sort: TransactionSort,
 }
 
-fn default_page() -> u32 {
-    1
+impl TransactionsQuery {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let (Some(from), Some(to)) = (self.from_date, self.to_date) {
+            if from > to {
+                return Err("from_date must not be after to_date");
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_amount, self.max_amount) {
+            if min > max {
+                return Err("min_amount must not be more than max_amount");
+            }
+        }
+        Ok(())
+    }
 }
 
-fn default_limit() -> u32 {
-    20
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatusFilter {
+    Pending,
+    Posted,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct TransactionsResponse {
-    pub data: Vec<EnrichedTransaction>,
-    pub page: u32,
-    pub total_pages: u32,
-    pub total_items: u64,
-    pub has_more: bool,
+impl TransactionStatusFilter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionStatusFilter::Pending => "pending",
+            TransactionStatusFilter::Posted => "posted",
+        }
+    }
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct EnrichedTransaction {
-    pub id: String,
-    pub account_id: String,
-    pub date: DateTime<Utc>,
-    pub description: Option<String>,
-    pub amount: f64,
-    pub currency: String,
-    pub category: Option<String>,
-    pub merchant: Option<String>,
-    pub merchant_logo: Option<String>,
-    pub pending: bool,
+/// Money coming in, with a positive amount, or going out, with a negative one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inflow,
+    Outflow,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct TransactionStats {
-    pub total_transactions: u64,
-    pub total_amount: f64,
-    pub average_amount: f64,
-    pub categories: Vec<CategoryStat>,
+/// `date` or `amount`, ascending, or descending with a leading `-`. Newest
+/// first by default. Ties are broken by id in the same direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum TransactionSort {
+    #[serde(rename = "date")]
+    Date,
+    #[default]
+    #[serde(rename = "-date")]
+    DateDesc,
+    #[serde(rename = "amount")]
+    Amount,
+    #[serde(rename = "-amount")]
+    AmountDesc,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct CategoryStat {
-    pub category: String,
-    pub count: u64,
-    pub total_amount: f64,
-    pub percentage: f64,
+impl TransactionSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionSort::Date => "date",
+            TransactionSort::DateDesc => "-date",
+            TransactionSort::Amount => "amount",
+            TransactionSort::AmountDesc => "-amount",
+        }
+    }
+
+    pub fn column(&self) -> &'static str {
+        match self {
+            TransactionSort::Date | TransactionSort::DateDesc => "t.transaction_date",
+            TransactionSort::Amount | TransactionSort::AmountDesc => "t.amount",
+        }
+    }
+
+    pub fn is_by_amount(&self) -> bool {
+        matches!(self, TransactionSort::Amount | TransactionSort::AmountDesc)
+    }
+
+    pub fn is_descending(&self) -> bool {
+        matches!(self, TransactionSort::DateDesc | TransactionSort::AmountDesc)
+    }
 }
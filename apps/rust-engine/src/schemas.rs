@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use crate::providers::types::{Account, Institution, Transaction};
+use crate::providers::types::{Account, Transaction};
 
 #[derive(Debug, Deserialize)]
 pub struct AccountQuery {
@@ -0,0 +1,244 @@
+use chrono::{Duration, NaiveDate};
+use rust_decimal::Decimal;
+use sqlx::FromRow;
+use std::collections::BTreeMap;
+
+use crate::{error::AppResult, providers::types::Account, utils::money};
+
+use super::DbTransaction;
+
+/// Where a balance snapshot came from, from most to least trustworthy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotSource {
+    /// The balance the provider reported when the account was synced
+    Reported,
+    /// The running balance the provider gave with that day's transactions
+    Running,
+    /// Worked out by undoing later transactions from a known balance
+    Derived,
+}
+
+impl SnapshotSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotSource::Reported => "reported",
+            SnapshotSource::Running => "running",
+            SnapshotSource::Derived => "derived",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub date: NaiveDate,
+    /// Balance at the end of the day
+    pub balance: Decimal,
+    pub source: SnapshotSource,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct DayTransaction {
+    pub transaction_date: NaiveDate,
+    pub amount: Decimal,
+    /// Balance right after the transaction, when the provider reports it
+    pub balance: Option<Decimal>,
+}
+
+/// Stores the account's balance as today's snapshot and fills in every day
+/// back to its oldest transaction. Backfilled days never overwrite a balance
+/// the provider reported on that day.
+pub async fn record_snapshots(tx: &mut DbTransaction<'_>, account: &Account, today: NaiveDate) -> AppResult<()> {
+    // Pending transactions are left out, as most providers only count them
+    // in the available balance
+    let transactions = sqlx::query_as::<_, DayTransaction>(
+        r#"
+        SELECT transaction_date, amount, balance
+        FROM transactions
+        WHERE account_id = $1 AND status = 'posted' AND superseded_by IS NULL
+          AND transaction_date IS NOT NULL
+        "#,
+    )
+    .bind(&account.id)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let today_snapshot = Snapshot {
+        date: today,
+        balance: account.balance.amount,
+        source: SnapshotSource::Reported,
+    };
+
+    let snapshots: Vec<Snapshot> = std::iter::once(today_snapshot)
+        .chain(backfill(account.balance.amount, today, &transactions))
+        .collect();
+    let dates: Vec<NaiveDate> = snapshots.iter().map(|snapshot| snapshot.date).collect();
+    let balances: Vec<Decimal> = snapshots
+        .iter()
+        .map(|snapshot| money::round(snapshot.balance, &account.currency))
+        .collect();
+    let sources: Vec<&str> = snapshots.iter().map(|snapshot| snapshot.source.as_str()).collect();
+
+    // One statement for the whole history, which only rewrites the days
+    // whose balance actually changed
+    sqlx::query(
+        r#"
+        INSERT INTO balance_snapshots (account_id, date, balance, currency, source)
+        SELECT $1, snapshot.date, snapshot.balance, $2, snapshot.source
+        FROM UNNEST($3::DATE[], $4::NUMERIC[], $5::TEXT[]) AS snapshot(date, balance, source)
+        ON CONFLICT (account_id, date) DO UPDATE SET
+            balance = EXCLUDED.balance,
+            currency = EXCLUDED.currency,
+            source = EXCLUDED.source,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE (EXCLUDED.source = 'reported' OR balance_snapshots.source <> 'reported')
+          AND (balance_snapshots.balance, balance_snapshots.currency, balance_snapshots.source)
+              IS DISTINCT FROM (EXCLUDED.balance, EXCLUDED.currency, EXCLUDED.source)
+        "#,
+    )
+    .bind(&account.id)
+    .bind(&account.currency)
+    .bind(&dates)
+    .bind(&balances)
+    .bind(&sources)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// End of day balances for every day before `today`, back to the day before
+/// the oldest transaction, found by walking backwards from `current`.
+/// Running balances re-anchor the walk wherever they pin down a day's end.
+pub fn backfill(current: Decimal, today: NaiveDate, transactions: &[DayTransaction]) -> Vec<Snapshot> {
+    let mut days: BTreeMap<NaiveDate, Vec<&DayTransaction>> = BTreeMap::new();
+    for transaction in transactions.iter().filter(|transaction| transaction.transaction_date <= today) {
+        days.entry(transaction.transaction_date).or_default().push(transaction);
+    }
+    let Some(&earliest) = days.keys().next() else {
+        return Vec::new();
+    };
+
+    let mut snapshots = Vec::new();
+    let mut end_of_day = current;
+    let mut date = today;
+
+    while date >= earliest {
+        let day = days.get(&date).map(Vec::as_slice).unwrap_or_default();
+        // Today ends at the reported balance, which beats a running balance
+        if date < today {
+            let snapshot = match day_end_from_running(day) {
+                Some(balance) => Snapshot {
+                    date,
+                    balance,
+                    source: SnapshotSource::Running,
+                },
+                None => Snapshot {
+                    date,
+                    balance: end_of_day,
+                    source: SnapshotSource::Derived,
+                },
+            };
+            end_of_day = snapshot.balance;
+            snapshots.push(snapshot);
+        }
+
+        end_of_day -= day.iter().map(|transaction| transaction.amount).sum::<Decimal>();
+        date -= Duration::days(1);
+    }
+
+    snapshots.push(Snapshot {
+        date,
+        balance: end_of_day,
+        source: SnapshotSource::Derived,
+    });
+    snapshots
+}
+
+/// The order of a day's transactions isn't stored, so the running balance of
+/// its last transaction is the one that, less the whole day's amounts,
+/// matches the balance before some transaction of that day.
+fn day_end_from_running(day: &[&DayTransaction]) -> Option<Decimal> {
+    let total: Decimal = day.iter().map(|transaction| transaction.amount).sum();
+    let starts: Vec<Decimal> = day
+        .iter()
+        .filter_map(|transaction| Some(transaction.balance? - transaction.amount))
+        .collect();
+
+    day.iter()
+        .filter_map(|transaction| transaction.balance)
+        .find(|balance| starts.contains(&(balance - total)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    fn transaction(day: u32, amount: Decimal, balance: Option<Decimal>) -> DayTransaction {
+        DayTransaction {
+            transaction_date: date(day),
+            amount,
+            balance,
+        }
+    }
+
+    fn balances(snapshots: &[Snapshot]) -> Vec<(NaiveDate, Decimal, SnapshotSource)> {
+        snapshots
+            .iter()
+            .map(|snapshot| (snapshot.date, snapshot.balance, snapshot.source))
+            .collect()
+    }
+
+    #[test]
+    fn test_backfill_walks_backwards() {
+        let transactions = [
+            transaction(2, dec!(-20), None),
+            transaction(4, dec!(100), None),
+            transaction(5, dec!(-5.5), None),
+        ];
+
+        let snapshots = backfill(dec!(500), date(5), &transactions);
+
+        assert_eq!(
+            balances(&snapshots),
+            vec![
+                (date(4), dec!(505.5), SnapshotSource::Derived),
+                (date(3), dec!(405.5), SnapshotSource::Derived),
+                (date(2), dec!(405.5), SnapshotSource::Derived),
+                (date(1), dec!(425.5), SnapshotSource::Derived),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backfill_anchors_on_running_balances() {
+        // The reported balance disagrees with the running balances, e.g.
+        // because of a transaction we never saw
+        let transactions = [
+            transaction(3, dec!(-10), Some(dec!(190))),
+            transaction(3, dec!(-30), Some(dec!(210))),
+            transaction(3, dec!(50), Some(dec!(240))),
+            transaction(4, dec!(-1), None),
+        ];
+
+        let snapshots = backfill(dec!(300), date(5), &transactions);
+
+        assert_eq!(
+            balances(&snapshots),
+            vec![
+                (date(4), dec!(300), SnapshotSource::Derived),
+                (date(3), dec!(210), SnapshotSource::Running),
+                (date(2), dec!(200), SnapshotSource::Derived),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backfill_without_transactions() {
+        assert!(backfill(dec!(100), date(5), &[]).is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::{
+    error::AppResult,
+    providers::types::{Capabilities, Transaction},
+    utils::money,
+};
+
+use super::DbTransaction;
+
+/// What a stored transaction's `id` is derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKey {
+    /// The provider's own id, falling back to the fingerprint for
+    /// transactions that come without one
+    ProviderId,
+    /// Always the fingerprint, for providers whose ids can't be relied on
+    Fingerprint,
+}
+
+impl TransactionKey {
+    pub fn for_capabilities(capabilities: &Capabilities) -> Self {
+        if capabilities.stable_transaction_ids {
+            TransactionKey::ProviderId
+        } else {
+            TransactionKey::Fingerprint
+        }
+    }
+}
+
+/// What writing a batch of transactions did to the database.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct IngestSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    /// Already stored exactly as reported
+    pub unchanged: usize,
+    /// The stored id of each transaction, in the order they were given
+    #[serde(skip)]
+    pub ids: Vec<String>,
+}
+
+/// Upserts transactions so that reading the same ones again changes nothing.
+/// Identical transactions within a batch, like two coffees on the same day,
+/// are told apart by the order they arrive in.
+pub async fn upsert_transactions(
+    tx: &mut DbTransaction<'_>,
+    connection_id: &str,
+    transactions: &[&Transaction],
+    key: TransactionKey,
+) -> AppResult<IngestSummary> {
+    let mut summary = IngestSummary::default();
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    let mut legacy_occurrences: HashMap<String, usize> = HashMap::new();
+
+    for &transaction in transactions {
+        let fingerprint = next_fingerprint(&mut occurrences, fingerprint_source(transaction));
+        let legacy = next_fingerprint(&mut legacy_occurrences, legacy_fingerprint_source(transaction));
+
+        let id = match key {
+            TransactionKey::ProviderId if !transaction.id.is_empty() => transaction.id.clone(),
+            _ if legacy != fingerprint => stored_id(tx, connection_id, transaction, &fingerprint, &legacy)
+                .await?
+                .unwrap_or_else(|| format!("fp_{}", fingerprint)),
+            _ => format!("fp_{}", fingerprint),
+        };
+
+        match upsert_transaction(tx, connection_id, &id, &fingerprint, transaction).await? {
+            Some(true) => summary.inserted += 1,
+            Some(false) => summary.updated += 1,
+            None => summary.unchanged += 1,
+        }
+        summary.ids.push(id);
+    }
+
+    Ok(summary)
+}
+
+/// A stable hash of the account, date, amount, currency and description.
+#[cfg(test)]
+pub fn fingerprint(transaction: &Transaction) -> String {
+    hash(&fingerprint_source(transaction), 0)
+}
+
+/// The fingerprint `transaction` was stored under before amounts were
+/// fingerprinted in their currency's decimals.
+#[cfg(test)]
+pub fn legacy_fingerprint(transaction: &Transaction) -> String {
+    hash(&legacy_fingerprint_source(transaction), 0)
+}
+
+/// Hashes `source`, numbering repeats of it within a batch.
+fn next_fingerprint(occurrences: &mut HashMap<String, usize>, source: String) -> String {
+    let occurrence = occurrences.entry(source.clone()).or_insert(0);
+    let fingerprint = hash(&source, *occurrence);
+    *occurrence += 1;
+    fingerprint
+}
+
+fn fingerprint_source(transaction: &Transaction) -> String {
+    // In the currency's own decimals, so three decimal amounts that differ
+    // in the last one aren't taken for the same transaction. Two decimal
+    // currencies read as they did before, and their stored fingerprints
+    // keep matching
+    source_with_amount(transaction, money::round(transaction.amount, &transaction.currency))
+}
+
+/// Always two decimals, as amounts were fingerprinted before. Only differs
+/// from `fingerprint_source` for currencies that don't use two.
+fn legacy_fingerprint_source(transaction: &Transaction) -> String {
+    source_with_amount(transaction, format!("{:.2}", transaction.amount))
+}
+
+fn source_with_amount(transaction: &Transaction, amount: impl std::fmt::Display) -> String {
+    format!(
+        "{}|{}|{}|{}|{}",
+        transaction.account_id,
+        transaction.date.date_naive(),
+        amount,
+        transaction.currency.to_uppercase(),
+        normalize_description(&transaction.description)
+    )
+}
+
+/// The id of the row already stored for a fingerprinted transaction in a
+/// currency that doesn't use two decimals. Rows stored under the legacy
+/// fingerprint are found by it the first time they are read again, and are
+/// then rewritten with the current one while keeping their id.
+async fn stored_id(
+    tx: &mut DbTransaction<'_>,
+    connection_id: &str,
+    transaction: &Transaction,
+    fingerprint: &str,
+    legacy: &str,
+) -> AppResult<Option<String>> {
+    Ok(sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT id FROM transactions
+        WHERE connection_id = $1 AND account_id = $2 AND fingerprint IN ($3, $4) AND id LIKE 'fp\_%'
+        ORDER BY fingerprint = $3 DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(connection_id)
+    .bind(&transaction.account_id)
+    .bind(fingerprint)
+    .bind(legacy)
+    .fetch_optional(&mut **tx)
+    .await?)
+}
+
+fn hash(source: &str, occurrence: usize) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_bytes());
+    if occurrence > 0 {
+        hasher.update(format!("#{}", occurrence).as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Banks are inconsistent about case, punctuation and spacing between reads
+/// of the same transaction, so only letters and digits are compared.
+fn normalize_description(description: &str) -> String {
+    description
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns whether the row was inserted, or `None` if it was already stored
+/// with the same values and left alone.
+async fn upsert_transaction(
+    tx: &mut DbTransaction<'_>,
+    connection_id: &str,
+    id: &str,
+    fingerprint: &str,
+    transaction: &Transaction,
+) -> AppResult<Option<bool>> {
+    let inserted = sqlx::query_scalar::<_, bool>(
+        r#"
+        INSERT INTO transactions (
+            id, connection_id, account_id, amount, currency, description,
+            merchant_name, merchant_category, transaction_date, status, balance,
+            currency_rate, currency_source, fingerprint
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+        ON CONFLICT (id) DO UPDATE SET
+            amount = EXCLUDED.amount,
+            currency = EXCLUDED.currency,
+            description = EXCLUDED.description,
+            merchant_name = EXCLUDED.merchant_name,
+            merchant_category = EXCLUDED.merchant_category,
+            transaction_date = EXCLUDED.transaction_date,
+            status = EXCLUDED.status,
+            balance = EXCLUDED.balance,
+            currency_rate = EXCLUDED.currency_rate,
+            currency_source = EXCLUDED.currency_source,
+            fingerprint = EXCLUDED.fingerprint,
+            -- A pending transaction that posts under its own id replaces
+            -- whatever it was linked to
+            superseded_by = CASE WHEN EXCLUDED.status = 'pending' THEN transactions.superseded_by END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE (
+            transactions.amount, transactions.currency, transactions.description,
+            transactions.merchant_name, transactions.merchant_category,
+            transactions.transaction_date, transactions.status, transactions.balance,
+            transactions.currency_rate, transactions.currency_source, transactions.fingerprint
+        ) IS DISTINCT FROM (
+            EXCLUDED.amount, EXCLUDED.currency, EXCLUDED.description,
+            EXCLUDED.merchant_name, EXCLUDED.merchant_category,
+            EXCLUDED.transaction_date, EXCLUDED.status, EXCLUDED.balance,
+            EXCLUDED.currency_rate, EXCLUDED.currency_source, EXCLUDED.fingerprint
+        )
+        RETURNING (xmax = 0)
+        "#,
+    )
+    .bind(id)
+    .bind(connection_id)
+    .bind(&transaction.account_id)
+    .bind(transaction.amount)
+    .bind(&transaction.currency)
+    .bind(&transaction.description)
+    .bind(&transaction.merchant)
+    .bind(&transaction.category)
+    .bind(transaction.date.date_naive())
+    .bind(transaction.status.to_string())
+    .bind(transaction.balance)
+    .bind(transaction.currency_rate)
+    .bind(&transaction.currency_source)
+    .bind(fingerprint)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::types::TransactionStatus;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+
+    fn transaction(description: &str) -> Transaction {
+        Transaction {
+            id: String::new(),
+            account_id: "acc_1".to_string(),
+            amount: dec!(-12.50),
+            currency: "EUR".to_string(),
+            date: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+            description: description.to_string(),
+            merchant: None,
+            category: None,
+            status: TransactionStatus::Posted,
+            balance: None,
+            currency_rate: None,
+            currency_source: None,
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_formatting() {
+        let original = transaction("CARD PAYMENT - Coffee Shop");
+
+        assert_eq!(fingerprint(&original), fingerprint(&transaction("card payment coffee  shop")));
+        assert_eq!(fingerprint(&original).len(), 64);
+
+        // Only the day counts, not the time
+        let mut later = transaction("CARD PAYMENT - Coffee Shop");
+        later.date = Utc.with_ymd_and_hms(2024, 3, 1, 18, 0, 0).unwrap();
+        assert_eq!(fingerprint(&original), fingerprint(&later));
+    }
+
+    #[test]
+    fn test_fingerprint_differs_by_content() {
+        let original = transaction("Coffee Shop");
+
+        let mut amount = transaction("Coffee Shop");
+        amount.amount = dec!(-12.51);
+        let mut currency = transaction("Coffee Shop");
+        currency.currency = "GBP".to_string();
+        let mut account = transaction("Coffee Shop");
+        account.account_id = "acc_2".to_string();
+
+        for other in [amount, currency, account, transaction("Bakery")] {
+            assert_ne!(fingerprint(&original), fingerprint(&other));
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_uses_currency_decimals() {
+        let mut fils = transaction("Coffee Shop");
+        fils.currency = "KWD".to_string();
+        fils.amount = dec!(-1.125);
+        let mut more_fils = fils.clone();
+        more_fils.amount = dec!(-1.124);
+        assert_ne!(fingerprint(&fils), fingerprint(&more_fils));
+
+        // Two decimal amounts hash the same as they always have
+        let legacy = hash("acc_1|2024-03-01|-12.50|EUR|coffee shop", 0);
+        let mut cents = transaction("Coffee Shop");
+        cents.amount = dec!(-12.5);
+        assert_eq!(fingerprint(&cents), legacy);
+    }
+}
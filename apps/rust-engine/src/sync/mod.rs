@@ -0,0 +1,845 @@
+use chrono::Utc;
+use serde::Serialize;
+use std::{future::Future, sync::Arc};
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres};
+
+use crate::{
+    credentials::{self, Credentials, SealedCredentials},
+    error::{AppError, AppResult},
+    providers::{
+        types::{
+            Account, Capability, ConnectionState, DeleteConnectionRequest, GetConnectionStatusRequest,
+            RefreshTokenRequest, SyncTransactionsRequest, TransactionSync,
+        },
+        Provider, ProviderError, ProviderFactory, ProviderResult,
+    },
+};
+
+pub mod balances;
+pub mod ingest;
+pub mod reconcile;
+pub mod scheduler;
+
+pub use ingest::TransactionKey;
+pub use reconcile::ReconcileConfig;
+pub use scheduler::SyncScheduler;
+
+type DbTransaction<'c> = sqlx::Transaction<'c, Postgres>;
+
+/// `added`, `modified` and `removed` are what the provider reported, the
+/// rest is what that did to the stored transactions. `reconciled` counts
+/// pending transactions superseded by their posted versions.
+#[derive(Debug, Default, Serialize)]
+pub struct SyncSummary {
+    pub accounts: usize,
+    pub added: usize,
+    pub modified: usize,
+    pub removed: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub reconciled: usize,
+}
+
+#[derive(Debug, FromRow)]
+struct SyncConnection {
+    provider: String,
+    sync_cursor: Option<String>,
+}
+
+#[derive(FromRow)]
+struct RefreshConnection {
+    provider: String,
+    #[sqlx(flatten)]
+    sealed: SealedCredentials,
+}
+
+/// Pulls everything that changed for a connection since its last checkpoint
+/// and writes it to the database.
+pub async fn sync_connection(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+) -> AppResult<SyncSummary> {
+    run_sync(pool, provider_factory, connection_id, false).await
+}
+
+/// Reads a connection's full history from the provider, as for a newly
+/// linked connection. The stored checkpoint is only replaced once the sync
+/// has been applied.
+pub async fn full_sync_connection(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+) -> AppResult<SyncSummary> {
+    run_sync(pool, provider_factory, connection_id, true).await
+}
+
+async fn run_sync(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+    full: bool,
+) -> AppResult<SyncSummary> {
+    let (provider, credentials, sync_cursor) = load_connection(pool, provider_factory, connection_id, Some(Capability::Transactions)).await?;
+
+    let checkpoint = if full { None } else { sync_cursor };
+    let sync = call_with_refresh(pool, provider_factory, connection_id, &provider, &credentials, |access_token| {
+        provider.sync_transactions(SyncTransactionsRequest {
+            access_token,
+            checkpoint: checkpoint.clone(),
+        })
+    })
+    .await?;
+
+    let key = TransactionKey::for_capabilities(&provider.capabilities());
+    let reconcile = ReconcileConfig::from(provider_factory.config());
+    apply_sync(pool, connection_id, &sync, key, &reconcile).await
+}
+
+/// Swaps a connection's refresh token for new credentials and stores them
+/// along with their expiry. The row stays locked meanwhile, so concurrent
+/// refreshes take turns rather than spending the same refresh token twice.
+///
+/// A rejected refresh token can only be fixed by the user reconnecting, so
+/// the connection is moved to `error` with the provider's reason.
+pub async fn refresh_connection_token(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+) -> AppResult<Credentials> {
+    let keyring = &provider_factory.config().token_keys;
+    let mut tx = pool.begin().await?;
+
+    let connection = sqlx::query_as::<_, RefreshConnection>(
+        r#"
+        SELECT provider, access_token, refresh_token, token_key_id, token_data_key
+        FROM connections
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(connection_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Connection not found".to_string()))?;
+
+    let provider = provider_factory.require(&connection.provider, Capability::TokenRefresh)?;
+    let stored = credentials::open(keyring, connection_id, &connection.sealed)?;
+    let refresh_token = stored
+        .refresh_token
+        .clone()
+        .ok_or_else(|| AppError::BadRequest("Connection has no refresh token".to_string()))?;
+
+    let token = match provider.refresh_token(RefreshTokenRequest { refresh_token }).await {
+        Ok(token) => token,
+        Err(e @ ProviderError::Unauthorized(_)) => {
+            log::warn!("Refresh token for connection {} was rejected", connection_id);
+            set_connection_status(&mut *tx, connection_id, &ConnectionState::Error, Some(&e.to_string())).await?;
+            tx.commit().await?;
+            return Err(e.into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Some providers only issue a new refresh token now and then
+    let credentials = Credentials {
+        access_token: Some(token.access_token),
+        refresh_token: token.refresh_token.or(stored.refresh_token),
+    };
+
+    credentials::store(&mut *tx, keyring, connection_id, &credentials).await?;
+    sqlx::query("UPDATE connections SET expires_at = $1 WHERE id = $2")
+        .bind(token.expires_at)
+        .bind(connection_id)
+        .execute(&mut *tx)
+        .await?;
+    set_connection_status(&mut *tx, connection_id, &ConnectionState::Connected, None).await?;
+    tx.commit().await?;
+
+    Ok(credentials)
+}
+
+/// Calls the provider with the connection's access token. If the token is
+/// rejected and the provider can refresh it, the call is retried once with a
+/// new one.
+async fn call_with_refresh<T, F, Fut>(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+    provider: &Arc<dyn Provider>,
+    credentials: &Credentials,
+    call: F,
+) -> AppResult<T>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = ProviderResult<T>>,
+{
+    let access_token = credentials
+        .access_token
+        .clone()
+        .ok_or_else(|| AppError::BadRequest("Connection has no access token".to_string()))?;
+    let refreshable = provider.capabilities().token_refresh && credentials.refresh_token.is_some();
+
+    match call(access_token).await {
+        Ok(result) => return Ok(result),
+        Err(ProviderError::Unauthorized(_)) if refreshable => {
+            log::info!("Access token for connection {} was rejected, refreshing it", connection_id);
+        }
+        Err(e) => return Err(handle_provider_error(pool, connection_id, e).await),
+    }
+
+    let refreshed = refresh_connection_token(pool, provider_factory, connection_id).await?;
+    let access_token = refreshed.access_token.unwrap_or_default();
+    match call(access_token).await {
+        Ok(result) => Ok(result),
+        Err(e) => Err(handle_provider_error(pool, connection_id, e).await),
+    }
+}
+
+/// The provider no longer accepts this connection's credentials, e.g. an
+/// expired consent, so stop treating it as active.
+async fn handle_provider_error(pool: &PgPool, connection_id: &str, error: ProviderError) -> AppError {
+    if let ProviderError::Unauthorized(_) = error {
+        let reason = error.to_string();
+        if let Err(e) = set_connection_status(pool, connection_id, &ConnectionState::Disconnected, Some(&reason)).await {
+            return e;
+        }
+    }
+    error.into()
+}
+
+/// Asks the provider whether a connection is still usable and records the
+/// answer in `connections.status`.
+pub async fn check_connection_status(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+) -> AppResult<ConnectionState> {
+    let (provider, credentials, _) = load_connection(pool, provider_factory, connection_id, None).await?;
+
+    let status = call_with_refresh(pool, provider_factory, connection_id, &provider, &credentials, |access_token| {
+        provider.get_connection_status(GetConnectionStatusRequest { access_token })
+    })
+    .await?
+    .status;
+
+    set_connection_status(pool, connection_id, &status, None).await?;
+    Ok(status)
+}
+
+/// Revokes a connection's access at its provider, where the provider
+/// supports that, before the connection is deleted. Access the provider
+/// already rejects or no longer knows about counts as revoked.
+pub async fn revoke_connection(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+) -> AppResult<()> {
+    let provider: String = sqlx::query_scalar("SELECT provider FROM connections WHERE id = $1")
+        .bind(connection_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Connection not found".to_string()))?;
+
+    let Some(provider) = provider_factory
+        .get_provider(&provider)
+        .filter(|provider| provider.capabilities().supports(Capability::DeleteConnection))
+    else {
+        return Ok(());
+    };
+    let credentials = credentials::load(pool, &provider_factory.config().token_keys, connection_id).await?;
+    let Some(access_token) = credentials.access_token else {
+        return Ok(());
+    };
+
+    match provider.delete_connection(DeleteConnectionRequest { access_token }).await {
+        Ok(()) | Err(ProviderError::Unauthorized(_)) | Err(ProviderError::NotFound(_)) => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+async fn load_connection(
+    pool: &PgPool,
+    provider_factory: &ProviderFactory,
+    connection_id: &str,
+    capability: Option<Capability>,
+) -> AppResult<(Arc<dyn Provider>, Credentials, Option<String>)> {
+    let connection = sqlx::query_as::<_, SyncConnection>(
+        "SELECT provider, sync_cursor FROM connections WHERE id = $1",
+    )
+    .bind(connection_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Connection not found".to_string()))?;
+
+    let provider = match capability {
+        Some(capability) => provider_factory.require(&connection.provider, capability)?,
+        None => provider_factory
+            .get_provider(&connection.provider)
+            .ok_or_else(|| AppError::BadRequest("Invalid provider".to_string()))?,
+    };
+    let credentials = credentials::load(pool, &provider_factory.config().token_keys, connection_id).await?;
+
+    Ok((provider, credentials, connection.sync_cursor))
+}
+
+/// `reason` says why a connection is in the error or disconnected state, and
+/// is cleared along with it.
+async fn set_connection_status<'e>(
+    executor: impl PgExecutor<'e>,
+    connection_id: &str,
+    status: &ConnectionState,
+    reason: Option<&str>,
+) -> AppResult<()> {
+    let status = match status {
+        ConnectionState::Connected => "active",
+        ConnectionState::Disconnected => "disconnected",
+        ConnectionState::Error => "error",
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE connections
+        SET status = $1, status_reason = $2, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $3
+        "#,
+    )
+    .bind(status)
+    .bind(reason)
+    .bind(connection_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Applies a provider change set and advances the connection's checkpoint in
+/// one database transaction. If anything fails the old checkpoint is kept, so
+/// the next sync resumes from the same place and replays the same changes.
+pub async fn apply_sync(
+    pool: &PgPool,
+    connection_id: &str,
+    sync: &TransactionSync,
+    key: TransactionKey,
+    reconcile: &ReconcileConfig,
+) -> AppResult<SyncSummary> {
+    let mut tx = pool.begin().await?;
+
+    for account in &sync.accounts {
+        upsert_account(&mut tx, connection_id, account).await?;
+    }
+
+    let transactions: Vec<_> = sync.added.iter().chain(&sync.modified).collect();
+    let ingested = ingest::upsert_transactions(&mut tx, connection_id, &transactions, key).await?;
+
+    if !sync.removed.is_empty() {
+        sqlx::query("DELETE FROM transactions WHERE connection_id = $1 AND id = ANY($2)")
+            .bind(connection_id)
+            .bind(&sync.removed)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let reconciled = reconcile::reconcile_pending(&mut tx, connection_id, &ingested.ids, reconcile).await?;
+
+    let today = Utc::now().date_naive();
+    for account in &sync.accounts {
+        balances::record_snapshots(&mut tx, account, today).await?;
+    }
+
+    // Providers without a checkpoint keep whatever cursor was stored
+    sqlx::query(
+        r#"
+        UPDATE connections
+        SET sync_cursor = COALESCE($1, sync_cursor),
+            last_sync = timezone('utc', now()),
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $2
+        "#,
+    )
+    .bind(&sync.checkpoint)
+    .bind(connection_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(SyncSummary {
+        accounts: sync.accounts.len(),
+        added: sync.added.len(),
+        modified: sync.modified.len(),
+        removed: sync.removed.len(),
+        inserted: ingested.inserted,
+        updated: ingested.updated,
+        unchanged: ingested.unchanged,
+        reconciled,
+    })
+}
+
+async fn upsert_account(
+    tx: &mut DbTransaction<'_>,
+    connection_id: &str,
+    account: &Account,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO accounts (id, connection_id, name, account_type, currency, balance, available)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (id) DO UPDATE SET
+            name = EXCLUDED.name,
+            account_type = EXCLUDED.account_type,
+            currency = EXCLUDED.currency,
+            balance = EXCLUDED.balance,
+            available = EXCLUDED.available,
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(&account.id)
+    .bind(connection_id)
+    .bind(&account.name)
+    .bind(account.account_type.to_string())
+    .bind(&account.currency)
+    .bind(account.balance.amount)
+    .bind(account.balance.available)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::types::{AccountType, Balance, Transaction, TransactionStatus};
+    use crate::utils::testing::{test_config, test_pool, FakeProvider, TEST_TEAM_ID};
+    use chrono::TimeZone;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn account() -> Account {
+        Account {
+            id: "acc_1".to_string(),
+            name: "Checking".to_string(),
+            account_type: AccountType::Checking,
+            balance: Balance {
+                amount: dec!(100),
+                currency: "USD".to_string(),
+                available: Some(dec!(90)),
+            },
+            currency: "USD".to_string(),
+            institution_id: "ins_1".to_string(),
+            last_sync: None,
+        }
+    }
+
+    fn transaction(id: &str, account_id: &str, status: TransactionStatus) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            account_id: account_id.to_string(),
+            amount: dec!(-12.5),
+            currency: "USD".to_string(),
+            date: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            description: "Coffee".to_string(),
+            merchant: None,
+            category: None,
+            status,
+            balance: None,
+            currency_rate: None,
+            currency_source: None,
+        }
+    }
+
+    async fn create_connection(pool: &PgPool) {
+        sqlx::query("INSERT INTO connections (id, provider, access_token, team_id) VALUES ('conn_1', 'plaid', 'token', $1)")
+            .bind(TEST_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    async fn apply(pool: &PgPool, sync: &TransactionSync, key: TransactionKey) -> AppResult<SyncSummary> {
+        apply_sync(pool, "conn_1", sync, key, &ReconcileConfig::default()).await
+    }
+
+    async fn cursor(pool: &PgPool) -> Option<String> {
+        sqlx::query_scalar("SELECT sync_cursor FROM connections WHERE id = 'conn_1'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_apply_sync_added_modified_removed() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let first = TransactionSync {
+            accounts: vec![account()],
+            added: vec![
+                transaction("tx_1", "acc_1", TransactionStatus::Pending),
+                transaction("tx_2", "acc_1", TransactionStatus::Posted),
+            ],
+            checkpoint: Some("cursor_1".to_string()),
+            ..Default::default()
+        };
+        apply(&pool, &first, TransactionKey::ProviderId).await.unwrap();
+        let balances: (Decimal, Option<Decimal>) = sqlx::query_as("SELECT balance, available FROM accounts")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(balances, (dec!(100), Some(dec!(90))));
+
+        let second = TransactionSync {
+            modified: vec![transaction("tx_1", "acc_1", TransactionStatus::Posted)],
+            removed: vec!["tx_2".to_string()],
+            checkpoint: Some("cursor_2".to_string()),
+            ..Default::default()
+        };
+        let summary = apply(&pool, &second, TransactionKey::ProviderId).await.unwrap();
+
+        assert_eq!(summary.modified, 1);
+        assert_eq!(summary.removed, 1);
+        let rows: Vec<(String, String)> = sqlx::query_as("SELECT id, status FROM transactions ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![("tx_1".to_string(), "posted".to_string())]);
+        assert_eq!(cursor(&pool).await.as_deref(), Some("cursor_2"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_sync_keeps_checkpoint() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let first = TransactionSync {
+            accounts: vec![account()],
+            checkpoint: Some("cursor_1".to_string()),
+            ..Default::default()
+        };
+        apply(&pool, &first, TransactionKey::ProviderId).await.unwrap();
+
+        // References an account that doesn't exist, so the insert fails
+        let broken = TransactionSync {
+            added: vec![
+                transaction("tx_1", "acc_1", TransactionStatus::Posted),
+                transaction("tx_2", "acc_missing", TransactionStatus::Posted),
+            ],
+            checkpoint: Some("cursor_2".to_string()),
+            ..Default::default()
+        };
+        assert!(apply(&pool, &broken, TransactionKey::ProviderId).await.is_err());
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(cursor(&pool).await.as_deref(), Some("cursor_1"));
+    }
+
+    #[tokio::test]
+    async fn test_reapplying_sync_leaves_transactions_unchanged() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let sync = TransactionSync {
+            accounts: vec![account()],
+            added: vec![
+                transaction("tx_1", "acc_1", TransactionStatus::Pending),
+                transaction("tx_2", "acc_1", TransactionStatus::Posted),
+            ],
+            ..Default::default()
+        };
+        let first = apply(&pool, &sync, TransactionKey::ProviderId).await.unwrap();
+        let second = apply(&pool, &sync, TransactionKey::ProviderId).await.unwrap();
+
+        assert_eq!((first.inserted, first.updated, first.unchanged), (2, 0, 0));
+        assert_eq!((second.inserted, second.updated, second.unchanged), (0, 0, 2));
+
+        let posted = TransactionSync {
+            modified: vec![transaction("tx_1", "acc_1", TransactionStatus::Posted)],
+            ..Default::default()
+        };
+        let third = apply(&pool, &posted, TransactionKey::ProviderId).await.unwrap();
+        assert_eq!((third.inserted, third.updated, third.unchanged), (0, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_dedupes_reissued_ids() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        // The bank sends a new id on every read, and one transaction twice
+        let read = |suffix: &str| TransactionSync {
+            accounts: vec![account()],
+            added: vec![
+                transaction(&format!("a_{}", suffix), "acc_1", TransactionStatus::Posted),
+                transaction(&format!("b_{}", suffix), "acc_1", TransactionStatus::Posted),
+                Transaction {
+                    description: "Rent".to_string(),
+                    ..transaction("", "acc_1", TransactionStatus::Posted)
+                },
+            ],
+            ..Default::default()
+        };
+        let first = apply(&pool, &read("1"), TransactionKey::Fingerprint).await.unwrap();
+        let second = apply(&pool, &read("2"), TransactionKey::Fingerprint).await.unwrap();
+
+        assert_eq!(first.inserted, 3);
+        assert_eq!((second.inserted, second.unchanged), (0, 3));
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM transactions")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.iter().all(|id| id.starts_with("fp_")));
+    }
+
+    #[tokio::test]
+    async fn test_rows_under_legacy_fingerprints_keep_their_id() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let yen = Transaction {
+            amount: dec!(-1500),
+            currency: "JPY".to_string(),
+            ..transaction("", "acc_1", TransactionStatus::Posted)
+        };
+        let sync = TransactionSync {
+            accounts: vec![account()],
+            added: vec![yen.clone()],
+            ..Default::default()
+        };
+        apply(&pool, &sync, TransactionKey::Fingerprint).await.unwrap();
+        // As stored before amounts were fingerprinted in their currency's decimals
+        let legacy = ingest::legacy_fingerprint(&yen);
+        assert_ne!(legacy, ingest::fingerprint(&yen));
+        sqlx::query("UPDATE transactions SET id = $1, fingerprint = $2")
+            .bind(format!("fp_{}", legacy))
+            .bind(&legacy)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let first = apply(&pool, &sync, TransactionKey::Fingerprint).await.unwrap();
+        let second = apply(&pool, &sync, TransactionKey::Fingerprint).await.unwrap();
+
+        assert_eq!((first.inserted, first.updated), (0, 1));
+        assert_eq!((second.inserted, second.unchanged), (0, 1));
+        let rows: Vec<(String, String)> = sqlx::query_as("SELECT id, fingerprint FROM transactions")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(format!("fp_{}", legacy), ingest::fingerprint(&yen))]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_ids_fall_back_to_fingerprint() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let sync = TransactionSync {
+            accounts: vec![account()],
+            added: vec![
+                transaction("tx_1", "acc_1", TransactionStatus::Posted),
+                Transaction {
+                    description: "Rent".to_string(),
+                    ..transaction("", "acc_1", TransactionStatus::Posted)
+                },
+            ],
+            ..Default::default()
+        };
+        apply(&pool, &sync, TransactionKey::ProviderId).await.unwrap();
+        let again = apply(&pool, &sync, TransactionKey::ProviderId).await.unwrap();
+
+        assert_eq!(again.unchanged, 2);
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM transactions ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(ids[0], format!("fp_{}", ingest::fingerprint(&sync.added[1])));
+        assert_eq!(ids[1], "tx_1");
+    }
+
+    #[tokio::test]
+    async fn test_posted_transaction_supersedes_pending() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let pending = TransactionSync {
+            accounts: vec![account()],
+            added: vec![transaction("tx_pending", "acc_1", TransactionStatus::Pending)],
+            ..Default::default()
+        };
+        apply(&pool, &pending, TransactionKey::ProviderId).await.unwrap();
+
+        // Posts two days later under a new id, with a tip added
+        let posted = TransactionSync {
+            added: vec![Transaction {
+                amount: dec!(-14),
+                date: Utc.with_ymd_and_hms(2024, 3, 3, 0, 0, 0).unwrap(),
+                ..transaction("tx_posted", "acc_1", TransactionStatus::Posted)
+            }],
+            ..Default::default()
+        };
+        let summary = apply(&pool, &posted, TransactionKey::ProviderId).await.unwrap();
+        assert_eq!(summary.reconciled, 1);
+
+        let superseded_by: Option<String> =
+            sqlx::query_scalar("SELECT superseded_by FROM transactions WHERE id = 'tx_pending'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(superseded_by.as_deref(), Some("tx_posted"));
+
+        // Reading both again doesn't link anything twice
+        let again = TransactionSync {
+            added: vec![
+                transaction("tx_pending", "acc_1", TransactionStatus::Pending),
+                posted.added[0].clone(),
+            ],
+            ..Default::default()
+        };
+        let summary = apply(&pool, &again, TransactionKey::ProviderId).await.unwrap();
+        assert_eq!((summary.unchanged, summary.reconciled), (2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_reported_pending_transaction_isnt_superseded() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+
+        let pending = TransactionSync {
+            accounts: vec![account()],
+            added: vec![transaction("tx_pending", "acc_1", TransactionStatus::Pending)],
+            ..Default::default()
+        };
+        apply(&pool, &pending, TransactionKey::ProviderId).await.unwrap();
+
+        // A second coffee posts the next day while the first is still pending
+        let both = TransactionSync {
+            added: vec![Transaction {
+                date: Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap(),
+                ..transaction("tx_posted", "acc_1", TransactionStatus::Posted)
+            }],
+            modified: pending.added.clone(),
+            ..Default::default()
+        };
+        let summary = apply(&pool, &both, TransactionKey::ProviderId).await.unwrap();
+        assert_eq!(summary.reconciled, 0);
+
+        let superseded_by: Option<String> =
+            sqlx::query_scalar("SELECT superseded_by FROM transactions WHERE id = 'tx_pending'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(superseded_by, None);
+    }
+
+    #[tokio::test]
+    async fn test_apply_sync_records_balance_snapshots() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool).await;
+        let today = Utc::now().date_naive();
+
+        let sync = TransactionSync {
+            accounts: vec![account()],
+            added: vec![Transaction {
+                amount: dec!(-40),
+                date: Utc::now() - chrono::Duration::days(2),
+                ..transaction("tx_1", "acc_1", TransactionStatus::Posted)
+            }],
+            ..Default::default()
+        };
+        apply(&pool, &sync, TransactionKey::ProviderId).await.unwrap();
+
+        let snapshots: Vec<(chrono::NaiveDate, Decimal, String)> = sqlx::query_as(
+            "SELECT date, balance, source FROM balance_snapshots WHERE account_id = 'acc_1' ORDER BY date DESC",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let day = |days: i64| today - chrono::Duration::days(days);
+        assert_eq!(
+            snapshots,
+            vec![
+                (day(0), dec!(100), "reported".to_string()),
+                (day(1), dec!(100), "derived".to_string()),
+                (day(2), dec!(100), "derived".to_string()),
+                (day(3), dec!(140), "derived".to_string()),
+            ]
+        );
+
+        // Syncing the same history again leaves every day alone
+        let updated_at = || async {
+            sqlx::query_scalar::<_, chrono::DateTime<Utc>>(
+                "SELECT MAX(updated_at) FROM balance_snapshots WHERE account_id = 'acc_1'",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        };
+        let before = updated_at().await;
+        apply(&pool, &sync, TransactionKey::ProviderId).await.unwrap();
+        assert_eq!(updated_at().await, before);
+    }
+
+    async fn create_refreshable_connection(pool: &PgPool, refresh_token: &str) -> ProviderFactory {
+        sqlx::query(
+            r#"
+            INSERT INTO connections (id, provider, access_token, refresh_token, team_id)
+            VALUES ('conn_1', 'plaid', 'expired', $1, $2)
+            "#,
+        )
+        .bind(refresh_token)
+        .bind(TEST_TEAM_ID)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let provider = Arc::new(FakeProvider::with_token_refresh());
+        ProviderFactory::new(Arc::new(test_config())).with_provider("plaid", provider)
+    }
+
+    async fn status(pool: &PgPool) -> (String, Option<String>) {
+        sqlx::query_as("SELECT status, status_reason FROM connections WHERE id = 'conn_1'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejected_access_token_is_refreshed_and_retried() {
+        let Some(pool) = test_pool().await else { return };
+        let provider_factory = create_refreshable_connection(&pool, "refresh_1").await;
+
+        let summary = sync_connection(&pool, &provider_factory, "conn_1").await.unwrap();
+        assert_eq!(summary.accounts, 1);
+
+        let keyring = &provider_factory.config().token_keys;
+        let stored = credentials::load(&pool, keyring, "conn_1").await.unwrap();
+        assert_eq!(stored.access_token.as_deref(), Some("fresh"));
+        assert_eq!(stored.refresh_token.as_deref(), Some("refresh_1"));
+        let expires_at: Option<chrono::DateTime<Utc>> =
+            sqlx::query_scalar("SELECT expires_at FROM connections WHERE id = 'conn_1'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(expires_at.unwrap() > Utc::now());
+        assert_eq!(status(&pool).await, ("active".to_string(), None));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_refresh_token_moves_connection_to_error() {
+        let Some(pool) = test_pool().await else { return };
+        let provider_factory = create_refreshable_connection(&pool, "revoked").await;
+
+        assert!(sync_connection(&pool, &provider_factory, "conn_1").await.is_err());
+
+        let (status, reason) = status(&pool).await;
+        assert_eq!(status, "error");
+        assert!(reason.unwrap().contains("invalid_grant"));
+    }
+}
@@ -0,0 +1,234 @@
+use chrono::{Duration, NaiveDate};
+use rust_decimal::Decimal;
+use sqlx::FromRow;
+use std::collections::HashSet;
+
+use crate::{error::AppResult, utils::config::Config};
+
+use super::DbTransaction;
+
+/// How far apart a pending transaction and the posted one replacing it may
+/// be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconcileConfig {
+    /// Days after the pending date within which it may post
+    pub window_days: i64,
+    /// How much the posted amount may differ, as a fraction of the pending
+    /// amount, to allow for tips and exchange rate changes
+    pub amount_tolerance: Decimal,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self {
+            window_days: 7,
+            amount_tolerance: Decimal::new(2, 1),
+        }
+    }
+}
+
+impl From<&Config> for ReconcileConfig {
+    fn from(config: &Config) -> Self {
+        Self {
+            window_days: config.pending_match_days,
+            amount_tolerance: Decimal::try_from(config.pending_match_tolerance).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+struct Candidate {
+    id: String,
+    account_id: String,
+    amount: Decimal,
+    currency: String,
+    transaction_date: NaiveDate,
+}
+
+/// Links pending transactions to the posted ones that replace them under a
+/// different id, by setting the pending row's `superseded_by`. Superseded
+/// transactions are kept for reference but no longer listed, so a charge
+/// isn't counted twice. Pending transactions in `reported`, the ones the
+/// provider still returned in this sync, haven't posted yet and are left
+/// alone. Returns how many were linked.
+pub async fn reconcile_pending(
+    tx: &mut DbTransaction<'_>,
+    connection_id: &str,
+    reported: &[String],
+    config: &ReconcileConfig,
+) -> AppResult<usize> {
+    let pending = sqlx::query_as::<_, Candidate>(
+        r#"
+        SELECT id, account_id, amount, currency, transaction_date
+        FROM transactions
+        WHERE connection_id = $1
+          AND status = 'pending'
+          AND superseded_by IS NULL
+          AND id <> ALL($2)
+        "#,
+    )
+    .bind(connection_id)
+    .bind(reported)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let Some(earliest) = pending.iter().map(|pending| pending.transaction_date).min() else {
+        return Ok(0);
+    };
+
+    let posted = sqlx::query_as::<_, Candidate>(
+        r#"
+        SELECT t.id, t.account_id, t.amount, t.currency, t.transaction_date
+        FROM transactions t
+        WHERE t.connection_id = $1
+          AND t.status = 'posted'
+          AND t.transaction_date >= $2
+          AND NOT EXISTS (SELECT 1 FROM transactions p WHERE p.superseded_by = t.id)
+        "#,
+    )
+    .bind(connection_id)
+    .bind(earliest - Duration::days(1))
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let matches = match_pending(&pending, &posted, config);
+
+    for (pending_id, posted_id) in &matches {
+        sqlx::query("UPDATE transactions SET superseded_by = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+            .bind(posted_id)
+            .bind(pending_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(matches.len())
+}
+
+/// Pairs each pending transaction with at most one posted transaction,
+/// closest amount first, then closest date.
+fn match_pending(pending: &[Candidate], posted: &[Candidate], config: &ReconcileConfig) -> Vec<(String, String)> {
+    let mut pairs: Vec<(Decimal, i64, &Candidate, &Candidate)> = pending
+        .iter()
+        .flat_map(|pending| posted.iter().map(move |posted| (pending, posted)))
+        .filter_map(|(pending, posted)| {
+            let (difference, days) = distance(pending, posted, config)?;
+            Some((difference, days, pending, posted))
+        })
+        .collect();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    let mut matched = HashSet::new();
+    let mut matches = Vec::new();
+
+    for (_, _, pending, posted) in pairs {
+        if matched.contains(&pending.id) || matched.contains(&posted.id) {
+            continue;
+        }
+        matched.insert(pending.id.clone());
+        matched.insert(posted.id.clone());
+        matches.push((pending.id.clone(), posted.id.clone()));
+    }
+
+    matches
+}
+
+/// How far the posted transaction is from the pending one, as the relative
+/// amount difference and the number of days, or `None` if it's too far to
+/// be the same charge.
+fn distance(pending: &Candidate, posted: &Candidate, config: &ReconcileConfig) -> Option<(Decimal, i64)> {
+    if pending.account_id != posted.account_id || !pending.currency.eq_ignore_ascii_case(&posted.currency) {
+        return None;
+    }
+
+    // Settling can be dated the day before the authorisation across time
+    // zones, but not earlier than that
+    let days = (posted.transaction_date - pending.transaction_date).num_days();
+    if !(-1..=config.window_days).contains(&days) {
+        return None;
+    }
+
+    if pending.amount.is_sign_negative() != posted.amount.is_sign_negative() {
+        return None;
+    }
+    let difference = (posted.amount - pending.amount).abs() / pending.amount.abs().max(Decimal::new(1, 2));
+    if difference > config.amount_tolerance {
+        return None;
+    }
+
+    Some((difference, days.abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn candidate(id: &str, amount: Decimal, day: u32) -> Candidate {
+        Candidate {
+            id: id.to_string(),
+            account_id: "acc_1".to_string(),
+            amount,
+            currency: "USD".to_string(),
+            transaction_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_matches_within_tolerance() {
+        let config = ReconcileConfig::default();
+
+        // A restaurant bill that posts two days later with the tip added
+        let matches = match_pending(&[candidate("p_1", dec!(-50), 1)], &[candidate("t_1", dec!(-58), 3)], &config);
+        assert_eq!(matches, vec![("p_1".to_string(), "t_1".to_string())]);
+
+        let too_different = match_pending(&[candidate("p_1", dec!(-50), 1)], &[candidate("t_1", dec!(-65), 3)], &config);
+        assert!(too_different.is_empty());
+
+        let too_late = match_pending(&[candidate("p_1", dec!(-50), 1)], &[candidate("t_1", dec!(-50), 10)], &config);
+        assert!(too_late.is_empty());
+
+        let refund = match_pending(&[candidate("p_1", dec!(-50), 1)], &[candidate("t_1", dec!(50), 2)], &config);
+        assert!(refund.is_empty());
+    }
+
+    #[test]
+    fn test_matches_closest_first() {
+        let config = ReconcileConfig::default();
+        let pending = [candidate("p_1", dec!(-10), 1), candidate("p_2", dec!(-12), 1)];
+        let posted = [candidate("t_1", dec!(-12), 2), candidate("t_2", dec!(-10), 3)];
+
+        let mut matches = match_pending(&pending, &posted, &config);
+        matches.sort();
+
+        assert_eq!(
+            matches,
+            vec![
+                ("p_1".to_string(), "t_2".to_string()),
+                ("p_2".to_string(), "t_1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_each_posted_matches_once() {
+        let config = ReconcileConfig::default();
+        let pending = [candidate("p_1", dec!(-10), 1), candidate("p_2", dec!(-10), 2)];
+
+        let matches = match_pending(&pending, &[candidate("t_1", dec!(-10), 2)], &config);
+
+        assert_eq!(matches.len(), 1);
+    }
+
+    #[test]
+    fn test_ignores_other_accounts_and_currencies() {
+        let config = ReconcileConfig::default();
+        let mut other_account = candidate("t_1", dec!(-10), 2);
+        other_account.account_id = "acc_2".to_string();
+        let mut other_currency = candidate("t_2", dec!(-10), 2);
+        other_currency.currency = "EUR".to_string();
+
+        let matches = match_pending(&[candidate("p_1", dec!(-10), 1)], &[other_account, other_currency], &config);
+
+        assert!(matches.is_empty());
+    }
+}
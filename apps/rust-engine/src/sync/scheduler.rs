@@ -0,0 +1,287 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+use crate::{
+    error::AppResult,
+    jobs::{self, JobKind},
+    providers::ProviderFactory,
+    utils::config::Config,
+};
+
+/// Used for providers without an entry in `SYNC_INTERVALS`.
+const DEFAULT_INTERVAL_MINUTES: i64 = 60;
+
+#[derive(Debug, FromRow)]
+struct ScheduledConnection {
+    id: String,
+    provider: String,
+    last_sync: Option<NaiveDateTime>,
+    /// When a sync job for the connection was last queued
+    last_queued: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+struct ExpiringConnection {
+    id: String,
+    provider: String,
+}
+
+/// Periodically queues an incremental sync for every active connection that
+/// is due. Each provider has its own interval. Connections whose access
+/// token is about to expire get a token refresh queued too. The jobs
+/// themselves are run by a `JobWorker`, so every instance can run a
+/// scheduler without the same connection being synced twice.
+pub struct SyncScheduler {
+    pool: PgPool,
+    provider_factory: Arc<ProviderFactory>,
+    tick: std::time::Duration,
+    intervals: HashMap<String, Duration>,
+    refresh_margin: Duration,
+}
+
+impl SyncScheduler {
+    pub fn new(pool: PgPool, provider_factory: Arc<ProviderFactory>, config: &Config) -> Arc<Self> {
+        let intervals = config
+            .sync_intervals
+            .iter()
+            .map(|(provider, minutes)| (provider.clone(), Duration::minutes(*minutes as i64)))
+            .collect();
+
+        Arc::new(Self {
+            pool,
+            provider_factory,
+            tick: std::time::Duration::from_secs(config.sync_tick_seconds.max(1)),
+            intervals,
+            refresh_margin: Duration::minutes(config.token_refresh_margin_minutes),
+        })
+    }
+
+    /// Runs the scheduler until the process exits.
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.tick);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.run_once().await {
+                    log::error!("Failed to schedule connection syncs: {}", e);
+                }
+            }
+        })
+    }
+
+    /// GoCardless only allows a handful of history requests per account each
+    /// day, so it is synced less often unless configured otherwise.
+    fn interval(&self, provider: &str) -> Duration {
+        self.intervals.get(provider).copied().unwrap_or_else(|| match provider {
+            "gocardless" => Duration::hours(6),
+            _ => Duration::minutes(DEFAULT_INTERVAL_MINUTES),
+        })
+    }
+
+    /// Queues the syncs and token refreshes that are due and returns how
+    /// many jobs were queued.
+    pub async fn run_once(&self) -> AppResult<usize> {
+        Ok(self.queue_token_refreshes().await? + self.queue_syncs().await?)
+    }
+
+    async fn queue_syncs(&self) -> AppResult<usize> {
+        // Failed syncs don't move `last_sync`, so the last queued job is
+        // taken into account to keep a broken connection from being retried
+        // on every tick. Retries are left to the job's own backoff.
+        let connections = sqlx::query_as::<_, ScheduledConnection>(
+            r#"
+            SELECT c.id, c.provider, c.last_sync, (
+                SELECT MAX(j.created_at) FROM jobs j
+                WHERE j.dedupe_key = 'incremental_sync:' || c.id
+            ) AS last_queued
+            FROM connections c
+            WHERE c.status = 'active' AND c.access_token IS NOT NULL
+            ORDER BY c.last_sync NULLS FIRST
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        let mut queued = 0;
+
+        for connection in connections {
+            if self.provider_factory.get_provider(&connection.provider).is_none() {
+                continue;
+            }
+
+            let last_sync = connection
+                .last_sync
+                .map(|last_sync| DateTime::<Utc>::from_naive_utc_and_offset(last_sync, Utc));
+            if !is_due(last_sync.max(connection.last_queued), self.interval(&connection.provider), now) {
+                continue;
+            }
+
+            jobs::enqueue(
+                &self.pool,
+                &JobKind::IncrementalSync {
+                    connection_id: connection.id,
+                },
+            )
+            .await?;
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+
+    /// A refresh that keeps failing isn't queued again until the margin has
+    /// passed, leaving retries to the job's own backoff.
+    async fn queue_token_refreshes(&self) -> AppResult<usize> {
+        let connections = sqlx::query_as::<_, ExpiringConnection>(
+            r#"
+            SELECT c.id, c.provider
+            FROM connections c
+            WHERE c.status = 'active'
+              AND c.refresh_token IS NOT NULL
+              AND c.expires_at <= now() + $1 * interval '1 minute'
+              AND NOT EXISTS (
+                  SELECT 1 FROM jobs j
+                  WHERE j.dedupe_key = 'token_refresh:' || c.id
+                    AND j.created_at > now() - $1 * interval '1 minute'
+              )
+            ORDER BY c.expires_at
+            "#,
+        )
+        .bind(self.refresh_margin.num_minutes() as f64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut queued = 0;
+
+        for connection in connections {
+            let refreshable = self
+                .provider_factory
+                .get_provider(&connection.provider)
+                .is_some_and(|provider| provider.capabilities().token_refresh);
+            if !refreshable {
+                continue;
+            }
+
+            jobs::enqueue(
+                &self.pool,
+                &JobKind::TokenRefresh {
+                    connection_id: connection.id,
+                },
+            )
+            .await?;
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+}
+
+fn is_due(last_sync: Option<DateTime<Utc>>, interval: Duration, now: DateTime<Utc>) -> bool {
+    last_sync.is_none_or(|last_sync| now - last_sync >= interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::ResponseCache;
+    use crate::jobs::{JobStatus, JobWorker};
+    use crate::utils::testing::{test_config, test_pool, FakeProvider, TEST_TEAM_ID};
+    use std::sync::atomic::Ordering;
+
+    async fn create_connection(pool: &PgPool, id: &str, status: &str, last_sync: &str) {
+        sqlx::query(&format!(
+            "INSERT INTO connections (id, provider, access_token, status, last_sync, team_id) VALUES ($1, 'plaid', $1, $2, {}, $3)",
+            last_sync
+        ))
+        .bind(id)
+        .bind(status)
+        .bind(TEST_TEAM_ID)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_once_queues_due_connections() {
+        let Some(pool) = test_pool().await else { return };
+        create_connection(&pool, "conn_1", "active", "NULL").await;
+        create_connection(&pool, "conn_2", "active", "timezone('utc', now())").await;
+        create_connection(&pool, "conn_3", "disconnected", "NULL").await;
+        let provider = Arc::new(FakeProvider::default());
+        let config = test_config();
+        let provider_factory =
+            Arc::new(ProviderFactory::new(Arc::new(test_config())).with_provider("plaid", provider.clone()));
+        let scheduler = SyncScheduler::new(pool.clone(), provider_factory.clone(), &config);
+
+        assert_eq!(scheduler.run_once().await.unwrap(), 1);
+        let queued = jobs::list(&pool, None, Some(JobStatus::Queued), None, 10).await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(
+            queued[0].job_kind().unwrap(),
+            JobKind::IncrementalSync {
+                connection_id: "conn_1".to_string()
+            }
+        );
+
+        // Nothing is due again until the interval has passed, whether or not
+        // the queued sync has run yet
+        assert_eq!(scheduler.run_once().await.unwrap(), 0);
+        let worker = JobWorker::new(pool.clone(), provider_factory, Arc::new(ResponseCache::in_memory(10)), &config);
+        for task in worker.run_once().await.unwrap() {
+            task.await.unwrap();
+        }
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(scheduler.run_once().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_run_once_queues_token_refreshes() {
+        let Some(pool) = test_pool().await else { return };
+        for (id, refresh_token, expires_in) in [
+            ("conn_1", Some("refresh"), "5 minutes"),
+            ("conn_2", Some("refresh"), "2 hours"),
+            ("conn_3", None, "5 minutes"),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO connections (id, provider, access_token, refresh_token, expires_at, last_sync, team_id)
+                VALUES ($1, 'plaid', $1, $2, now() + $3::interval, timezone('utc', now()), $4)
+                "#,
+            )
+            .bind(id)
+            .bind(refresh_token)
+            .bind(expires_in)
+            .bind(TEST_TEAM_ID)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        let provider = Arc::new(FakeProvider::with_token_refresh());
+        let config = test_config();
+        let provider_factory = Arc::new(ProviderFactory::new(Arc::new(test_config())).with_provider("plaid", provider));
+        let scheduler = SyncScheduler::new(pool.clone(), provider_factory, &config);
+
+        assert_eq!(scheduler.run_once().await.unwrap(), 1);
+        let queued = jobs::list(&pool, None, Some(JobStatus::Queued), None, 10).await.unwrap();
+        assert_eq!(
+            queued[0].job_kind().unwrap(),
+            JobKind::TokenRefresh {
+                connection_id: "conn_1".to_string()
+            }
+        );
+        assert_eq!(scheduler.run_once().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_is_due() {
+        let now = Utc::now();
+
+        assert!(is_due(None, Duration::hours(1), now));
+        assert!(is_due(Some(now - Duration::hours(2)), Duration::hours(1), now));
+        assert!(!is_due(Some(now - Duration::minutes(30)), Duration::hours(1), now));
+    }
+}
@@ -0,0 +1,398 @@
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool, Postgres, Transaction,
+};
+use std::{
+    fmt,
+    future::{ready, Ready},
+    ops::Deref,
+    str::FromStr,
+};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+
+/// Something a credential is allowed to do. `admin` allows everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "read:accounts")]
+    ReadAccounts,
+    #[serde(rename = "read:transactions")]
+    ReadTransactions,
+    #[serde(rename = "read:connections")]
+    ReadConnections,
+    #[serde(rename = "write:connections")]
+    WriteConnections,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::ReadAccounts,
+        Scope::ReadTransactions,
+        Scope::ReadConnections,
+        Scope::WriteConnections,
+        Scope::Admin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadAccounts => "read:accounts",
+            Scope::ReadTransactions => "read:transactions",
+            Scope::ReadConnections => "read:connections",
+            Scope::WriteConnections => "write:connections",
+            Scope::Admin => "admin",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = AppError;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|candidate| candidate.as_str() == scope)
+            .ok_or_else(|| AppError::BadRequest(format!("Invalid scope {}", scope)))
+    }
+}
+
+/// A set of scopes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Scopes(u8);
+
+impl Scopes {
+    #[cfg(test)]
+    pub fn all() -> Self {
+        Scope::ALL.into_iter().collect()
+    }
+
+    pub fn contains(self, scope: Scope) -> bool {
+        self.0 & (Scope::Admin.bit() | scope.bit()) != 0
+    }
+
+    pub fn to_vec(self) -> Vec<Scope> {
+        Scope::ALL.into_iter().filter(|scope| self.0 & scope.bit() != 0).collect()
+    }
+}
+
+impl FromIterator<Scope> for Scopes {
+    fn from_iter<I: IntoIterator<Item = Scope>>(scopes: I) -> Self {
+        Scopes(scopes.into_iter().fold(0, |bits, scope| bits | scope.bit()))
+    }
+}
+
+/// The team a request acts for and what it may do there. The `Auth`
+/// middleware resolves it from the request's credentials, and handlers take
+/// it as an extractor. Everything a handler reads or changes must belong to
+/// this team.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tenant {
+    pub team_id: Uuid,
+    pub scopes: Scopes,
+}
+
+impl Tenant {
+    pub fn new(team_id: Uuid, scopes: Scopes) -> Self {
+        Self { team_id, scopes }
+    }
+
+    /// Fails with forbidden unless the credential was granted `scope`.
+    pub fn require(&self, scope: Scope) -> AppResult<()> {
+        if self.scopes.contains(scope) {
+            Ok(())
+        } else {
+            Err(AppError::Authorization(format!("Requires the {} scope", scope)))
+        }
+    }
+
+    /// Starts a transaction that can only see this team's connections,
+    /// accounts and transactions. Row level security enforces it on top of
+    /// each query's own `team_id` filter, so a query missing one still can't
+    /// read another team's rows.
+    pub async fn begin(&self, pool: &PgPool) -> AppResult<Transaction<'static, Postgres>> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT set_config('app.team_id', $1, true)")
+            .bind(self.team_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        Ok(tx)
+    }
+}
+
+impl FromRequest for Tenant {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Tenant>()
+                .copied()
+                .ok_or_else(|| AppError::Authentication("No team for this request".to_string())),
+        )
+    }
+}
+
+/// A pool whose sessions see every team's rows. Row level security hides
+/// everything from a session without a team, so background work, and the
+/// parts of a request that hand a connection it already checked to them,
+/// go through this instead of the request pool.
+#[derive(Debug, Clone)]
+pub struct SystemPool(PgPool);
+
+impl SystemPool {
+    pub async fn connect(options: PgPoolOptions, url: &str) -> sqlx::Result<Self> {
+        let connect_options = url.parse::<PgConnectOptions>()?.options([("app.bypass_rls", "on")]);
+        Ok(Self(options.connect_with(connect_options).await?))
+    }
+
+    /// Test pools connect as a superuser, which row level security doesn't
+    /// apply to, so they can stand in for a system pool as they are.
+    #[cfg(test)]
+    pub fn for_tests(pool: PgPool) -> Self {
+        Self(pool)
+    }
+}
+
+impl Deref for SystemPool {
+    type Target = PgPool;
+
+    fn deref(&self) -> &PgPool {
+        &self.0
+    }
+}
+
+/// Who made a request: an API key, or the subject of a bearer token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subject {
+    ApiKey(Uuid),
+    User(String),
+}
+
+/// An authenticated caller along with the tenant it acts for. The `Auth`
+/// middleware inserts both, so handlers that only need the team take
+/// `Tenant` and ones that care who is asking take this.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub subject: Subject,
+    pub tenant: Tenant,
+}
+
+impl FromRequest for Principal {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Principal>()
+                .cloned()
+                .ok_or_else(|| AppError::Authentication("Not authenticated".to_string())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{test_pool, TEST_TEAM_ID};
+    use sqlx::Executor;
+
+    const OTHER_TEAM_ID: Uuid = Uuid::from_u128(2);
+
+    async fn create_data(pool: &PgPool) {
+        sqlx::query("INSERT INTO teams (id, name) VALUES ($1, 'Other')")
+            .bind(OTHER_TEAM_ID)
+            .execute(pool)
+            .await
+            .unwrap();
+        for (connection_id, team_id) in [("conn_1", TEST_TEAM_ID), ("conn_2", OTHER_TEAM_ID)] {
+            sqlx::query("INSERT INTO connections (id, provider, team_id) VALUES ($1, 'plaid', $2)")
+                .bind(connection_id)
+                .bind(team_id)
+                .execute(pool)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO accounts (id, connection_id) VALUES ($1, $2)")
+                .bind(format!("acc_{}", connection_id))
+                .bind(connection_id)
+                .execute(pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                r#"
+                INSERT INTO transactions (id, account_id, connection_id, amount, transaction_date, description)
+                VALUES ($1, $2, $3, 10, CURRENT_DATE, 'Coffee')
+                "#,
+            )
+            .bind(format!("tx_{}", connection_id))
+            .bind(format!("acc_{}", connection_id))
+            .bind(connection_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    /// Superusers bypass row level security, so the checks run as a role
+    /// that doesn't.
+    async fn as_unprivileged_role(tx: &mut Transaction<'static, Postgres>) {
+        sqlx::query(
+            r#"
+            DO $$
+            BEGIN
+                CREATE ROLE engine_tenant_test NOLOGIN;
+            EXCEPTION WHEN duplicate_object OR unique_violation THEN NULL;
+            END
+            $$
+            "#,
+        )
+        .execute(&mut **tx)
+        .await
+        .unwrap();
+        let schema: String = sqlx::query_scalar("SELECT current_schema()").fetch_one(&mut **tx).await.unwrap();
+        (&mut **tx)
+            .execute(
+                format!(
+                    "GRANT USAGE ON SCHEMA {0} TO engine_tenant_test; GRANT ALL ON ALL TABLES IN SCHEMA {0} TO engine_tenant_test",
+                    schema
+                )
+                .as_str(),
+            )
+            .await
+            .unwrap();
+        sqlx::query("SET LOCAL ROLE engine_tenant_test").execute(&mut **tx).await.unwrap();
+    }
+
+    #[test]
+    fn test_scopes() {
+        let scopes: Scopes = [Scope::ReadAccounts, Scope::WriteConnections].into_iter().collect();
+
+        assert!(scopes.contains(Scope::ReadAccounts));
+        assert!(!scopes.contains(Scope::ReadTransactions));
+        assert_eq!(scopes.to_vec(), vec![Scope::ReadAccounts, Scope::WriteConnections]);
+        assert!([Scope::Admin].into_iter().collect::<Scopes>().contains(Scope::ReadTransactions));
+        assert_eq!("read:transactions".parse::<Scope>().unwrap(), Scope::ReadTransactions);
+        assert!("write:everything".parse::<Scope>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_team_only_sees_its_own_rows() {
+        let Some(pool) = test_pool().await else { return };
+        create_data(&pool).await;
+
+        let mut tx = Tenant::new(TEST_TEAM_ID, Scopes::all()).begin(&pool).await.unwrap();
+        as_unprivileged_role(&mut tx).await;
+
+        // Not filtered by team, and asking for the other team's rows by id
+        for table in ["connections", "accounts", "transactions"] {
+            let ids: Vec<String> = sqlx::query_scalar(&format!("SELECT id FROM {} ORDER BY id", table))
+                .fetch_all(&mut *tx)
+                .await
+                .unwrap();
+            assert_eq!(ids.len(), 1, "{}", table);
+            assert!(ids[0].ends_with("conn_1"), "{}", table);
+        }
+        let other: Option<String> = sqlx::query_scalar("SELECT id FROM transactions WHERE id = 'tx_conn_2'")
+            .fetch_optional(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(other, None);
+
+        // Nor change them
+        let updated = sqlx::query("UPDATE transactions SET description = 'Changed'")
+            .execute(&mut *tx)
+            .await
+            .unwrap()
+            .rows_affected();
+        assert_eq!(updated, 1);
+    }
+
+    #[tokio::test]
+    async fn test_no_team_sees_nothing() {
+        let Some(pool) = test_pool().await else { return };
+        create_data(&pool).await;
+
+        let mut tx = pool.begin().await.unwrap();
+        as_unprivileged_role(&mut tx).await;
+        for table in ["connections", "accounts", "transactions"] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&mut *tx)
+                .await
+                .unwrap();
+            assert_eq!(count, 0, "{}", table);
+        }
+
+        // Unless it asks to see every team
+        sqlx::query("SELECT set_config('app.bypass_rls', 'on', true)")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions").fetch_one(&mut *tx).await.unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_system_pool_bypasses_row_level_security() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else { return };
+        let pool = SystemPool::connect(PgPoolOptions::new().max_connections(1), &url).await.unwrap();
+
+        let bypass: Option<String> = sqlx::query_scalar("SELECT current_setting('app.bypass_rls', true)")
+            .fetch_one(&*pool)
+            .await
+            .unwrap();
+        assert_eq!(bypass.as_deref(), Some("on"));
+    }
+
+    #[tokio::test]
+    async fn test_rows_take_their_connections_team() {
+        let Some(pool) = test_pool().await else { return };
+        create_data(&pool).await;
+
+        let teams: Vec<(String, Uuid)> = sqlx::query_as(
+            "SELECT id, team_id FROM accounts UNION ALL SELECT id, team_id FROM transactions ORDER BY id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            teams,
+            vec![
+                ("acc_conn_1".to_string(), TEST_TEAM_ID),
+                ("acc_conn_2".to_string(), OTHER_TEAM_ID),
+                ("tx_conn_1".to_string(), TEST_TEAM_ID),
+                ("tx_conn_2".to_string(), OTHER_TEAM_ID),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_team_cannot_attach_rows_to_another_teams_connection() {
+        let Some(pool) = test_pool().await else { return };
+        create_data(&pool).await;
+
+        let mut tx = Tenant::new(TEST_TEAM_ID, Scopes::all()).begin(&pool).await.unwrap();
+        as_unprivileged_role(&mut tx).await;
+
+        let result = sqlx::query("INSERT INTO accounts (id, connection_id) VALUES ('acc_3', 'conn_2')")
+            .execute(&mut *tx)
+            .await;
+        assert!(result.is_err());
+    }
+}
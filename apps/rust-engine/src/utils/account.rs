@@ -1,4 +1,4 @@
-use crate::providers::types::{Account, AccountType};
+use crate::providers::types::AccountType;
 use uuid::Uuid;
 
 pub fn generate_account_id() -> String {
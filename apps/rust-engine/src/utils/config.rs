@@ -1,5 +1,20 @@
 use serde::Deserialize;
-use std::env;
+use std::collections::HashMap;
+use std::{env, fs};
+use thiserror::Error;
+use uuid::Uuid;
+
+use super::crypto::Keyring;
+use crate::jwt::JwtVerifier;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Missing(#[from] env::VarError),
+
+    #[error("Invalid {0}: {1}")]
+    Invalid(&'static str, String),
+}
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -9,19 +24,60 @@ pub struct Config {
     pub plaid_client_id: String,
     pub plaid_secret: String,
     pub plaid_environment: String,
-    pub gocardless_client_id: String,
-    pub gocardless_secret: String,
-    pub gocardless_environment: String,
+    pub teller_environment: String,
+    pub teller_certificate_path: Option<String>,
+    pub teller_private_key_path: Option<String>,
+    pub gocardless_secret_id: String,
+    pub gocardless_secret_key: String,
     pub truelayer_client_id: String,
     pub truelayer_secret: String,
     pub truelayer_environment: String,
-    pub wise_client_id: String,
-    pub wise_secret: String,
+    pub wise_api_token: Option<String>,
+    /// The only team `wise_api_token` may be connected for
+    pub wise_team_id: Option<Uuid>,
+    pub wise_client_id: Option<String>,
+    pub wise_secret: Option<String>,
     pub wise_environment: String,
+    /// Providers turned off even though they are configured
+    pub disabled_providers: Vec<String>,
+    /// How many background jobs, such as connection syncs, each instance
+    /// runs at once
+    pub sync_max_concurrency: usize,
+    /// How often the scheduler looks for connections that are due
+    pub sync_tick_seconds: u64,
+    /// Per-provider overrides of the sync interval, in minutes
+    pub sync_intervals: HashMap<String, u64>,
+    /// How often the job worker polls for queued work
+    pub job_poll_seconds: u64,
+    /// Days after a pending transaction within which its posted version is
+    /// looked for
+    pub pending_match_days: i64,
+    /// How much a posted amount may differ from the pending one, as a
+    /// fraction of it
+    pub pending_match_tolerance: f64,
+    /// How long before its access token expires a connection is refreshed
+    pub token_refresh_margin_minutes: i64,
+    /// Master keys provider tokens are encrypted with, active key first
+    #[serde(skip)]
+    pub token_keys: Keyring,
+    /// Requests each API key or bearer token may make per window, by route
+    /// group, with `default` for groups not listed
+    pub rate_limits: HashMap<String, u64>,
+    /// Requests a team may make per window across all its credentials
+    pub tenant_rate_limits: HashMap<String, u64>,
+    pub rate_limit_window_seconds: u64,
+    /// Seconds GET responses are cached for, by route group. Groups not
+    /// listed aren't cached
+    pub cache_ttls: HashMap<String, u64>,
+    /// How many responses are kept when caching in memory
+    pub cache_capacity: usize,
+    /// Checks bearer tokens, if they are accepted
+    #[serde(skip)]
+    pub jwt: Option<JwtVerifier>,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
+    pub fn from_env() -> Result<Self, ConfigError> {
         Ok(Config {
             database_url: env::var("DATABASE_URL")?,
             redis_url: env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string()),
@@ -32,15 +88,137 @@ impl Config {
             plaid_client_id: env::var("PLAID_CLIENT_ID")?,
             plaid_secret: env::var("PLAID_SECRET")?,
             plaid_environment: env::var("PLAID_ENVIRONMENT").unwrap_or_else(|_| "sandbox".to_string()),
-            gocardless_client_id: env::var("GOCARDLESS_CLIENT_ID")?,
-            gocardless_secret: env::var("GOCARDLESS_SECRET")?,
-            gocardless_environment: env::var("GOCARDLESS_ENVIRONMENT").unwrap_or_else(|_| "sandbox".to_string()),
+            teller_environment: env::var("TELLER_ENVIRONMENT").unwrap_or_else(|_| "sandbox".to_string()),
+            teller_certificate_path: env::var("TELLER_CERTIFICATE_PATH").ok(),
+            teller_private_key_path: env::var("TELLER_PRIVATE_KEY_PATH").ok(),
+            gocardless_secret_id: env::var("GOCARDLESS_SECRET_ID")?,
+            gocardless_secret_key: env::var("GOCARDLESS_SECRET_KEY")?,
             truelayer_client_id: env::var("TRUELAYER_CLIENT_ID")?,
             truelayer_secret: env::var("TRUELAYER_SECRET")?,
             truelayer_environment: env::var("TRUELAYER_ENVIRONMENT").unwrap_or_else(|_| "sandbox".to_string()),
-            wise_client_id: env::var("WISE_CLIENT_ID")?,
-            wise_secret: env::var("WISE_SECRET")?,
+            wise_api_token: env::var("WISE_API_TOKEN").ok().filter(|token| !token.is_empty()),
+            wise_team_id: env::var("WISE_TEAM_ID")
+                .ok()
+                .filter(|team_id| !team_id.is_empty())
+                .map(|team_id| Uuid::parse_str(&team_id))
+                .transpose()
+                .map_err(|e| ConfigError::Invalid("WISE_TEAM_ID", e.to_string()))?,
+            wise_client_id: env::var("WISE_CLIENT_ID").ok(),
+            wise_secret: env::var("WISE_SECRET").ok(),
             wise_environment: env::var("WISE_ENVIRONMENT").unwrap_or_else(|_| "sandbox".to_string()),
+            disabled_providers: env::var("DISABLED_PROVIDERS")
+                .map(|providers| {
+                    providers
+                        .split(',')
+                        .map(|provider| provider.trim().to_lowercase())
+                        .filter(|provider| !provider.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            sync_max_concurrency: env::var("SYNC_MAX_CONCURRENCY")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(4),
+            sync_tick_seconds: env::var("SYNC_TICK_SECONDS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(60),
+            sync_intervals: env::var("SYNC_INTERVALS")
+                .map(|intervals| parse_counts(&intervals))
+                .unwrap_or_default(),
+            job_poll_seconds: env::var("JOB_POLL_SECONDS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(5),
+            pending_match_days: env::var("PENDING_MATCH_DAYS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value >= 0)
+                .unwrap_or(7),
+            pending_match_tolerance: env::var("PENDING_MATCH_TOLERANCE")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value: &f64| *value >= 0.0)
+                .unwrap_or(0.2),
+            token_refresh_margin_minutes: env::var("TOKEN_REFRESH_MARGIN_MINUTES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value >= 0)
+                .unwrap_or(10),
+            token_keys: Keyring::parse(&env::var("TOKEN_ENCRYPTION_KEYS")?)
+                .map_err(|e| ConfigError::Invalid("TOKEN_ENCRYPTION_KEYS", e))?,
+            rate_limits: rate_limits_from_env("RATE_LIMITS", 120),
+            tenant_rate_limits: rate_limits_from_env("TENANT_RATE_LIMITS", 600),
+            rate_limit_window_seconds: env::var("RATE_LIMIT_WINDOW_SECONDS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(60),
+            cache_ttls: cache_ttls_from_env(),
+            cache_capacity: env::var("RESPONSE_CACHE_CAPACITY")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(1000),
+            jwt: jwt_from_env()?,
         })
     }
 }
+
+/// Route groups cached by default, overridden by `CACHE_TTLS`, e.g.
+/// `institutions=86400,transactions=30`.
+fn cache_ttls_from_env() -> HashMap<String, u64> {
+    let mut ttls = HashMap::from([
+        ("institutions".to_string(), 3600),
+        ("rates".to_string(), 900),
+        ("accounts".to_string(), 60),
+        ("transactions".to_string(), 60),
+    ]);
+    ttls.extend(env::var("CACHE_TTLS").map(|ttls| parse_counts(&ttls)).unwrap_or_default());
+    ttls
+}
+
+/// Bearer tokens are accepted once `JWT_SECRET` or `JWT_JWKS_PATH` is set,
+/// and then have to be for `JWT_AUDIENCE` from `JWT_ISSUER`.
+fn jwt_from_env() -> Result<Option<JwtVerifier>, ConfigError> {
+    let secret = env::var("JWT_SECRET").ok().filter(|secret| !secret.is_empty());
+    let jwks_path = env::var("JWT_JWKS_PATH").ok().filter(|path| !path.is_empty());
+    if secret.is_none() && jwks_path.is_none() {
+        return Ok(None);
+    }
+
+    let jwks = jwks_path
+        .map(|path| fs::read_to_string(&path).map_err(|e| ConfigError::Invalid("JWT_JWKS_PATH", e.to_string())))
+        .transpose()?;
+    let verifier = JwtVerifier::new(
+        secret.as_deref(),
+        jwks.as_deref(),
+        &env::var("JWT_AUDIENCE")?,
+        &env::var("JWT_ISSUER")?,
+    )
+    .map_err(|e| ConfigError::Invalid("JWT configuration", e))?;
+
+    Ok(Some(verifier))
+}
+
+/// Parses `name=count` pairs such as `plaid=60,gocardless=360`, skipping
+/// anything malformed.
+fn parse_counts(pairs: &str) -> HashMap<String, u64> {
+    pairs
+        .split(',')
+        .filter_map(|pair| {
+            let (name, count) = pair.split_once('=')?;
+            let count = count.trim().parse().ok().filter(|count| *count > 0)?;
+            Some((name.trim().to_lowercase(), count))
+        })
+        .collect()
+}
+
+/// Per route group limits such as `default=120,transactions=60`, with
+/// `default` falling back to `default_limit`.
+fn rate_limits_from_env(name: &str, default_limit: u64) -> HashMap<String, u64> {
+    let mut limits = env::var(name).map(|limits| parse_counts(&limits)).unwrap_or_default();
+    limits.entry("default".to_string()).or_insert(default_limit);
+    limits
+}
@@ -0,0 +1,242 @@
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fmt;
+
+use crate::error::{AppError, AppResult};
+
+const NONCE_LEN: usize = 12;
+
+/// Master keys for envelope encryption. Each secret is encrypted with its own
+/// random data key, and only the data key is encrypted with a master key, so
+/// rotating master keys means re-wrapping data keys rather than re-encrypting
+/// every secret.
+///
+/// The first key wraps new data keys. The others can still unwrap, so rows
+/// written with them keep working until they are re-encrypted.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: Vec<(String, Aes256Gcm)>,
+}
+
+impl Keyring {
+    /// Parses `id:base64` pairs of 256-bit keys, e.g. `2024b:...,2024a:...`,
+    /// active key first. Generate a key with `openssl rand -base64 32`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut keys: Vec<(String, Aes256Gcm)> = Vec::new();
+
+        for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (id, key) = pair
+                .split_once(':')
+                .ok_or_else(|| "expected comma-separated id:base64 pairs".to_string())?;
+            let id = id.trim();
+            let key = STANDARD
+                .decode(key.trim())
+                .map_err(|_| format!("key {} is not valid base64", id))?;
+            if id.is_empty() || key.len() != 32 {
+                return Err(format!("key '{}' must have an id and be 32 bytes", id));
+            }
+            if keys.iter().any(|(existing, _)| existing == id) {
+                return Err(format!("key {} is listed twice", id));
+            }
+            keys.push((id.to_string(), Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))));
+        }
+
+        if keys.is_empty() {
+            return Err("no keys given".to_string());
+        }
+        Ok(Self { keys })
+    }
+
+    /// The key new data keys are wrapped with
+    pub fn active_key_id(&self) -> AppResult<&str> {
+        self.keys
+            .first()
+            .map(|(id, _)| id.as_str())
+            .ok_or_else(|| AppError::Internal("No token encryption key is configured".to_string()))
+    }
+
+    /// The keys that can still unwrap data keys but no longer wrap new ones
+    pub fn retired_key_ids(&self) -> Vec<&str> {
+        self.keys.iter().skip(1).map(|(id, _)| id.as_str()).collect()
+    }
+
+    /// A new random data key, wrapped with the active key.
+    pub fn new_data_key(&self) -> AppResult<DataKey> {
+        self.wrap(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Unwraps a data key stored alongside the secrets it encrypts.
+    pub fn open_data_key(&self, key_id: &str, wrapped: &[u8]) -> AppResult<DataKey> {
+        let master = self.master(key_id)?;
+        let key = open(master, wrapped, key_id.as_bytes())?;
+        if key.len() != 32 {
+            return Err(AppError::Internal("Invalid data key".to_string()));
+        }
+
+        Ok(DataKey {
+            key_id: key_id.to_string(),
+            wrapped: wrapped.to_vec(),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            key: *Key::<Aes256Gcm>::from_slice(&key),
+        })
+    }
+
+    /// Wraps an existing data key with the active key. The secrets it
+    /// encrypts stay as they are.
+    pub fn rewrap(&self, data_key: &DataKey) -> AppResult<DataKey> {
+        self.wrap(data_key.key)
+    }
+
+    fn wrap(&self, key: Key<Aes256Gcm>) -> AppResult<DataKey> {
+        let key_id = self.active_key_id()?;
+        let wrapped = seal(self.master(key_id)?, &key, key_id.as_bytes())?;
+
+        Ok(DataKey {
+            key_id: key_id.to_string(),
+            wrapped,
+            cipher: Aes256Gcm::new(&key),
+            key,
+        })
+    }
+
+    fn master(&self, key_id: &str) -> AppResult<&Aes256Gcm> {
+        self.keys
+            .iter()
+            .find(|(id, _)| id == key_id)
+            .map(|(_, cipher)| cipher)
+            .ok_or_else(|| AppError::Internal(format!("Token encryption key {} is not configured", key_id)))
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.keys.iter().map(|(id, _)| id)).finish()
+    }
+}
+
+/// A data key along with its wrapped form, which is what gets stored.
+pub struct DataKey {
+    key_id: String,
+    wrapped: Vec<u8>,
+    cipher: Aes256Gcm,
+    key: Key<Aes256Gcm>,
+}
+
+impl DataKey {
+    /// The master key this data key is wrapped with
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn wrapped(&self) -> &[u8] {
+        &self.wrapped
+    }
+
+    /// Encrypts to base64. `context` is authenticated but not stored, so the
+    /// result can only be decrypted for the same context, e.g. the same row
+    /// and column.
+    pub fn encrypt(&self, plaintext: &str, context: &str) -> AppResult<String> {
+        Ok(STANDARD.encode(seal(&self.cipher, plaintext.as_bytes(), context.as_bytes())?))
+    }
+
+    pub fn decrypt(&self, encrypted: &str, context: &str) -> AppResult<String> {
+        let sealed = STANDARD
+            .decode(encrypted)
+            .map_err(|_| AppError::Internal("Encrypted value is not valid base64".to_string()))?;
+        String::from_utf8(open(&self.cipher, &sealed, context.as_bytes())?)
+            .map_err(|_| AppError::Internal("Decrypted value is not valid UTF-8".to_string()))
+    }
+}
+
+impl fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataKey").field("key_id", &self.key_id).finish_non_exhaustive()
+    }
+}
+
+/// `N` bytes from the operating system's secure random number generator
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// The nonce followed by the ciphertext
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8], aad: &[u8]) -> AppResult<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| AppError::Internal("Encryption failed".to_string()))?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn open(cipher: &Aes256Gcm, sealed: &[u8], aad: &[u8]) -> AppResult<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(AppError::Internal("Encrypted value is too short".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| AppError::Internal("Decryption failed".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const NEW_KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let keyring = Keyring::parse(&format!("k1:{}", OLD_KEY)).unwrap();
+        let data_key = keyring.new_data_key().unwrap();
+
+        let encrypted = data_key.encrypt("secret-token", "conn_1:access_token").unwrap();
+        assert!(!encrypted.contains("secret-token"));
+
+        let reopened = keyring.open_data_key(data_key.key_id(), data_key.wrapped()).unwrap();
+        assert_eq!(reopened.decrypt(&encrypted, "conn_1:access_token").unwrap(), "secret-token");
+        // Bound to where it was stored
+        assert!(reopened.decrypt(&encrypted, "conn_2:access_token").is_err());
+    }
+
+    #[test]
+    fn test_rewrap_with_rotated_key() {
+        let old = Keyring::parse(&format!("k1:{}", OLD_KEY)).unwrap();
+        let data_key = old.new_data_key().unwrap();
+        let encrypted = data_key.encrypt("secret-token", "context").unwrap();
+
+        let rotated = Keyring::parse(&format!("k2:{},k1:{}", NEW_KEY, OLD_KEY)).unwrap();
+        let rewrapped = rotated
+            .rewrap(&rotated.open_data_key("k1", data_key.wrapped()).unwrap())
+            .unwrap();
+        assert_eq!(rewrapped.key_id(), "k2");
+
+        // The old key can be retired once everything is rewrapped
+        let retired = Keyring::parse(&format!("k2:{}", NEW_KEY)).unwrap();
+        let reopened = retired.open_data_key("k2", rewrapped.wrapped()).unwrap();
+        assert_eq!(reopened.decrypt(&encrypted, "context").unwrap(), "secret-token");
+        assert!(retired.open_data_key("k1", data_key.wrapped()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_keys() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("no-separator").is_err());
+        assert!(Keyring::parse("k1:c2hvcnQ=").is_err());
+        assert!(Keyring::parse(&format!("k1:{},k1:{}", OLD_KEY, NEW_KEY)).is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_keys() {
+        let keyring = Keyring::parse(&format!("k2:{},k1:{}", NEW_KEY, OLD_KEY)).unwrap();
+
+        assert_eq!(format!("{:?}", keyring), r#"["k2", "k1"]"#);
+    }
+}
@@ -1,5 +1,4 @@
 use crate::providers::types::Transaction;
-use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -102,20 +101,25 @@ fn get_logo_url(merchant: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::types::TransactionStatus;
     use uuid::Uuid;
     use chrono::Utc;
+    use rust_decimal_macros::dec;
 
     fn create_test_transaction(description: &str) -> Transaction {
         Transaction {
             id: Uuid::new_v4().to_string(),
-            description: Some(description.to_string()),
-            amount: 100.0,
+            description: description.to_string(),
+            amount: dec!(100),
             date: Utc::now(),
             currency: "USD".to_string(),
             account_id: "acc_123".to_string(),
             category: None,
             merchant: None,
-            pending: false,
+            status: TransactionStatus::Posted,
+            balance: None,
+            currency_rate: None,
+            currency_source: None,
         }
     }
 
@@ -1,20 +1,26 @@
+// Helpers for endpoints that aren't mounted yet, like rates and enrichment
+#[allow(dead_code)]
 pub mod account;
 pub mod config;
+#[allow(dead_code)]
 pub mod countries;
+pub mod crypto;
+#[allow(dead_code)]
 pub mod enrich;
+#[allow(dead_code)]
 pub mod error;
 pub mod logo;
+pub mod money;
 pub mod paginate;
+pub mod query;
+#[allow(dead_code)]
 pub mod rates;
 pub mod retry;
+#[allow(dead_code)]
 pub mod search;
+#[cfg(test)]
+pub mod testing;
 
 // Re-export commonly used utilities
-pub use account::{generate_account_id, normalize_account_type};
-pub use countries::{get_country_name, is_supported_country};
-pub use enrich::enrich_transaction;
-pub use error::{ApiError, ApiResult};
 pub use logo::get_institution_logo;
-pub use paginate::{PaginatedResponse, PaginationParams};
 pub use rates::RatesClient;
-pub use retry::{retry, RetryConfig};
@@ -0,0 +1,86 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::str::FromStr;
+
+/// Currencies that don't use two decimals, per ISO 4217. Anything not
+/// listed, including unknown codes, is taken to use two.
+const MINOR_UNITS: &[(&str, u32)] = &[
+    ("BIF", 0),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("ISK", 0),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("UGX", 0),
+    ("UYI", 0),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+    ("BHD", 3),
+    ("IQD", 3),
+    ("JOD", 3),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("TND", 3),
+];
+
+/// How many decimals amounts in this currency have.
+pub fn minor_units(currency: &str) -> u32 {
+    MINOR_UNITS
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(currency))
+        .map(|(_, units)| *units)
+        .unwrap_or(2)
+}
+
+/// Rounds to the currency's minor units, half away from zero, and pads to
+/// them so the amount always reads with the same number of decimals.
+pub fn round(amount: Decimal, currency: &str) -> Decimal {
+    let units = minor_units(currency);
+    let mut rounded = amount.round_dp_with_strategy(units, RoundingStrategy::MidpointAwayFromZero);
+    rounded.rescale(units);
+    rounded
+}
+
+/// Parses a decimal string as providers send it, e.g. `"-12.50"` or `"1e3"`.
+pub fn parse(amount: &str) -> Option<Decimal> {
+    let amount = amount.trim();
+    Decimal::from_str(amount)
+        .or_else(|_| Decimal::from_scientific(amount))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_minor_units() {
+        assert_eq!(minor_units("EUR"), 2);
+        assert_eq!(minor_units("jpy"), 0);
+        assert_eq!(minor_units("KWD"), 3);
+        assert_eq!(minor_units("XXX"), 2);
+    }
+
+    #[test]
+    fn test_round_to_currency() {
+        assert_eq!(round(dec!(12.5), "EUR").to_string(), "12.50");
+        assert_eq!(round(dec!(-0.125), "USD").to_string(), "-0.13");
+        assert_eq!(round(dec!(1500.4), "JPY").to_string(), "1500");
+        assert_eq!(round(dec!(1.2345), "KWD").to_string(), "1.235");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" -12.50 "), Some(dec!(-12.50)));
+        assert_eq!(parse("1e3"), Some(dec!(1000)));
+        assert_eq!(parse("abc"), None);
+    }
+}
@@ -1,11 +1,27 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
 
+use crate::{
+    error::{AppError, AppResult},
+    utils::query::{Filter, Value},
+};
+
+/// Either a page number, or a cursor from a previous page's `next_cursor` or
+/// `prev_cursor`, which takes precedence. Cursors stay put as rows are added,
+/// where page numbers shift.
 #[derive(Debug, Deserialize)]
 pub struct PaginationParams {
     #[serde(default = "default_page")]
     pub page: u32,
-    #[serde(default = "default_limit")]
+    #[serde(default = "default_limit", alias = "per_page")]
     pub limit: u32,
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Counting every matching row is slow on large tables, so totals are
+    /// only returned when asked for
+    #[serde(default)]
+    pub include_total: bool,
 }
 
 fn default_page() -> u32 {
@@ -19,10 +35,80 @@ fn default_limit() -> u32 {
 #[derive(Debug, Serialize)]
 pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
-    pub page: u32,
-    pub total_pages: u32,
-    pub total_items: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<u64>,
     pub has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prev_cursor: Option<String>,
+}
+
+/// A row's position in a keyset ordered by `key` and then `id`, and which
+/// way to page from it. Opaque to clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "k")]
+    pub key: String,
+    #[serde(rename = "i")]
+    pub id: String,
+    /// The sort `key` is for, where a list can be sorted more than one way
+    #[serde(rename = "s", default, skip_serializing_if = "String::is_empty")]
+    pub sort: String,
+    /// Set for the rows before this one rather than after
+    #[serde(rename = "b", default, skip_serializing_if = "std::ops::Not::not")]
+    pub backward: bool,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> AppResult<Self> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| AppError::BadRequest("Invalid cursor".to_string()))
+    }
+}
+
+/// The order a list is paged through by cursor: by the `key` column, then
+/// by the `id` column to break ties.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyset {
+    pub key: &'static str,
+    pub id: &'static str,
+    pub descending: bool,
+}
+
+impl Keyset {
+    /// Narrows `filter` to the rows past `cursor`, in the direction it pages.
+    /// `key` is the cursor's key parsed as the key column's type.
+    pub fn past(&self, filter: Filter, cursor: &Cursor, key: Value) -> Filter {
+        filter.past(self.key, self.id, self.descending != cursor.backward, key, &cursor.id)
+    }
+
+    /// Appends the order and a limit of one row more than the page, as
+    /// `PaginatedResponse::keyset` expects, and without a cursor the page's
+    /// offset.
+    pub fn push_page(&self, builder: &mut QueryBuilder<'_, Postgres>, params: &PaginationParams, cursor: Option<&Cursor>) {
+        let descending = self.descending != cursor.is_some_and(|cursor| cursor.backward);
+        let direction = if descending { "DESC" } else { "ASC" };
+        builder.push(format_args!(" ORDER BY {} {}, {} {}", self.key, direction, self.id, direction));
+
+        builder.push(" LIMIT ");
+        builder.push_bind(i64::from(params.limit) + 1);
+        if cursor.is_none() {
+            builder.push(" OFFSET ");
+            builder.push_bind(i64::from(params.offset()));
+        }
+    }
 }
 
 impl PaginationParams {
@@ -42,65 +128,177 @@ impl PaginationParams {
         }
         Ok(())
     }
+
+    /// The decoded cursor, if one was passed.
+    pub fn cursor(&self) -> AppResult<Option<Cursor>> {
+        self.cursor.as_deref().map(Cursor::decode).transpose()
+    }
 }
 
 impl<T> PaginatedResponse<T> {
+    #[allow(dead_code)]
     pub fn new(data: Vec<T>, page: u32, limit: u32, total_items: u64) -> Self {
         let total_pages = ((total_items as f64) / (limit as f64)).ceil() as u32;
         let has_more = page < total_pages;
 
         Self {
             data,
-            page,
-            total_pages,
-            total_items,
+            page: Some(page),
+            total_pages: Some(total_pages),
+            total_items: Some(total_items),
             has_more,
+            next_cursor: None,
+            prev_cursor: None,
         }
     }
+
+    /// A page of keyset paginated rows, from up to `limit + 1` of them
+    /// fetched in the direction `cursor` pages in. The extra row only tells
+    /// whether there are more. Without a cursor the rows are the page
+    /// `params.page` at its offset, and cursors are still returned so
+    /// callers can switch to them. `position` is a cursor at a row.
+    pub fn keyset(
+        mut rows: Vec<T>,
+        params: &PaginationParams,
+        cursor: Option<&Cursor>,
+        position: impl Fn(&T) -> Cursor,
+    ) -> Self {
+        let more = rows.len() > params.limit as usize;
+        rows.truncate(params.limit as usize);
+
+        let backward = cursor.is_some_and(|cursor| cursor.backward);
+        if backward {
+            rows.reverse();
+        }
+        let cursor_at = |row: Option<&T>, backward: bool| {
+            row.map(|row| Cursor { backward, ..position(row) }.encode())
+        };
+
+        // Paging backward there are always rows after this page, the ones
+        // the cursor came from, and paging forward from a cursor or past the
+        // first page there are always rows before it
+        let has_next = if backward { true } else { more };
+        let has_prev = if backward { more } else { cursor.is_some() || params.page > 1 };
+
+        Self {
+            page: cursor.is_none().then_some(params.page),
+            total_pages: None,
+            total_items: None,
+            has_more: has_next && !rows.is_empty(),
+            next_cursor: if has_next { cursor_at(rows.last(), false) } else { None },
+            prev_cursor: if has_prev { cursor_at(rows.first(), true) } else { None },
+            data: rows,
+        }
+    }
+
+    /// The same page with each row converted by `f`.
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> PaginatedResponse<U> {
+        PaginatedResponse {
+            data: self.data.into_iter().map(f).collect(),
+            page: self.page,
+            total_pages: self.total_pages,
+            total_items: self.total_items,
+            has_more: self.has_more,
+            next_cursor: self.next_cursor,
+            prev_cursor: self.prev_cursor,
+        }
+    }
+
+    /// Adds the total count of matching rows, and of pages if paging by
+    /// page number.
+    pub fn with_total(mut self, limit: u32, total_items: u64) -> Self {
+        self.total_items = Some(total_items);
+        if self.page.is_some() {
+            self.total_pages = Some(((total_items as f64) / (limit as f64)).ceil() as u32);
+        }
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn params(page: u32, limit: u32) -> PaginationParams {
+        PaginationParams {
+            page,
+            limit,
+            cursor: None,
+            include_total: false,
+        }
+    }
+
+    fn position(row: &u32) -> Cursor {
+        Cursor {
+            key: row.to_string(),
+            id: format!("id_{}", row),
+            ..Cursor::default()
+        }
+    }
+
     #[test]
     fn test_pagination_params() {
-        let params = PaginationParams {
-            page: 2,
-            limit: 10,
-        };
-        assert_eq!(params.offset(), 10);
+        assert_eq!(params(2, 10).offset(), 10);
     }
 
     #[test]
     fn test_pagination_validation() {
-        let valid_params = PaginationParams {
-            page: 1,
-            limit: 20,
-        };
-        assert!(valid_params.validate().is_ok());
-
-        let invalid_page = PaginationParams {
-            page: 0,
-            limit: 20,
-        };
-        assert!(invalid_page.validate().is_err());
-
-        let invalid_limit = PaginationParams {
-            page: 1,
-            limit: 101,
-        };
-        assert!(invalid_limit.validate().is_err());
+        assert!(params(1, 20).validate().is_ok());
+        assert!(params(0, 20).validate().is_err());
+        assert!(params(1, 101).validate().is_err());
     }
 
     #[test]
     fn test_paginated_response() {
         let data = vec![1, 2, 3];
         let response = PaginatedResponse::new(data, 1, 3, 10);
-        
-        assert_eq!(response.page, 1);
-        assert_eq!(response.total_pages, 4);
-        assert_eq!(response.total_items, 10);
+
+        assert_eq!(response.page, Some(1));
+        assert_eq!(response.total_pages, Some(4));
+        assert_eq!(response.total_items, Some(10));
         assert!(response.has_more);
     }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor {
+            key: "2024-01-31".to_string(),
+            id: "tx_1".to_string(),
+            sort: "-date".to_string(),
+            backward: true,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+
+        assert!(matches!(Cursor::decode("not a cursor"), Err(AppError::BadRequest(_))));
+        assert!(matches!(Cursor::decode(&URL_SAFE_NO_PAD.encode("{}")), Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn test_keyset_pages() {
+        // The first page has a next cursor but no previous one
+        let first = PaginatedResponse::keyset(vec![9, 8, 7], &params(1, 2), None, position);
+        assert_eq!(first.data, vec![9, 8]);
+        assert_eq!(first.page, Some(1));
+        assert!(first.has_more);
+        assert!(first.prev_cursor.is_none());
+        let next = Cursor::decode(first.next_cursor.as_deref().unwrap()).unwrap();
+        assert_eq!((next.key.as_str(), next.backward), ("8", false));
+
+        // The last page has a previous cursor but no next one
+        let last = PaginatedResponse::keyset(vec![7], &params(1, 2), Some(&next), position);
+        assert_eq!(last.page, None);
+        assert!(!last.has_more);
+        assert!(last.next_cursor.is_none());
+        let prev = Cursor::decode(last.prev_cursor.as_deref().unwrap()).unwrap();
+        assert_eq!((prev.key.as_str(), prev.backward), ("7", true));
+
+        // Paging back, rows come nearest first and are put back in order
+        let back = PaginatedResponse::keyset(vec![8, 9], &params(1, 2), Some(&prev), position);
+        assert_eq!(back.data, vec![9, 8]);
+        assert!(back.prev_cursor.is_none());
+        assert!(back.next_cursor.is_some());
+
+        let counted = PaginatedResponse::keyset(vec![9, 8, 7], &params(1, 2), None, position).with_total(2, 3);
+        assert_eq!((counted.total_items, counted.total_pages), (Some(3), Some(2)));
+    }
 }
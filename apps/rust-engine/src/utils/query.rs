@@ -0,0 +1,267 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+/// A value bound into a filter. `Null` stands for a filter that wasn't
+/// given, which is left out rather than compared against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Text(String),
+    Texts(Vec<String>),
+    Date(NaiveDate),
+    Decimal(Decimal),
+    Uuid(Uuid),
+    Bool(bool),
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_string())
+    }
+}
+
+impl From<&String> for Value {
+    fn from(value: &String) -> Self {
+        Value::Text(value.clone())
+    }
+}
+
+impl From<Vec<String>> for Value {
+    fn from(value: Vec<String>) -> Self {
+        Value::Texts(value)
+    }
+}
+
+impl From<NaiveDate> for Value {
+    fn from(value: NaiveDate) -> Self {
+        Value::Date(value)
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(value: Decimal) -> Self {
+        Value::Decimal(value)
+    }
+}
+
+impl From<Uuid> for Value {
+    fn from(value: Uuid) -> Self {
+        Value::Uuid(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+#[derive(Clone)]
+enum Part {
+    Sql(String),
+    Bind(Value),
+}
+
+/// The conditions of a listing's `WHERE` clause, all of which must hold.
+/// Values are always bound rather than written into the SQL, and filters
+/// given `None` are left out, so a handler can pass its optional query
+/// parameters straight through. The same filter can be pushed onto both a
+/// page's query and its count.
+#[derive(Clone, Default)]
+pub struct Filter {
+    conditions: Vec<Vec<Part>>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eq(self, column: &str, value: impl Into<Value>) -> Self {
+        self.compare(column, "=", value.into())
+    }
+
+    pub fn gte(self, column: &str, value: impl Into<Value>) -> Self {
+        self.compare(column, ">=", value.into())
+    }
+
+    pub fn lte(self, column: &str, value: impl Into<Value>) -> Self {
+        self.compare(column, "<=", value.into())
+    }
+
+    /// `column` is any of `values`, unless there are none.
+    pub fn any(self, column: &str, values: &[String]) -> Self {
+        if values.is_empty() {
+            return self;
+        }
+        self.condition(vec![
+            Part::Sql(format!("{} = ANY(", column)),
+            Part::Bind(Value::Texts(values.to_vec())),
+            Part::Sql(")".to_string()),
+        ])
+    }
+
+    /// `column` contains `value`, in any case.
+    pub fn contains(self, column: &str, value: Option<&str>) -> Self {
+        let pattern = value.map(contains_pattern);
+        self.compare(column, "ILIKE", pattern.into())
+    }
+
+    /// A condition without values, e.g. `t.superseded_by IS NULL`.
+    pub fn sql(self, condition: &str) -> Self {
+        self.condition(vec![Part::Sql(condition.to_string())])
+    }
+
+    /// Rows after `(key, id)` in an order by `key_column` and then
+    /// `id_column`, or before it if `descending`.
+    pub fn past(self, key_column: &str, id_column: &str, descending: bool, key: Value, id: &str) -> Self {
+        self.condition(vec![
+            Part::Sql(format!("({}, {}) {} (", key_column, id_column, if descending { "<" } else { ">" })),
+            Part::Bind(key),
+            Part::Sql(", ".to_string()),
+            Part::Bind(Value::Text(id.to_string())),
+            Part::Sql(")".to_string()),
+        ])
+    }
+
+    /// Appends ` WHERE` and the conditions, if there are any.
+    pub fn push_where(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        for (i, condition) in self.conditions.iter().enumerate() {
+            builder.push(if i == 0 { " WHERE " } else { " AND " });
+            for part in condition {
+                match part {
+                    Part::Sql(sql) => {
+                        builder.push(sql);
+                    }
+                    Part::Bind(value) => push_value(builder, value.clone()),
+                }
+            }
+        }
+    }
+
+    fn compare(self, column: &str, op: &str, value: Value) -> Self {
+        if value == Value::Null {
+            return self;
+        }
+        self.condition(vec![Part::Sql(format!("{} {} ", column, op)), Part::Bind(value)])
+    }
+
+    fn condition(mut self, parts: Vec<Part>) -> Self {
+        self.conditions.push(parts);
+        self
+    }
+}
+
+fn push_value(builder: &mut QueryBuilder<'_, Postgres>, value: Value) {
+    match value {
+        Value::Null => builder.push("NULL"),
+        Value::Text(value) => builder.push_bind(value),
+        Value::Texts(value) => builder.push_bind(value),
+        Value::Date(value) => builder.push_bind(value),
+        Value::Decimal(value) => builder.push_bind(value),
+        Value::Uuid(value) => builder.push_bind(value),
+        Value::Bool(value) => builder.push_bind(value),
+    };
+}
+
+/// Reads a query parameter such as `account_id=a,b` as each of its values,
+/// skipping empty ones.
+pub fn comma_separated<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let values = String::deserialize(deserializer)?;
+    Ok(values
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// `value` as an `ILIKE` pattern matching it anywhere, with its own `%`, `_`
+/// and `\` taken literally.
+pub fn contains_pattern(value: &str) -> String {
+    let mut pattern = String::with_capacity(value.len() + 2);
+    pattern.push('%');
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web;
+
+    #[derive(Debug, Deserialize)]
+    struct Query {
+        #[serde(default, deserialize_with = "comma_separated")]
+        ids: Vec<String>,
+    }
+
+    #[test]
+    fn test_comma_separated() {
+        let query = web::Query::<Query>::from_query("ids=a,%20b,,c").unwrap();
+        assert_eq!(query.ids, vec!["a", "b", "c"]);
+
+        let query = web::Query::<Query>::from_query("").unwrap();
+        assert!(query.ids.is_empty());
+    }
+
+    #[test]
+    fn test_filter_leaves_out_missing_values() {
+        let filter = Filter::new()
+            .eq("team_id", Uuid::nil())
+            .eq("currency", None::<String>)
+            .any("account_id", &[])
+            .any("connection_id", &["conn_1".to_string()])
+            .gte("date", NaiveDate::from_ymd_opt(2024, 1, 1))
+            .contains("description", Some("50%"))
+            .sql("superseded_by IS NULL");
+
+        let mut builder = QueryBuilder::new("SELECT * FROM transactions");
+        filter.push_where(&mut builder);
+        assert_eq!(
+            builder.sql(),
+            "SELECT * FROM transactions WHERE team_id = $1 AND connection_id = ANY($2) \
+             AND date >= $3 AND description ILIKE $4 AND superseded_by IS NULL"
+        );
+
+        let mut builder = QueryBuilder::new("SELECT * FROM accounts");
+        Filter::new().eq("currency", None::<&str>).push_where(&mut builder);
+        assert_eq!(builder.sql(), "SELECT * FROM accounts");
+    }
+
+    #[test]
+    fn test_filter_past() {
+        let mut builder = QueryBuilder::new("SELECT * FROM accounts");
+        Filter::new()
+            .past("name", "id", false, Value::from("Checking"), "acc_1")
+            .push_where(&mut builder);
+        assert_eq!(builder.sql(), "SELECT * FROM accounts WHERE (name, id) > ($1, $2)");
+    }
+
+    #[test]
+    fn test_contains_pattern() {
+        assert_eq!(contains_pattern("coffee"), "%coffee%");
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+}
@@ -2,6 +2,7 @@ use std::future::Future;
 use std::time::Duration;
 use tokio::time::sleep;
 
+#[derive(Debug, Clone)]
 pub struct RetryConfig {
     pub max_attempts: u32,
     pub initial_delay_ms: u64,
@@ -20,6 +21,17 @@ impl Default for RetryConfig {
     }
 }
 
+impl RetryConfig {
+    /// How long to wait after the given attempt fails, counting from 1. The
+    /// delay grows by `backoff_factor` each time, up to `max_delay_ms`.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay_ms as f64 * self.backoff_factor.powi(exponent);
+        Duration::from_millis(delay.min(self.max_delay_ms as f64) as u64)
+    }
+}
+
+#[allow(dead_code)]
 pub async fn retry<T, E, Fut, F>(
     operation: F,
     config: RetryConfig,
@@ -30,7 +42,6 @@ where
     E: std::fmt::Debug,
 {
     let mut attempts = 0;
-    let mut delay = config.initial_delay_ms;
 
     loop {
         attempts += 1;
@@ -42,20 +53,16 @@ where
                     return Err(error);
                 }
 
+                let delay = config.delay_for(attempts);
                 log::warn!(
                     "Attempt {}/{} failed: {:?}. Retrying in {}ms...",
                     attempts,
                     config.max_attempts,
                     error,
-                    delay
+                    delay.as_millis()
                 );
 
-                sleep(Duration::from_millis(delay)).await;
-
-                delay = (delay as f64 * config.backoff_factor) as u64;
-                if delay > config.max_delay_ms {
-                    delay = config.max_delay_ms;
-                }
+                sleep(delay).await;
             }
         }
     }
@@ -124,4 +131,14 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(attempts.load(Ordering::SeqCst), 2);
     }
+
+    #[test]
+    fn test_delay_for() {
+        let config = RetryConfig::default();
+
+        assert_eq!(config.delay_for(1), Duration::from_millis(100));
+        assert_eq!(config.delay_for(2), Duration::from_millis(200));
+        assert_eq!(config.delay_for(4), Duration::from_millis(800));
+        assert_eq!(config.delay_for(10), Duration::from_millis(5000));
+    }
 }
@@ -0,0 +1,235 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use rust_decimal_macros::dec;
+use sqlx::{postgres::PgPoolOptions, Executor, PgPool};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+use super::config::Config;
+use super::crypto::Keyring;
+use crate::providers::{types::*, Provider, ProviderError, ProviderResult};
+
+/// The team `test_pool` creates, for tests that don't need more than one.
+pub const TEST_TEAM_ID: Uuid = Uuid::from_u128(1);
+
+/// Sandbox settings with dummy credentials. Wise is left unconfigured.
+pub fn test_config() -> Config {
+    Config {
+        database_url: String::new(),
+        redis_url: String::new(),
+        port: 8080,
+        plaid_client_id: "client".to_string(),
+        plaid_secret: "secret".to_string(),
+        plaid_environment: "sandbox".to_string(),
+        teller_environment: "sandbox".to_string(),
+        teller_certificate_path: None,
+        teller_private_key_path: None,
+        gocardless_secret_id: "secret_id".to_string(),
+        gocardless_secret_key: "secret_key".to_string(),
+        truelayer_client_id: "client".to_string(),
+        truelayer_secret: "secret".to_string(),
+        truelayer_environment: "sandbox".to_string(),
+        wise_api_token: None,
+        wise_team_id: None,
+        wise_client_id: None,
+        wise_secret: None,
+        wise_environment: "sandbox".to_string(),
+        disabled_providers: Vec::new(),
+        sync_max_concurrency: 4,
+        sync_tick_seconds: 60,
+        sync_intervals: HashMap::new(),
+        job_poll_seconds: 5,
+        pending_match_days: 7,
+        pending_match_tolerance: 0.2,
+        token_refresh_margin_minutes: 10,
+        token_keys: Keyring::parse("test:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap(),
+        rate_limits: HashMap::from([("default".to_string(), 120)]),
+        tenant_rate_limits: HashMap::from([("default".to_string(), 600)]),
+        rate_limit_window_seconds: 60,
+        cache_ttls: HashMap::new(),
+        cache_capacity: 100,
+        jwt: None,
+    }
+}
+
+/// Connects to the database in `TEST_DATABASE_URL` and migrates a fresh,
+/// uniquely named schema so tests can run in parallel, with `TEST_TEAM_ID`
+/// already created. Returns `None` when the variable is unset so database
+/// tests are skipped rather than failed.
+pub async fn test_pool() -> Option<PgPool> {
+    let url = std::env::var("TEST_DATABASE_URL").ok()?;
+    let schema = format!("test_{}", Uuid::new_v4().simple());
+
+    let admin = PgPool::connect(&url).await.expect("Failed to connect to test database");
+    admin
+        .execute(format!("CREATE SCHEMA {}", schema).as_str())
+        .await
+        .expect("Failed to create test schema");
+    admin.close().await;
+
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .after_connect(move |conn, _| {
+            let search_path = format!("SET search_path TO {}", schema);
+            Box::pin(async move {
+                conn.execute(search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(&url)
+        .await
+        .expect("Failed to connect to test database");
+
+    crate::migrate::up(&pool)
+        .await
+        .expect("Failed to run database migrations");
+    sqlx::query("INSERT INTO teams (id, name) VALUES ($1, 'Test')")
+        .bind(TEST_TEAM_ID)
+        .execute(&pool)
+        .await
+        .expect("Failed to create test team");
+
+    Some(pool)
+}
+
+/// Reports one account per connection, keyed by its access token, and
+/// records how many syncs overlap. Lists a single institution without a
+/// logo.
+///
+/// Access tokens starting with `expired` are rejected. With `token_refresh`
+/// set, refresh tokens are swapped for a `fresh` access token valid for an
+/// hour, unless they start with `revoked`.
+#[derive(Default)]
+pub struct FakeProvider {
+    pub calls: AtomicUsize,
+    current: AtomicUsize,
+    pub max_concurrent: AtomicUsize,
+    token_refresh: bool,
+}
+
+impl FakeProvider {
+    pub fn with_token_refresh() -> Self {
+        Self {
+            token_refresh: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for FakeProvider {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            accounts: true,
+            balances: true,
+            transactions: true,
+            pending_transactions: false,
+            transaction_sync: true,
+            date_range: false,
+            stable_transaction_ids: true,
+            token_refresh: self.token_refresh,
+            identity: false,
+            institutions: true,
+            delete_connection: false,
+        }
+    }
+
+    async fn exchange_token(&self, _request: ExchangeTokenRequest) -> ProviderResult<TokenResponse> {
+        Err(ProviderError::Unsupported("exchange_token".to_string()))
+    }
+
+    async fn refresh_token(&self, request: RefreshTokenRequest) -> ProviderResult<TokenResponse> {
+        if !self.token_refresh {
+            return Err(ProviderError::Unsupported("refresh_token".to_string()));
+        }
+        if request.refresh_token.starts_with("revoked") {
+            return Err(ProviderError::Unauthorized("invalid_grant".to_string()));
+        }
+
+        Ok(TokenResponse {
+            access_token: "fresh".to_string(),
+            refresh_token: None,
+            expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+        })
+    }
+
+    async fn get_accounts(&self, _request: GetAccountsRequest) -> ProviderResult<Vec<Account>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_account_balance(&self, _request: GetAccountBalanceRequest) -> ProviderResult<Balance> {
+        Err(ProviderError::Unsupported("get_account_balance".to_string()))
+    }
+
+    async fn get_transactions(&self, _request: GetTransactionsRequest) -> ProviderResult<TransactionPage> {
+        Ok(TransactionPage::default())
+    }
+
+    async fn sync_transactions(&self, request: SyncTransactionsRequest) -> ProviderResult<TransactionSync> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if request.access_token.starts_with("expired") {
+            return Err(ProviderError::Unauthorized("token expired".to_string()));
+        }
+        let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_concurrent.fetch_max(current, Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        self.current.fetch_sub(1, Ordering::SeqCst);
+
+        let account_id = format!("acc_{}", request.access_token);
+        Ok(TransactionSync {
+            accounts: vec![Account {
+                id: account_id.clone(),
+                name: "Checking".to_string(),
+                account_type: AccountType::Checking,
+                balance: Balance {
+                    amount: dec!(250),
+                    currency: "USD".to_string(),
+                    available: None,
+                },
+                currency: "USD".to_string(),
+                institution_id: "ins_1".to_string(),
+                last_sync: None,
+            }],
+            added: vec![Transaction {
+                id: format!("tx_{}", request.access_token),
+                account_id,
+                amount: dec!(-12.5),
+                currency: "USD".to_string(),
+                date: Utc::now(),
+                description: "Coffee".to_string(),
+                merchant: None,
+                category: None,
+                status: TransactionStatus::Posted,
+                balance: None,
+                currency_rate: None,
+                currency_source: None,
+            }],
+            checkpoint: Some("cursor_1".to_string()),
+            ..Default::default()
+        })
+    }
+
+    async fn get_institutions(&self, _request: GetInstitutionsRequest) -> ProviderResult<Vec<Institution>> {
+        Ok(vec![Institution {
+            id: "chase".to_string(),
+            name: "Chase".to_string(),
+            logo_url: None,
+            website: Some("https://www.chase.com".to_string()),
+            country: "US".to_string(),
+        }])
+    }
+
+    async fn get_connection_status(
+        &self,
+        _request: GetConnectionStatusRequest,
+    ) -> ProviderResult<ConnectionStatus> {
+        Ok(ConnectionStatus {
+            status: ConnectionState::Connected,
+        })
+    }
+
+    async fn delete_connection(&self, _request: DeleteConnectionRequest) -> ProviderResult<()> {
+        Ok(())
+    }
+}